@@ -0,0 +1,16 @@
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+
+use detect_inj::coalesce::OrderedCoalesce;
+
+// segments follow one another as a big-endian sequence number, a length byte and the payload
+fuzz_target!(|data: &[u8]| {
+    let mut stream = OrderedCoalesce::with_history(64);
+    let mut data = data;
+    while data.len() >= 5 {
+        let seq = u32::from_be_bytes([data[0], data[1], data[2], data[3]]);
+        let len = (data[4] as usize).min(data.len() - 5);
+        let _ = stream.insert(seq.into(), &data[5..5 + len]);
+        data = &data[5 + len..];
+    }
+});
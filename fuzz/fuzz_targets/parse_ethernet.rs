@@ -0,0 +1,8 @@
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+
+use detect_inj::tcp_iterator::TcpIterator;
+
+fuzz_target!(|data: &[u8]| {
+    let _ = TcpIterator::parse_ethernet(data);
+});
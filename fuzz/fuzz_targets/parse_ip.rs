@@ -0,0 +1,13 @@
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+
+use detect_inj::tcp_iterator::TcpIterator;
+
+// first two bytes pick the ethertype, the rest is the IP datagram
+fuzz_target!(|data: &[u8]| {
+    if data.len() < 2 {
+        return
+    }
+    let ty = u16::from_be_bytes([data[0], data[1]]);
+    let _ = TcpIterator::parse_ip(ty, &data[2..]);
+});
@@ -0,0 +1,18 @@
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+
+use detect_inj::tcp_iterator::TcpIterator;
+use detect_inj::types::IpLayer;
+
+use std::net::Ipv4Addr;
+
+fuzz_target!(|data: &[u8]| {
+    let ip = IpLayer {
+        src: Ipv4Addr::new(1, 2, 3, 4).into(),
+        dst: Ipv4Addr::new(2, 3, 4, 5).into(),
+        total_len: 20 + data.len() as u32,
+        id: None,
+        ttl: 64,
+    };
+    let _ = TcpIterator::parse_tcp(ip, data);
+});
@@ -0,0 +1,207 @@
+//! Higher level alerts raised on patterns in the stream of attack reports.
+
+use std::cell::RefCell;
+use std::collections::{HashSet, VecDeque};
+use std::fmt;
+use std::net::IpAddr;
+use std::rc::Rc;
+use std::convert::TryFrom;
+use std::time::Duration;
+
+use time::PrimitiveDateTime;
+
+use crate::event::{AnomalyReport, AttackReport, AttackReporter};
+use crate::types::Flow;
+
+/// Weight of the latest window in the report rate baseline.
+const BASELINE_WEIGHT: f64 = 0.1;
+/// Windows without reports folded into the baseline at most, after a longer silence it's just zero.
+const MAX_IDLE_WINDOWS: u32 = 1000;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum MetaAlert {
+    /// Attacks on many distinct flows to the same server within a window.
+    Campaign { server: IpAddr, flows: usize },
+    /// Many more reports within a window than the sensor usually sees.
+    RateSpike { reports: usize, baseline: f64 },
+}
+
+impl MetaAlert {
+    /// Stable code, numbered apart from the codes of single attack types.
+    pub fn code(&self) -> &'static str {
+        match self {
+            MetaAlert::Campaign { .. } => "INJ-101",
+            MetaAlert::RateSpike { .. } => "INJ-102",
+        }
+    }
+}
+
+impl fmt::Display for MetaAlert {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            MetaAlert::Campaign { server, flows } =>
+                write!(f, "{} campaign: {} attacked flows to {}", self.code(), flows, server),
+            MetaAlert::RateSpike { reports, baseline } =>
+                write!(f, "{} rate spike: {} reports, baseline {:.1}", self.code(), reports, baseline),
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone)]
+pub struct MetaAlertConfig {
+    pub window: Duration,
+    /// Distinct attacked flows to one server within a window making a campaign.
+    pub campaign_flows: usize,
+    /// How many times the baseline rate within a window makes a spike.
+    pub spike_factor: f64,
+    /// Fewest reports within a window making a spike, so that a quiet sensor doesn't alert on a few.
+    pub spike_min_reports: usize,
+}
+
+impl Default for MetaAlertConfig {
+    fn default() -> Self {
+        Self {
+            window: Duration::from_secs(300),
+            campaign_flows: 5,
+            spike_factor: 4.0,
+            spike_min_reports: 10,
+        }
+    }
+}
+
+/// Sliding window of recent reports and a moving average of reports per window, by the time of
+/// reports so that a capture file replayed at full speed alerts as it would have when recorded.
+///
+/// Each alert is raised once when its threshold is reached, and again only after
+/// the pattern has faded and reappeared.
+pub struct MetaAlerts {
+    config: MetaAlertConfig,
+    recent: VecDeque<(PrimitiveDateTime, IpAddr, Flow)>,
+    baseline: f64,
+    bucket_start: Option<PrimitiveDateTime>,
+    bucket_reports: usize,
+}
+
+impl MetaAlerts {
+    pub fn new(config: MetaAlertConfig) -> Self {
+        Self{ config, recent: VecDeque::new(), baseline: 0.0, bucket_start: None, bucket_reports: 0 }
+    }
+
+    pub fn observe(&mut self, report: &AttackReport) -> Vec<MetaAlert> {
+        let now = report.time;
+        let mut alerts = Vec::new();
+        while self.recent.front().is_some_and(|&(at, _, _)| elapsed(at, now) >= self.config.window) {
+            self.recent.pop_front();
+        }
+
+        let server = report.server();
+        let is_new_flow = !self.recent.iter().any(|(_, _, flow)| *flow == report.flow);
+        self.recent.push_back((now, server, report.flow));
+        if is_new_flow {
+            let flows: HashSet<_> = self.recent.iter()
+                .filter(|&&(_, addr, _)| addr == server)
+                .map(|(_, _, flow)| flow)
+                .collect();
+            if flows.len() == self.config.campaign_flows {
+                alerts.push(MetaAlert::Campaign{ server, flows: flows.len() });
+            }
+        }
+
+        self.roll_bucket(now);
+        self.bucket_reports += 1;
+        let threshold = (self.baseline * self.config.spike_factor).ceil() as usize;
+        if self.bucket_reports == threshold.max(self.config.spike_min_reports) {
+            alerts.push(MetaAlert::RateSpike{ reports: self.bucket_reports, baseline: self.baseline });
+        }
+        alerts
+    }
+
+    /// Folds finished windows into the baseline.
+    fn roll_bucket(&mut self, now: PrimitiveDateTime) {
+        let start = *self.bucket_start.get_or_insert(now);
+        let windows = elapsed(start, now).as_secs_f64() / self.config.window.as_secs_f64();
+        let finished = windows.min(f64::from(MAX_IDLE_WINDOWS)) as u32;
+        if finished == 0 {
+            return
+        }
+        self.baseline += BASELINE_WEIGHT * (self.bucket_reports as f64 - self.baseline);
+        for _ in 1..finished {
+            self.baseline -= BASELINE_WEIGHT * self.baseline;
+        }
+        self.bucket_start = Some(now);
+        self.bucket_reports = 0;
+    }
+}
+
+/// Time from `since` to `now`, zero if `now` is earlier as reports of different connections may
+/// come slightly out of order.
+fn elapsed(since: PrimitiveDateTime, now: PrimitiveDateTime) -> Duration {
+    Duration::try_from(now - since).unwrap_or_default()
+}
+
+/// Feeds reports to the shared aggregator and prints meta-alerts, passing reports on.
+pub struct MetaAlertReporter {
+    inner: Box<dyn AttackReporter>,
+    alerts: Rc<RefCell<MetaAlerts>>,
+}
+
+impl MetaAlertReporter {
+    pub fn new(inner: Box<dyn AttackReporter>, alerts: Rc<RefCell<MetaAlerts>>) -> Self {
+        Self{ inner, alerts }
+    }
+}
+
+impl AttackReporter for MetaAlertReporter {
+    fn attacks_reported(&self) -> u32 {
+        self.inner.attacks_reported()
+    }
+
+    fn report_attack(&mut self, report: AttackReport) {
+        for alert in self.alerts.borrow_mut().observe(&report) {
+            eprintln!("Meta-alert {}", alert);
+        }
+        self.inner.report_attack(report);
+    }
+
+    fn report_anomaly(&mut self, report: AnomalyReport) {
+        self.inner.report_anomaly(report);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::event::AttackKind;
+    use time::{Date, Time};
+
+    #[test]
+    fn campaign_and_spike() {
+        let mut alerts = MetaAlerts::new(MetaAlertConfig {
+            window: Duration::from_secs(60),
+            campaign_flows: 3,
+            spike_factor: 2.0,
+            spike_min_reports: 4,
+        });
+        let start = Date::try_from_ymd(2020, 3, 1).unwrap().with_time(Time::try_from_hms(12, 0, 0).unwrap());
+        let report = |client_port: u16, secs| AttackReport::new(
+            start + Duration::from_secs(secs),
+            format!("10.0.0.1:443 <-> 10.0.0.2:{}", client_port).parse().unwrap(),
+            AttackKind::HandshakeHijack { packet_count: 2, hijack_seq: 1, hijack_ack: 2, first: None, competing: Default::default(), differing: Vec::new() },
+        );
+        assert!(alerts.observe(&report(1, 0)).is_empty());
+        assert!(alerts.observe(&report(1, 1)).is_empty());
+        assert!(alerts.observe(&report(2, 2)).is_empty());
+        let server = "10.0.0.1".parse().unwrap();
+        assert_eq!(alerts.observe(&report(3, 3)), vec![
+            MetaAlert::Campaign{ server, flows: 3 },
+            MetaAlert::RateSpike{ reports: 4, baseline: 0.0 },
+        ]);
+        assert!(alerts.observe(&report(4, 4)).is_empty());
+
+        // the busy window raised the baseline, the two quiet ones lowered it
+        assert!(alerts.observe(&report(1, 200)).is_empty());
+        assert!((alerts.baseline - 0.405).abs() < 1e-9);
+        assert!(alerts.observe(&report(2, 201)).is_empty());
+        assert_eq!(alerts.observe(&report(3, 202)), vec![MetaAlert::Campaign{ server, flows: 3 }]);
+    }
+}
@@ -0,0 +1,252 @@
+//! Reconstruction of byte streams of attacked connections for offline analysis.
+
+use std::fmt::{self, Write};
+use std::fs;
+use std::io;
+use std::ops::Range;
+use std::path::Path;
+
+use crate::types::{Flow, Sequence};
+
+/// Which copy of the data counts when segments overlap. Receiving stacks differ in this,
+/// so an injection may be seen differently by different victims.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum OverlapPolicy {
+    FirstWins,
+    LastWins,
+}
+
+impl OverlapPolicy {
+    pub fn name(self) -> &'static str {
+        match self {
+            OverlapPolicy::FirstWins => "first",
+            OverlapPolicy::LastWins => "last",
+        }
+    }
+}
+
+/// Payload segments of one direction in arrival order, up to a byte limit.
+#[derive(Debug, Clone)]
+pub struct StreamRecorder {
+    segments: Vec<(Sequence, Vec<u8>)>,
+    bytes: usize,
+    limit: usize,
+    truncated: bool,
+}
+
+impl StreamRecorder {
+    pub fn new(limit: usize) -> Self {
+        Self{ segments: Vec::new(), bytes: 0, limit, truncated: false }
+    }
+
+    /// Keeps a segment, unless it's over the byte limit or lies as far as that from the first one:
+    /// the reconstruction spans the segments, so a stray one far away would make it huge.
+    pub fn record(&mut self, seq: Sequence, payload: &[u8]) {
+        if payload.is_empty() {
+            return
+        }
+        let span = self.segments.first().map_or(0, |&(first, _)| i64::from(first.distance(seq)).unsigned_abs() as usize);
+        if self.bytes + payload.len() > self.limit || span + payload.len() > self.limit {
+            self.truncated = true;
+            return
+        }
+        self.bytes += payload.len();
+        self.segments.push((seq, payload.to_vec()));
+    }
+
+    /// Whether segments were dropped because of the limit.
+    pub fn is_truncated(&self) -> bool {
+        self.truncated
+    }
+
+    /// Lays the segments out by sequence number. Holes are left zero filled.
+    pub fn reconstruct(&self, policy: OverlapPolicy) -> Reconstruction {
+        let first = match self.segments.first() {
+            Some(&(first, _)) => first,
+            None => return Reconstruction{ start: Sequence::from(0), data: Vec::new() },
+        };
+        let offset = |seq: Sequence| i64::from(first.distance(seq));
+        let base = self.segments.iter().map(|&(seq, _)| offset(seq)).min().unwrap_or(0);
+        let end = self.segments.iter().map(|(seq, payload)| offset(*seq) + payload.len() as i64).max().unwrap_or(0);
+
+        let mut data = vec![0; (end - base) as usize];
+        let mut written = vec![false; data.len()];
+        for (seq, payload) in &self.segments {
+            let at = (offset(*seq) - base) as usize;
+            for (i, &byte) in payload.iter().enumerate() {
+                if policy == OverlapPolicy::LastWins || !written[at + i] {
+                    data[at + i] = byte;
+                    written[at + i] = true;
+                }
+            }
+        }
+        Reconstruction{ start: first + base as u32, data }
+    }
+}
+
+/// Contiguous reconstructed stream starting at `start`.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Reconstruction {
+    pub start: Sequence,
+    pub data: Vec<u8>,
+}
+
+/// Range where first-wins and last-wins reconstructions disagree.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct DiffRange {
+    pub start: Sequence,
+    pub len: usize,
+    pub first: Option<Artifact>,
+    pub last: Option<Artifact>,
+}
+
+/// Recognizable application data in a differing range.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum Artifact {
+    HttpRequest,
+    HttpResponse{ status: u16 },
+    TlsRecord{ content_type: u8 },
+}
+
+impl fmt::Display for Artifact {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Artifact::HttpRequest => write!(f, "http-request"),
+            Artifact::HttpResponse{ status } => write!(f, "http-response/{}", status),
+            Artifact::TlsRecord{ content_type } => write!(f, "tls-record/{}", content_type),
+        }
+    }
+}
+
+const HTTP_METHODS: [&[u8]; 9] =
+    [b"GET ", b"POST ", b"HEAD ", b"PUT ", b"DELETE ", b"OPTIONS ", b"PATCH ", b"CONNECT ", b"TRACE "];
+
+/// How far before a differing range a status line may start, so that a range covering
+/// just the status code is still recognized.
+const ARTIFACT_LOOKBACK: usize = 16;
+
+impl Artifact {
+    /// Looks for a message start within `range` of `data`.
+    fn find(data: &[u8], range: Range<usize>) -> Option<Artifact> {
+        let context_start = range.start.saturating_sub(ARTIFACT_LOOKBACK);
+        if let Some(at) = data[context_start..range.end].windows(7).position(|w| w == b"HTTP/1.") {
+            let at = context_start + at;
+            let status = data.get(at + 9..at + 12)
+                .and_then(|status| std::str::from_utf8(status).ok())
+                .and_then(|status| status.parse().ok());
+            if let Some(status) = status {
+                return Some(Artifact::HttpResponse{ status })
+            }
+        }
+        let data = &data[range];
+        if HTTP_METHODS.iter().any(|method| data.starts_with(method)) {
+            return Some(Artifact::HttpRequest)
+        }
+        match data {
+            [content_type @ 0x14..=0x17, 0x03, 0x00..=0x04, ..] => Some(Artifact::TlsRecord{ content_type: *content_type }),
+            _ => None,
+        }
+    }
+}
+
+/// Compares the first-wins and last-wins reconstructions of a stream.
+pub fn diff(recorder: &StreamRecorder) -> Vec<DiffRange> {
+    let first = recorder.reconstruct(OverlapPolicy::FirstWins);
+    let last = recorder.reconstruct(OverlapPolicy::LastWins);
+    let mut ranges: Vec<Range<usize>> = Vec::new();
+    for i in (0..first.data.len()).filter(|&i| first.data[i] != last.data[i]) {
+        match ranges.last_mut() {
+            Some(range) if range.end == i => range.end += 1,
+            _ => ranges.push(i..i + 1),
+        }
+    }
+    ranges.into_iter()
+        .map(|range| DiffRange {
+            start: first.start + range.start as u32,
+            len: range.len(),
+            first: Artifact::find(&first.data, range.clone()),
+            last: Artifact::find(&last.data, range),
+        })
+        .collect()
+}
+
+/// Writes `<flow>-<client|server>-<first|last>.bin` files for both directions and both policies,
+/// and `<flow>-diff.txt` listing where the policies disagree.
+pub fn write_streams(dir: &Path, flow: &Flow, client: &StreamRecorder, server: &StreamRecorder) -> io::Result<()> {
+    fs::create_dir_all(dir)?;
+    let name: String = flow.to_string().chars()
+        .map(|c| if c.is_ascii_alphanumeric() || c == '.' { c } else { '_' })
+        .collect();
+    let mut report = format!("flow {}\n", flow);
+    for &(side, recorder) in &[("client", client), ("server", server)] {
+        for &policy in &[OverlapPolicy::FirstWins, OverlapPolicy::LastWins] {
+            let path = dir.join(format!("{}-{}-{}.bin", name, side, policy.name()));
+            fs::write(path, recorder.reconstruct(policy).data)?;
+        }
+        write_diff(&mut report, side, recorder).expect("writing to a string");
+    }
+    fs::write(dir.join(format!("{}-diff.txt", name)), report)
+}
+
+/// One `<side> seq=<start> len=<len> first=<artifact> last=<artifact>` line per differing range.
+fn write_diff(out: &mut String, side: &str, recorder: &StreamRecorder) -> fmt::Result {
+    let artifact = |artifact: Option<Artifact>| artifact.map_or_else(|| "-".to_owned(), |artifact| artifact.to_string());
+    for range in diff(recorder) {
+        writeln!(out, "{} seq={} len={} first={} last={}",
+                 side, u32::from(range.start), range.len, artifact(range.first), artifact(range.last))?;
+    }
+    if recorder.is_truncated() {
+        writeln!(out, "{} truncated", side)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn overlap_policies_across_wrap() {
+        let mut recorder = StreamRecorder::new(64);
+        let start = Sequence::from(u32::MAX - 2);
+        recorder.record(start, b"GET /");
+        recorder.record(start + 8, b"ok");
+        // injected copy of the first bytes, arriving later
+        recorder.record(start + 2, b"XXXXX");
+        recorder.record(start + 100, &[0; 60]);
+        assert!(recorder.is_truncated());
+        // a byte far ahead of the stream doesn't stretch the reconstruction
+        recorder.record(start + (1 << 31), b"X");
+        recorder.record(start + 63, b"XX");
+
+        let first = recorder.reconstruct(OverlapPolicy::FirstWins);
+        assert_eq!(first.start, start);
+        assert_eq!(first.data, b"GET /XX\0ok");
+        assert_eq!(recorder.reconstruct(OverlapPolicy::LastWins).data, b"GEXXXXX\0ok");
+    }
+
+    #[test]
+    fn diff_finds_injected_response() {
+        let mut recorder = StreamRecorder::new(1024);
+        let genuine = b"HTTP/1.1 200 OK\r\nContent-Length: 2\r\n\r\nhi";
+        let injected = b"HTTP/1.1 302 Found\r\nLocation: http://blocked/\r\n";
+        recorder.record(Sequence::from(1000), genuine);
+        recorder.record(Sequence::from(1000), injected);
+
+        let ranges = diff(&recorder);
+        assert_eq!(ranges[0].start, Sequence::from(1009));
+        assert_eq!(ranges[0].first, Some(Artifact::HttpResponse{ status: 200 }));
+        assert_eq!(ranges[0].last, Some(Artifact::HttpResponse{ status: 302 }));
+        assert!(ranges.iter().all(|range| u32::from(range.start) + range.len as u32 <= 1000 + genuine.len() as u32));
+
+        let mut whole = StreamRecorder::new(1024);
+        whole.record(Sequence::from(1), b"\x17\x03\x03\x00\x10");
+        whole.record(Sequence::from(1), b"HTTP/1.0 403 Forbidden");
+        assert_eq!(diff(&whole), vec![DiffRange {
+            start: Sequence::from(1),
+            len: 5,
+            first: Some(Artifact::TlsRecord{ content_type: 0x17 }),
+            last: None,
+        }]);
+    }
+}
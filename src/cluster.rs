@@ -0,0 +1,284 @@
+//! Sensor to collector streaming for fleet deployments.
+//!
+//! Sensors connect to the collector over TCP and send one message per line:
+//! `hello <sensor>`, `heartbeat <sensor>` or `report <sensor> <code> <attack type> <flow>`.
+//! The collector merges reports of all sensors, dropping reports of the same attack on the same
+//! flow seen by several sensors, and keeps track of which sensors are alive.
+
+use std::collections::HashMap;
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::str::FromStr;
+use std::sync::{mpsc, Arc, Mutex};
+use std::time::{Duration, Instant};
+use std::{cmp, error, fmt, thread};
+
+use crate::event::{AnomalyReport, AttackReport, AttackReporter};
+use crate::types::Flow;
+
+pub const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(10);
+/// A sensor missing three heartbeats is considered down.
+const SENSOR_TIMEOUT: Duration = Duration::from_secs(30);
+/// Reports of the same attack on the same flow within this time are duplicates.
+const DEDUP_WINDOW: Duration = Duration::from_secs(60);
+const FLEET_METRICS_INTERVAL: Duration = Duration::from_secs(60);
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum Message {
+    Hello { sensor: String },
+    Heartbeat { sensor: String },
+    Report { sensor: String, code: String, kind: String, flow: Flow },
+}
+
+impl Message {
+    pub fn sensor(&self) -> &str {
+        match self {
+            Message::Hello { sensor } | Message::Heartbeat { sensor } | Message::Report { sensor, .. } => sensor,
+        }
+    }
+}
+
+impl fmt::Display for Message {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Message::Hello { sensor } => write!(f, "hello {}", sensor),
+            Message::Heartbeat { sensor } => write!(f, "heartbeat {}", sensor),
+            Message::Report { sensor, code, kind, flow } => write!(f, "report {} {} {} {}", sensor, code, kind, flow),
+        }
+    }
+}
+
+impl FromStr for Message {
+    type Err = ParseMessageError;
+    fn from_str(s: &str) -> Result<Self, ParseMessageError> {
+        let err = || ParseMessageError(s.to_owned());
+        let mut fields = s.trim().splitn(5, ' ');
+        let (message, sensor) = (fields.next().ok_or_else(err)?, fields.next().ok_or_else(err)?.to_owned());
+        match (message, fields.next(), fields.next(), fields.next()) {
+            ("hello", None, _, _) => Ok(Message::Hello{ sensor }),
+            ("heartbeat", None, _, _) => Ok(Message::Heartbeat{ sensor }),
+            ("report", Some(code), Some(kind), Some(flow)) => Ok(Message::Report {
+                sensor,
+                code: code.to_owned(),
+                kind: kind.to_owned(),
+                flow: flow.parse().map_err(|_| err())?,
+            }),
+            _ => Err(err()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct ParseMessageError(String);
+
+impl fmt::Display for ParseMessageError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "invalid collector message `{}`", self.0)
+    }
+}
+
+impl error::Error for ParseMessageError {}
+
+/// Connection of a sensor to the collector, reconnected on the next message after a failure.
+#[derive(Clone)]
+pub struct CollectorClient {
+    collector: SocketAddr,
+    sensor: String,
+    stream: Arc<Mutex<Option<TcpStream>>>,
+}
+
+impl CollectorClient {
+    pub fn new(collector: SocketAddr, sensor: String) -> Self {
+        Self{ collector, sensor, stream: Arc::new(Mutex::new(None)) }
+    }
+
+    /// Starts a thread sending heartbeats.
+    pub fn spawn_heartbeats(&self) -> thread::JoinHandle<()> {
+        let client = self.clone();
+        thread::spawn(move || loop {
+            if let Err(err) = client.send(&Message::Heartbeat{ sensor: client.sensor.clone() }) {
+                eprintln!("Collector {} unreachable: {}", client.collector, err);
+            }
+            thread::sleep(HEARTBEAT_INTERVAL);
+        })
+    }
+
+    pub fn send_report(&self, report: &AttackReport) -> io::Result<()> {
+        self.send(&Message::Report {
+            sensor: self.sensor.clone(),
+            code: report.kind.code().to_owned(),
+            kind: report.kind.name().to_owned(),
+            flow: report.flow,
+        })
+    }
+
+    fn send(&self, message: &Message) -> io::Result<()> {
+        let mut stream = self.stream.lock().unwrap();
+        if stream.is_none() {
+            let mut new_stream = TcpStream::connect_timeout(&self.collector, CONNECT_TIMEOUT)?;
+            new_stream.set_write_timeout(Some(CONNECT_TIMEOUT))?;
+            writeln!(new_stream, "{}", Message::Hello{ sensor: self.sensor.clone() })?;
+            *stream = Some(new_stream);
+        }
+        let result = writeln!(stream.as_mut().expect("connected above"), "{}", message);
+        if result.is_err() {
+            *stream = None;
+        }
+        result
+    }
+}
+
+/// Streams reports to the collector, passing them on.
+pub struct CollectorReporter {
+    inner: Box<dyn AttackReporter>,
+    client: CollectorClient,
+}
+
+impl CollectorReporter {
+    pub fn new(inner: Box<dyn AttackReporter>, client: CollectorClient) -> Self {
+        Self{ inner, client }
+    }
+}
+
+impl AttackReporter for CollectorReporter {
+    fn attacks_reported(&self) -> u32 {
+        self.inner.attacks_reported()
+    }
+
+    fn report_attack(&mut self, report: AttackReport) {
+        if let Err(err) = self.client.send_report(&report) {
+            eprintln!("Failed to send report to collector: {}", err);
+        }
+        self.inner.report_attack(report);
+    }
+
+    fn report_anomaly(&mut self, report: AnomalyReport) {
+        self.inner.report_anomaly(report);
+    }
+}
+
+/// Merged view of all sensors.
+#[derive(Debug, Default)]
+pub struct Fleet {
+    /// Last message time of every sensor ever seen.
+    sensors: HashMap<String, Instant>,
+    /// First report time of recently reported attacks, by attack code and flow in canonical direction.
+    recent: HashMap<(String, Flow), Instant>,
+    reports: u64,
+    duplicates: u64,
+}
+
+impl Fleet {
+    /// Takes a message in, returns it if it's a report not seen from another sensor yet.
+    pub fn receive(&mut self, message: Message, now: Instant) -> Option<Message> {
+        self.sensors.insert(message.sensor().to_owned(), now);
+        let key = match &message {
+            Message::Report { code, flow, .. } => (code.clone(), cmp::min(*flow, flow.reverse())),
+            _ => return None,
+        };
+        self.recent.retain(|_, &mut at| now.duration_since(at) < DEDUP_WINDOW);
+        if self.recent.contains_key(&key) {
+            self.duplicates += 1;
+            return None
+        }
+        self.recent.insert(key, now);
+        self.reports += 1;
+        Some(message)
+    }
+
+    pub fn sensors_alive(&self, now: Instant) -> usize {
+        self.sensors.values().filter(|&&seen| now.duration_since(seen) < SENSOR_TIMEOUT).count()
+    }
+
+    fn summary(&self, now: Instant) -> String {
+        format!("sensors={} alive={} reports={} duplicates={}",
+                self.sensors.len(), self.sensors_alive(now), self.reports, self.duplicates)
+    }
+}
+
+/// Runs the collector: accepts sensors on `listen`, prints merged reports and fleet metrics.
+pub fn run_collector(listen: SocketAddr) -> io::Result<()> {
+    let listener = TcpListener::bind(listen)?;
+    let (sender, messages) = mpsc::channel();
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            match stream {
+                Ok(stream) => {
+                    let sender = sender.clone();
+                    thread::spawn(move || receive_sensor(stream, sender));
+                }
+                Err(err) => eprintln!("Failed to accept sensor: {}", err),
+            }
+        }
+    });
+
+    let mut fleet = Fleet::default();
+    let mut metrics_printed_at = Instant::now();
+    loop {
+        match messages.recv_timeout(Duration::from_secs(1)) {
+            Ok(message) => {
+                if let Some(Message::Report { sensor, code, kind, flow }) = fleet.receive(message, Instant::now()) {
+                    println!("Reported attack {} ({}) on {} by {}", code, kind, flow, sensor);
+                }
+            }
+            Err(mpsc::RecvTimeoutError::Timeout) => {}
+            Err(mpsc::RecvTimeoutError::Disconnected) => return Err(io::Error::other("sensor listener stopped")),
+        }
+        if metrics_printed_at.elapsed() >= FLEET_METRICS_INTERVAL {
+            eprintln!("Fleet: {}", fleet.summary(Instant::now()));
+            metrics_printed_at = Instant::now();
+        }
+    }
+}
+
+fn receive_sensor(stream: TcpStream, messages: mpsc::Sender<Message>) {
+    let peer = stream.peer_addr().map(|peer| peer.to_string()).unwrap_or_default();
+    for line in BufReader::new(stream).lines() {
+        let line = match line {
+            Ok(line) => line,
+            Err(err) => {
+                eprintln!("Sensor {} disconnected: {}", peer, err);
+                return
+            }
+        };
+        match line.parse() {
+            Ok(message) => {
+                if messages.send(message).is_err() {
+                    return
+                }
+            }
+            Err(err) => eprintln!("Sensor {}: {}", peer, err),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn messages_and_dedup() {
+        let report = |sensor: &str, flow: &str| Message::Report {
+            sensor: sensor.to_owned(),
+            code: "INJ-001".to_owned(),
+            kind: "handshake_hijack".to_owned(),
+            flow: flow.parse().unwrap(),
+        };
+        let message = report("edge-1", "1.2.3.4:443 <-> 5.6.7.8:5000 vlan 7");
+        assert_eq!(message.to_string().parse(), Ok(message.clone()));
+        assert_eq!("heartbeat edge-2".parse(), Ok(Message::Heartbeat{ sensor: "edge-2".to_owned() }));
+        assert!("heartbeat".parse::<Message>().is_err());
+        assert!("report edge-1 INJ-001 handshake_hijack".parse::<Message>().is_err());
+
+        let mut fleet = Fleet::default();
+        let now = Instant::now();
+        assert_eq!(fleet.receive(message.clone(), now), Some(message));
+        // the other sensor sees the flow in the other direction
+        assert_eq!(fleet.receive(report("edge-2", "5.6.7.8:5000 <-> 1.2.3.4:443 vlan 7"), now), None);
+        let later = now + DEDUP_WINDOW;
+        assert!(fleet.receive(report("edge-2", "1.2.3.4:443 <-> 5.6.7.8:5000 vlan 7"), later).is_some());
+        assert_eq!(fleet.sensors_alive(later), 1);
+        assert_eq!(fleet.summary(later), "sensors=2 alive=1 reports=2 duplicates=1");
+    }
+}
@@ -0,0 +1,444 @@
+//! Ordering the segments of one direction of a TCP connection.
+//!
+//! Segments arriving ahead of the stream are buffered until the gap before them is filled.
+//! Two segments covering the same range with different bytes mean two senders disagree about
+//! what the stream holds, the signature of an injection racing the genuine data. The latest
+//! bytes delivered in order are kept as history, retransmissions of them are compared as well.
+
+use std::cell::Cell;
+use std::collections::VecDeque;
+
+use crate::types::{Sequence, WrappingRange};
+
+/// Most segments buffered ahead of a gap, the stream gives up on the gap beyond this.
+const MAX_BUFFERED_SEGMENTS: usize = 256;
+/// Delivered bytes kept per stream by default.
+pub const DEFAULT_STREAM_HISTORY: usize = 16 << 10;
+/// Out-of-order bytes a connection buffers by default, both directions together.
+pub const DEFAULT_CONNECTION_BUFFER: usize = 1 << 20;
+/// Out-of-order bytes all connections buffer by default.
+pub const DEFAULT_REASSEMBLY_BUDGET: usize = 256 << 20;
+/// Most bytes of each version an overlap keeps, the start of a rewrite shows what it is about.
+pub const MAX_OVERLAP_BYTES: usize = 64;
+
+/// Range where a segment disagrees with a buffered one.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Overlap {
+    pub range: WrappingRange,
+    /// Bytes the stream held already, up to `MAX_OVERLAP_BYTES` from the start of the range.
+    pub winner: Vec<u8>,
+    /// Bytes of the segment disagreeing with them, as many.
+    pub loser: Vec<u8>,
+}
+
+/// Caps on buffered out-of-order bytes, per connection and for all of them, shared by the connections.
+///
+/// A connection over its cap gives up on its oldest gaps. Once all connections together go over the
+/// budget, the connection buffering at the time stops buffering, and so comparing segments, for good.
+#[derive(Debug)]
+pub struct ReassemblyBudget {
+    connection_limit: usize,
+    limit: usize,
+    used: Cell<usize>,
+    degraded: Cell<u64>,
+}
+
+impl Default for ReassemblyBudget {
+    fn default() -> Self {
+        Self::new(DEFAULT_CONNECTION_BUFFER, DEFAULT_REASSEMBLY_BUDGET)
+    }
+}
+
+impl ReassemblyBudget {
+    pub fn new(connection_limit: usize, limit: usize) -> Self {
+        Self{ connection_limit, limit, used: Cell::new(0), degraded: Cell::new(0) }
+    }
+
+    /// Most bytes one connection buffers.
+    pub fn connection_limit(&self) -> usize {
+        self.connection_limit
+    }
+
+    /// Bytes buffered by all connections.
+    pub fn used(&self) -> usize {
+        self.used.get()
+    }
+
+    /// Connections that stopped buffering as the budget ran out.
+    pub fn degraded(&self) -> u64 {
+        self.degraded.get()
+    }
+
+    pub fn is_exceeded(&self) -> bool {
+        self.used.get() > self.limit
+    }
+
+    /// Takes note of a connection's buffered bytes going from `before` to `after`.
+    pub fn account(&self, before: usize, after: usize) {
+        self.used.set(self.used.get() - before + after);
+    }
+
+    pub fn note_degraded(&self) {
+        self.degraded.set(self.degraded.get() + 1);
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Segment {
+    range: WrappingRange,
+    payload: Vec<u8>,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct OrderedCoalesce {
+    /// Next byte expected in order, learned from the first segment.
+    next_seq: Option<Sequence>,
+    /// Segments ahead of `next_seq`, in arrival order.
+    buffered: Vec<Segment>,
+    /// Payload bytes of the buffered segments.
+    total_size: usize,
+    /// Latest delivered bytes, ending at `next_seq`.
+    history: VecDeque<u8>,
+    history_limit: usize,
+}
+
+impl OrderedCoalesce {
+    /// Stream without history, retransmissions of delivered data aren't compared.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Stream keeping up to `history_limit` delivered bytes to compare retransmissions against.
+    pub fn with_history(history_limit: usize) -> Self {
+        Self{ history_limit, ..Self::default() }
+    }
+
+    /// Adds a segment starting at `seq`, the SYN already accounted for.
+    /// Returns where it disagrees with the history or with segments still buffered.
+    pub fn insert(&mut self, seq: Sequence, payload: &[u8]) -> Vec<Overlap> {
+        let next_seq = *self.next_seq.get_or_insert(seq);
+        let mut overlaps = Vec::new();
+        // the part already delivered is compared against the history, as far as it goes back
+        let behind = seq.distance(next_seq);
+        if behind > 0 && !self.history.is_empty() {
+            let history_range = WrappingRange::new(next_seq + (self.history.len() as u32).wrapping_neg(), self.history.len() as u32);
+            let (history_start, history_end) = self.history.as_slices();
+            let history = [history_start, history_end].concat();
+            overlaps.extend(differing(history_range, &history, WrappingRange::new(seq, payload.len() as u32), payload));
+        }
+        let (seq, payload) = match behind {
+            behind if behind <= 0 => (seq, payload),
+            behind if behind as usize >= payload.len() => return overlaps,
+            behind => (next_seq, &payload[behind as usize..]),
+        };
+        let range = WrappingRange::new(seq, payload.len() as u32);
+
+        overlaps.extend(self.buffered.iter().filter_map(|segment| differing(segment.range, &segment.payload, range, payload)));
+        if seq == next_seq {
+            self.next_seq = Some(range.end());
+            self.record_history(payload);
+        } else if !payload.is_empty() {
+            self.buffered.push(Segment{ range, payload: payload.to_vec() });
+            self.total_size += payload.len();
+            if self.buffered.len() > MAX_BUFFERED_SEGMENTS {
+                self.skip_gap();
+            }
+        }
+        self.deliver();
+        overlaps
+    }
+
+    /// Next byte expected in order, `None` until a segment was seen.
+    pub fn next_seq(&self) -> Option<Sequence> {
+        self.next_seq
+    }
+
+    /// End of the furthest byte seen, buffered bytes included, `None` until a segment was seen.
+    pub fn seen_end(&self) -> Option<Sequence> {
+        let next_seq = self.next_seq?;
+        Some(self.buffered.iter()
+            .map(|segment| segment.range.end())
+            .fold(next_seq, |end, segment_end| if segment_end.is_after(end) { segment_end } else { end }))
+    }
+
+    /// Payload bytes buffered ahead of a gap.
+    pub fn total_size(&self) -> usize {
+        self.total_size
+    }
+
+    /// Gives up on gaps, the earliest first, until at most `limit` bytes are buffered.
+    pub fn shed(&mut self, limit: usize) {
+        while self.total_size > limit {
+            self.skip_gap();
+            self.deliver();
+        }
+    }
+
+    /// Releases buffered segments the receiver acknowledged up to `ack`. The receiver holds the
+    /// bytes before `ack`, so a gap below it was lost to the sensor only and is never filled.
+    pub fn advance_ack(&mut self, ack: Sequence) {
+        while self.buffered.iter().any(|segment| !segment.range.end().is_after(ack)) {
+            self.skip_gap();
+            self.deliver();
+        }
+    }
+
+    /// Stops keeping history and frees the buffers; with `shed(0)` after each segment nothing is
+    /// compared any more, the stream is only followed.
+    pub fn degrade(&mut self) {
+        self.history_limit = 0;
+        self.release();
+    }
+
+    /// Frees the buffered segments and the history, the stream goes on from `next_seq` without them.
+    pub fn release(&mut self) {
+        self.buffered = Vec::new();
+        self.total_size = 0;
+        self.history = VecDeque::new();
+    }
+
+    /// Moves past buffered segments the stream has caught up with.
+    fn deliver(&mut self) {
+        while let Some(next_seq) = self.next_seq {
+            let caught_up = self.buffered.iter().position(|segment| !segment.range.start().is_after(next_seq));
+            let segment = match caught_up {
+                Some(i) => self.buffered.swap_remove(i),
+                None => return,
+            };
+            self.total_size -= segment.payload.len();
+            if segment.range.end().is_after(next_seq) {
+                self.next_seq = Some(segment.range.end());
+                self.record_history(&segment.payload[segment.range.start().distance(next_seq) as usize..]);
+            }
+        }
+    }
+
+    /// Appends bytes just delivered to the history, dropping the oldest beyond the limit.
+    fn record_history(&mut self, delivered: &[u8]) {
+        let kept = &delivered[delivered.len().saturating_sub(self.history_limit)..];
+        let excess = (self.history.len() + kept.len()).saturating_sub(self.history_limit);
+        self.history.drain(..excess);
+        self.history.extend(kept);
+    }
+
+    /// Treats the gap before the earliest buffered segment as lost.
+    fn skip_gap(&mut self) {
+        let next_seq = match self.next_seq {
+            Some(next_seq) => next_seq,
+            None => return,
+        };
+        let earliest = self.buffered.iter().map(|segment| segment.range.start()).min_by_key(|&start| next_seq.distance(start));
+        self.next_seq = earliest;
+        // the history has to end at the next byte expected
+        self.history.clear();
+    }
+}
+
+/// Part of the common range of two byte ranges where the bytes differ.
+fn differing(their_range: WrappingRange, theirs: &[u8], range: WrappingRange, payload: &[u8]) -> Option<Overlap> {
+    let common = their_range.intersection(&range)?;
+    let theirs = &theirs[their_range.start().distance(common.start()) as usize..][..common.len() as usize];
+    let ours = &payload[range.start().distance(common.start()) as usize..][..common.len() as usize];
+    let first = theirs.iter().zip(ours).position(|(a, b)| a != b)?;
+    let last = theirs.iter().zip(ours).rposition(|(a, b)| a != b)?;
+    let excerpt = first..(last + 1).min(first + MAX_OVERLAP_BYTES);
+    Some(Overlap {
+        range: WrappingRange::new(common.start() + first as u32, (last - first + 1) as u32),
+        winner: theirs[excerpt.clone()].to_vec(),
+        loser: ours[excerpt].to_vec(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn buffers_out_of_order_and_finds_conflicts() {
+        let start = Sequence::from(u32::MAX - 2);
+        let mut stream = OrderedCoalesce::new();
+        assert!(stream.insert(start, b"abc").is_empty());
+        assert_eq!(stream.next_seq(), Some(start + 3));
+
+        // a gap, then two versions of the same range
+        assert!(stream.insert(start + 6, b"ghij").is_empty());
+        assert_eq!(stream.total_size(), 4);
+        assert_eq!(stream.seen_end(), Some(start + 10));
+        let overlaps = stream.insert(start + 7, b"hXYj");
+        assert_eq!(overlaps, vec![Overlap{ range: WrappingRange::new(start + 8, 2), winner: b"ij".to_vec(), loser: b"XY".to_vec() }]);
+
+        // the gap is filled, all of it is delivered
+        assert!(stream.insert(start + 3, b"def").is_empty());
+        assert_eq!(stream.next_seq(), Some(start + 11));
+        assert_eq!(stream.total_size(), 0);
+        // delivered data is not compared without history
+        assert!(stream.insert(start, b"zzzzzz").is_empty());
+
+        // released buffers are gone, the position stays
+        assert!(stream.insert(start + 13, b"mn").is_empty());
+        stream.release();
+        assert_eq!((stream.total_size(), stream.next_seq()), (0, Some(start + 11)));
+    }
+
+    #[test]
+    fn compares_retransmissions_with_history() {
+        let start = Sequence::from(u32::MAX - 2);
+        let mut stream = OrderedCoalesce::with_history(8);
+        assert!(stream.insert(start, b"abcdef").is_empty());
+        assert!(stream.insert(start + 8, b"ijkl").is_empty());
+        assert!(stream.insert(start + 6, b"gh").is_empty());
+
+        // a faithful retransmission, then one changing delivered bytes
+        assert!(stream.insert(start + 5, b"fghi").is_empty());
+        assert_eq!(stream.insert(start + 9, b"jXlm"), vec![Overlap{ range: WrappingRange::new(start + 10, 1), winner: b"k".to_vec(), loser: b"X".to_vec() }]);
+        // older bytes than the history holds aren't compared
+        assert!(stream.insert(start, b"XXXX").is_empty());
+        assert_eq!(stream.next_seq(), Some(start + 13));
+
+        // long rewrites are cut short
+        let mut stream = OrderedCoalesce::with_history(1000);
+        assert!(stream.insert(start, &[b'a'; 200]).is_empty());
+        let overlap = stream.insert(start, &[b'b'; 200]).remove(0);
+        assert_eq!((overlap.range.len(), overlap.winner.len(), overlap.loser.len()), (200, MAX_OVERLAP_BYTES, MAX_OVERLAP_BYTES));
+    }
+
+    #[test]
+    fn sheds_gaps_and_degrades() {
+        let start = Sequence::from(100);
+        let mut stream = OrderedCoalesce::with_history(8);
+        assert!(stream.insert(start, b"ab").is_empty());
+        assert!(stream.insert(start + 4, b"ef").is_empty());
+        assert!(stream.insert(start + 8, b"ijkl").is_empty());
+        // the earliest gap goes first, then the next
+        stream.shed(4);
+        assert_eq!((stream.total_size(), stream.next_seq()), (4, Some(start + 6)));
+        stream.shed(0);
+        assert_eq!((stream.total_size(), stream.next_seq()), (0, Some(start + 12)));
+
+        // no history is kept to compare with any more
+        stream.degrade();
+        assert!(stream.insert(start + 12, b"mn").is_empty());
+        assert!(stream.insert(start + 12, b"XY").is_empty());
+
+        let budget = ReassemblyBudget::new(10, 12);
+        budget.account(0, 8);
+        budget.account(0, 8);
+        assert!(budget.is_exceeded());
+        budget.account(8, 0);
+        assert_eq!((budget.used(), budget.is_exceeded()), (8, false));
+    }
+
+    #[test]
+    fn releases_acknowledged_segments() {
+        let start = Sequence::from(100);
+        let mut stream = OrderedCoalesce::with_history(8);
+        assert!(stream.insert(start, b"ab").is_empty());
+        assert!(stream.insert(start + 4, b"ef").is_empty());
+        assert!(stream.insert(start + 8, b"ij").is_empty());
+        // acknowledging into a segment leaves it buffered
+        stream.advance_ack(start + 9);
+        assert_eq!((stream.total_size(), stream.next_seq()), (2, Some(start + 6)));
+        stream.advance_ack(start + 10);
+        assert_eq!((stream.total_size(), stream.next_seq()), (0, Some(start + 10)));
+        // without a segment buffered an ACK ahead of the stream leaves it where it is
+        stream.advance_ack(start + 20);
+        assert_eq!(stream.next_seq(), Some(start + 10));
+        assert_eq!(stream.insert(start + 8, b"iX").len(), 1);
+    }
+
+    /// Reference stream in plain offsets from the first segment, no wrapping and nothing ever given up.
+    #[derive(Default)]
+    struct Model {
+        delivered: Vec<u8>,
+        buffered: Vec<(usize, Vec<u8>)>,
+        history_limit: usize,
+    }
+
+    impl Model {
+        /// Where `payload` at `offset` differs from `theirs` at `their_offset`, as the stream starting at
+        /// `start` reports it.
+        fn differing(start: Sequence, their_offset: usize, theirs: &[u8], offset: usize, payload: &[u8]) -> Option<Overlap> {
+            let common = their_offset.max(offset)..(their_offset + theirs.len()).min(offset + payload.len());
+            let differ: Vec<usize> = common.filter(|&at| theirs[at - their_offset] != payload[at - offset]).collect();
+            let (first, last) = (*differ.first()?, *differ.last()?);
+            let excerpt = first..(last + 1).min(first + MAX_OVERLAP_BYTES);
+            Some(Overlap {
+                range: WrappingRange::new(start + first as u32, (last - first + 1) as u32),
+                winner: excerpt.clone().map(|at| theirs[at - their_offset]).collect(),
+                loser: excerpt.map(|at| payload[at - offset]).collect(),
+            })
+        }
+
+        fn insert(&mut self, start: Sequence, offset: usize, payload: &[u8]) -> Vec<Overlap> {
+            let mut overlaps = Vec::new();
+            let next = self.delivered.len();
+            if offset < next && self.history_limit > 0 {
+                let history_offset = next.saturating_sub(self.history_limit);
+                overlaps.extend(Self::differing(start, history_offset, &self.delivered[history_offset..], offset, payload));
+            }
+            if offset + payload.len() <= next && offset < next {
+                return overlaps;
+            }
+            let (offset, payload) = if offset < next { (next, &payload[next - offset..]) } else { (offset, payload) };
+            overlaps.extend(self.buffered.iter().filter_map(|(their_offset, theirs)| Self::differing(start, *their_offset, theirs, offset, payload)));
+            if offset == next {
+                self.delivered.extend(payload);
+            } else if !payload.is_empty() {
+                self.buffered.push((offset, payload.to_vec()));
+            }
+            while let Some(i) = self.buffered.iter().position(|(offset, _)| *offset <= self.delivered.len()) {
+                let (offset, payload) = self.buffered.remove(i);
+                let next = self.delivered.len();
+                if offset + payload.len() > next {
+                    self.delivered.extend(&payload[next - offset..]);
+                }
+            }
+            overlaps
+        }
+    }
+
+    #[test]
+    fn matches_reference_model() {
+        // seeded so failures reproduce, cases start anywhere from wrapping right away to not at all
+        let mut state = 0x2545_f491_4f6c_dd1d_u64;
+        let mut random = move |bound: usize| {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            (state % bound as u64) as usize
+        };
+        let genuine = |offset: usize| (offset * 31 + 7) as u8;
+
+        for case in 0..500 {
+            let start = Sequence::from(u32::MAX - random(400) as u32);
+            let history_limit = [0, 8, 64][case % 3];
+            let mut stream = OrderedCoalesce::with_history(history_limit);
+            let mut model = Model{ history_limit, ..Model::default() };
+            for i in 0..80 {
+                let delivered = model.delivered.len();
+                // the first segment sets the start, later ones land behind, at or ahead of the stream
+                let offset = if i == 0 { 0 } else { random(delivered + 48).saturating_sub(16) };
+                let mut payload: Vec<u8> = (offset..offset + random(24)).map(genuine).collect();
+                // segments racing the stream or retransmitting it may carry forged bytes, buffered
+                // segments always agree with each other
+                if offset <= delivered && !payload.is_empty() && random(3) == 0 {
+                    let at = random(payload.len());
+                    payload[at] ^= 1 + random(255) as u8;
+                }
+
+                let mut overlaps = stream.insert(start + offset as u32, &payload);
+                let mut expected = model.insert(start, offset, &payload);
+                let order = |overlap: &Overlap| (start.distance(overlap.range.start()), overlap.winner.clone());
+                overlaps.sort_by_key(order);
+                expected.sort_by_key(order);
+                let context = format!("case {} segment {} at {} of {}", case, i, offset, payload.len());
+                assert_eq!(overlaps, expected, "{}", context);
+                assert_eq!(stream.next_seq(), Some(start + model.delivered.len() as u32), "{}", context);
+                let buffered = model.buffered.iter().map(|(_, payload)| payload.len()).sum::<usize>();
+                assert_eq!(stream.total_size(), buffered, "{}", context);
+                let seen_end = model.buffered.iter().map(|(offset, payload)| offset + payload.len()).fold(model.delivered.len(), usize::max);
+                assert_eq!(stream.seen_end(), Some(start + seen_end as u32), "{}", context);
+            }
+        }
+    }
+}
@@ -1,34 +1,270 @@
+use std::fmt;
+use std::iter::Sum;
 use std::net::IpAddr;
+use std::path::PathBuf;
+use std::rc::Rc;
+use std::time::{Duration, SystemTime};
 
 use time::PrimitiveDateTime;
 use pnet::packet::Packet;
 use pnet::packet::tcp::TcpFlags;
 
-use crate::types::{Sequence, PacketManifest, SideIdentifier, Side, Flow};
+use crate::types::{Sequence, PacketManifest, SideIdentifier, Side, Flow, HomeNetwork, Direction, WindowTracker, EthernetLayer, TcpOptions, Ring, TtlModel, TtlClass, IpIdModel, IpIdClass, RstClass, SegmentClass};
 use crate::utils::BitMask;
-use crate::event::{AttackReporter, AttackReport};
+use crate::event::{AttackReporter, AttackReport, AttackKind, Excerpt, PacketSummary, InsertionReason, KeepAliveMismatch, UrgentAbuse, OptionChange, SynAckFingerprint, HijackVerdict, AnomalyReport, HandshakeStep, HandshakeAnomaly};
+use crate::probe::{self, ProbeQueue};
+use crate::carve::{self, StreamRecorder};
+use crate::coalesce::{OrderedCoalesce, ReassemblyBudget};
+use crate::http::HttpResponses;
+use crate::dns::{DnsResponses, DNS_PORT};
+use crate::ipfix::{FlowEndReason, FlowRecord, ANOMALY_ATTACK_REPORTED};
+use crate::schedule::DetectorSwitches;
+use crate::policy::DetectionPolicy;
 
 pub struct ConnectionOptions {
     pub attack_reporter: Box<dyn AttackReporter>,
-    pub skip_hijack_detection_count: u64
+    pub skip_hijack_detection_count: u64,
+    /// How long from the connection's first packet, in capture time, hijacks are looked for after the
+    /// handshake; the packet count limit applies instead if `None`.
+    pub hijack_detection_period: Option<Duration>,
+    pub home_network: Rc<HomeNetwork>,
+    /// Where to put keep-alive probes verifying suspected hijacks, probing is off if `None`.
+    pub probes: Option<ProbeQueue>,
+    /// Label of the tenant the connection belongs to, attached to every report.
+    pub tenant: Option<String>,
+    /// Where to write reconstructed streams of attacked connections, carving is off if `None`.
+    pub carve_dir: Option<Rc<PathBuf>>,
+    /// Detectors currently enabled, shared by all connections.
+    pub switches: Rc<DetectorSwitches>,
+    /// Delivered bytes kept per direction to compare retransmissions against.
+    pub stream_history: usize,
+    /// Caps on out-of-order bytes buffered, shared by all connections.
+    pub reassembly_budget: Rc<ReassemblyBudget>,
+    /// Latest packets kept to attach to reports.
+    pub packet_history: usize,
+    /// Whether the packets kept carry a digest of their payload.
+    pub history_digests: bool,
+    /// Detections run on the connection, reports of others are dropped.
+    pub policy: DetectionPolicy,
+    /// Whether a connection picked up after its handshake learns the sides' sequence numbers before
+    /// it is reported on, instead of taking the first packet for the client's and going by guesses.
+    pub midstream: bool,
 }
 
+/// Packets attached to reports by default.
+pub const DEFAULT_PACKET_HISTORY: usize = 16;
+/// Bytes recorded per direction for carving, the rest of a longer stream is not kept.
+const CARVE_LIMIT: usize = 1 << 20;
+/// RSTs the receiver wouldn't take within this long, in capture time, count as probing the window.
+const BLIND_RESET_PERIOD: Duration = Duration::from_secs(10);
+/// RSTs probing the window before a blind reset attempt is reported.
+const BLIND_RESET_PROBES: u32 = 4;
+/// A reset side still sending within this long after its RST, in capture time, didn't send the RST.
+const RST_FOLLOW_UP: Duration = Duration::from_secs(10);
+/// A SYN reusing the tuple of a reset connection within this long, in capture time, is checked for
+/// an ISN its sender's stack wouldn't pick.
+const REUSE_PERIOD: Duration = Duration::from_secs(2);
+/// ACKs in a row acknowledging unseen data, and for how long in capture time, before the sides of a
+/// connection count as desynchronized; capture drops are caught up with by the sender's next segments.
+const DESYNC_ACKS: u32 = 8;
+const DESYNC_PERIOD: Duration = Duration::from_secs(5);
+/// One byte urgent segments a side may send, as Telnet and FTP interrupts do, before it is reported.
+const MAX_URGENT_BYTES: u32 = 3;
+
 pub struct Connection {
     attack_reporter: Box<dyn AttackReporter>,
     side_id: SideIdentifier,
+    /// Direction of client to server traffic.
+    direction: Direction,
     packet_count: u64,
+    octet_count: u64,
+    /// Segments of either side as they fit the stream, see `ConnectionStats`.
+    payload_octets: u64,
+    retransmissions: u64,
+    out_of_order: u64,
+    overlaps: u64,
+    first_seen: SystemTime,
+    last_seen: SystemTime,
+    tcp_flags_seen: u8,
     skip_hijack_detection_count: u64,
+    hijack_detection_period: Option<Duration>,
     hijack_next_ack: Sequence,
+    /// Data carried by the client's SYN, the SYN-ACK only acknowledges it if taken with Fast Open.
+    syn_data_len: u32,
+    /// Whether the client's SYN carried a Fast Open cookie.
+    fast_open: bool,
     state: TcpState,
     client_next_seq: Sequence,
     server_next_seq: Option<Sequence>,
     first_syn_ack_seq: Option<u32>,
+    first_syn_ack: Option<SynAckFingerprint>,
+    /// Options of the client's SYN, if the connection was seen from its start.
+    syn_options: Option<TcpOptions>,
+    client_window: WindowTracker,
+    server_window: WindowTracker,
+    /// Bytes sent by the client and by the server, ordered independently.
+    client_stream: OrderedCoalesce,
+    server_stream: OrderedCoalesce,
+    /// Delivered bytes the streams keep, for the streams of a connection reusing the tuple.
+    stream_history: usize,
+    reassembly_budget: Rc<ReassemblyBudget>,
+    /// Out-of-order bytes of both streams the budget was last told of.
+    buffered: usize,
+    /// Whether the streams stopped buffering as the budget ran out.
+    degraded: bool,
+    /// When a RST the receiver took closed the connection.
+    reset_at: Option<SystemTime>,
+    /// Latest packets of either side, attached to reports.
+    history: Ring<PacketSummary>,
+    history_digests: bool,
+    /// Latest RST sent by the client and by the server, until the follow-up period is over.
+    client_reset: Option<Reset>,
+    server_reset: Option<Reset>,
+    client_rst_probes: RstProbes,
+    server_rst_probes: RstProbes,
+    client_ttl: TtlModel,
+    server_ttl: TtlModel,
+    client_ip_id: IpIdModel,
+    server_ip_id: IpIdModel,
+    /// One byte urgent segments sent by the client and by the server.
+    client_urgent_bytes: u32,
+    server_urgent_bytes: u32,
+    /// Heads of the server's HTTP responses, if the policy compares them.
+    http_responses: Option<HttpResponses>,
+    /// Responses of a DNS server, if the connection is to one.
+    dns_responses: Option<DnsResponses>,
+    /// Furthest byte of the client's and of the server's data their receiver acknowledged,
+    /// cumulatively or with SACK blocks, while the sensor hadn't seen it sent.
+    client_acked_unseen: Option<Sequence>,
+    server_acked_unseen: Option<Sequence>,
+    /// ACKs in a row beyond the client's and beyond the server's data seen.
+    client_divergence: Option<Divergence>,
+    server_divergence: Option<Divergence>,
+    /// Link layer addresses of the latest frames towards the client and the server.
+    ethernet_to_client: Option<EthernetLayer>,
+    ethernet_to_server: Option<EthernetLayer>,
+    probes: Option<ProbeQueue>,
+    pending_probe: Option<PendingProbe>,
+    tenant: Option<String>,
+    /// ERSPAN session of the latest mirrored packet.
+    erspan_session: Option<u16>,
+    carving: Option<Carving>,
+    switches: Rc<DetectorSwitches>,
+    policy: DetectionPolicy,
+}
+
+impl Drop for Connection {
+    fn drop(&mut self) {
+        self.reassembly_budget.account(self.buffered, 0);
+    }
+}
+
+struct Carving {
+    dir: Rc<PathBuf>,
+    client: StreamRecorder,
+    server: StreamRecorder,
+}
+
+#[derive(Debug, Copy, Clone)]
+struct Reset {
+    seq: Sequence,
+    ts: SystemTime,
+    /// Whether the RST was at the next sequence number the side was expected to send.
+    in_sequence: bool,
+}
+
+/// RSTs from one side the receiver wouldn't take, counted since the first of them.
+#[derive(Debug, Copy, Clone, Default)]
+struct RstProbes {
+    since: Option<SystemTime>,
+    off_window: u32,
+    challenge_acks: u32,
+}
+
+/// ACKs acknowledging data of one side the sensor hasn't seen, counted since the first of them.
+#[derive(Debug, Copy, Clone)]
+struct Divergence {
+    since: SystemTime,
+    acks: u32,
+}
+
+/// Answers awaited after probing both endpoints of a suspected hijack.
+struct PendingProbe {
+    /// Next server sequence number if the first SYN-ACK was genuine.
+    first: Sequence,
+    /// Next server sequence number if the later SYN-ACK was genuine.
+    later: Sequence,
+    verdict: Option<HijackVerdict>,
+    client_ack: Option<Sequence>,
+}
+
+/// Point-in-time view of a connection's tracking state.
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ConnectionSnapshot {
+    pub client_flow: Flow,
+    pub state: TcpState,
+    pub packet_count: u64,
+    pub octet_count: u64,
+    pub client_next_seq: Sequence,
+    pub server_next_seq: Option<Sequence>,
+    /// Out-of-order bytes of both streams.
+    pub buffered_bytes: usize,
+}
+
+impl fmt::Display for ConnectionSnapshot {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} state={:?} packets={} octets={} buffered_bytes={}",
+               self.client_flow, self.state, self.packet_count, self.octet_count, self.buffered_bytes)
+    }
+}
+
+/// Counts telling a noisy path, retransmitting and reordering, from an attacked connection.
+#[derive(Eq, PartialEq, Copy, Clone, Debug, Default)]
+pub struct ConnectionStats {
+    pub packets: u64,
+    pub octets: u64,
+    /// Stream bytes of both sides, retransmitted ones included.
+    pub payload_octets: u64,
+    /// Segments with data resending part of the stream already seen.
+    pub retransmissions: u64,
+    /// Segments with data ahead of the stream, after a gap.
+    pub out_of_order: u64,
+    /// Segments with data disagreeing with data seen before for the same part of the stream.
+    pub overlaps: u64,
+}
+
+impl Sum for ConnectionStats {
+    fn sum<I: Iterator<Item = Self>>(iter: I) -> Self {
+        iter.fold(Self::default(), |total, stats| Self {
+            packets: total.packets + stats.packets,
+            octets: total.octets + stats.octets,
+            payload_octets: total.payload_octets + stats.payload_octets,
+            retransmissions: total.retransmissions + stats.retransmissions,
+            out_of_order: total.out_of_order + stats.out_of_order,
+            overlaps: total.overlaps + stats.overlaps,
+        })
+    }
+}
+
+impl fmt::Display for ConnectionStats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "packets={} octets={} payload_octets={} retransmissions={} out_of_order={} overlaps={}",
+               self.packets, self.octets, self.payload_octets, self.retransmissions, self.out_of_order, self.overlaps)
+    }
 }
 
 #[derive(Eq, PartialEq, Copy, Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum TcpState {
     ConnectionRequest,
+    /// The server answered the SYN with a SYN of its own, as in a simultaneous open or a split
+    /// handshake. Data transfer begins once both SYNs are acknowledged.
+    SimultaneousOpen { client_syn_acked: bool, server_syn_acked: bool },
     ConnectionEstablished,
+    /// Picked up after the handshake. Reports are held back until each side acknowledged the next
+    /// sequence number seen from the other, the sensor missed the ISNs.
+    Midstream { client_synced: bool, server_synced: bool },
     DataTransfer,
     ConnectionClosing(TcpClosing),
     Invalid,
@@ -36,6 +272,7 @@ pub enum TcpState {
 }
 
 #[derive(Eq, PartialEq, Copy, Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct TcpClosing {
     initiator: Side,
     initiator_state: TcpInitiatingClosingState,
@@ -43,6 +280,7 @@ pub struct TcpClosing {
 }
 
 #[derive(Eq, PartialEq, Copy, Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum TcpInitiatingClosingState {
     FinWait1,
     FinWait2,
@@ -51,6 +289,7 @@ pub enum TcpInitiatingClosingState {
 }
 
 #[derive(Eq, PartialEq, Copy, Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum TcpInitiatedClosingState {
     CloseWait,
     LastAck,
@@ -60,31 +299,214 @@ impl Connection {
     pub fn from_packet(packet: PacketManifest, options: ConnectionOptions) -> Self {
         let is_initial_packet = packet.tcp.flags.syn && !packet.tcp.flags.ack;
         let is_closing_packet = !is_initial_packet && (packet.tcp.flags.fin || packet.tcp.flags.rst);
-        let client_next_seq = Sequence::from(packet.tcp.seq) + 1 + packet.tcp_payload.len() as u32;
+        let pickup = options.midstream && !is_initial_packet && !is_closing_packet;
+        // servers listen on the lower port as a rule, ephemeral ports are high
+        let first_side = if pickup && packet.tcp.src < packet.tcp.dst { Side::Server } else { Side::Client };
+        let (client_next_seq, server_next_seq) = match first_side {
+            Side::Client => (Sequence::from(packet.tcp.seq) + 1 + packet.tcp_payload.len() as u32, None),
+            Side::Server => (Sequence::from(packet.tcp.ack), Some(Sequence::from(packet.tcp.seq))),
+        };
+        let side_id = SideIdentifier::from_client_flow(match first_side {
+            Side::Client => Flow::from(&packet),
+            Side::Server => Flow::from(&packet).reverse(),
+        });
+        let direction = match first_side {
+            Side::Client => options.home_network.direction(packet.ip.src, packet.ip.dst),
+            Side::Server => options.home_network.direction(packet.ip.dst, packet.ip.src),
+        };
+        let mut first_stream = OrderedCoalesce::with_history(options.stream_history);
+        let mut history = Ring::new(options.packet_history);
+        history.push(PacketSummary::new(&packet, first_side, options.history_digests));
+        first_stream.insert(Sequence::from(packet.tcp.seq) + u32::from(packet.tcp.flags.syn), packet.tcp_payload);
+        let mut first_ttl = TtlModel::new();
+        first_ttl.observe(packet.ip.ttl);
+        let mut first_ip_id = IpIdModel::new();
+        if let Some(id) = packet.ip.id {
+            first_ip_id.observe(id);
+        }
+        let (client_stream, server_stream, client_ttl, server_ttl, client_ip_id, server_ip_id) = match first_side {
+            Side::Client => (first_stream, OrderedCoalesce::with_history(options.stream_history), first_ttl, TtlModel::new(), first_ip_id, IpIdModel::new()),
+            Side::Server => (OrderedCoalesce::with_history(options.stream_history), first_stream, TtlModel::new(), first_ttl, IpIdModel::new(), first_ip_id),
+        };
+        let mut client_window = WindowTracker::new();
+        if is_initial_packet {
+            // only takes effect if the server agrees in its SYN-ACK
+            client_window.set_scale(packet.tcp.options.window_scale().unwrap_or(0));
+        }
 
         Self {
             attack_reporter: options.attack_reporter,
             state: if is_initial_packet { TcpState::ConnectionRequest }
                    else if is_closing_packet { TcpState::Closed }
+                   else if pickup { TcpState::Midstream{ client_synced: false, server_synced: false } }
                    else { TcpState::DataTransfer },
             client_next_seq,
-            server_next_seq: None,
+            server_next_seq,
             skip_hijack_detection_count: if is_initial_packet { options.skip_hijack_detection_count } else { 0 },
+            hijack_detection_period: if is_initial_packet { options.hijack_detection_period } else { None },
             hijack_next_ack: if is_initial_packet { client_next_seq } else { Sequence::from(0) },
+            syn_data_len: if is_initial_packet { packet.tcp_payload.len() as u32 } else { 0 },
+            fast_open: is_initial_packet && packet.tcp.options.fast_open().is_some_and(|cookie_len| cookie_len > 0),
             packet_count: 1,
+            octet_count: u64::from(packet.ip.total_len),
+            payload_octets: packet.tcp_payload.len() as u64,
+            retransmissions: 0,
+            out_of_order: 0,
+            overlaps: 0,
+            first_seen: packet.meta.ts,
+            last_seen: packet.meta.ts,
+            tcp_flags_seen: packet.tcp.flags.bits(),
             first_syn_ack_seq: None,
-            side_id: SideIdentifier::from_client_flow(Flow::from(&packet)),
+            first_syn_ack: None,
+            syn_options: if is_initial_packet { Some(packet.tcp.options) } else { None },
+            client_window,
+            server_window: WindowTracker::new(),
+            client_stream,
+            server_stream,
+            stream_history: options.stream_history,
+            reassembly_budget: options.reassembly_budget,
+            buffered: 0,
+            degraded: false,
+            reset_at: if is_closing_packet && packet.tcp.flags.rst { Some(packet.meta.ts) } else { None },
+            history,
+            history_digests: options.history_digests,
+            client_reset: None,
+            server_reset: None,
+            client_rst_probes: RstProbes::default(),
+            server_rst_probes: RstProbes::default(),
+            client_ttl,
+            server_ttl,
+            client_ip_id,
+            server_ip_id,
+            client_urgent_bytes: 0,
+            server_urgent_bytes: 0,
+            http_responses: if options.policy.http { Some(HttpResponses::new()) } else { None },
+            dns_responses: if options.policy.dns && side_id.client_flow().dst().1 == DNS_PORT { Some(DnsResponses::new()) } else { None },
+            client_acked_unseen: None,
+            server_acked_unseen: None,
+            client_divergence: None,
+            server_divergence: None,
+            ethernet_to_client: if first_side == Side::Server { packet.ethernet } else { None },
+            ethernet_to_server: if first_side == Side::Client { packet.ethernet } else { None },
+            probes: options.probes,
+            pending_probe: None,
+            tenant: options.tenant,
+            erspan_session: packet.erspan_session,
+            carving: options.carve_dir.map(|dir| {
+                let mut carving = Carving{ dir, client: StreamRecorder::new(CARVE_LIMIT), server: StreamRecorder::new(CARVE_LIMIT) };
+                let recorder = match first_side {
+                    Side::Client => &mut carving.client,
+                    Side::Server => &mut carving.server,
+                };
+                recorder.record(Sequence::from(packet.tcp.seq) + u32::from(packet.tcp.flags.syn), packet.tcp_payload);
+                carving
+            }),
+            switches: options.switches,
+            policy: options.policy,
+            side_id,
+            direction,
         }
     }
 
     pub fn receive_packet(&mut self, packet: PacketManifest) {
         self.packet_count += 1;
+        self.octet_count += u64::from(packet.ip.total_len);
+        self.last_seen = packet.meta.ts;
+        self.tcp_flags_seen |= packet.tcp.flags.bits();
+        if packet.erspan_session.is_some() {
+            self.erspan_session = packet.erspan_session;
+        }
+        let side = self.side_id.identify(&packet).ok();
+        if let Some(side) = side {
+            self.history.push(PacketSummary::new(&packet, side, self.history_digests));
+        }
+        if packet.bad_checksum {
+            // the receiver drops it, it's no part of the connection
+            if let Some(side) = side {
+                self.report_insertion(&packet, side, InsertionReason::BadChecksum);
+            }
+            return
+        }
+        let insertion = side.and_then(|side| self.insertion_reason(&packet, side).map(|reason| (side, reason)));
+        if let Some((side, reason)) = insertion {
+            self.report_insertion(&packet, side, reason);
+            return
+        }
+        if let Some(side) = side {
+            if packet.tcp.flags.ack {
+                let ack = Sequence::from(packet.tcp.ack);
+                if packet.tcp.flags.syn {
+                    self.receive_window_mut(side).update_from_syn(ack, packet.tcp.window);
+                } else {
+                    self.check_window(&packet, side);
+                    self.receive_window_mut(side).update(ack, packet.tcp.window);
+                    self.check_acknowledged(&packet, side);
+                    self.release_acknowledged(side.peer(), ack);
+                }
+            }
+            if packet.ethernet.is_some() {
+                match side {
+                    Side::Client => self.ethernet_to_server = packet.ethernet,
+                    Side::Server => self.ethernet_to_client = packet.ethernet,
+                }
+            }
+            if self.pending_probe.is_some() {
+                self.receive_probe_answer(&packet, side);
+            }
+            let keep_alive = self.check_keep_alive(&packet, side);
+            // keep-alives had their TTL checked already
+            if !keep_alive {
+                self.check_ttl(&packet, side);
+            }
+            if let Some(id) = packet.ip.id {
+                self.check_ip_id(&packet, side, id);
+            }
+            self.check_options(&packet, side);
+            if packet.tcp.flags.urg && !packet.tcp_payload.is_empty() {
+                self.check_urgent(&packet, side);
+            }
+            if packet.tcp.flags.rst {
+                self.check_blind_reset(&packet, side);
+            }
+            self.check_reset(&packet, side);
+            // the garbage byte of a keep-alive is no stream data
+            if !keep_alive {
+                self.receive_data(&packet, side);
+            }
+            if side == Side::Server && !packet.tcp_payload.is_empty() {
+                self.check_http_response(&packet);
+                self.check_dns_response(&packet);
+            }
+            if let Some(carving) = &mut self.carving {
+                let recorder = match side {
+                    Side::Client => &mut carving.client,
+                    Side::Server => &mut carving.server,
+                };
+                recorder.record(Sequence::from(packet.tcp.seq) + u32::from(packet.tcp.flags.syn), packet.tcp_payload);
+            }
+            if (packet.tcp.flags.fin || packet.tcp.flags.rst) && self.attack_reporter.attacks_reported() > 0 {
+                self.carve();
+            }
+        }
+
+        // a RST the receiver takes ends the connection, whatever its state
+        if let (true, Some(side)) = (packet.tcp.flags.rst, side) {
+            let taken = self.classify_rst(&packet, side) == RstClass::Exact;
+            if taken && self.state != TcpState::Closed {
+                self.close(packet.meta.ts);
+                return
+            }
+        }
 
         match self.state {
             TcpState::ConnectionRequest
                 => self.state_connection_request(packet),
+            TcpState::SimultaneousOpen { client_syn_acked, server_syn_acked }
+                => self.state_simultaneous_open(packet, client_syn_acked, server_syn_acked),
             TcpState::ConnectionEstablished
                 => self.state_connection_established(packet),
+            TcpState::Midstream { client_synced, server_synced }
+                => self.state_midstream(packet, client_synced, server_synced),
             TcpState::DataTransfer
                 => self.state_data_transfer(packet),
             TcpState::ConnectionClosing(sub_state)
@@ -97,85 +519,939 @@ impl Connection {
         }
     }
 
+    /// Why the receiver won't take a segment the sensor sees, `None` if it will.
+    fn insertion_reason(&self, packet: &PacketManifest, side: Side) -> Option<InsertionReason> {
+        if packet.tcp.flags.syn {
+            return None
+        }
+        let receiver_ttl = match side {
+            Side::Client => &self.server_ttl,
+            Side::Server => &self.client_ttl,
+        };
+        if receiver_ttl.expires_before(packet.ip.ttl) {
+            return Some(InsertionReason::TtlExpires{ ttl: packet.ip.ttl, hops: receiver_ttl.hops()? })
+        }
+        // the window scale is only known from the handshake
+        if packet.tcp_payload.is_empty() || self.first_syn_ack_seq.is_none() {
+            return None
+        }
+        let window = self.receive_window(side.peer());
+        match window.classify(Sequence::from(packet.tcp.seq), packet.tcp_payload.len() as u32) {
+            SegmentClass::OutOfWindow => Some(InsertionReason::BeyondWindow{ window_end: u32::from(window.highest_ack()? + window.window()) }),
+            _ => None,
+        }
+    }
+
+    /// Data or a RST only a sensor accepts is an insertion attempt.
+    fn report_insertion(&mut self, packet: &PacketManifest, side: Side, reason: InsertionReason) {
+        if packet.tcp_payload.is_empty() && !packet.tcp.flags.rst {
+            return
+        }
+        self.report_attack(AttackReport::new(PrimitiveDateTime::from(packet.meta.ts), Flow::from(packet), AttackKind::Insertion {
+            sender: side,
+            seq: packet.tcp.seq,
+            payload_len: packet.tcp_payload.len(),
+            rst: packet.tcp.flags.rst,
+            reason,
+        }));
+    }
+
+    fn check_ttl(&mut self, packet: &PacketManifest, side: Side) {
+        let model = match side {
+            Side::Client => &mut self.client_ttl,
+            Side::Server => &mut self.server_ttl,
+        };
+        if let TtlClass::Deviating { typical } = model.observe(packet.ip.ttl) {
+            self.report_attack(AttackReport::new(PrimitiveDateTime::from(packet.meta.ts), Flow::from(packet), AttackKind::TtlDeviation {
+                sender: side,
+                seq: packet.tcp.seq,
+                ttl: packet.ip.ttl,
+                typical,
+            }));
+        }
+    }
+
+    /// Checks keep-alive probes, resending the byte before the sender's next one with no or one byte of payload,
+    /// returning whether the segment is one. Genuine probes come from where the sender's segments do, and only
+    /// once all its data is acknowledged.
+    fn check_keep_alive(&mut self, packet: &PacketManifest, side: Side) -> bool {
+        let flags = packet.tcp.flags;
+        if !matches!(self.state, TcpState::ConnectionEstablished | TcpState::DataTransfer)
+            || !flags.ack || flags.syn || flags.fin || flags.rst || packet.tcp_payload.len() > 1 {
+            return false
+        }
+        let (stream, ttl) = match side {
+            Side::Client => (&self.client_stream, &self.client_ttl),
+            Side::Server => (&self.server_stream, &self.server_ttl),
+        };
+        let next_seq = match stream.next_seq() {
+            Some(next_seq) => next_seq,
+            None => return false,
+        };
+        let seq = Sequence::from(packet.tcp.seq);
+        let (keep_alive, reason) = if seq == next_seq {
+            // an ordinary ACK, or new data
+            return false
+        } else if seq + 1 == next_seq {
+            match ttl.classify(packet.ip.ttl) {
+                TtlClass::Deviating { typical } => (true, KeepAliveMismatch::Ttl{ ttl: packet.ip.ttl, typical }),
+                TtlClass::Typical => return true,
+            }
+        } else if self.receive_window(side.peer()).highest_ack() == Some(next_seq) {
+            // nothing in flight, so neither a retransmission nor a late segment
+            (false, KeepAliveMismatch::Sequence{ expected: u32::from(next_seq).wrapping_sub(1) })
+        } else {
+            return false
+        };
+        self.report_attack(AttackReport::new(PrimitiveDateTime::from(packet.meta.ts), Flow::from(packet), AttackKind::SpoofedKeepAlive {
+            sender: side,
+            seq: packet.tcp.seq,
+            reason,
+        }));
+        keep_alive
+    }
+
+    /// Compares options to the ones negotiated. A server only answers to options the SYN offered, and
+    /// timestamps once agreed on are sent on every segment but RSTs.
+    fn check_options(&mut self, packet: &PacketManifest, side: Side) {
+        let syn_options = match self.syn_options {
+            Some(syn_options) => syn_options,
+            None => return,
+        };
+        let options = &packet.tcp.options;
+        let (option, change) = if packet.tcp.flags.syn {
+            if side != Side::Server || !packet.tcp.flags.ack {
+                return
+            }
+            if options.sack_permitted() && !syn_options.sack_permitted() {
+                ("sack_permitted", OptionChange::Unnegotiated)
+            } else if options.window_scale().is_some() && syn_options.window_scale().is_none() {
+                ("window_scale", OptionChange::Unnegotiated)
+            } else if options.timestamps().is_some() && syn_options.timestamps().is_none() {
+                ("timestamps", OptionChange::Unnegotiated)
+            } else {
+                return
+            }
+        } else {
+            let syn_ack_options = match self.first_syn_ack {
+                Some(first_syn_ack) => first_syn_ack.options,
+                None => return,
+            };
+            let timestamps = syn_options.timestamps().is_some() && syn_ack_options.timestamps().is_some();
+            let sack = syn_options.sack_permitted() && syn_ack_options.sack_permitted();
+            if timestamps && options.timestamps().is_none() && !packet.tcp.flags.rst {
+                ("timestamps", OptionChange::Vanished)
+            } else if !sack && !options.sack_blocks().is_empty() {
+                ("sack", OptionChange::Unnegotiated)
+            } else {
+                return
+            }
+        };
+        self.report_attack(AttackReport::new(PrimitiveDateTime::from(packet.meta.ts), Flow::from(packet), AttackKind::OptionTampering {
+            sender: side,
+            seq: packet.tcp.seq,
+            option,
+            change,
+        }));
+    }
+
+    /// Urgent data is rare, receivers differ in taking it inline or out of band and in where it ends.
+    fn check_urgent(&mut self, packet: &PacketManifest, side: Side) {
+        let urgent_ptr = packet.tcp.urgent_ptr;
+        let payload_len = packet.tcp_payload.len();
+        let urgent_bytes = match side {
+            Side::Client => &mut self.client_urgent_bytes,
+            Side::Server => &mut self.server_urgent_bytes,
+        };
+        let reason = if urgent_ptr == 0 || usize::from(urgent_ptr) > payload_len {
+            UrgentAbuse::OutsideSegment{ urgent_ptr, payload_len }
+        } else if payload_len == 1 {
+            *urgent_bytes += 1;
+            if *urgent_bytes <= MAX_URGENT_BYTES {
+                return
+            }
+            UrgentAbuse::RepeatedSingleBytes{ count: *urgent_bytes }
+        } else {
+            return
+        };
+        self.report_attack(AttackReport::new(PrimitiveDateTime::from(packet.meta.ts), Flow::from(packet), AttackKind::UrgentDataAbuse {
+            sender: side,
+            seq: packet.tcp.seq,
+            reason,
+        }));
+    }
+
+    fn check_ip_id(&mut self, packet: &PacketManifest, side: Side, id: u16) {
+        let model = match side {
+            Side::Client => &mut self.client_ip_id,
+            Side::Server => &mut self.server_ip_id,
+        };
+        if let IpIdClass::Deviating { expected } = model.observe(id) {
+            self.report_attack(AttackReport::new(PrimitiveDateTime::from(packet.meta.ts), Flow::from(packet), AttackKind::IpIdDeviation {
+                sender: side,
+                seq: packet.tcp.seq,
+                ip_id: id,
+                expected,
+            }));
+        }
+    }
+
+    /// Remembers RSTs, a side going on sending after its RST shows the RST was spoofed.
+    fn check_reset(&mut self, packet: &PacketManifest, side: Side) {
+        let (reset, stream) = match side {
+            Side::Client => (&mut self.client_reset, &self.client_stream),
+            Side::Server => (&mut self.server_reset, &self.server_stream),
+        };
+        let seq = Sequence::from(packet.tcp.seq);
+        if packet.tcp.flags.rst {
+            *reset = Some(Reset{ seq, ts: packet.meta.ts, in_sequence: stream.next_seq().is_none_or(|next_seq| next_seq == seq) });
+            return
+        }
+        let earlier = match *reset {
+            Some(earlier) => earlier,
+            None => return,
+        };
+        if packet.meta.ts.duration_since(earlier.ts).is_ok_and(|since| since > RST_FOLLOW_UP) {
+            *reset = None;
+            return
+        }
+        // a new connection on the same ports, or segments sent before the RST
+        if packet.tcp.flags.syn || seq.is_before(earlier.seq) {
+            return
+        }
+        if packet.tcp_payload.is_empty() && !packet.tcp.flags.fin {
+            return
+        }
+        *reset = None;
+        self.report_attack(AttackReport::new(PrimitiveDateTime::from(packet.meta.ts), Flow::from(packet), AttackKind::RstInjection {
+            sender: side,
+            rst_seq: u32::from(earlier.seq),
+            rst_in_sequence: earlier.in_sequence,
+            follow_up_seq: packet.tcp.seq,
+        }));
+    }
+
+    /// Receivers don't take back window they offered, a segment taking back much of it likely didn't come
+    /// from the receiver. Throttling middleboxes forge zero windows so.
+    fn check_window(&mut self, packet: &PacketManifest, side: Side) {
+        let tracker = *self.receive_window(side);
+        let retraction = match tracker.retraction(Sequence::from(packet.tcp.ack), packet.tcp.window) {
+            Some(retraction) => retraction,
+            None => return,
+        };
+        // scaling rounds the window down by up to a unit
+        if retraction <= 1 << tracker.scale() || retraction <= tracker.window() / 2 {
+            return
+        }
+        self.report_attack(AttackReport::new(PrimitiveDateTime::from(packet.meta.ts), Flow::from(packet), AttackKind::WindowShrink {
+            sender: side,
+            window: u32::from(packet.tcp.window) << tracker.scale(),
+            previous_window: tracker.window(),
+            retraction,
+        }));
+    }
+
+    /// Counts RSTs the receiver wouldn't take, an attacker guessing sequence numbers sends many of them.
+    fn check_blind_reset(&mut self, packet: &PacketManifest, side: Side) {
+        let class = self.classify_rst(packet, side);
+        let probes = match side {
+            Side::Client => &mut self.client_rst_probes,
+            Side::Server => &mut self.server_rst_probes,
+        };
+        let now = packet.meta.ts;
+        match probes.since {
+            Some(since) if now.duration_since(since).is_ok_and(|elapsed| elapsed > BLIND_RESET_PERIOD) => *probes = RstProbes::default(),
+            _ => {}
+        }
+        match class {
+            RstClass::Exact => return,
+            RstClass::ChallengeAck => probes.challenge_acks += 1,
+            RstClass::OffWindow => probes.off_window += 1,
+        }
+        probes.since.get_or_insert(now);
+        let probes = *probes;
+        if probes.off_window + probes.challenge_acks == BLIND_RESET_PROBES {
+            self.report_attack(AttackReport::new(PrimitiveDateTime::from(now), Flow::from(packet), AttackKind::BlindReset {
+                sender: side,
+                off_window: probes.off_window,
+                challenge_acks: probes.challenge_acks,
+            }));
+        }
+    }
+
+    /// Notes data of the other side that the sender of `packet` acknowledges before the sensor saw it sent.
+    fn check_acknowledged(&mut self, packet: &PacketManifest, side: Side) {
+        let (stream, acked_unseen) = match side.peer() {
+            Side::Client => (&self.client_stream, &mut self.client_acked_unseen),
+            Side::Server => (&self.server_stream, &mut self.server_acked_unseen),
+        };
+        let seen_end = match stream.seen_end() {
+            Some(seen_end) => seen_end,
+            None => return,
+        };
+        let acked = packet.tcp.options.sack_blocks().iter()
+            .map(|&(_, right_edge)| Sequence::from(right_edge))
+            .fold(Sequence::from(packet.tcp.ack), |acked, right_edge| if right_edge.is_after(acked) { right_edge } else { acked });
+        if acked.is_after(seen_end) && acked_unseen.is_none_or(|earlier| acked.is_after(earlier)) {
+            *acked_unseen = Some(acked);
+        }
+        self.check_desync(packet, side.peer(), seen_end, acked);
+    }
+
+    /// Counts ACKs in a row acknowledging data of `sender` beyond `seen_end`, reporting the sides
+    /// desynchronized once they keep it up.
+    fn check_desync(&mut self, packet: &PacketManifest, sender: Side, seen_end: Sequence, acked: Sequence) {
+        let divergence = match sender {
+            Side::Client => &mut self.client_divergence,
+            Side::Server => &mut self.server_divergence,
+        };
+        // a FIN takes a sequence number the stream doesn't hold
+        if !acked.is_after(seen_end + 1) {
+            *divergence = None;
+            return
+        }
+        let now = packet.meta.ts;
+        let divergence = divergence.get_or_insert(Divergence{ since: now, acks: 0 });
+        divergence.acks += 1;
+        let lasting = now.duration_since(divergence.since).is_ok_and(|elapsed| elapsed >= DESYNC_PERIOD);
+        let acks = divergence.acks;
+        if acks >= DESYNC_ACKS && lasting {
+            // counted afresh, a desynchronization going on is reported again later
+            *divergence = Divergence{ since: now, acks: 0 };
+            self.report_attack(AttackReport::new(PrimitiveDateTime::from(packet.meta.ts), Flow::from(packet), AttackKind::StreamDesync {
+                sender,
+                seen_end: u32::from(seen_end),
+                acknowledged: u32::from(acked),
+                acks,
+            }));
+        }
+    }
+
+    /// Orders the payload into the stream of `side`, reporting data conflicting with buffered data.
+    fn receive_data(&mut self, packet: &PacketManifest, side: Side) {
+        let (stream, acked_unseen) = match side {
+            Side::Client => (&self.client_stream, &mut self.client_acked_unseen),
+            Side::Server => (&self.server_stream, &mut self.server_acked_unseen),
+        };
+        let seq = Sequence::from(packet.tcp.seq) + u32::from(packet.tcp.flags.syn);
+        let acked = if packet.tcp_payload.is_empty() { None } else { acked_unseen.take() };
+        // the sender goes on where the sensor saw it stop, yet the receiver already had these bytes;
+        // someone past the sensor sent them, as a gap would show capture drops
+        if let Some(acked) = acked.filter(|&acked| Some(seq) == stream.next_seq() && seq.is_before(acked)) {
+            self.report_attack(AttackReport::new(PrimitiveDateTime::from(packet.meta.ts), Flow::from(packet), AttackKind::UnseenDataAcknowledged {
+                sender: side,
+                seq: packet.tcp.seq,
+                acknowledged: u32::from(acked),
+            }));
+        }
+        let stream = match side {
+            Side::Client => &mut self.client_stream,
+            Side::Server => &mut self.server_stream,
+        };
+        if !packet.tcp_payload.is_empty() {
+            self.payload_octets += packet.tcp_payload.len() as u64;
+            match stream.next_seq() {
+                Some(next_seq) if seq.is_before(next_seq) => self.retransmissions += 1,
+                Some(next_seq) if seq.is_after(next_seq) => self.out_of_order += 1,
+                _ => {}
+            }
+        }
+        let overlaps = stream.insert(seq, packet.tcp_payload);
+        self.enforce_budget(side);
+        if !overlaps.is_empty() {
+            self.overlaps += 1;
+        }
+        if let Some(overlap) = overlaps.into_iter().next() {
+            self.report_attack(AttackReport::new(PrimitiveDateTime::from(packet.meta.ts), Flow::from(packet), AttackKind::StreamOverlap {
+                sender: side,
+                range: overlap.range,
+                winner: Excerpt(overlap.winner),
+                loser: Excerpt(overlap.loser),
+            }));
+        }
+    }
+
+    /// Compares a response starting in the segment to the ones before, after the stream did, which
+    /// misses responses racing each other if drops hid one's segment.
+    fn check_http_response(&mut self, packet: &PacketManifest) {
+        let responses = match &mut self.http_responses {
+            Some(responses) => responses,
+            None => return,
+        };
+        let seq = Sequence::from(packet.tcp.seq) + u32::from(packet.tcp.flags.syn);
+        if let Some((first, second)) = responses.observe(seq, packet.tcp_payload) {
+            self.report_attack(AttackReport::new(PrimitiveDateTime::from(packet.meta.ts), Flow::from(packet), AttackKind::HttpResponseConflict {
+                seq: packet.tcp.seq,
+                first_status: first.status,
+                second_status: second.status,
+            }));
+        }
+    }
+
+    fn check_dns_response(&mut self, packet: &PacketManifest) {
+        let responses = match &mut self.dns_responses {
+            Some(responses) => responses,
+            None => return,
+        };
+        let seq = Sequence::from(packet.tcp.seq) + u32::from(packet.tcp.flags.syn);
+        if let Some((id, first, second)) = responses.observe(seq, packet.tcp_payload) {
+            self.report_attack(AttackReport::new(PrimitiveDateTime::from(packet.meta.ts), Flow::from(packet), AttackKind::DnsResponseConflict {
+                id,
+                first,
+                second,
+            }));
+        }
+    }
+
+    /// Reports an attack on this connection detected outside of it.
+    pub fn report_attack(&mut self, mut report: AttackReport) {
+        if !self.policy.allows(&report.kind) {
+            return
+        }
+        // checks of a connection picked up midstream go by guesses until the sides are synced
+        if matches!(self.state, TcpState::Midstream { .. }) && !matches!(report.kind, AttackKind::DecoyTripped { .. }) {
+            return
+        }
+        report.context.tenant = self.tenant.clone();
+        report.context.erspan_session = self.erspan_session;
+        report.history = self.history.iter().copied().collect();
+        self.attack_reporter.report_attack(report);
+        self.carve();
+    }
+
+    fn report_anomaly(&mut self, packet: &PacketManifest, step: HandshakeStep, kind: HandshakeAnomaly) {
+        self.attack_reporter.report_anomaly(AnomalyReport {
+            time: PrimitiveDateTime::from(packet.meta.ts),
+            flow: Flow::from(packet),
+            step,
+            kind,
+            flags: packet.tcp.flags,
+            seq: packet.tcp.seq,
+            ack: packet.tcp.ack,
+        });
+    }
+
+    /// Writes streams reconstructed so far, if carving is on.
+    fn carve(&self) {
+        if let Some(carving) = &self.carving {
+            let flow = self.side_id.client_flow();
+            if let Err(err) = carve::write_streams(&carving.dir, &flow, &carving.client, &carving.server) {
+                eprintln!("Failed to carve streams of {}: {}", flow, err);
+            }
+        }
+    }
+
+    pub fn tenant(&self) -> Option<&str> {
+        self.tenant.as_deref()
+    }
+
+    /// Time of the latest packet of the connection.
+    pub fn last_seen(&self) -> SystemTime {
+        self.last_seen
+    }
+
+    /// Whether the connection was reset long enough before `now`, in capture time, for data sent
+    /// after the RST and reuse of the tuple to have shown up; its entry can go then.
+    pub fn is_finished(&self, now: SystemTime) -> bool {
+        self.state == TcpState::Closed && self.reset_at.is_some_and(|reset_at| now.duration_since(reset_at).is_ok_and(|since| since >= RST_FOLLOW_UP))
+    }
+
+    /// Whether no packet of the connection came for `timeout` up to `now`, in capture time.
+    pub fn is_idle(&self, now: SystemTime, timeout: Duration) -> bool {
+        now.duration_since(self.last_seen).is_ok_and(|idle| idle >= timeout)
+    }
+
+    /// Direction of client to server traffic relative to the home network.
+    pub fn direction(&self) -> Direction {
+        self.direction
+    }
+
+    /// Receive window of `side`, segments sent towards it can be classified against this.
+    pub fn receive_window(&self, side: Side) -> &WindowTracker {
+        match side {
+            Side::Client => &self.client_window,
+            Side::Server => &self.server_window,
+        }
+    }
+
+    /// Classifies a RST from `side`. Besides the sequence the receiver last acknowledged, a RST sent after
+    /// data not acknowledged yet carries the sender's next sequence number, which the receiver takes too.
+    fn classify_rst(&self, packet: &PacketManifest, side: Side) -> RstClass {
+        let seq = Sequence::from(packet.tcp.seq);
+        let stream = match side {
+            Side::Client => &self.client_stream,
+            Side::Server => &self.server_stream,
+        };
+        if stream.next_seq() == Some(seq) {
+            return RstClass::Exact
+        }
+        self.receive_window(side.peer()).classify_rst(seq)
+    }
+
+    fn receive_window_mut(&mut self, side: Side) -> &mut WindowTracker {
+        match side {
+            Side::Client => &mut self.client_window,
+            Side::Server => &mut self.server_window,
+        }
+    }
+
+    pub fn snapshot(&self) -> ConnectionSnapshot {
+        ConnectionSnapshot {
+            client_flow: self.side_id.client_flow(),
+            state: self.state,
+            packet_count: self.packet_count,
+            octet_count: self.octet_count,
+            client_next_seq: self.client_next_seq,
+            server_next_seq: self.server_next_seq,
+            buffered_bytes: self.client_stream.total_size() + self.server_stream.total_size(),
+        }
+    }
+
+    pub fn stats(&self) -> ConnectionStats {
+        ConnectionStats {
+            packets: self.packet_count,
+            octets: self.octet_count,
+            payload_octets: self.payload_octets,
+            retransmissions: self.retransmissions,
+            out_of_order: self.out_of_order,
+            overlaps: self.overlaps,
+        }
+    }
+
+    /// Summarizes the connection for flow export, as it ends for `end_reason` or goes on.
+    pub fn flow_record(&self, end_reason: FlowEndReason) -> FlowRecord {
+        FlowRecord {
+            flow: self.side_id.client_flow(),
+            start: self.first_seen,
+            end: self.last_seen,
+            packets: self.packet_count,
+            octets: self.octet_count,
+            tcp_flags: self.tcp_flags_seen,
+            anomalies: if self.attack_reporter.attacks_reported() > 0 { ANOMALY_ATTACK_REPORTED } else { 0 },
+            end_reason,
+        }
+    }
+
     fn state_connection_request(&mut self, packet: PacketManifest) {
-        if self.side_id.identify(&packet) != Side::Server {
-            // handshake anomaly
+        let step = HandshakeStep::SynAck;
+        if self.side_id.identify(&packet) != Ok(Side::Server) {
+            // a retransmitted SYN is no news, with or without the data of the first one
+            if !(packet.tcp.flags.syn && Sequence::from(packet.tcp.seq) + 1 == self.syn_end()) {
+                self.report_anomaly(&packet, step, HandshakeAnomaly::UnexpectedSender{ expected: Side::Server });
+            }
+            return
+        }
+        if packet.tcp.flags.syn && !packet.tcp.flags.ack {
+            self.state = TcpState::SimultaneousOpen{ client_syn_acked: false, server_syn_acked: false };
+            self.accept_server_syn(&packet);
+            return
+        }
+        // a split handshake acknowledges the SYN before sending its own
+        let ack = Sequence::from(packet.tcp.ack);
+        if packet.tcp.flags.ack && !packet.tcp.flags.syn && packet.tcp_payload.is_empty() && self.acknowledges_syn(ack) {
             return
         }
         if !(packet.tcp.flags.syn && packet.tcp.flags.ack) {
-            // handshake anomaly
+            self.report_anomaly(&packet, step, HandshakeAnomaly::UnexpectedFlags);
             return
         }
-        if self.client_next_seq - Sequence::from(packet.tcp.ack) != 0 {
-            // handshake anomaly
+        if !self.acknowledges_syn(ack) {
+            let expected = if self.fast_open { self.hijack_next_ack } else { self.syn_end() };
+            self.report_anomaly(&packet, step, HandshakeAnomaly::AckMismatch{ expected: u32::from(expected) });
             return
         }
         self.state = TcpState::ConnectionEstablished;
+        // SYN data the server didn't take is sent again
+        self.client_next_seq = ack;
+        self.accept_server_syn(&packet);
+        self.first_syn_ack = Some(SynAckFingerprint::from(&packet));
+    }
+
+    /// End of the client's SYN, its data left out.
+    fn syn_end(&self) -> Sequence {
+        self.hijack_next_ack + self.syn_data_len.wrapping_neg()
+    }
+
+    /// Whether `ack` acknowledges the client's SYN, with its data only if the server took it with Fast Open.
+    fn acknowledges_syn(&self, ack: Sequence) -> bool {
+        ack == self.syn_end() || (self.fast_open && ack == self.hijack_next_ack)
+    }
+
+    /// Takes the server's initial sequence number and window scale from its SYN or SYN-ACK.
+    fn accept_server_syn(&mut self, packet: &PacketManifest) {
+        match packet.tcp.options.window_scale() {
+            Some(shift) => self.server_window.set_scale(shift),
+            None => self.client_window.set_scale(0),
+        }
         self.server_next_seq = Some(Sequence::from(packet.tcp.seq) + (packet.tcp_payload.len() as u32 + 1));
         self.first_syn_ack_seq = Some(packet.tcp.seq);
     }
 
-    fn state_connection_established(&mut self, packet: PacketManifest) {
-        if !self.attack_reporter.is_attack_detected() {
-            if let Some(report) = self.detect_hijack(&packet) {
-                self.attack_reporter.report_attack(report);
+    fn state_simultaneous_open(&mut self, packet: PacketManifest, mut client_syn_acked: bool, mut server_syn_acked: bool) {
+        if let Some(report) = self.detect_hijack(&packet) {
+            self.report_attack(report);
+            self.probe_hijack(&packet);
+        }
+        let step = HandshakeStep::Ack;
+        let (side, server_next_seq) = match (self.side_id.identify(&packet), self.server_next_seq) {
+            (Ok(side), Some(server_next_seq)) => (side, server_next_seq),
+            _ => return,
+        };
+        let (next_seq, acked) = match side {
+            Side::Client => (self.client_next_seq, server_next_seq),
+            Side::Server => (server_next_seq, self.client_next_seq),
+        };
+        // SYNs of either side may be repeated, with or without acknowledging the other one
+        let seq = Sequence::from(packet.tcp.seq) + u32::from(packet.tcp.flags.syn);
+        if seq != next_seq {
+            self.report_anomaly(&packet, step, HandshakeAnomaly::SeqMismatch{ expected: u32::from(next_seq).wrapping_sub(u32::from(packet.tcp.flags.syn)) });
+            return
+        }
+        if !packet.tcp.flags.ack {
+            if !packet.tcp.flags.syn {
+                self.report_anomaly(&packet, step, HandshakeAnomaly::UnexpectedFlags);
             }
+            return
         }
-        if self.side_id.identify(&packet) != Side::Client {
-            // handshake anomaly
+        if Sequence::from(packet.tcp.ack) != acked {
+            self.report_anomaly(&packet, step, HandshakeAnomaly::AckMismatch{ expected: u32::from(acked) });
+            return
+        }
+        match side {
+            Side::Client => server_syn_acked = true,
+            Side::Server => {
+                client_syn_acked = true;
+                // later SYN-ACKs are compared to the first one, not to the SYN
+                if packet.tcp.flags.syn && self.first_syn_ack.is_none() {
+                    self.first_syn_ack = Some(SynAckFingerprint::from(&packet));
+                }
+            }
+        }
+        self.state = if client_syn_acked && server_syn_acked {
+            TcpState::DataTransfer
+        } else {
+            TcpState::SimultaneousOpen{ client_syn_acked, server_syn_acked }
+        };
+    }
+
+    fn state_connection_established(&mut self, packet: PacketManifest) {
+        if let Some(report) = self.detect_hijack(&packet) {
+            self.report_attack(report);
+            self.probe_hijack(&packet);
+        }
+        let step = HandshakeStep::Ack;
+        if self.side_id.identify(&packet) != Ok(Side::Client) {
+            // a retransmitted SYN-ACK is no news, another one is a hijack handled above
+            if !(packet.tcp.flags.syn && Some(packet.tcp.seq) == self.first_syn_ack_seq) {
+                self.report_anomaly(&packet, step, HandshakeAnomaly::UnexpectedSender{ expected: Side::Client });
+            }
             return
         }
         if packet.tcp.flags.syn || !packet.tcp.flags.ack {
-            // handshake anomaly
+            self.report_anomaly(&packet, step, HandshakeAnomaly::UnexpectedFlags);
             return
         }
         if Sequence::from(packet.tcp.seq) != self.client_next_seq {
-            // handshake anomaly
+            self.report_anomaly(&packet, step, HandshakeAnomaly::SeqMismatch{ expected: u32::from(self.client_next_seq) });
             return
         }
-        if Some(Sequence::from(packet.tcp.ack)) != self.server_next_seq {
-            // handshake anomaly
+        let ack = Sequence::from(packet.tcp.ack);
+        if let Some(server_next_seq) = self.server_next_seq.filter(|&server_next_seq| server_next_seq != ack) {
+            self.report_anomaly(&packet, step, HandshakeAnomaly::AckMismatch{ expected: u32::from(server_next_seq) });
             return
         }
 
         self.state = TcpState::DataTransfer;
     }
 
+    /// Learns the sequence numbers of a connection picked up midstream from the sides' ACKs, going
+    /// on as in data transfer once each side acknowledged what the sensor saw the other one send up to.
+    fn state_midstream(&mut self, packet: PacketManifest, mut client_synced: bool, mut server_synced: bool) {
+        let side = match self.side_id.identify(&packet) {
+            Ok(side) => side,
+            Err(_) => return,
+        };
+        if side == Side::Server && self.server_next_seq.is_none() {
+            self.server_next_seq = Some(Sequence::from(packet.tcp.seq));
+        }
+        let peer_next_seq = match side.peer() {
+            Side::Client => self.client_stream.next_seq(),
+            Side::Server => self.server_stream.next_seq(),
+        };
+        if !packet.tcp.flags.ack || peer_next_seq != Some(Sequence::from(packet.tcp.ack)) {
+            return
+        }
+        match side {
+            Side::Client => client_synced = true,
+            Side::Server => server_synced = true,
+        }
+        if !(client_synced && server_synced) {
+            self.state = TcpState::Midstream{ client_synced, server_synced };
+            return
+        }
+        // ACKs of data from before the pickup aren't news
+        self.client_acked_unseen = None;
+        self.server_acked_unseen = None;
+        self.client_divergence = None;
+        self.server_divergence = None;
+        self.state = TcpState::DataTransfer;
+    }
+
     fn state_data_transfer(&mut self, packet: PacketManifest) {
-        if self.server_next_seq.is_none() && self.side_id.identify(&packet) == Side::Server {
+        if self.server_next_seq.is_none() && self.side_id.identify(&packet) == Ok(Side::Server) {
             self.server_next_seq = Some(Sequence::from(packet.tcp.seq));
         }
 
-        if self.packet_count < self.skip_hijack_detection_count {
+        if self.detects_hijacks() {
             if let Some(report) = self.detect_hijack(&packet) {
-                self.attack_reporter.report_attack(report);
+                self.report_attack(report);
+                self.probe_hijack(&packet);
             }
         }
     }
 
+    /// Whether hijacks are still looked for after the handshake, for a while or for a number of
+    /// packets from the connection's start.
+    fn detects_hijacks(&self) -> bool {
+        match self.hijack_detection_period {
+            // capture time going backwards counts as no time passing
+            Some(period) => self.last_seen.duration_since(self.first_seen).map_or(true, |elapsed| elapsed < period),
+            None => self.packet_count < self.skip_hijack_detection_count,
+        }
+    }
+
     fn state_connection_closing(&mut self, packet: PacketManifest, state: TcpClosing) {}
-    fn state_closed(&mut self, packet: PacketManifest) {}
+
+    /// Ends the connection on a RST, freeing the stream buffers. The tuple is watched a while longer
+    /// for data after the RST and for its reuse.
+    fn close(&mut self, ts: SystemTime) {
+        self.state = TcpState::Closed;
+        self.reset_at = Some(ts);
+        self.client_stream.release();
+        self.server_stream.release();
+        self.account_buffered();
+    }
+
+    /// Keeps the out-of-order bytes the connection buffers within its cap and the global budget,
+    /// after data of `side` was buffered.
+    fn enforce_budget(&mut self, side: Side) {
+        let limit = if self.degraded { 0 } else { self.reassembly_budget.connection_limit() };
+        let (stream, other) = match side {
+            Side::Client => (&mut self.client_stream, &self.server_stream),
+            Side::Server => (&mut self.server_stream, &self.client_stream),
+        };
+        stream.shed(limit.saturating_sub(other.total_size()));
+        self.account_buffered();
+        if self.reassembly_budget.is_exceeded() && !self.degraded {
+            self.degraded = true;
+            self.client_stream.degrade();
+            self.server_stream.degrade();
+            self.reassembly_budget.note_degraded();
+            self.account_buffered();
+        }
+    }
+
+    /// Frees segments of `sender` buffered ahead of a gap its peer acknowledged past, the sensor
+    /// missed the gap and won't see it retransmitted.
+    fn release_acknowledged(&mut self, sender: Side, ack: Sequence) {
+        let stream = match sender {
+            Side::Client => &mut self.client_stream,
+            Side::Server => &mut self.server_stream,
+        };
+        if stream.total_size() > 0 {
+            stream.advance_ack(ack);
+            self.account_buffered();
+        }
+    }
+
+    /// Tells the budget of the bytes the streams buffer now.
+    fn account_buffered(&mut self) {
+        let buffered = self.client_stream.total_size() + self.server_stream.total_size();
+        self.reassembly_budget.account(self.buffered, buffered);
+        self.buffered = buffered;
+    }
+
+    /// Watches the tuple of a reset connection for reuse. Stacks pick the ISN of a tuple past the
+    /// sequence numbers it used before; a SYN right after the reset starting behind them rather
+    /// comes from someone taking the tuple, and the NAT binding of it, over.
+    fn state_closed(&mut self, packet: PacketManifest) {
+        let side = match self.side_id.identify(&packet) {
+            Ok(side) => side,
+            Err(_) => return,
+        };
+        let flags = packet.tcp.flags;
+        if !flags.syn || flags.ack {
+            // the sender goes on, so the RST didn't come from it, as reported on its own
+            if self.reset_at.is_some() && !packet.tcp_payload.is_empty() {
+                self.state = TcpState::DataTransfer;
+                self.reset_at = None;
+            }
+            return
+        }
+        let stream = match side {
+            Side::Client => &self.client_stream,
+            Side::Server => &self.server_stream,
+        };
+        let isn = Sequence::from(packet.tcp.seq);
+        let since_reset = self.reset_at.and_then(|reset_at| packet.meta.ts.duration_since(reset_at).ok());
+        if let (Some(since_reset), Some(previous_end)) = (since_reset, stream.next_seq()) {
+            if since_reset <= REUSE_PERIOD && !isn.is_after(previous_end) {
+                self.report_attack(AttackReport::new(PrimitiveDateTime::from(packet.meta.ts), Flow::from(&packet), AttackKind::ConnectionReuse {
+                    sender: side,
+                    isn: packet.tcp.seq,
+                    previous_end: u32::from(previous_end),
+                    after_reset_ms: since_reset.as_millis() as u64,
+                }));
+            }
+        }
+        // the server side of the tuple opening it is left to the decoys and hijack checks of the old one
+        if side == Side::Client {
+            self.restart(&packet);
+        }
+    }
+
+    /// Follows the handshake of a new connection on the tuple from the client's SYN, as from the
+    /// first packet of a connection. What is learned about the hosts, e.g. their TTLs, is kept.
+    fn restart(&mut self, packet: &PacketManifest) {
+        let syn_data_len = packet.tcp_payload.len() as u32;
+        let syn_end = Sequence::from(packet.tcp.seq) + 1 + syn_data_len;
+        self.state = TcpState::ConnectionRequest;
+        self.reset_at = None;
+        self.client_next_seq = syn_end;
+        self.server_next_seq = None;
+        self.hijack_next_ack = syn_end;
+        self.syn_data_len = syn_data_len;
+        self.fast_open = packet.tcp.options.fast_open().is_some_and(|cookie_len| cookie_len > 0);
+        self.first_syn_ack_seq = None;
+        self.first_syn_ack = None;
+        self.syn_options = Some(packet.tcp.options);
+        self.client_window = WindowTracker::new();
+        self.client_window.set_scale(packet.tcp.options.window_scale().unwrap_or(0));
+        self.server_window = WindowTracker::new();
+        self.client_stream = OrderedCoalesce::with_history(self.stream_history);
+        self.client_stream.insert(Sequence::from(packet.tcp.seq) + 1, packet.tcp_payload);
+        self.server_stream = OrderedCoalesce::with_history(self.stream_history);
+        self.client_reset = None;
+        self.server_reset = None;
+        self.client_urgent_bytes = 0;
+        self.server_urgent_bytes = 0;
+        self.client_acked_unseen = None;
+        self.server_acked_unseen = None;
+        self.client_divergence = None;
+        self.server_divergence = None;
+        if self.degraded {
+            self.client_stream.degrade();
+            self.server_stream.degrade();
+        }
+        self.account_buffered();
+        if self.http_responses.is_some() {
+            self.http_responses = Some(HttpResponses::new());
+        }
+        if self.dns_responses.is_some() {
+            self.dns_responses = Some(DnsResponses::new());
+        }
+    }
+
+    /// Sends keep-alives to both endpoints, their answers tell which SYN-ACK was genuine.
+    fn probe_hijack(&mut self, hijack: &PacketManifest) {
+        let (probes, first_syn_ack_seq, server_next_seq) = match (&self.probes, self.first_syn_ack_seq, self.server_next_seq) {
+            (Some(probes), Some(first_syn_ack_seq), Some(server_next_seq)) => (probes, first_syn_ack_seq, server_next_seq),
+            _ => return,
+        };
+        let (ethernet_to_client, ethernet_to_server) = match (self.ethernet_to_client, self.ethernet_to_server) {
+            (Some(to_client), Some(to_server)) => (to_client, to_server),
+            _ => return,
+        };
+        // answers to an earlier probe would be taken for this one's
+        if self.pending_probe.is_some() {
+            return
+        }
+        let flow = self.side_id.client_flow();
+        // probes would have to be encapsulated the way the tunnel does
+        if flow.tunnel().is_some() {
+            return
+        }
+        let (client, server) = (flow.src(), flow.dst());
+        // one byte before what the receiver expects next, answered with a bare ACK
+        let to_client = probe::keepalive_frame(ethernet_to_client, flow.vlans(), server, client,
+                                               server_next_seq + u32::MAX, self.client_next_seq, 0);
+        let to_server = probe::keepalive_frame(ethernet_to_server, flow.vlans(), client, server,
+                                               self.client_next_seq + u32::MAX, server_next_seq, 0);
+        probes.borrow_mut().extend(to_client.into_iter().chain(to_server));
+        self.pending_probe = Some(PendingProbe {
+            first: Sequence::from(first_syn_ack_seq) + 1,
+            later: Sequence::from(hijack.tcp.seq) + 1,
+            verdict: None,
+            client_ack: None,
+        });
+    }
+
+    fn receive_probe_answer(&mut self, packet: &PacketManifest, side: Side) {
+        let pending = match &mut self.pending_probe {
+            Some(pending) => pending,
+            None => return,
+        };
+        if !packet.tcp.flags.ack || packet.tcp.flags.syn || packet.tcp.flags.rst {
+            return
+        }
+        let (seq, ack) = (Sequence::from(packet.tcp.seq), Sequence::from(packet.tcp.ack));
+        match side {
+            Side::Server if seq == pending.first => pending.verdict = Some(HijackVerdict::FirstGenuine),
+            Side::Server if seq == pending.later => pending.verdict = Some(HijackVerdict::LaterGenuine),
+            // our own probe to the server is one byte behind the client, don't take it for an answer
+            Side::Client if seq != self.client_next_seq + u32::MAX && (ack == pending.first || ack == pending.later)
+                => pending.client_ack = Some(ack),
+            _ => return,
+        }
+        if let (Some(verdict), Some(client_ack)) = (pending.verdict, pending.client_ack) {
+            let genuine = match verdict {
+                HijackVerdict::FirstGenuine => pending.first,
+                HijackVerdict::LaterGenuine => pending.later,
+            };
+            let report = AttackReport::new(
+                PrimitiveDateTime::from(packet.meta.ts),
+                self.side_id.client_flow(),
+                AttackKind::HijackVerified{ verdict, client_desynchronized: client_ack != genuine },
+            );
+            self.pending_probe = None;
+            self.report_attack(report);
+        }
+    }
 
     fn detect_hijack(&self, packet: &PacketManifest) -> Option<AttackReport> {
-        if self.side_id.identify(packet) != Side::Server {
+        // probing a hijack nobody wants reported is of no use either
+        if !self.switches.hijack.get() || !self.policy.hijack {
+            return None
+        }
+        if self.side_id.identify(packet) != Ok(Side::Server) {
             return None
         }
         if !packet.tcp.flags.ack || !packet.tcp.flags.syn {
             return None
         }
-        if Sequence::from(packet.tcp.ack) != self.hijack_next_ack {
+        if !self.acknowledges_syn(Sequence::from(packet.tcp.ack)) {
             return None
         }
         if Some(packet.tcp.seq) == self.first_syn_ack_seq {
-            return None
+            // a retransmission, unless it differs from the first SYN-ACK in what hosts repeat
+            let differing = self.first_syn_ack?.differences(&SynAckFingerprint::from(packet));
+            if differing.is_empty() {
+                return None
+            }
+            return Some(AttackReport::new(PrimitiveDateTime::from(packet.meta.ts), Flow::from(packet), AttackKind::SynAckMismatch {
+                seq: packet.tcp.seq,
+                differing,
+            }))
         }
-        Some(AttackReport::HandshakeHijack {
-            time: PrimitiveDateTime::now(),
+        let competing = SynAckFingerprint::from(packet);
+        Some(AttackReport::new(PrimitiveDateTime::from(packet.meta.ts), Flow::from(packet), AttackKind::HandshakeHijack {
             packet_count: self.packet_count,
-            flow: Flow::from(packet),
             hijack_seq: packet.tcp.seq,
             hijack_ack: packet.tcp.ack,
-        })
+            differing: self.first_syn_ack.map_or_else(Vec::new, |first| first.differences(&competing)),
+            first: self.first_syn_ack,
+            competing,
+        }))
     }
 }
 
@@ -183,122 +1459,788 @@ impl Connection {
 mod tests {
     use super::*;
     use crate::event::test_utils::DummyAttackReporter;
-    use crate::types::{IpLayer, TcpLayer, TcpFlags};
+    use crate::coalesce::DEFAULT_STREAM_HISTORY;
+    use crate::testing::TcpScenario;
 
     use std::rc::Rc;
     use std::cell::RefCell;
     use std::net::Ipv4Addr;
 
+    /// Options with every setting at its default, reporting to `reporter`.
+    fn options(reporter: DummyAttackReporter) -> ConnectionOptions {
+        ConnectionOptions {
+            skip_hijack_detection_count: 0,
+            hijack_detection_period: None,
+            home_network: Default::default(),
+            attack_reporter: Box::new(reporter),
+            probes: None,
+            tenant: None,
+            carve_dir: None,
+            switches: Default::default(),
+            stream_history: DEFAULT_STREAM_HISTORY,
+            reassembly_budget: Default::default(),
+            packet_history: DEFAULT_PACKET_HISTORY,
+            history_digests: false,
+            policy: Default::default(),
+            midstream: false,
+        }
+    }
+
+    /// A client at 1.2.3.4:1 and a server at 2.3.4.5:2, starting their sequences at 3 and 9.
+    fn new_scenario() -> TcpScenario {
+        TcpScenario::new(
+            (Ipv4Addr::new(1, 2, 3, 4).into(), 1),
+            (Ipv4Addr::new(2, 3, 4, 5).into(), 2),
+            3, 9,
+        )
+    }
+
+    /// A connection past the handshake of `scenario`.
+    fn connect(scenario: &mut TcpScenario, options: ConnectionOptions) -> Connection {
+        let [syn, syn_ack, ack] = scenario.handshake();
+        let mut connection = Connection::from_packet(syn, options);
+        connection.receive_packet(syn_ack);
+        connection.receive_packet(ack);
+        connection
+    }
+
     #[test]
     fn detect_tcp_hijack() {
         let shared_reports: Rc<RefCell<Vec<_>>> = Default::default();
         let connection_options = ConnectionOptions {
             skip_hijack_detection_count: 12,
-            attack_reporter: Box::new(DummyAttackReporter::new(shared_reports.clone())),
-        };
-
-        let client_ip = IpLayer {
-            src: Ipv4Addr::new(1, 2, 3, 4).into(),
-            dst: Ipv4Addr::new(2, 3, 4, 5).into(),
-        };
-        let server_ip = IpLayer {
-            src: Ipv4Addr::new(2, 3, 4, 5).into(),
-            dst: Ipv4Addr::new(1, 2, 3, 4).into(),
+            ..options(DummyAttackReporter::new(shared_reports.clone()))
         };
+        let mut scenario = new_scenario();
 
         // initial packet
-
-        let packet = PacketManifest {
-            ip: client_ip,
-            tcp: TcpLayer {
-                src: 1,
-                dst: 2,
-                seq: 3,
-                flags: TcpFlags {
-                    syn: true,
-                    ack: false,
-                    ..Default::default()
-                },
-                ..Default::default()
-            },
-            tcp_payload: &[],
-        };
-        let mut connection = Connection::from_packet(packet, connection_options);
+        let mut connection = Connection::from_packet(scenario.syn(), connection_options);
         assert_eq!(connection.state, TcpState::ConnectionRequest, "invalid state transaction");
 
         // next packet
-        connection.receive_packet(PacketManifest {
-            ip: server_ip,
-            tcp: TcpLayer {
-                src: 2,
-                dst: 1,
-                seq: 9,
-                ack: 4,
-                flags: TcpFlags {
-                    syn: true,
-                    ack: true,
-                    ..Default::default()
-                },
-            },
-            tcp_payload: &[],
-        });
+        connection.receive_packet(scenario.syn_ack());
         assert_eq!(connection.state, TcpState::ConnectionEstablished, "invalid state transaction");
 
         // test hijack
-        connection.receive_packet(PacketManifest {
-            ip: server_ip,
-            tcp: TcpLayer {
-                src: 2,
-                dst: 1,
-                seq: 6699,
-                ack: 4,
-                flags: TcpFlags {
-                    syn: true,
-                    ack: true,
-                    ..Default::default()
-                },
-            },
-          tcp_payload: &[],
-        });
-
+        connection.receive_packet(scenario.inject_syn_ack(6699));
         let reports_count = shared_reports.borrow().len();
         assert_eq!(reports_count, 1, "hijack detection fail");
+        match &shared_reports.borrow()[0].kind {
+            AttackKind::HandshakeHijack { first, competing, differing, .. } => {
+                assert_eq!(first.map(|first| first.seq), Some(9));
+                assert_eq!(competing.seq, 6699);
+                assert_eq!(differing, &["seq"]);
+            }
+            kind => panic!("unexpected report {:?}", kind),
+        };
 
         // Going to data transfer state
-        connection.receive_packet(PacketManifest {
-            ip: client_ip,
-            tcp: TcpLayer {
-                src: 1,
-                dst: 2,
-                seq: 4,
-                ack: 10,
-                flags: TcpFlags {
-                    syn: false,
-                    ack: true,
-                    ..Default::default()
-                },
-            },
-            tcp_payload: &[],
-        });
+        connection.receive_packet(scenario.ack());
         assert_eq!(connection.state, TcpState::DataTransfer, "invalid state transition");
 
         // test hijack in transfer state
-        connection.receive_packet(PacketManifest {
-            ip: server_ip,
-            tcp: TcpLayer {
-                src: 2,
-                dst: 1,
-                seq: 7711,
-                ack: 4,
-                flags: TcpFlags {
-                    syn: true,
-                    ack: true,
-                    ..Default::default()
-                },
-            },
-            tcp_payload: &[],
-        });
+        connection.receive_packet(scenario.inject_syn_ack(7711));
         let reports_count = shared_reports.borrow().len();
         assert_eq!(reports_count, 2, "hijack detection fail");
     }
+
+    #[test]
+    fn detect_hijacks_for_a_period() {
+        let shared_reports: Rc<RefCell<Vec<AttackReport>>> = Default::default();
+        let connection_options = ConnectionOptions {
+            hijack_detection_period: Some(Duration::from_secs(60)),
+            ..options(DummyAttackReporter::new(shared_reports.clone()))
+        };
+        let mut scenario = new_scenario();
+        let mut connection = connect(&mut scenario, connection_options);
+        let hijack_at = |seconds, isn| {
+            scenario.server_packet().seq(isn).syn().ack(scenario.client_next_seq()).ts(SystemTime::UNIX_EPOCH + Duration::from_secs(seconds)).build(&[])
+        };
+
+        // however few packets went by, only the time since the start counts
+        connection.receive_packet(hijack_at(59, 6699));
+        assert_eq!(shared_reports.borrow().len(), 1);
+        connection.receive_packet(hijack_at(61, 7711));
+        assert_eq!(shared_reports.borrow().len(), 1);
+    }
+
+    #[test]
+    fn idle_in_capture_time() {
+        let connection_options = options(DummyAttackReporter::new(Default::default()));
+        let mut scenario = new_scenario();
+        let at = |seconds| SystemTime::UNIX_EPOCH + Duration::from_secs(seconds);
+        let mut connection = Connection::from_packet(scenario.syn(), connection_options);
+        connection.receive_packet(scenario.server_packet().syn().ack(scenario.client_next_seq()).ts(at(100)).build(&[]));
+        let timeout = Duration::from_secs(300);
+        assert!(!connection.is_idle(at(399), timeout));
+        assert!(connection.is_idle(at(400), timeout));
+        // packets captured out of order don't make it idle
+        assert!(!connection.is_idle(at(50), timeout));
+    }
+
+    #[test]
+    fn detect_conflicting_server_data() {
+        let shared_reports: Rc<RefCell<Vec<AttackReport>>> = Default::default();
+        let connection_options = options(DummyAttackReporter::new(shared_reports.clone()));
+        let mut scenario = new_scenario();
+        let mut connection = connect(&mut scenario, connection_options);
+        connection.receive_packet(scenario.client_data(b"GET / HTTP/1.1"));
+
+        // the first response segment is late, both versions of the second one are buffered
+        let gap = scenario.server_next_seq();
+        connection.receive_packet(scenario.inject_from_server(gap + 4, b"200 OK"));
+        connection.receive_packet(scenario.inject_from_server(gap + 4, b"302 OK"));
+        assert!(!shared_reports.borrow().is_empty());
+        match &shared_reports.borrow()[0].kind {
+            AttackKind::StreamOverlap { sender, range, winner, loser } => {
+                assert_eq!((*sender, u32::from(range.start()), range.len()), (Side::Server, gap + 4, 3));
+                assert_eq!((winner, loser), (&Excerpt(b"200".to_vec()), &Excerpt(b"302".to_vec())));
+                assert_eq!(format!("{:?}", loser), "b\"302\"");
+            }
+            ref kind => panic!("unexpected report {:?}", kind),
+        }
+        assert_eq!(shared_reports.borrow()[0].server(), Ipv4Addr::new(2, 3, 4, 5));
+        // the whole connection so far, up to the conflicting segment
+        let history = &shared_reports.borrow()[0].history;
+        assert_eq!(history.iter().map(|packet| packet.sender).collect::<Vec<_>>(), [Side::Client, Side::Server, Side::Client, Side::Client, Side::Server, Side::Server]);
+        assert_eq!((history[5].seq, history[5].payload_len, history[5].payload_digest), (gap + 4, 6, None));
+    }
+
+    #[test]
+    fn detect_reset_followed_by_data() {
+        let shared_reports: Rc<RefCell<Vec<AttackReport>>> = Default::default();
+        let connection_options = options(DummyAttackReporter::new(shared_reports.clone()));
+        let mut scenario = new_scenario();
+        let mut connection = connect(&mut scenario, connection_options);
+        connection.receive_packet(scenario.client_data(b"GET /forbidden HTTP/1.1"));
+
+        // a reset as the server, then the server answers anyway
+        let rst_seq = scenario.server_next_seq();
+        connection.receive_packet(scenario.inject_rst_from_server(rst_seq));
+        assert!(shared_reports.borrow().is_empty());
+        connection.receive_packet(scenario.server_data(b"HTTP/1.1 200 OK"));
+        match shared_reports.borrow()[0].kind {
+            AttackKind::RstInjection { sender, rst_seq: seq, rst_in_sequence, follow_up_seq } => {
+                assert_eq!((sender, seq, rst_in_sequence, follow_up_seq), (Side::Server, rst_seq, true, rst_seq))
+            }
+            ref kind => panic!("unexpected report {:?}", kind),
+        };
+    }
+
+    #[test]
+    fn detect_connection_reuse() {
+        let shared_reports: Rc<RefCell<Vec<AttackReport>>> = Default::default();
+        let connection_options = options(DummyAttackReporter::new(shared_reports.clone()));
+        let (client, server) = ((Ipv4Addr::new(1, 2, 3, 4).into(), 1), (Ipv4Addr::new(2, 3, 4, 5).into(), 2));
+        let mut scenario = TcpScenario::new(client, server, 1000, 9);
+        let mut connection = connect(&mut scenario, connection_options);
+        connection.receive_packet(scenario.client_data(b"GET / HTTP/1.1"));
+        connection.receive_packet(scenario.server_data(b"HTTP/1.1 200 OK"));
+        let reset_at = SystemTime::UNIX_EPOCH + Duration::from_secs(100);
+        connection.receive_packet(scenario.client_packet().rst().ts(reset_at).build(&[]));
+        assert_eq!(connection.state, TcpState::Closed);
+
+        // a SYN on the tuple right away, starting behind what the client sent on it
+        connection.receive_packet(scenario.client_packet().seq(500).syn().ts(reset_at + Duration::from_millis(50)).build(&[]));
+        assert_eq!(shared_reports.borrow().len(), 1);
+        match shared_reports.borrow()[0].kind {
+            AttackKind::ConnectionReuse { sender, isn, previous_end, after_reset_ms } => {
+                assert_eq!((sender, isn, previous_end, after_reset_ms), (Side::Client, 500, scenario.client_next_seq(), 50))
+            }
+            ref kind => panic!("unexpected report {:?}", kind),
+        }
+
+        // the new connection is followed from its handshake, and reusing its tuple past it is fine
+        let mut reused = TcpScenario::new(client, server, 500, 70000);
+        reused.syn();
+        connection.receive_packet(reused.syn_ack());
+        assert_eq!(connection.state, TcpState::ConnectionEstablished);
+        connection.receive_packet(reused.ack());
+        connection.receive_packet(reused.client_packet().rst().ts(reset_at).build(&[]));
+        connection.receive_packet(reused.client_packet().seq(90000).syn().ts(reset_at).build(&[]));
+        assert_eq!(connection.state, TcpState::ConnectionRequest);
+        assert_eq!(shared_reports.borrow().len(), 1);
+    }
+
+    #[test]
+    fn close_on_reset_in_any_state() {
+        let reporter = DummyAttackReporter::new(Default::default());
+        let anomalies = reporter.anomalies.clone();
+        let at = |seconds| SystemTime::UNIX_EPOCH + Duration::from_secs(seconds);
+
+        // refused, the RST answers the SYN
+        let mut scenario = new_scenario();
+        let mut connection = Connection::from_packet(scenario.syn(), options(reporter));
+        connection.receive_packet(scenario.server_packet().seq(0).rst().ack(scenario.client_next_seq()).ts(at(100)).build(&[]));
+        assert_eq!(connection.state, TcpState::Closed);
+        assert!(anomalies.borrow().is_empty());
+        assert!(!connection.is_finished(at(105)));
+        assert!(connection.is_finished(at(110)));
+
+        // reset before the handshake completes, with a segment buffered ahead of a gap
+        let mut scenario = new_scenario();
+        let mut connection = Connection::from_packet(scenario.syn(), options(DummyAttackReporter::new(Default::default())));
+        connection.receive_packet(scenario.syn_ack());
+        connection.receive_packet(scenario.inject_from_client(scenario.client_next_seq() + 10, b"later"));
+        assert_eq!(connection.client_stream.total_size(), 5);
+        connection.receive_packet(scenario.client_packet().rst().ts(at(100)).build(&[]));
+        assert_eq!(connection.state, TcpState::Closed);
+        assert_eq!(connection.client_stream.total_size(), 0);
+
+        // reset right after data the server hasn't acknowledged yet, at the client's next sequence number
+        let mut scenario = new_scenario();
+        let mut connection = connect(&mut scenario, options(DummyAttackReporter::new(Default::default())));
+        connection.receive_packet(scenario.client_data(b"0123456789"));
+        assert_eq!(scenario.client_next_seq(), 14);
+        connection.receive_packet(scenario.client_packet().rst().ts(at(100)).build(&[]));
+        assert_eq!(connection.state, TcpState::Closed);
+        assert_eq!(connection.client_rst_probes.challenge_acks, 0);
+    }
+
+    #[test]
+    fn keep_within_reassembly_budget() {
+        let shared_reports: Rc<RefCell<Vec<AttackReport>>> = Default::default();
+        let budget = Rc::new(ReassemblyBudget::new(10, 15));
+        let budgeted = |reporter| ConnectionOptions {
+            reassembly_budget: budget.clone(),
+            ..options(reporter)
+        };
+        let server = (Ipv4Addr::new(2, 3, 4, 5).into(), 2);
+        let mut first = TcpScenario::new((Ipv4Addr::new(1, 2, 3, 4).into(), 1), server, 3, 9);
+        let [syn, syn_ack, ack] = first.handshake();
+        let mut first_connection = Connection::from_packet(syn, budgeted(DummyAttackReporter::new(Default::default())));
+        first_connection.receive_packet(syn_ack);
+        first_connection.receive_packet(ack);
+
+        // over the connection's cap, the server's gap is given up on
+        first_connection.receive_packet(first.inject_from_client(first.client_next_seq() + 4, b"12345678"));
+        first_connection.receive_packet(first.inject_from_server(first.server_next_seq() + 4, b"abcdef"));
+        assert_eq!(budget.used(), 8);
+        assert_eq!(first_connection.server_stream.next_seq(), Some(Sequence::from(first.server_next_seq() + 10)));
+
+        // over the global budget, the connection buffering then stops comparing segments
+        let mut second = TcpScenario::new((Ipv4Addr::new(1, 2, 3, 4).into(), 7), server, 3, 9);
+        let [syn, syn_ack, ack] = second.handshake();
+        let mut second_connection = Connection::from_packet(syn, budgeted(DummyAttackReporter::new(shared_reports.clone())));
+        second_connection.receive_packet(syn_ack);
+        second_connection.receive_packet(ack);
+        second_connection.receive_packet(second.inject_from_client(second.client_next_seq() + 4, b"12345678"));
+        assert_eq!((budget.used(), budget.degraded()), (8, 1));
+        second_connection.receive_packet(second.inject_from_client(second.client_next_seq() + 20, b"abcd"));
+        second_connection.receive_packet(second.inject_from_client(second.client_next_seq() + 20, b"wxyz"));
+        assert!(shared_reports.borrow().is_empty());
+
+        drop(first_connection);
+        assert_eq!(budget.used(), 0);
+    }
+
+    #[test]
+    fn report_handshake_anomaly() {
+        let reporter = DummyAttackReporter::new(Default::default());
+        let anomalies = reporter.anomalies.clone();
+        let connection_options = options(reporter);
+        let mut scenario = new_scenario();
+        let mut connection = Connection::from_packet(scenario.syn(), connection_options);
+        // retransmitted SYN
+        connection.receive_packet(scenario.client_packet().seq(3).syn().build(&[]));
+        assert!(anomalies.borrow().is_empty());
+        connection.receive_packet(scenario.syn_ack());
+
+        // final ACK acknowledging more than the server sent
+        let expected = scenario.server_next_seq();
+        connection.receive_packet(scenario.client_packet().ack(expected + 100).build(&[]));
+        assert_eq!(connection.state, TcpState::ConnectionEstablished);
+        let anomalies = anomalies.borrow();
+        assert_eq!(anomalies.len(), 1);
+        assert_eq!((anomalies[0].step, anomalies[0].kind), (HandshakeStep::Ack, HandshakeAnomaly::AckMismatch{ expected }));
+        assert_eq!(anomalies[0].ack, expected + 100);
+    }
+
+    #[test]
+    fn follow_simultaneous_open_and_split_handshake() {
+        // one store of anomalies for both connections
+        let anomalies: Rc<RefCell<Vec<AnomalyReport>>> = Default::default();
+        let connection_options = || {
+            let mut reporter = DummyAttackReporter::new(Default::default());
+            reporter.anomalies = anomalies.clone();
+            options(reporter)
+        };
+        let mut scenario = new_scenario();
+
+        // both SYNs cross, then both sides acknowledge with a SYN-ACK
+        let mut connection = Connection::from_packet(scenario.syn(), connection_options());
+        connection.receive_packet(scenario.server_syn());
+        assert_eq!(connection.state, TcpState::SimultaneousOpen{ client_syn_acked: false, server_syn_acked: false });
+        connection.receive_packet(scenario.client_packet().seq(3).syn().ack(scenario.server_next_seq()).build(&[]));
+        connection.receive_packet(scenario.server_packet().seq(9).syn().ack(scenario.client_next_seq()).build(&[]));
+        assert_eq!(connection.state, TcpState::DataTransfer);
+        connection.receive_packet(scenario.client_data(b"GET / HTTP/1.1"));
+        assert!(anomalies.borrow().is_empty());
+
+        // the server acknowledges the SYN, sends its own and has the client acknowledge it
+        let mut scenario = new_scenario();
+        let mut connection = Connection::from_packet(scenario.syn(), connection_options());
+        connection.receive_packet(scenario.server_packet().ack(scenario.client_next_seq()).build(&[]));
+        assert_eq!(connection.state, TcpState::ConnectionRequest);
+        connection.receive_packet(scenario.server_syn());
+        connection.receive_packet(scenario.client_packet().seq(3).syn().ack(scenario.server_next_seq()).build(&[]));
+        assert_eq!(connection.state, TcpState::SimultaneousOpen{ client_syn_acked: false, server_syn_acked: true });
+        connection.receive_packet(scenario.server_packet().ack(scenario.client_next_seq()).build(&[]));
+        assert_eq!(connection.state, TcpState::DataTransfer);
+        assert!(anomalies.borrow().is_empty());
+    }
+
+    #[test]
+    fn follow_fast_open_handshakes() {
+        let reporter = DummyAttackReporter::new(Default::default());
+        let anomalies = reporter.anomalies.clone();
+        let connection_options = ConnectionOptions {
+            skip_hijack_detection_count: 10,
+            ..options(reporter)
+        };
+        let mut scenario = new_scenario();
+        // the server takes the data of a SYN carrying a cookie
+        let syn = scenario.client_packet().syn().fast_open(&[1, 2, 3, 4, 5, 6, 7, 8]).build(b"GET / HTTP/1.1");
+        let mut connection = Connection::from_packet(syn, connection_options);
+        connection.receive_packet(scenario.server_packet().syn().ack(4 + 14).build(&[]));
+        assert_eq!(connection.state, TcpState::ConnectionEstablished);
+        assert_eq!(connection.client_next_seq, Sequence::from(4 + 14));
+        assert!(anomalies.borrow().is_empty());
+
+        // without a cookie only the SYN is acknowledged, the data comes again
+        let reporter = DummyAttackReporter::new(Default::default());
+        let anomalies = reporter.anomalies.clone();
+        let connection_options = ConnectionOptions {
+            skip_hijack_detection_count: 10,
+            ..options(reporter)
+        };
+        let syn = scenario.client_packet().syn().fast_open(&[]).build(b"GET / HTTP/1.1");
+        let mut connection = Connection::from_packet(syn, connection_options);
+        // retransmitted without the data
+        connection.receive_packet(scenario.syn());
+        connection.receive_packet(scenario.server_packet().syn().ack(4 + 14).build(&[]));
+        assert_eq!(connection.state, TcpState::ConnectionRequest);
+        connection.receive_packet(scenario.syn_ack());
+        assert_eq!(connection.client_next_seq, Sequence::from(4));
+        connection.receive_packet(scenario.client_data(b"GET / HTTP/1.1"));
+        assert_eq!(connection.state, TcpState::DataTransfer);
+        let anomalies = anomalies.borrow();
+        assert_eq!(anomalies.len(), 1);
+        assert_eq!(anomalies[0].kind, HandshakeAnomaly::AckMismatch{ expected: 4 });
+    }
+
+    #[test]
+    fn detect_ttl_deviation() {
+        let shared_reports: Rc<RefCell<Vec<AttackReport>>> = Default::default();
+        let connection_options = options(DummyAttackReporter::new(shared_reports.clone()));
+        let mut scenario = new_scenario();
+        let mut connection = connect(&mut scenario, connection_options);
+        connection.receive_packet(scenario.client_data(b"GET / HTTP/1.1"));
+        assert!(shared_reports.borrow().is_empty());
+
+        // the injector sits a dozen hops closer than the server
+        let seq = scenario.server_next_seq();
+        connection.receive_packet(scenario.server_packet().ttl(76).build(b"HTTP/1.1 302 Found"));
+        match shared_reports.borrow()[0].kind {
+            AttackKind::TtlDeviation { sender, seq: report_seq, ttl, typical } => {
+                assert_eq!((sender, report_seq, ttl, typical), (Side::Server, seq, 76, 64))
+            }
+            ref kind => panic!("unexpected report {:?}", kind),
+        };
+        // every injected segment is reported, not only the first
+        connection.receive_packet(scenario.server_packet().ttl(76).build(b"HTTP/1.1 302 Found"));
+        assert_eq!(shared_reports.borrow().len(), 2);
+    }
+
+    #[test]
+    fn detect_ip_id_deviation() {
+        let shared_reports: Rc<RefCell<Vec<AttackReport>>> = Default::default();
+        let connection_options = options(DummyAttackReporter::new(shared_reports.clone()));
+        let mut scenario = new_scenario();
+        let [mut syn, mut syn_ack, mut ack] = scenario.handshake();
+        syn.ip.id = Some(100);
+        syn_ack.ip.id = Some(5000);
+        ack.ip.id = Some(101);
+        let mut connection = Connection::from_packet(syn, connection_options);
+        connection.receive_packet(syn_ack);
+        connection.receive_packet(ack);
+        for (id, chunk) in (5001..).zip(&[&b"HTTP/1.1 200 OK\r\n"[..], b"Server: x\r\n", b"Date: y\r\n", b"Content-Length: 9\r\n"]) {
+            let mut segment = scenario.server_data(chunk);
+            segment.ip.id = Some(id);
+            connection.receive_packet(segment);
+        }
+        assert!(shared_reports.borrow().is_empty());
+
+        // the injector stamps its own host's counter
+        let seq = scenario.server_next_seq();
+        connection.receive_packet(scenario.server_packet().ip_id(41000).build(b"\r\nredirect"));
+        match shared_reports.borrow()[0].kind {
+            AttackKind::IpIdDeviation { sender, seq: report_seq, ip_id, expected } => {
+                assert_eq!((sender, report_seq, ip_id, expected), (Side::Server, seq, 41000, 5005))
+            }
+            ref kind => panic!("unexpected report {:?}", kind),
+        };
+    }
+
+    #[test]
+    fn detect_spoofed_keep_alives() {
+        let shared_reports: Rc<RefCell<Vec<AttackReport>>> = Default::default();
+        let connection_options = || options(DummyAttackReporter::new(shared_reports.clone()));
+        let open = || {
+            let mut scenario = new_scenario();
+            let mut connection = connect(&mut scenario, connection_options());
+            connection.receive_packet(scenario.client_data(b"GET / HTTP/1.1"));
+            connection.receive_packet(scenario.server_data(b"HTTP/1.1 200 OK"));
+            connection.receive_packet(scenario.ack());
+            (scenario, connection)
+        };
+        let (scenario, mut connection) = open();
+        let probe_seq = scenario.client_next_seq().wrapping_sub(1);
+        let keep_alive = || scenario.client_packet().seq(probe_seq).ack(scenario.server_next_seq());
+
+        // genuine probes, with and without a garbage byte
+        connection.receive_packet(keep_alive().build(&[]));
+        connection.receive_packet(keep_alive().build(b"\0"));
+        assert!(shared_reports.borrow().is_empty());
+
+        // a middlebox closer to the server than the client is
+        connection.receive_packet(keep_alive().ttl(120).build(&[]));
+        match shared_reports.borrow()[0].kind {
+            AttackKind::SpoofedKeepAlive { sender, seq, reason } => {
+                assert_eq!((sender, seq, reason), (Side::Client, probe_seq, KeepAliveMismatch::Ttl{ ttl: 120, typical: 64 }))
+            }
+            ref kind => panic!("unexpected report {:?}", kind),
+        };
+
+        // a middlebox having lost track of the client's sequence numbers
+        let (scenario, mut connection) = open();
+        let stale_seq = scenario.client_next_seq().wrapping_sub(7);
+        connection.receive_packet(scenario.client_packet().seq(stale_seq).ack(scenario.server_next_seq()).build(&[]));
+        match shared_reports.borrow()[1].kind {
+            AttackKind::SpoofedKeepAlive { sender, seq, reason } => {
+                assert_eq!((sender, seq, reason), (Side::Client, stale_seq, KeepAliveMismatch::Sequence{ expected: probe_seq }))
+            }
+            ref kind => panic!("unexpected report {:?}", kind),
+        };
+    }
+
+    #[test]
+    fn detect_urgent_data_abuse() {
+        let shared_reports: Rc<RefCell<Vec<AttackReport>>> = Default::default();
+        let connection_options = || options(DummyAttackReporter::new(shared_reports.clone()));
+        let open = || {
+            let mut scenario = new_scenario();
+            let connection = connect(&mut scenario, connection_options());
+            (scenario, connection)
+        };
+        let (mut scenario, mut connection) = open();
+        let mut send_urgent = |payload: &'static [u8], urgent_ptr: u16| {
+            let mut segment = scenario.client_data(payload);
+            segment.tcp.flags.urg = true;
+            segment.tcp.urgent_ptr = urgent_ptr;
+            connection.receive_packet(segment);
+        };
+
+        // interrupts of an interactive session
+        send_urgent(b"\xf2", 1);
+        send_urgent(b"ABOR\xf2", 5);
+        send_urgent(b"\xf2", 1);
+        send_urgent(b"\xf2", 1);
+        assert!(shared_reports.borrow().is_empty());
+        send_urgent(b"X", 1);
+        match shared_reports.borrow()[0].kind {
+            AttackKind::UrgentDataAbuse { sender, reason, .. } => {
+                assert_eq!((sender, reason), (Side::Client, UrgentAbuse::RepeatedSingleBytes{ count: 4 }))
+            }
+            ref kind => panic!("unexpected report {:?}", kind),
+        };
+
+        let (scenario, mut connection) = open();
+        let seq = scenario.client_next_seq();
+        connection.receive_packet(scenario.client_packet().ack(scenario.server_next_seq()).urgent(40).build(b"GET /admin"));
+        match shared_reports.borrow()[1].kind {
+            AttackKind::UrgentDataAbuse { sender, seq: report_seq, reason } => {
+                let reason_expected = UrgentAbuse::OutsideSegment{ urgent_ptr: 40, payload_len: 10 };
+                assert_eq!((sender, report_seq, reason), (Side::Client, seq, reason_expected))
+            }
+            ref kind => panic!("unexpected report {:?}", kind),
+        };
+    }
+
+    #[test]
+    fn detect_http_response_conflicts() {
+        let shared_reports: Rc<RefCell<Vec<AttackReport>>> = Default::default();
+        let connection_options = ConnectionOptions {
+            // the genuine response is gone from the history, so the stream can't compare
+            stream_history: 0,
+            policy: DetectionPolicy{ http: true, ..Default::default() },
+            ..options(DummyAttackReporter::new(shared_reports.clone()))
+        };
+        let mut scenario = TcpScenario::new(
+            (Ipv4Addr::new(1, 2, 3, 4).into(), 1),
+            (Ipv4Addr::new(2, 3, 4, 5).into(), 80),
+            3, 9,
+        );
+        let mut connection = connect(&mut scenario, connection_options);
+        connection.receive_packet(scenario.client_data(b"GET / HTTP/1.1\r\nHost: x\r\n\r\n"));
+        let seq = scenario.server_next_seq();
+        connection.receive_packet(scenario.server_data(b"HTTP/1.1 200 OK\r\nContent-Length: 2\r\n\r\nok"));
+        assert!(shared_reports.borrow().is_empty());
+
+        connection.receive_packet(scenario.inject_from_server(seq, b"HTTP/1.1 302 Found\r\nLocation: http://x/\r\n\r\n"));
+        match shared_reports.borrow()[0].kind {
+            AttackKind::HttpResponseConflict { seq: report_seq, first_status, second_status } => {
+                assert_eq!((report_seq, first_status, second_status), (seq, 200, 302))
+            }
+            ref kind => panic!("unexpected report {:?}", kind),
+        };
+    }
+
+    #[test]
+    fn detect_option_tampering() {
+        let shared_reports: Rc<RefCell<Vec<AttackReport>>> = Default::default();
+        let connection_options = || options(DummyAttackReporter::new(shared_reports.clone()));
+        let timestamps = [8, 10, 0, 0, 0, 1, 0, 0, 0, 2];
+
+        // timestamps agreed on, then stripped from the server's data
+        let mut tcp = new_scenario();
+        let with_timestamps = tcp.client_packet().raw_options(&timestamps).build(&[]).tcp.options;
+        let [mut syn, mut syn_ack, mut ack] = tcp.handshake();
+        for packet in [&mut syn, &mut syn_ack, &mut ack] {
+            packet.tcp.options = with_timestamps;
+        }
+        let mut connection = Connection::from_packet(syn, connection_options());
+        connection.receive_packet(syn_ack);
+        connection.receive_packet(ack);
+        let mut request = tcp.client_data(b"GET /");
+        request.tcp.options = with_timestamps;
+        connection.receive_packet(request);
+        // RSTs may go without
+        connection.receive_packet(tcp.client_packet().rst().build(&[]));
+        assert!(shared_reports.borrow().is_empty());
+        connection.receive_packet(tcp.server_data(b"HTTP/1.1 200 OK"));
+        match shared_reports.borrow()[0].kind {
+            AttackKind::OptionTampering { sender, option, change, .. } => {
+                assert_eq!((sender, option, change), (Side::Server, "timestamps", OptionChange::Vanished))
+            }
+            ref kind => panic!("unexpected report {:?}", kind),
+        };
+
+        // SACK stripped from the SYN on its way, the server still offering it
+        let mut tcp = new_scenario();
+        let syn = tcp.syn();
+        let syn_ack = tcp.server_packet().syn().ack(tcp.client_next_seq()).raw_options(&[4, 2]).build(&[]);
+        let mut connection = Connection::from_packet(syn, connection_options());
+        connection.receive_packet(syn_ack);
+        match shared_reports.borrow()[1].kind {
+            AttackKind::OptionTampering { sender, option, change, .. } => {
+                assert_eq!((sender, option, change), (Side::Server, "sack_permitted", OptionChange::Unnegotiated))
+            }
+            ref kind => panic!("unexpected report {:?}", kind),
+        };
+    }
+
+    #[test]
+    fn detect_insertion_attempts() {
+        let shared_reports: Rc<RefCell<Vec<AttackReport>>> = Default::default();
+        let connection_options = || options(DummyAttackReporter::new(shared_reports.clone()));
+        let open = || {
+            let mut scenario = new_scenario();
+            // the client is a dozen hops past the sensor
+            let [mut syn, syn_ack, mut ack] = scenario.handshake();
+            syn.ip.ttl = 52;
+            ack.ip.ttl = 52;
+            let mut connection = Connection::from_packet(syn, connection_options());
+            connection.receive_packet(syn_ack);
+            connection.receive_packet(ack);
+            (scenario, connection)
+        };
+        let (mut scenario, mut connection) = open();
+
+        // a segment that expires halfway, the client never sees it
+        let seq = scenario.server_next_seq();
+        connection.receive_packet(scenario.server_packet().ttl(5).build(b"HTTP/1.1 302 Found"));
+        match shared_reports.borrow()[0].kind {
+            AttackKind::Insertion { sender, seq: report_seq, reason, .. } => {
+                assert_eq!((sender, report_seq, reason), (Side::Server, seq, InsertionReason::TtlExpires{ ttl: 5, hops: 12 }))
+            }
+            ref kind => panic!("unexpected report {:?}", kind),
+        };
+        // the genuine response is not taken for a conflicting one
+        connection.receive_packet(scenario.server_data(b"HTTP/1.1 200 OK"));
+        assert_eq!(connection.server_stream.next_seq(), Some(Sequence::from(scenario.server_next_seq())));
+
+        // data past the window the client advertised
+        let (scenario, mut connection) = open();
+        let window_end = scenario.server_next_seq().wrapping_add(u32::from(u16::MAX));
+        connection.receive_packet(scenario.inject_from_server(window_end, b"HTTP/1.1 302 Found"));
+        match shared_reports.borrow()[1].kind {
+            AttackKind::Insertion { reason, .. } => assert_eq!(reason, InsertionReason::BeyondWindow{ window_end }),
+            ref kind => panic!("unexpected report {:?}", kind),
+        };
+    }
+
+    #[test]
+    fn detect_syn_ack_reusing_isn() {
+        let shared_reports: Rc<RefCell<Vec<AttackReport>>> = Default::default();
+        let connection_options = options(DummyAttackReporter::new(shared_reports.clone()));
+        let mut scenario = new_scenario();
+        let mut connection = Connection::from_packet(scenario.syn(), connection_options);
+        connection.receive_packet(scenario.syn_ack());
+        // a genuine retransmission
+        connection.receive_packet(scenario.inject_syn_ack(9));
+        assert!(shared_reports.borrow().is_empty());
+
+        let mut forged = scenario.inject_syn_ack(9);
+        // a hop off, too little to stand out by TTL alone
+        forged.ip.ttl = 65;
+        forged.tcp.window = 1024;
+        connection.receive_packet(forged);
+        match &shared_reports.borrow()[0].kind {
+            AttackKind::SynAckMismatch { seq, differing } => assert_eq!((*seq, &differing[..]), (9, &["window", "ttl"][..])),
+            kind => panic!("unexpected report {:?}", kind),
+        };
+    }
+
+    #[test]
+    fn detect_forged_zero_window() {
+        let shared_reports: Rc<RefCell<Vec<AttackReport>>> = Default::default();
+        let connection_options = options(DummyAttackReporter::new(shared_reports.clone()));
+        let mut scenario = new_scenario();
+        let mut connection = connect(&mut scenario, connection_options);
+        connection.receive_packet(scenario.client_data(b"GET / HTTP/1.1"));
+        // the window moves along with the data
+        connection.receive_packet(scenario.server_data(b"HTTP/1.1 200 OK"));
+        assert!(shared_reports.borrow().is_empty());
+
+        connection.receive_packet(scenario.client_data(b"GET /video HTTP/1.1"));
+        connection.receive_packet(scenario.server_packet().ack(scenario.client_next_seq()).window(0).build(&[]));
+        match shared_reports.borrow()[0].kind {
+            AttackKind::WindowShrink { sender, window, previous_window, .. } => {
+                assert_eq!((sender, window, previous_window), (Side::Server, 0, u32::from(u16::MAX)))
+            }
+            ref kind => panic!("unexpected report {:?}", kind),
+        };
+    }
+
+    #[test]
+    fn conversation_across_sequence_wrap() {
+        let shared_reports: Rc<RefCell<Vec<AttackReport>>> = Default::default();
+        let connection_options = ConnectionOptions {
+            skip_hijack_detection_count: 12,
+            ..options(DummyAttackReporter::new(shared_reports.clone()))
+        };
+        let mut scenario = TcpScenario::new(
+            (Ipv4Addr::new(1, 2, 3, 4).into(), 1),
+            (Ipv4Addr::new(2, 3, 4, 5).into(), 2),
+            u32::MAX - 5, u32::MAX,
+        );
+        let mut connection = connect(&mut scenario, connection_options);
+        assert_eq!(connection.state, TcpState::DataTransfer);
+        for _ in 0..3 {
+            connection.receive_packet(scenario.client_data(b"GET / HTTP/1.1"));
+            connection.receive_packet(scenario.server_data(b"HTTP/1.1 200 OK"));
+        }
+        assert!(shared_reports.borrow().is_empty());
+    }
+
+    #[test]
+    fn detect_data_acknowledged_before_sent() {
+        let shared_reports: Rc<RefCell<Vec<AttackReport>>> = Default::default();
+        let connection_options = options(DummyAttackReporter::new(shared_reports.clone()));
+        let mut scenario = new_scenario();
+        let mut connection = connect(&mut scenario, connection_options);
+        connection.receive_packet(scenario.client_data(b"GET / HTTP/1.1"));
+
+        // a request injected past the sensor is acknowledged, then the client sends its own
+        let seq = scenario.client_next_seq();
+        connection.receive_packet(scenario.server_packet().ack(seq + 14).build(&[]));
+        assert!(shared_reports.borrow().is_empty());
+        connection.receive_packet(scenario.client_data(b"GET /2 HTTP/1.1"));
+        match shared_reports.borrow()[0].kind {
+            AttackKind::UnseenDataAcknowledged { sender, seq: report_seq, acknowledged } => {
+                assert_eq!((sender, report_seq, acknowledged), (Side::Client, seq, seq + 14))
+            }
+            ref kind => panic!("unexpected report {:?}", kind),
+        };
+    }
+
+    #[test]
+    fn detect_stream_desync() {
+        let shared_reports: Rc<RefCell<Vec<AttackReport>>> = Default::default();
+        let connection_options = options(DummyAttackReporter::new(shared_reports.clone()));
+        let mut scenario = new_scenario();
+        let mut connection = connect(&mut scenario, connection_options);
+        connection.receive_packet(scenario.client_data(b"GET / HTTP/1.1"));
+
+        // a segment the capture dropped, the client's next one catches up with it
+        scenario.client_data(b"Host:");
+        connection.receive_packet(scenario.server_packet().ack(scenario.client_next_seq()).build(&[]));
+        assert!(connection.client_divergence.is_some());
+        connection.receive_packet(scenario.client_data(b" x"));
+        connection.receive_packet(scenario.server_data(b"HTTP/1.1 100 Continue"));
+        assert!(connection.client_divergence.is_none());
+
+        // the server talks to a hijacker taking the client's place
+        let hijacked = scenario.client_next_seq() + 400;
+        for second in 0..DESYNC_ACKS as u64 {
+            let mut response = scenario.server_packet().ack(hijacked).build(b"HTTP/1.1 200 OK");
+            response.meta.ts = SystemTime::UNIX_EPOCH + Duration::from_secs(second);
+            connection.receive_packet(response);
+        }
+        match shared_reports.borrow()[0].kind {
+            AttackKind::StreamDesync { sender, seen_end, acknowledged, acks } => {
+                assert_eq!((sender, seen_end, acknowledged, acks), (Side::Client, scenario.client_next_seq(), hijacked, DESYNC_ACKS))
+            }
+            ref kind => panic!("unexpected report {:?}", kind),
+        };
+    }
+
+    #[test]
+    fn pick_up_midstream() {
+        let shared_reports: Rc<RefCell<Vec<AttackReport>>> = Default::default();
+        let connection_options = ConnectionOptions {
+            midstream: true,
+            ..options(DummyAttackReporter::new(shared_reports.clone()))
+        };
+        let mut scenario = TcpScenario::new(
+            (Ipv4Addr::new(10, 0, 0, 1).into(), 40000),
+            (Ipv4Addr::new(10, 0, 0, 2).into(), 80),
+            5000, 1000,
+        );
+        // the server's packet is seen first, yet the client is the side on the high port
+        let mut connection = Connection::from_packet(scenario.server_data(b"hello"), connection_options);
+        assert_eq!(connection.side_id.client_flow().dst().1, 80);
+
+        // a retransmission changing the data isn't reported before the sides are synced
+        connection.receive_packet(scenario.inject_from_server(1000, b"jello"));
+        assert!(shared_reports.borrow().is_empty());
+        connection.receive_packet(scenario.client_data(b"GET /"));
+        assert_eq!(connection.state, TcpState::Midstream{ client_synced: true, server_synced: false });
+        connection.receive_packet(scenario.server_data(b"world"));
+        assert_eq!(connection.state, TcpState::DataTransfer);
+
+        connection.receive_packet(scenario.inject_from_server(1000, b"jelly"));
+        assert_eq!(shared_reports.borrow().len(), 1);
+        assert!(matches!(shared_reports.borrow()[0].kind, AttackKind::StreamOverlap { sender: Side::Server, .. }));
+    }
+
+    #[test]
+    fn count_segments() {
+        let connection_options = options(DummyAttackReporter::new(Default::default()));
+        let mut scenario = new_scenario();
+        let mut connection = connect(&mut scenario, connection_options);
+        let start = scenario.client_next_seq();
+        connection.receive_packet(scenario.client_data(b"abcd"));
+        // resent as it was, then changed, then data after a gap
+        connection.receive_packet(scenario.inject_from_client(start, b"abcd"));
+        connection.receive_packet(scenario.inject_from_client(start + 2, b"cX"));
+        connection.receive_packet(scenario.inject_from_client(start + 8, b"ij"));
+
+        let stats = connection.stats();
+        assert_eq!((stats.packets, stats.payload_octets), (7, 12));
+        assert_eq!((stats.retransmissions, stats.out_of_order, stats.overlaps), (2, 1, 1));
+        let total: ConnectionStats = vec![stats, stats].into_iter().sum();
+        assert_eq!(total.retransmissions, 4);
+    }
 }
@@ -0,0 +1,162 @@
+//! Decoy connections used as an injection tripwire.
+//!
+//! The sensor periodically connects to bait listeners which accept a connection and then stay
+//! silent until the client closes it. A bait never sends data or resets, so any such segment
+//! seen on a decoy connection was injected.
+
+use std::collections::HashSet;
+use std::io::{self, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::time::Duration;
+
+use time::PrimitiveDateTime;
+
+use crate::event::{AttackKind, AttackReport};
+use crate::types::{Flow, PacketManifest};
+
+/// Request sent over each decoy connection, plain HTTP to attract censorship middleboxes.
+const DECOY_REQUEST: &[u8] = b"GET / HTTP/1.1\r\nHost: example.com\r\nUser-Agent: curl/7.68.0\r\n\r\n";
+/// How long a decoy connection is held open.
+const DECOY_HOLD: Duration = Duration::from_secs(10);
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+/// How often a paused decoy thread checks whether it may resume.
+const PAUSE_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Decoy connections currently open, as `(local, bait)` address pairs.
+#[derive(Clone, Default)]
+pub struct DecoyFlows {
+    flows: Arc<Mutex<HashSet<(SocketAddr, SocketAddr)>>>,
+    paused: Arc<AtomicBool>,
+}
+
+impl DecoyFlows {
+    /// Pauses or resumes decoy rounds, connections already open are held until they finish.
+    pub fn set_enabled(&self, enabled: bool) {
+        self.paused.store(!enabled, Ordering::Relaxed);
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        !self.paused.load(Ordering::Relaxed)
+    }
+
+    fn insert(&self, local: SocketAddr, bait: SocketAddr) {
+        self.flows.lock().unwrap().insert((local, bait));
+    }
+
+    fn remove(&self, local: SocketAddr, bait: SocketAddr) {
+        self.flows.lock().unwrap().remove(&(local, bait));
+    }
+
+    /// Checks a captured packet, returns a report if it is a segment no bait would send.
+    pub fn inspect(&self, packet: &PacketManifest) -> Option<AttackReport> {
+        if packet.tcp_payload.is_empty() && !packet.tcp.flags.rst {
+            return None
+        }
+        let from_bait = (
+            SocketAddr::new(packet.ip.dst, packet.tcp.dst),
+            SocketAddr::new(packet.ip.src, packet.tcp.src),
+        );
+        if !self.flows.lock().unwrap().contains(&from_bait) {
+            return None
+        }
+        Some(AttackReport::new(PrimitiveDateTime::from(packet.meta.ts), Flow::from(packet), AttackKind::DecoyTripped {
+            seq: packet.tcp.seq,
+            payload_len: packet.tcp_payload.len(),
+            rst: packet.tcp.flags.rst,
+        }))
+    }
+}
+
+/// Starts a thread connecting to every bait each `interval`.
+pub fn spawn_decoys(baits: Vec<SocketAddr>, interval: Duration, flows: DecoyFlows) -> thread::JoinHandle<()> {
+    thread::spawn(move || loop {
+        if !flows.is_enabled() {
+            thread::sleep(PAUSE_CHECK_INTERVAL);
+            continue
+        }
+        let connections: Vec<_> = baits.iter()
+            .map(|&bait| {
+                let flows = flows.clone();
+                thread::spawn(move || {
+                    if let Err(err) = run_decoy(bait, &flows) {
+                        eprintln!("Decoy connection to {} failed: {}", bait, err);
+                    }
+                })
+            })
+            .collect();
+        for connection in connections {
+            let _ = connection.join();
+        }
+        thread::sleep(interval);
+    })
+}
+
+fn run_decoy(bait: SocketAddr, flows: &DecoyFlows) -> io::Result<()> {
+    let mut stream = TcpStream::connect_timeout(&bait, CONNECT_TIMEOUT)?;
+    let local = stream.local_addr()?;
+    flows.insert(local, bait);
+    let result = hold_decoy(&mut stream);
+    // segments still in flight may arrive after the close
+    thread::sleep(CONNECT_TIMEOUT);
+    flows.remove(local, bait);
+    result
+}
+
+fn hold_decoy(stream: &mut TcpStream) -> io::Result<()> {
+    stream.write_all(DECOY_REQUEST)?;
+    stream.set_read_timeout(Some(DECOY_HOLD))?;
+    let mut buf = [0; 1500];
+    match stream.read(&mut buf) {
+        // the bait is silent, whatever we get was injected and is reported from the capture
+        Ok(_) => Ok(()),
+        Err(err) if err.kind() == io::ErrorKind::WouldBlock || err.kind() == io::ErrorKind::TimedOut => Ok(()),
+        Err(err) if err.kind() == io::ErrorKind::ConnectionReset => Ok(()),
+        Err(err) => Err(err),
+    }
+}
+
+/// Starts a silent bait listener for decoys of other sensors.
+pub fn spawn_bait(addr: SocketAddr) -> io::Result<thread::JoinHandle<()>> {
+    let listener = TcpListener::bind(addr)?;
+    Ok(thread::spawn(move || {
+        for stream in listener.incoming() {
+            match stream {
+                Ok(mut stream) => {
+                    thread::spawn(move || {
+                        // drain until the client closes, never answer
+                        let _ = io::copy(&mut stream, &mut io::sink());
+                    });
+                }
+                Err(err) => eprintln!("Bait listener failed to accept: {}", err),
+            }
+        }
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TcpScenario;
+
+    #[test]
+    fn trips_on_data_from_bait() {
+        let local: SocketAddr = "10.0.0.2:40000".parse().unwrap();
+        let bait: SocketAddr = "192.0.2.80:80".parse().unwrap();
+        let flows = DecoyFlows::default();
+        flows.insert(local, bait);
+
+        let mut scenario = TcpScenario::new((local.ip(), local.port()), (bait.ip(), bait.port()), 100, 5000);
+        for packet in &scenario.handshake() {
+            assert!(flows.inspect(packet).is_none());
+        }
+        assert!(flows.inspect(&scenario.client_data(DECOY_REQUEST)).is_none());
+        assert!(flows.inspect(&scenario.inject_from_server(5001, b"HTTP/1.1 403 Forbidden\r\n\r\n")).is_some());
+        assert!(flows.inspect(&scenario.inject_rst_from_server(5001)).is_some());
+
+        flows.remove(local, bait);
+        assert!(flows.inspect(&scenario.inject_rst_from_server(5001)).is_none());
+    }
+}
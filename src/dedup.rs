@@ -0,0 +1,105 @@
+//! Dropping copies of a packet mirrored more than once.
+//!
+//! SPAN ports mirroring both the ingress and the egress of a switch deliver every frame twice,
+//! microseconds apart, and the second copy would look like a retransmission. Copies are told
+//! apart from retransmissions by the IP ID, which a retransmission changes, and by arriving
+//! within a short window of capture time. Link layer fields are left out since they may differ
+//! between the mirrored directions.
+
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{HashMap, VecDeque};
+use std::hash::{Hash, Hasher};
+use std::time::{Duration, SystemTime};
+
+use crate::types::PacketManifest;
+
+/// Copies further apart than this in capture time are passed on.
+pub const DEFAULT_DEDUP_WINDOW: Duration = Duration::from_millis(10);
+/// Packets remembered at once, the oldest are forgotten early on floods.
+const MAX_RECENT: usize = 1 << 16;
+
+pub struct MirrorDedup {
+    window: Duration,
+    /// Capture time a packet was last seen, by its hash.
+    seen: HashMap<u64, SystemTime>,
+    /// Hashes in capture order, for expiry.
+    recent: VecDeque<(u64, SystemTime)>,
+    duplicates: u64,
+}
+
+impl MirrorDedup {
+    pub fn new(window: Duration) -> Self {
+        Self{ window, seen: HashMap::new(), recent: VecDeque::new(), duplicates: 0 }
+    }
+
+    /// Whether the packet is a copy of one seen within the window, it should be dropped then.
+    pub fn is_duplicate(&mut self, packet: &PacketManifest) -> bool {
+        let now = packet.meta.ts;
+        self.expire(now);
+        let hash = packet_hash(packet);
+        if self.seen.contains_key(&hash) {
+            self.duplicates += 1;
+            return true
+        }
+        if self.recent.len() == MAX_RECENT {
+            if let Some((oldest, _)) = self.recent.pop_front() {
+                self.seen.remove(&oldest);
+            }
+        }
+        self.seen.insert(hash, now);
+        self.recent.push_back((hash, now));
+        false
+    }
+
+    /// Copies dropped so far.
+    pub fn duplicates(&self) -> u64 {
+        self.duplicates
+    }
+
+    fn expire(&mut self, now: SystemTime) {
+        while let Some(&(hash, seen_at)) = self.recent.front() {
+            // capture time may go back a little, such packets stay
+            if now.duration_since(seen_at).map_or(true, |age| age <= self.window) {
+                break
+            }
+            self.recent.pop_front();
+            self.seen.remove(&hash);
+        }
+    }
+}
+
+fn packet_hash(packet: &PacketManifest) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    (packet.ip.src, packet.ip.dst, packet.ip.id, packet.ip.total_len).hash(&mut hasher);
+    let tcp = &packet.tcp;
+    (tcp.src, tcp.dst, tcp.seq, tcp.ack, tcp.window).hash(&mut hasher);
+    (tcp.flags.syn, tcp.flags.ack, tcp.flags.fin, tcp.flags.rst).hash(&mut hasher);
+    packet.tcp_payload.hash(&mut hasher);
+    hasher.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::PacketBuilder;
+    use std::time::UNIX_EPOCH;
+
+    #[test]
+    fn drops_mirrored_copies_only() {
+        let client = ("10.0.0.5".parse().unwrap(), 40000);
+        let server = ("192.168.1.1".parse().unwrap(), 443);
+        let at = |millis| UNIX_EPOCH + Duration::from_millis(millis);
+        let mut dedup = MirrorDedup::new(DEFAULT_DEDUP_WINDOW);
+
+        let segment = PacketBuilder::new(client, server).seq(100).ip_id(7);
+        assert!(!dedup.is_duplicate(&segment.ts(at(1000)).build(b"data")));
+        // the egress copy, tagged with another VLAN
+        assert!(dedup.is_duplicate(&segment.vlan(7).ts(at(1001)).build(b"data")));
+        // a retransmission has another IP ID, or comes later
+        assert!(!dedup.is_duplicate(&segment.ip_id(8).ts(at(1002)).build(b"data")));
+        assert!(!dedup.is_duplicate(&segment.ts(at(1300)).build(b"data")));
+        // same sequence number but other bytes
+        assert!(!dedup.is_duplicate(&segment.ts(at(1301)).build(b"evil")));
+        assert_eq!(dedup.duplicates(), 1);
+    }
+}
@@ -0,0 +1,207 @@
+//! Spotting conflicting DNS responses on a TCP connection.
+//!
+//! DNS over TCP prefixes each message with its length. A server answers a query once, so two
+//! responses with the ID of one query and different answers mean one of them was injected,
+//! poisoning the resolver that takes it.
+
+use std::collections::VecDeque;
+use std::fmt;
+use std::net::{Ipv4Addr, Ipv6Addr};
+
+use crate::types::Sequence;
+
+pub const DNS_PORT: u16 = 53;
+/// Responses remembered per connection, resolvers pipeline many queries on one.
+const MAX_RESPONSES: usize = 64;
+const HEADER_LEN: usize = 12;
+const TYPE_A: u16 = 1;
+const TYPE_AAAA: u16 = 28;
+
+/// Resource record of an answer section, the owner name, class and TTL left out.
+#[derive(Clone, Eq, PartialEq, Ord, PartialOrd)]
+pub struct DnsAnswer {
+    pub rtype: u16,
+    pub data: Vec<u8>,
+}
+
+impl fmt::Display for DnsAnswer {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match (self.rtype, self.data.len()) {
+            (TYPE_A, 4) => write!(f, "A {}", Ipv4Addr::new(self.data[0], self.data[1], self.data[2], self.data[3])),
+            (TYPE_AAAA, 16) => {
+                let mut octets = [0; 16];
+                octets.copy_from_slice(&self.data);
+                write!(f, "AAAA {}", Ipv6Addr::from(octets))
+            }
+            (rtype, len) => write!(f, "TYPE{} ({} bytes)", rtype, len),
+        }
+    }
+}
+
+/// Records show as in the Display form in reports, e.g. `A 192.0.2.1`.
+impl fmt::Debug for DnsAnswer {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Display::fmt(self, f)
+    }
+}
+
+/// Outcome of a query as far as resolvers go by it.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct DnsResponse {
+    pub rcode: u8,
+    /// Answers in a canonical order, servers may rotate them.
+    pub answers: Vec<DnsAnswer>,
+}
+
+impl DnsResponse {
+    /// Parses a message without its length prefix, `None` for queries and malformed messages.
+    pub fn parse(message: &[u8]) -> Option<(u16, Self)> {
+        let header = message.get(..HEADER_LEN)?;
+        let id = u16::from_be_bytes([header[0], header[1]]);
+        let flags = u16::from_be_bytes([header[2], header[3]]);
+        if flags & 0x8000 == 0 {
+            return None
+        }
+        let questions = u16::from_be_bytes([header[4], header[5]]);
+        let answer_count = u16::from_be_bytes([header[6], header[7]]);
+        let mut offset = HEADER_LEN;
+        for _ in 0..questions {
+            offset = skip_name(message, offset)? + 4;
+        }
+        let mut answers = Vec::new();
+        for _ in 0..answer_count {
+            offset = skip_name(message, offset)?;
+            let fixed = message.get(offset..offset + 10)?;
+            let rtype = u16::from_be_bytes([fixed[0], fixed[1]]);
+            let data_len = usize::from(u16::from_be_bytes([fixed[8], fixed[9]]));
+            let data = message.get(offset + 10..offset + 10 + data_len)?;
+            answers.push(DnsAnswer{ rtype, data: data.to_vec() });
+            offset += 10 + data_len;
+        }
+        answers.sort();
+        Some((id, Self{ rcode: (flags & 0xf) as u8, answers }))
+    }
+}
+
+/// Offset after the name starting at `offset`, labels or a compression pointer.
+fn skip_name(message: &[u8], mut offset: usize) -> Option<usize> {
+    loop {
+        let len = *message.get(offset)?;
+        match len {
+            0 => return Some(offset + 1),
+            len if len & 0xc0 == 0xc0 => return Some(offset + 2),
+            len => offset += 1 + usize::from(len),
+        }
+    }
+}
+
+/// Responses of the server side of a DNS connection, split into messages.
+#[derive(Debug, Clone, Default)]
+pub struct DnsResponses {
+    /// Stream position following `pending`.
+    next_seq: Option<Sequence>,
+    /// Bytes of a message not yet complete.
+    pending: Vec<u8>,
+    responses: VecDeque<(u16, DnsResponse)>,
+}
+
+impl DnsResponses {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Takes a segment of the server's stream. Returns the ID, the earlier response and the later
+    /// one of the first query answered twice differently.
+    pub fn observe(&mut self, seq: Sequence, payload: &[u8]) -> Option<(u16, DnsResponse, DnsResponse)> {
+        let next_seq = *self.next_seq.get_or_insert(seq);
+        let messages = if seq == next_seq {
+            self.pending.extend_from_slice(payload);
+            self.next_seq = Some(seq + payload.len() as u32);
+            let (messages, consumed) = split_messages(&self.pending);
+            self.pending.drain(..consumed);
+            messages
+        } else if seq.is_before(next_seq) {
+            // a retransmission, or a response racing the one taken, if it holds whole messages
+            split_messages(payload).0
+        } else {
+            // lost segments, messages are looked for again from here on
+            self.pending = payload.to_vec();
+            self.next_seq = Some(seq + payload.len() as u32);
+            let (messages, consumed) = split_messages(&self.pending);
+            self.pending.drain(..consumed);
+            messages
+        };
+
+        let mut conflict = None;
+        for (id, response) in messages {
+            match self.responses.iter().find(|(earlier_id, _)| *earlier_id == id) {
+                Some((_, earlier)) if *earlier != response => {
+                    conflict.get_or_insert((id, earlier.clone(), response));
+                }
+                Some(_) => {}
+                None => {
+                    if self.responses.len() == MAX_RESPONSES {
+                        self.responses.pop_front();
+                    }
+                    self.responses.push_back((id, response));
+                }
+            }
+        }
+        conflict
+    }
+}
+
+/// Responses among the whole length prefixed messages `buffer` starts with, and the bytes they take.
+fn split_messages(buffer: &[u8]) -> (Vec<(u16, DnsResponse)>, usize) {
+    let mut messages = Vec::new();
+    let mut offset = 0;
+    while let Some(prefix) = buffer.get(offset..offset + 2) {
+        let len = usize::from(u16::from_be_bytes([prefix[0], prefix[1]]));
+        let message = match buffer.get(offset + 2..offset + 2 + len) {
+            Some(message) => message,
+            None => break,
+        };
+        messages.extend(DnsResponse::parse(message));
+        offset += 2 + len;
+    }
+    (messages, offset)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Length prefixed response to an A query of `example.com`.
+    fn response(id: u16, addresses: &[[u8; 4]]) -> Vec<u8> {
+        let mut message = id.to_be_bytes().to_vec();
+        message.extend_from_slice(&[0x81, 0x80, 0, 1, 0, addresses.len() as u8, 0, 0, 0, 0]);
+        message.extend_from_slice(b"\x07example\x03com\x00\x00\x01\x00\x01");
+        for address in addresses {
+            message.extend_from_slice(&[0xc0, 12, 0, 1, 0, 1, 0, 0, 0x0e, 0x10, 0, 4]);
+            message.extend_from_slice(address);
+        }
+        let mut framed = (message.len() as u16).to_be_bytes().to_vec();
+        framed.extend(message);
+        framed
+    }
+
+    #[test]
+    fn conflicting_responses_to_one_query() {
+        let mut responses = DnsResponses::new();
+        let genuine = response(7, &[[192, 0, 2, 1], [192, 0, 2, 2]]);
+        let other = response(8, &[[192, 0, 2, 3]]);
+        // a response split over segments, then one for another query
+        let start = Sequence::from(100);
+        assert_eq!(responses.observe(start, &genuine[..20]), None);
+        assert_eq!(responses.observe(start + 20, &genuine[20..]), None);
+        assert_eq!(responses.observe(start + genuine.len() as u32, &other), None);
+        // retransmitted with the answers rotated
+        assert_eq!(responses.observe(start, &response(7, &[[192, 0, 2, 2], [192, 0, 2, 1]])), None);
+
+        let (id, first, second) = responses.observe(start, &response(7, &[[203, 0, 113, 66]])).unwrap();
+        assert_eq!(id, 7);
+        assert_eq!(first.answers.iter().map(ToString::to_string).collect::<Vec<_>>(), ["A 192.0.2.1", "A 192.0.2.2"]);
+        assert_eq!(second.answers[0].to_string(), "A 203.0.113.66");
+        assert!(DnsResponse::parse(&genuine[2..12]).is_none());
+    }
+}
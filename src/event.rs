@@ -1,36 +1,789 @@
+use std::cell::RefCell;
+use std::collections::hash_map::DefaultHasher;
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::io::Write;
+use std::net::IpAddr;
+use std::rc::Rc;
+
 use time::PrimitiveDateTime;
 
-use crate::types::packet::Flow;
+use crate::dns::DnsResponse;
+use crate::process::ProcessInfo;
+use crate::types::WrappingRange;
+use crate::types::packet::{Flow, Side, TcpFlags, TcpOptions, PacketManifest};
 
+/// Takes the reports of one connection. Every attack detected is reported, repeated ones and ones of
+/// other types alike, reporters suppressing some of them do so on their own.
 pub trait AttackReporter {
-    fn is_attack_detected(&self) -> bool;
+    /// Reports passed on so far.
+    fn attacks_reported(&self) -> u32;
     fn report_attack(&mut self, report: AttackReport);
+    /// Anomalies don't count as a detected attack.
+    fn report_anomaly(&mut self, report: AnomalyReport);
+}
+
+/// Detected attack: what happened, when and on which flow.
+#[derive(Debug)]
+pub struct AttackReport {
+    pub time: PrimitiveDateTime,
+    pub flow: Flow,
+    pub kind: AttackKind,
+    pub severity: Severity,
+    /// How sure the detection is, in percent, higher with more signals corroborating it.
+    pub confidence: u8,
+    /// Details filled in by reporters on the way, not by detectors.
+    pub context: ReportContext,
+    /// Latest packets of the connection up to the one reported, the oldest first.
+    pub history: Vec<PacketSummary>,
+}
+
+/// Headers of a packet leading up to a report.
+#[derive(Debug, Copy, Clone)]
+pub struct PacketSummary {
+    pub time: PrimitiveDateTime,
+    pub sender: Side,
+    pub flags: TcpFlags,
+    pub seq: u32,
+    pub ack: u32,
+    pub window: u16,
+    pub ttl: u8,
+    pub payload_len: usize,
+    /// Hash of the payload, telling apart segments of equal headers, if digests are kept.
+    pub payload_digest: Option<u64>,
+}
+
+impl PacketSummary {
+    pub fn new(packet: &PacketManifest, sender: Side, digest: bool) -> Self {
+        let payload_digest = if digest {
+            let mut hasher = DefaultHasher::new();
+            packet.tcp_payload.hash(&mut hasher);
+            Some(hasher.finish())
+        } else {
+            None
+        };
+        Self {
+            time: PrimitiveDateTime::from(packet.meta.ts),
+            sender,
+            flags: packet.tcp.flags,
+            seq: packet.tcp.seq,
+            ack: packet.tcp.ack,
+            window: packet.tcp.window,
+            ttl: packet.ip.ttl,
+            payload_len: packet.tcp_payload.len(),
+            payload_digest,
+        }
+    }
+}
+
+/// Harm a report suggests if it's right, for triage.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Hash)]
+pub enum Severity {
+    Low,
+    Medium,
+    High,
+}
+
+impl Severity {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Severity::Low => "low",
+            Severity::Medium => "medium",
+            Severity::High => "high",
+        }
+    }
 }
 
 #[derive(Debug)]
-pub enum AttackReport {
+pub enum AttackKind {
     HandshakeHijack {
-        time: PrimitiveDateTime,
         packet_count: u64,
-        flow: Flow,
         hijack_seq: u32,
         hijack_ack: u32,
+        /// The SYN-ACK answering the SYN first, `None` if it went unseen.
+        first: Option<SynAckFingerprint>,
+        /// The later SYN-ACK with another sequence number.
+        competing: SynAckFingerprint,
+        /// Fields the competing SYN-ACK differs in from the first one, e.g. `seq` or `ttl`.
+        differing: Vec<&'static str>,
+    },
+    /// SYN-ACK with the sequence number of the first one, but differing in what a retransmission repeats.
+    SynAckMismatch {
+        seq: u32,
+        /// Fields differing from the first SYN-ACK, e.g. `ttl` or `options`.
+        differing: Vec<&'static str>,
+    },
+    /// Outcome of probing the endpoints after a suspected handshake hijack.
+    HijackVerified {
+        verdict: HijackVerdict,
+        /// Client acknowledged the injected SYN-ACK instead of the genuine one.
+        client_desynchronized: bool,
+    },
+    /// Segment a silent bait would never send arrived on a decoy connection.
+    DecoyTripped {
+        seq: u32,
+        payload_len: usize,
+        rst: bool,
+    },
+    /// RST followed by data from the side it claims to come from, which thus didn't reset.
+    RstInjection {
+        /// Side the RST claims to come from.
+        sender: Side,
+        rst_seq: u32,
+        /// Whether the RST was at the sequence number the side was expected to send next,
+        /// as an injector seeing the traffic gets it.
+        rst_in_sequence: bool,
+        /// Sequence number of the data sent after the RST.
+        follow_up_seq: u32,
+    },
+    /// SYN reusing the tuple of a connection reset just before, with an ISN behind the sequence numbers
+    /// its sender used on the tuple, as someone taking the tuple and its NAT binding over sends.
+    ConnectionReuse {
+        /// Side of the reset connection the SYN comes from.
+        sender: Side,
+        isn: u32,
+        /// Next sequence number the sender had on the reset connection.
+        previous_end: u32,
+        after_reset_ms: u64,
+    },
+    /// RSTs at guessed sequence numbers, off the window or eliciting challenge ACKs, probing for one that resets.
+    BlindReset {
+        /// Side the RSTs claim to come from.
+        sender: Side,
+        off_window: u32,
+        challenge_acks: u32,
+    },
+    /// Segment taking back much of the window its claimed sender offered, which receivers don't do.
+    WindowShrink {
+        sender: Side,
+        /// Window of the segment and the one offered before, in bytes.
+        window: u32,
+        previous_window: u32,
+        /// Bytes the right edge of the window moved back.
+        retraction: u32,
+    },
+    /// Data its receiver acknowledged before the sender sent it, as seen by the sensor, so the receiver
+    /// got the bytes from an injector past the sensor.
+    UnseenDataAcknowledged {
+        sender: Side,
+        /// Start of the data the sender sent afterwards, and the end of what was acknowledged.
+        seq: u32,
+        acknowledged: u32,
+    },
+    /// Receiver acknowledging data of the sender the sensor never saw, ACK after ACK, as the two sides
+    /// of a hijacked connection end up: the receiver talks to the hijacker, the sender is left behind.
+    StreamDesync {
+        sender: Side,
+        /// End of the sender's data seen, and the furthest the receiver acknowledged.
+        seen_end: u32,
+        acknowledged: u32,
+        /// ACKs acknowledging unseen data in a row.
+        acks: u32,
+    },
+    /// Segment arriving with a TTL its claimed sender doesn't send with.
+    TtlDeviation {
+        sender: Side,
+        seq: u32,
+        ttl: u8,
+        /// TTL the sender's earlier segments arrived with.
+        typical: u8,
+    },
+    /// Keep-alive probe its claimed sender didn't send, as middleboxes keeping hijacked connections open do.
+    SpoofedKeepAlive {
+        sender: Side,
+        seq: u32,
+        reason: KeepAliveMismatch,
+    },
+    /// Two different HTTP responses starting at the same position of the server's stream, as a
+    /// quantum insert racing the genuine response leaves.
+    HttpResponseConflict {
+        /// Start of the later response.
+        seq: u32,
+        first_status: u16,
+        second_status: u16,
+    },
+    /// Two different responses to one DNS query on a TCP connection, one of them poisoning the resolver.
+    DnsResponseConflict {
+        /// Query ID both responses carry.
+        id: u16,
+        first: DnsResponse,
+        second: DnsResponse,
+    },
+    /// TCP options used otherwise than negotiated in the handshake, as middleboxes rewriting segments
+    /// leave; the endpoints then see other segments than the sensor does.
+    OptionTampering {
+        sender: Side,
+        seq: u32,
+        /// Option concerned, e.g. `timestamps` or `sack`.
+        option: &'static str,
+        change: OptionChange,
+    },
+    /// Urgent data used so that receivers and sensors disagree on the stream, as evasion does.
+    UrgentDataAbuse {
+        sender: Side,
+        seq: u32,
+        reason: UrgentAbuse,
+    },
+    /// Segment whose IPv4 identification doesn't follow the pattern of its claimed sender's segments.
+    IpIdDeviation {
+        sender: Side,
+        seq: u32,
+        ip_id: u16,
+        /// ID the sender's pattern called for next.
+        expected: u16,
+    },
+    /// Data or RST the sensor sees but the receiver won't take, sent to desynchronize sensors.
+    Insertion {
+        sender: Side,
+        seq: u32,
+        payload_len: usize,
+        rst: bool,
+        reason: InsertionReason,
+    },
+    /// Segment whose bytes differ from another segment's covering the same part of the stream.
+    StreamOverlap {
+        /// Side both segments claim to come from.
+        sender: Side,
+        /// Sequence numbers from the first to the last differing byte.
+        range: WrappingRange,
+        /// Start of the range as the stream held it, and as the later segment had it.
+        winner: Excerpt,
+        loser: Excerpt,
+    },
+}
+
+/// Header fields of a SYN-ACK telling apart the hosts sending them; a retransmission repeats all of them.
+#[derive(Debug, Copy, Clone, Default, Eq, PartialEq)]
+pub struct SynAckFingerprint {
+    pub seq: u32,
+    pub window: u16,
+    pub ttl: u8,
+    pub options: TcpOptions,
+}
+
+impl SynAckFingerprint {
+    pub fn from(packet: &PacketManifest) -> Self {
+        Self{ seq: packet.tcp.seq, window: packet.tcp.window, ttl: packet.ip.ttl, options: packet.tcp.options }
+    }
+
+    /// Names of the fields `other` differs in.
+    pub fn differences(&self, other: &Self) -> Vec<&'static str> {
+        let mut differing = Vec::new();
+        if self.seq != other.seq {
+            differing.push("seq");
+        }
+        if self.window != other.window {
+            differing.push("window");
+        }
+        if self.ttl != other.ttl {
+            differing.push("ttl");
+        }
+        if self.options.kinds() != other.options.kinds() {
+            differing.push("options");
+        }
+        if self.options.mss() != other.options.mss() {
+            differing.push("mss");
+        }
+        if self.options.window_scale() != other.options.window_scale() {
+            differing.push("window_scale");
+        }
+        differing
+    }
+}
+
+/// Confidence a SYN-ACK field differing from the first SYN-ACK adds, as another host sent it.
+fn field_weight(field: &str) -> u32 {
+    match field {
+        // the hop count is hardest to fake
+        "ttl" => 20,
+        "options" | "mss" | "window_scale" => 10,
+        "window" => 5,
+        _ => 0,
+    }
+}
+
+/// What gives a keep-alive probe away as spoofed.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum KeepAliveMismatch {
+    /// The probe isn't one byte before the sender's next one, with all of the sender's data acknowledged.
+    Sequence { expected: u32 },
+    /// The probe arrived with a TTL the sender's segments don't.
+    Ttl { ttl: u8, typical: u8 },
+}
+
+/// How a segment's urgent data desynchronizes reassembly.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum UrgentAbuse {
+    /// The urgent pointer points outside the segment's data, receivers differ in what they make of it.
+    OutsideSegment { urgent_ptr: u16, payload_len: usize },
+    /// One byte segments of urgent data, `count` so far, receivers taking them out of band or inline.
+    RepeatedSingleBytes { count: u32 },
+}
+
+/// How a segment's options differ from the negotiated ones.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum OptionChange {
+    /// An option negotiated for every segment is missing.
+    Vanished,
+    /// An option is used without both SYNs agreeing on it, or a SYN-ACK answers to an option the SYN lacked.
+    Unnegotiated,
+}
+
+/// Bytes of a payload, shown in reports like a byte string literal, e.g. `b"302 Found\r\n"`.
+#[derive(Clone, Default, Eq, PartialEq)]
+pub struct Excerpt(pub Vec<u8>);
+
+impl fmt::Debug for Excerpt {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "b\"{}\"", self.0.escape_ascii())
+    }
+}
+
+/// Why the receiver won't take an inserted segment.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum InsertionReason {
+    /// The TCP checksum is wrong.
+    BadChecksum,
+    /// The TTL runs out before the receiver, `hops` away from the sensor as far as its TTL tells.
+    TtlExpires { ttl: u8, hops: u8 },
+    /// The data starts past the right edge of the receiver's window.
+    BeyondWindow { window_end: u32 },
+}
+
+/// Packet not fitting the handshake. Not an attack by itself, but of help investigating one.
+#[derive(Debug)]
+pub struct AnomalyReport {
+    pub time: PrimitiveDateTime,
+    /// Flow of the packet.
+    pub flow: Flow,
+    /// Handshake packet awaited.
+    pub step: HandshakeStep,
+    pub kind: HandshakeAnomaly,
+    pub flags: TcpFlags,
+    pub seq: u32,
+    pub ack: u32,
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum HandshakeStep {
+    SynAck,
+    Ack,
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum HandshakeAnomaly {
+    /// Packet from the side not having its turn.
+    UnexpectedSender { expected: Side },
+    /// Flags the step doesn't allow.
+    UnexpectedFlags,
+    SeqMismatch { expected: u32 },
+    AckMismatch { expected: u32 },
+}
+
+/// Which of two competing SYN-ACKs the server really sent, according to its answer to a probe.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum HijackVerdict {
+    /// The first SYN-ACK was genuine, the later one was injected.
+    FirstGenuine,
+    /// The later SYN-ACK was genuine, the first one was injected.
+    LaterGenuine,
+}
+
+/// Enrichment of a report, every part is optional.
+#[derive(Debug, Clone, Default)]
+pub struct ReportContext {
+    /// Local process owning the attacked socket.
+    pub process: Option<ProcessInfo>,
+    /// Workloads, e.g. `namespace/pod`, the flow endpoints belong to.
+    pub src_workload: Option<String>,
+    pub dst_workload: Option<String>,
+    pub tenant: Option<String>,
+    /// Earlier reports of the offender found in the reputation store, by address or by fingerprint
+    /// of the offending packet, `None` if not looked up.
+    pub prior_reports: Option<u32>,
+    /// ERSPAN session the connection was mirrored by.
+    pub erspan_session: Option<u16>,
+}
+
+impl AttackReport {
+    pub fn new(time: PrimitiveDateTime, flow: Flow, kind: AttackKind) -> Self {
+        Self{ time, flow, severity: kind.severity(), confidence: kind.confidence(), kind, context: ReportContext::default(), history: Vec::new() }
+    }
+
+    /// Source address of the offending packet, `None` where an injection has to spoof an endpoint of
+    /// the connection anyway. It may well be spoofed still.
+    pub fn offender(&self) -> Option<IpAddr> {
+        match self.kind {
+            AttackKind::HandshakeHijack { .. } => Some(self.flow.src().0),
+            AttackKind::SynAckMismatch { .. } => Some(self.flow.src().0),
+            // verdict is about a connection, not about a particular packet
+            AttackKind::HijackVerified { .. } => None,
+            // spoofed as our own bait
+            AttackKind::DecoyTripped { .. } => None,
+            // spoofed as the genuine sender
+            AttackKind::StreamOverlap { .. } => None,
+            AttackKind::Insertion { .. } => None,
+            AttackKind::UrgentDataAbuse { .. } => None,
+            // spoofed as the reset side
+            AttackKind::RstInjection { .. } => None,
+            // spoofed as the tuple's own endpoint
+            AttackKind::ConnectionReuse { .. } => None,
+            AttackKind::TtlDeviation { .. } => None,
+            AttackKind::IpIdDeviation { .. } => None,
+            AttackKind::SpoofedKeepAlive { .. } => None,
+            // spoofed as the server
+            AttackKind::HttpResponseConflict { .. } => None,
+            AttackKind::DnsResponseConflict { .. } => None,
+            // a middlebox on the path, not an endpoint
+            AttackKind::OptionTampering { .. } => None,
+            AttackKind::BlindReset { .. } => None,
+            AttackKind::WindowShrink { .. } => None,
+            // the injector is past the sensor
+            AttackKind::UnseenDataAcknowledged { .. } => None,
+            AttackKind::StreamDesync { .. } => None,
+        }
+    }
+
+    /// Server endpoint of the attacked connection.
+    pub fn server(&self) -> IpAddr {
+        match self.kind {
+            // flow of the injected SYN-ACK
+            AttackKind::HandshakeHijack { .. } => self.flow.src().0,
+            AttackKind::SynAckMismatch { .. } => self.flow.src().0,
+            // client to server flow
+            AttackKind::HijackVerified { .. } => self.flow.dst().0,
+            // flow of the injected segment, sent as the bait
+            AttackKind::DecoyTripped { .. } => self.flow.src().0,
+            // flow of the conflicting segment
+            AttackKind::StreamOverlap { sender: Side::Server, .. } => self.flow.src().0,
+            AttackKind::StreamOverlap { sender: Side::Client, .. } => self.flow.dst().0,
+            // flow of the data sent after the RST
+            AttackKind::RstInjection { sender: Side::Server, .. } => self.flow.src().0,
+            AttackKind::RstInjection { sender: Side::Client, .. } => self.flow.dst().0,
+            // flow of the SYN
+            AttackKind::ConnectionReuse { sender: Side::Server, .. } => self.flow.src().0,
+            AttackKind::ConnectionReuse { sender: Side::Client, .. } => self.flow.dst().0,
+            // flow of the deviating segment
+            AttackKind::TtlDeviation { sender: Side::Server, .. } => self.flow.src().0,
+            AttackKind::TtlDeviation { sender: Side::Client, .. } => self.flow.dst().0,
+            AttackKind::IpIdDeviation { sender: Side::Server, .. } => self.flow.src().0,
+            AttackKind::IpIdDeviation { sender: Side::Client, .. } => self.flow.dst().0,
+            // flow of the probe
+            AttackKind::SpoofedKeepAlive { sender: Side::Server, .. } => self.flow.src().0,
+            AttackKind::SpoofedKeepAlive { sender: Side::Client, .. } => self.flow.dst().0,
+            // flow of the later response
+            AttackKind::HttpResponseConflict { .. } => self.flow.src().0,
+            AttackKind::DnsResponseConflict { .. } => self.flow.src().0,
+            // flow of the tampered segment
+            AttackKind::OptionTampering { sender: Side::Server, .. } => self.flow.src().0,
+            AttackKind::OptionTampering { sender: Side::Client, .. } => self.flow.dst().0,
+            // flow of the urgent segment
+            AttackKind::UrgentDataAbuse { sender: Side::Server, .. } => self.flow.src().0,
+            AttackKind::UrgentDataAbuse { sender: Side::Client, .. } => self.flow.dst().0,
+            // flow of the latest RST
+            AttackKind::BlindReset { sender: Side::Server, .. } => self.flow.src().0,
+            AttackKind::BlindReset { sender: Side::Client, .. } => self.flow.dst().0,
+            // flow of the shrinking segment
+            AttackKind::WindowShrink { sender: Side::Server, .. } => self.flow.src().0,
+            AttackKind::WindowShrink { sender: Side::Client, .. } => self.flow.dst().0,
+            // flow of the genuine data
+            AttackKind::UnseenDataAcknowledged { sender: Side::Server, .. } => self.flow.src().0,
+            AttackKind::UnseenDataAcknowledged { sender: Side::Client, .. } => self.flow.dst().0,
+            // flow of the receiver's ACK
+            AttackKind::StreamDesync { sender: Side::Server, .. } => self.flow.dst().0,
+            AttackKind::StreamDesync { sender: Side::Client, .. } => self.flow.src().0,
+            // flow of the segment
+            AttackKind::Insertion { sender: Side::Server, .. } => self.flow.src().0,
+            AttackKind::Insertion { sender: Side::Client, .. } => self.flow.dst().0,
+        }
+    }
+}
+
+impl AttackKind {
+    /// Short stable name of the attack type.
+    pub fn name(&self) -> &'static str {
+        match self {
+            AttackKind::HandshakeHijack { .. } => "handshake_hijack",
+            AttackKind::HijackVerified { .. } => "hijack_verified",
+            AttackKind::DecoyTripped { .. } => "decoy_tripped",
+            AttackKind::StreamOverlap { .. } => "stream_overlap",
+            AttackKind::RstInjection { .. } => "rst_injection",
+            AttackKind::ConnectionReuse { .. } => "connection_reuse",
+            AttackKind::TtlDeviation { .. } => "ttl_deviation",
+            AttackKind::IpIdDeviation { .. } => "ip_id_deviation",
+            AttackKind::SpoofedKeepAlive { .. } => "spoofed_keep_alive",
+            AttackKind::UrgentDataAbuse { .. } => "urgent_data_abuse",
+            AttackKind::HttpResponseConflict { .. } => "http_response_conflict",
+            AttackKind::DnsResponseConflict { .. } => "dns_response_conflict",
+            AttackKind::OptionTampering { .. } => "option_tampering",
+            AttackKind::Insertion { .. } => "insertion",
+            AttackKind::SynAckMismatch { .. } => "syn_ack_mismatch",
+            AttackKind::BlindReset { .. } => "blind_reset",
+            AttackKind::WindowShrink { .. } => "window_shrink",
+            AttackKind::UnseenDataAcknowledged { .. } => "unseen_data_acknowledged",
+            AttackKind::StreamDesync { .. } => "stream_desync",
+        }
+    }
+
+    /// Harm done if the report is right: taken over or torn down connections weigh most.
+    pub fn severity(&self) -> Severity {
+        match self {
+            AttackKind::HandshakeHijack { .. } => Severity::High,
+            AttackKind::HijackVerified { .. } => Severity::High,
+            AttackKind::DecoyTripped { .. } => Severity::High,
+            AttackKind::RstInjection { .. } => Severity::High,
+            AttackKind::ConnectionReuse { .. } => Severity::High,
+            AttackKind::HttpResponseConflict { .. } => Severity::High,
+            AttackKind::DnsResponseConflict { .. } => Severity::High,
+            AttackKind::StreamDesync { .. } => Severity::High,
+            AttackKind::StreamOverlap { .. } => Severity::Medium,
+            AttackKind::SynAckMismatch { .. } => Severity::Medium,
+            AttackKind::BlindReset { .. } => Severity::Medium,
+            AttackKind::Insertion { .. } => Severity::Medium,
+            AttackKind::UnseenDataAcknowledged { .. } => Severity::Medium,
+            AttackKind::SpoofedKeepAlive { .. } => Severity::Medium,
+            AttackKind::UrgentDataAbuse { .. } => Severity::Medium,
+            AttackKind::TtlDeviation { .. } => Severity::Low,
+            AttackKind::IpIdDeviation { .. } => Severity::Low,
+            AttackKind::WindowShrink { .. } => Severity::Low,
+            AttackKind::OptionTampering { .. } => Severity::Low,
+        }
+    }
+
+    /// Confidence in percent, from what corroborates the detection: header fields of the offending
+    /// segment that differ from the genuine sender's, and how many bytes it changes.
+    pub fn confidence(&self) -> u8 {
+        let confidence: u32 = match self {
+            AttackKind::HandshakeHijack { first: None, .. } => 50,
+            AttackKind::HandshakeHijack { differing, .. } => 60 + differing.iter().map(|&field| field_weight(field)).sum::<u32>(),
+            AttackKind::SynAckMismatch { differing, .. } => 30 + differing.iter().map(|&field| field_weight(field)).sum::<u32>(),
+            AttackKind::HijackVerified { client_desynchronized, .. } => if *client_desynchronized { 100 } else { 95 },
+            AttackKind::DecoyTripped { .. } => 90,
+            AttackKind::RstInjection { rst_in_sequence, .. } => if *rst_in_sequence { 85 } else { 65 },
+            AttackKind::BlindReset { off_window, challenge_acks, .. } => 40 + 5 * (off_window + challenge_acks),
+            // stacks with random ISNs land behind half the time, a reconnect within milliseconds is no person's
+            AttackKind::ConnectionReuse { after_reset_ms, .. } => if *after_reset_ms < 100 { 70 } else { 50 },
+            AttackKind::Insertion { reason: InsertionReason::TtlExpires { .. }, .. } => 80,
+            AttackKind::Insertion { reason: InsertionReason::BadChecksum, .. } => 70,
+            AttackKind::Insertion { reason: InsertionReason::BeyondWindow { .. }, .. } => 50,
+            AttackKind::UnseenDataAcknowledged { .. } => 60,
+            AttackKind::StreamDesync { acks, .. } => 50 + 2 * acks,
+            // a byte or two may be a corrupted copy, a rewritten message takes more
+            AttackKind::StreamOverlap { range, .. } => match range.len() {
+                0..=3 => 30,
+                4..=63 => 60,
+                _ => 80,
+            },
+            AttackKind::TtlDeviation { ttl, typical, .. } => 30 + u32::from(ttl.abs_diff(*typical)),
+            AttackKind::WindowShrink { .. } => 40,
+            // hosts with a counter shared by many connections jump now and then
+            AttackKind::IpIdDeviation { .. } => 35,
+            AttackKind::SpoofedKeepAlive { reason: KeepAliveMismatch::Ttl { ttl, typical }, .. } => 40 + u32::from(ttl.abs_diff(*typical)),
+            AttackKind::SpoofedKeepAlive { reason: KeepAliveMismatch::Sequence { .. }, .. } => 55,
+            // urgent mode may outlast a segment, so a pointer past it isn't much by itself
+            AttackKind::UrgentDataAbuse { reason: UrgentAbuse::OutsideSegment { .. }, .. } => 40,
+            AttackKind::UrgentDataAbuse { reason: UrgentAbuse::RepeatedSingleBytes { count }, .. } => 40 + 5 * count,
+            // the same status with other headers may be a server varying them, e.g. cookies
+            AttackKind::HttpResponseConflict { first_status, second_status, .. } => if first_status != second_status { 85 } else { 65 },
+            // responses sharing some answers may come from a server whose records changed in between
+            AttackKind::OptionTampering { change: OptionChange::Vanished, .. } => 70,
+            AttackKind::OptionTampering { change: OptionChange::Unnegotiated, .. } => 60,
+            AttackKind::DnsResponseConflict { first, second, .. } => {
+                if first.answers.iter().any(|answer| second.answers.contains(answer)) { 60 } else { 85 }
+            }
+        };
+        confidence.min(100) as u8
+    }
+
+    /// Stable code of the attack type for playbooks and suppression rules to refer to.
+    /// Codes are never reused or renumbered, new types get the next free number.
+    pub fn code(&self) -> &'static str {
+        match self {
+            AttackKind::HandshakeHijack { .. } => "INJ-001",
+            AttackKind::HijackVerified { .. } => "INJ-002",
+            AttackKind::DecoyTripped { .. } => "INJ-003",
+            AttackKind::StreamOverlap { .. } => "INJ-004",
+            AttackKind::RstInjection { .. } => "INJ-005",
+            AttackKind::TtlDeviation { .. } => "INJ-006",
+            AttackKind::Insertion { .. } => "INJ-007",
+            AttackKind::SynAckMismatch { .. } => "INJ-008",
+            AttackKind::BlindReset { .. } => "INJ-009",
+            AttackKind::WindowShrink { .. } => "INJ-010",
+            AttackKind::UnseenDataAcknowledged { .. } => "INJ-011",
+            AttackKind::IpIdDeviation { .. } => "INJ-012",
+            AttackKind::SpoofedKeepAlive { .. } => "INJ-013",
+            AttackKind::UrgentDataAbuse { .. } => "INJ-014",
+            AttackKind::HttpResponseConflict { .. } => "INJ-015",
+            AttackKind::DnsResponseConflict { .. } => "INJ-016",
+            AttackKind::OptionTampering { .. } => "INJ-017",
+            AttackKind::StreamDesync { .. } => "INJ-018",
+            AttackKind::ConnectionReuse { .. } => "INJ-019",
+        }
     }
 }
 
 #[derive(Default)]
 pub struct ConsoleReporter {
-    attack_reported: bool
+    attacks_reported: u32,
 }
 
 impl AttackReporter for ConsoleReporter {
-    fn is_attack_detected(&self) -> bool {
-        self.attack_reported
+    fn attacks_reported(&self) -> u32 {
+        self.attacks_reported
+    }
+
+    fn report_attack(&mut self, report: AttackReport) {
+        self.attacks_reported += 1;
+        eprintln!("Reported attack {} #{} on the flow ({} severity, {}% confidence) on {}: {:?}",
+                  report.kind.code(), self.attacks_reported, report.severity.name(), report.confidence, report.flow, report);
+    }
+
+    fn report_anomaly(&mut self, report: AnomalyReport) {
+        eprintln!("Handshake anomaly on {}: {:?}", report.flow, report);
+    }
+}
+
+/// Passes on only the first report of each of some attack types on its connection, so that an attack
+/// repeated on every segment doesn't drown out the others.
+pub struct SuppressingReporter {
+    inner: Box<dyn AttackReporter>,
+    /// Names or codes of the types reported once, `all` for every type.
+    once: Rc<Vec<String>>,
+    /// Codes of the types reported already.
+    reported: Vec<&'static str>,
+}
+
+impl SuppressingReporter {
+    pub fn new(inner: Box<dyn AttackReporter>, once: Rc<Vec<String>>) -> Self {
+        Self{ inner, once, reported: Vec::new() }
+    }
+
+    fn reported_once(&self, kind: &AttackKind) -> bool {
+        self.once.iter().any(|once| once == "all" || once == kind.name() || once == kind.code())
+    }
+}
+
+impl AttackReporter for SuppressingReporter {
+    fn attacks_reported(&self) -> u32 {
+        self.inner.attacks_reported()
+    }
+
+    fn report_attack(&mut self, report: AttackReport) {
+        let code = report.kind.code();
+        if self.reported_once(&report.kind) {
+            if self.reported.contains(&code) {
+                return
+            }
+            self.reported.push(code);
+        }
+        self.inner.report_attack(report);
+    }
+
+    fn report_anomaly(&mut self, report: AnomalyReport) {
+        self.inner.report_anomaly(report);
+    }
+}
+
+/// Writes a line per report in a format fail2ban filters can match, passing reports on.
+///
+/// The line format is stable:
+/// `<YYYY-MM-DD HH:MM:SS> detect-inj: <attack type> from <offender ip> flow <flow> code <code>`,
+/// time is in UTC. Reports without an offender are not logged.
+/// See `contrib/fail2ban` for a matching filter.
+pub struct Fail2banReporter<W: Write> {
+    inner: Box<dyn AttackReporter>,
+    log: Rc<RefCell<W>>,
+}
+
+impl<W: Write> Fail2banReporter<W> {
+    pub fn new(inner: Box<dyn AttackReporter>, log: Rc<RefCell<W>>) -> Self {
+        Self{ inner, log }
+    }
+}
+
+impl<W: Write> AttackReporter for Fail2banReporter<W> {
+    fn attacks_reported(&self) -> u32 {
+        self.inner.attacks_reported()
     }
 
     fn report_attack(&mut self, report: AttackReport) {
-        self.attack_reported = true;
-        eprintln!("Reported attack: {:?}", report);
+        if let Some(offender) = report.offender() {
+            let line = format!("{} detect-inj: {} from {} flow {} code {}\n",
+                               report.time.format("%Y-%m-%d %H:%M:%S"), report.kind.name(), offender, report.flow,
+                               report.kind.code());
+            let mut log = self.log.borrow_mut();
+            if let Err(err) = log.write_all(line.as_bytes()).and_then(|()| log.flush()) {
+                eprintln!("Failed to write fail2ban log: {}", err);
+            }
+        }
+        self.inner.report_attack(report);
+    }
+
+    fn report_anomaly(&mut self, report: AnomalyReport) {
+        self.inner.report_anomaly(report);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::test_utils::DummyAttackReporter;
+    use time::{Date, Time};
+
+    #[test]
+    fn scores_by_corroborating_signals() {
+        let hijack = |differing| AttackKind::HandshakeHijack {
+            packet_count: 2,
+            hijack_seq: 1,
+            hijack_ack: 2,
+            first: Some(Default::default()),
+            competing: Default::default(),
+            differing,
+        };
+        assert_eq!(hijack(vec!["seq"]).severity(), Severity::High);
+        assert_eq!(hijack(vec!["seq"]).confidence(), 60);
+        assert_eq!(hijack(vec!["seq", "ttl", "options"]).confidence(), 90);
+
+        let overlap = |len| AttackKind::StreamOverlap {
+            sender: Side::Server,
+            range: WrappingRange::new(1.into(), len),
+            winner: Excerpt::default(),
+            loser: Excerpt::default(),
+        }.confidence();
+        assert!(overlap(1) < overlap(10) && overlap(10) < overlap(1000));
+        assert_eq!(AttackKind::TtlDeviation{ sender: Side::Server, seq: 1, ttl: 200, typical: 50 }.confidence(), 100);
+    }
+
+    #[test]
+    fn suppresses_repeats_of_chosen_types() {
+        let reports = Rc::new(RefCell::new(Vec::new()));
+        let once = Rc::new(vec!["ttl_deviation".to_owned(), "INJ-009".to_owned()]);
+        let mut reporter = SuppressingReporter::new(Box::new(DummyAttackReporter::new(reports.clone())), once);
+        let report = |kind| AttackReport::new(Date::try_from_ymd(2020, 3, 1).unwrap().midnight(), "1.2.3.4:443 <-> 5.6.7.8:51234".parse().unwrap(), kind);
+        for _ in 0..3 {
+            reporter.report_attack(report(AttackKind::TtlDeviation{ sender: Side::Server, seq: 1, ttl: 70, typical: 50 }));
+            reporter.report_attack(report(AttackKind::BlindReset{ sender: Side::Client, off_window: 4, challenge_acks: 0 }));
+            reporter.report_attack(report(AttackKind::WindowShrink{ sender: Side::Client, window: 0, previous_window: 100, retraction: 100 }));
+        }
+        assert_eq!(reporter.attacks_reported(), 5);
+        let codes: Vec<_> = reports.borrow().iter().map(|report| report.kind.code()).collect();
+        assert_eq!(codes, ["INJ-006", "INJ-009", "INJ-010", "INJ-010", "INJ-010"]);
+    }
+
+    #[test]
+    fn fail2ban_line() {
+        let log = Rc::new(RefCell::new(Vec::new()));
+        let mut reporter = Fail2banReporter::new(Box::new(DummyAttackReporter::new(Default::default())), log.clone());
+        reporter.report_attack(AttackReport::new(
+            Date::try_from_ymd(2020, 3, 1).unwrap().with_time(Time::try_from_hms(12, 30, 5).unwrap()),
+            "1.2.3.4:443 <-> 5.6.7.8:51234".parse().unwrap(),
+            AttackKind::HandshakeHijack { packet_count: 2, hijack_seq: 1, hijack_ack: 2, first: None, competing: Default::default(), differing: Vec::new() },
+        ));
+        assert_eq!(reporter.attacks_reported(), 1);
+        assert_eq!(
+            String::from_utf8(log.borrow().clone()).unwrap(),
+            "2020-03-01 12:30:05 detect-inj: handshake_hijack from 1.2.3.4 flow 1.2.3.4:443 <-> 5.6.7.8:51234 code INJ-001\n",
+        );
     }
 }
 
@@ -42,27 +795,33 @@ pub mod test_utils {
 
     pub struct DummyAttackReporter {
         pub reports: Rc<RefCell<Vec<AttackReport>>>,
-        attack_reported: bool,
+        pub anomalies: Rc<RefCell<Vec<AnomalyReport>>>,
+        attacks_reported: u32,
     }
 
     impl DummyAttackReporter {
         pub fn new(shared_reports_store: Rc<RefCell<Vec<AttackReport>>>) -> Self {
             Self{
                 reports: shared_reports_store,
-                attack_reported: false,
+                anomalies: Default::default(),
+                attacks_reported: 0,
             }
         }
     }
 
     impl AttackReporter for DummyAttackReporter {
-        fn is_attack_detected(&self) -> bool {
-            self.attack_reported
+        fn attacks_reported(&self) -> u32 {
+            self.attacks_reported
         }
 
         fn report_attack(&mut self, report: AttackReport) {
-            self.attack_reported = true;
+            self.attacks_reported += 1;
             self.reports.borrow_mut().push(report);
         }
+
+        fn report_anomaly(&mut self, report: AnomalyReport) {
+            self.anomalies.borrow_mut().push(report);
+        }
     }
 }
 
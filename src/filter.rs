@@ -0,0 +1,583 @@
+//! Capture filter expressions in the tcpdump syntax.
+//!
+//! A subset of the syntax is supported: `host`, `net`, `port` and `vlan` primitives, optionally
+//! qualified with `src` or `dst`, the `ip`, `ip6` and `tcp` protocols, combined with `and`,
+//! `or`, `not` (or `&&`, `||`, `!`) and parentheses. Only TCP packets ever get to the filter.
+//!
+//! A filter also compiles to a classic BPF program for capture sockets, so that the kernel drops
+//! most of the traffic not matching it before it's copied to the sensor. The program passes
+//! whatever it can't judge, e.g. tunnels, fragments and VLAN tags, and the filter is still applied
+//! to the packets parsed.
+
+use std::convert::TryFrom;
+use std::net::IpAddr;
+use std::str::FromStr;
+use std::{error, fmt};
+
+use crate::types::{Cidr, PacketManifest};
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum Endpoint {
+    Src,
+    Dst,
+    /// Either endpoint, the default.
+    Either,
+}
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum Filter {
+    And(Box<Filter>, Box<Filter>),
+    Or(Box<Filter>, Box<Filter>),
+    Not(Box<Filter>),
+    Host(Endpoint, IpAddr),
+    Net(Endpoint, Cidr),
+    Port(Endpoint, u16),
+    /// Any VLAN if no ID is given, otherwise any of the stacked tags.
+    Vlan(Option<u16>),
+    Ipv4,
+    Ipv6,
+    Tcp,
+}
+
+impl Filter {
+    pub fn matches(&self, packet: &PacketManifest) -> bool {
+        let endpoint = |side: Endpoint, matches: &dyn Fn(IpAddr, u16) -> bool| match side {
+            Endpoint::Src => matches(packet.ip.src, packet.tcp.src),
+            Endpoint::Dst => matches(packet.ip.dst, packet.tcp.dst),
+            Endpoint::Either => matches(packet.ip.src, packet.tcp.src) || matches(packet.ip.dst, packet.tcp.dst),
+        };
+        match self {
+            Filter::And(a, b) => a.matches(packet) && b.matches(packet),
+            Filter::Or(a, b) => a.matches(packet) || b.matches(packet),
+            Filter::Not(a) => !a.matches(packet),
+            Filter::Host(side, host) => endpoint(*side, &|addr, _| addr == *host),
+            Filter::Net(side, net) => endpoint(*side, &|addr, _| net.contains(addr)),
+            Filter::Port(side, port) => endpoint(*side, &|_, p| p == *port),
+            Filter::Vlan(vlan) => match vlan {
+                Some(vlan) => packet.vlans.ids().contains(vlan),
+                None => !packet.vlans.is_empty(),
+            },
+            Filter::Ipv4 => packet.ip.src.is_ipv4(),
+            Filter::Ipv6 => packet.ip.src.is_ipv6(),
+            Filter::Tcp => true,
+        }
+    }
+}
+
+/// Instruction of a classic BPF program, laid out as `struct sock_filter`.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+#[repr(C)]
+pub struct BpfInstruction {
+    pub code: u16,
+    pub jt: u8,
+    pub jf: u8,
+    pub k: u32,
+}
+
+const BPF_LD: u16 = 0x00;
+const BPF_LDX: u16 = 0x01;
+const BPF_ALU: u16 = 0x04;
+const BPF_JMP: u16 = 0x05;
+const BPF_RET: u16 = 0x06;
+const BPF_W: u16 = 0x00;
+const BPF_H: u16 = 0x08;
+const BPF_B: u16 = 0x10;
+const BPF_ABS: u16 = 0x20;
+const BPF_IND: u16 = 0x40;
+const BPF_MSH: u16 = 0xa0;
+const BPF_AND: u16 = 0x50;
+const BPF_JA: u16 = 0x00;
+const BPF_JEQ: u16 = 0x10;
+const BPF_JSET: u16 = 0x40;
+
+/// Offsets within an Ethernet frame.
+const ETHERTYPE: u32 = 12;
+const IPV4_HEADER: u32 = 14;
+const IPV4_FLAGS_FRAGMENT: u32 = 20;
+const IPV4_PROTOCOL: u32 = 23;
+const IPV4_SRC: u32 = 26;
+const IPV4_DST: u32 = 30;
+const IPV6_NEXT_HEADER: u32 = 20;
+const IPV6_SRC: u32 = 22;
+const IPV6_DST: u32 = 38;
+const IPV6_TCP: u32 = 54;
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+enum Family {
+    Ipv4,
+    Ipv6,
+}
+
+type Label = usize;
+
+/// Instructions with symbolic jump targets, resolved once the program is complete.
+enum Op {
+    Stmt(u16, u32),
+    Jump(u16, u32, Label, Label),
+    Goto(Label),
+    Place(Label),
+}
+
+#[derive(Default)]
+struct Codegen {
+    ops: Vec<Op>,
+    labels: usize,
+}
+
+impl Codegen {
+    fn label(&mut self) -> Label {
+        self.labels += 1;
+        self.labels
+    }
+
+    fn stmt(&mut self, code: u16, k: u32) {
+        self.ops.push(Op::Stmt(code, k));
+    }
+
+    fn jump(&mut self, code: u16, k: u32, t: Label, f: Label) {
+        self.ops.push(Op::Jump(code, k, t, f));
+    }
+
+    fn place(&mut self, label: Label) {
+        self.ops.push(Op::Place(label));
+    }
+
+    /// Word at `offset` equal to `value` under `mask`.
+    fn word(&mut self, offset: u32, mask: u32, value: u32, t: Label, f: Label) {
+        self.stmt(BPF_LD | BPF_W | BPF_ABS, offset);
+        if mask != u32::MAX {
+            self.stmt(BPF_ALU | BPF_AND, mask);
+        }
+        self.jump(BPF_JMP | BPF_JEQ, value & mask, t, f);
+    }
+
+    /// Address at `offset` within the network `addr/prefix_len`.
+    fn net(&mut self, offset: u32, addr: IpAddr, prefix_len: u8, t: Label, f: Label) {
+        let words: Vec<u32> = match addr {
+            IpAddr::V4(addr) => vec![u32::from(addr)],
+            IpAddr::V6(addr) => addr.octets().chunks(4).map(|word| u32::from_be_bytes([word[0], word[1], word[2], word[3]])).collect(),
+        };
+        let mut rest = u32::from(prefix_len);
+        for (i, &word) in words.iter().enumerate() {
+            if rest == 0 {
+                break
+            }
+            let mask = if rest >= 32 { u32::MAX } else { !(u32::MAX >> rest) };
+            rest = rest.saturating_sub(32);
+            let next = self.label();
+            self.word(offset + 4 * i as u32, mask, word, next, f);
+            self.place(next);
+        }
+        self.ops.push(Op::Goto(t));
+    }
+
+    /// Address of `side` within the network `addr/prefix_len`.
+    fn addr(&mut self, side: Endpoint, addr: IpAddr, prefix_len: u8, family: Family, t: Label, f: Label) {
+        let (src, dst) = match (family, addr) {
+            (Family::Ipv4, IpAddr::V4(_)) => (IPV4_SRC, IPV4_DST),
+            (Family::Ipv6, IpAddr::V6(_)) => (IPV6_SRC, IPV6_DST),
+            _ => return self.ops.push(Op::Goto(f)),
+        };
+        self.endpoint(side, t, f, |codegen, is_src, t, f| codegen.net(if is_src { src } else { dst }, addr, prefix_len, t, f));
+    }
+
+    /// Either endpoint matching, the source tried first.
+    fn endpoint(&mut self, side: Endpoint, t: Label, f: Label, mut matches: impl FnMut(&mut Self, bool, Label, Label)) {
+        match side {
+            Endpoint::Src => matches(self, true, t, f),
+            Endpoint::Dst => matches(self, false, t, f),
+            Endpoint::Either => {
+                let dst = self.label();
+                matches(self, true, t, dst);
+                self.place(dst);
+                matches(self, false, t, f);
+            }
+        }
+    }
+
+    /// Jumps to `t` if the packet, a TCP segment of `family`, matches `filter`, to `f` otherwise.
+    /// The IPv4 header length is in X.
+    fn filter(&mut self, filter: &Filter, family: Family, t: Label, f: Label) {
+        let of_family = |ipv4| if (family == Family::Ipv4) == ipv4 { t } else { f };
+        match filter {
+            Filter::And(a, b) => {
+                let next = self.label();
+                self.filter(a, family, next, f);
+                self.place(next);
+                self.filter(b, family, t, f);
+            }
+            Filter::Or(a, b) => {
+                let next = self.label();
+                self.filter(a, family, t, next);
+                self.place(next);
+                self.filter(b, family, t, f);
+            }
+            Filter::Not(a) => self.filter(a, family, f, t),
+            Filter::Host(side, host) => self.addr(*side, *host, if host.is_ipv4() { 32 } else { 128 }, family, t, f),
+            Filter::Net(side, net) => self.addr(*side, net.addr(), net.prefix_len(), family, t, f),
+            Filter::Port(side, port) => {
+                self.endpoint(*side, t, f, |codegen, is_src, t, f| {
+                    let offset = if is_src { 0 } else { 2 };
+                    match family {
+                        Family::Ipv4 => codegen.stmt(BPF_LD | BPF_H | BPF_IND, IPV4_HEADER + offset),
+                        Family::Ipv6 => codegen.stmt(BPF_LD | BPF_H | BPF_ABS, IPV6_TCP + offset),
+                    }
+                    codegen.jump(BPF_JMP | BPF_JEQ, u32::from(*port), t, f);
+                });
+            }
+            // left out of kernel programs by `Filter::kernel_superset`
+            Filter::Vlan(_) => self.ops.push(Op::Goto(t)),
+            Filter::Ipv4 => self.ops.push(Op::Goto(of_family(true))),
+            Filter::Ipv6 => self.ops.push(Op::Goto(of_family(false))),
+            Filter::Tcp => self.ops.push(Op::Goto(t)),
+        }
+    }
+
+    /// Resolves labels, `None` if a conditional jump is too long.
+    fn assemble(self) -> Option<Vec<BpfInstruction>> {
+        let mut targets = vec![0; self.labels + 1];
+        let mut at = 0;
+        for op in &self.ops {
+            match op {
+                Op::Place(label) => targets[*label] = at,
+                _ => at += 1,
+            }
+        }
+        let mut program = Vec::with_capacity(at);
+        for op in self.ops {
+            let offset = |label: Label| targets[label].checked_sub(program.len() + 1);
+            let instruction = match op {
+                Op::Stmt(code, k) => BpfInstruction{ code, jt: 0, jf: 0, k },
+                Op::Jump(code, k, t, f) => BpfInstruction {
+                    code,
+                    jt: u8::try_from(offset(t)?).ok()?,
+                    jf: u8::try_from(offset(f)?).ok()?,
+                    k,
+                },
+                Op::Goto(label) => BpfInstruction{ code: BPF_JMP | BPF_JA, jt: 0, jf: 0, k: u32::try_from(offset(label)?).ok()? },
+                Op::Place(_) => continue,
+            };
+            program.push(instruction);
+        }
+        Some(program)
+    }
+}
+
+impl Filter {
+    /// The filter without the primitives a kernel program can't judge, matching at least the
+    /// packets the filter does, and whether it matches exactly those. `None` if it matches any.
+    fn kernel_superset(&self) -> Option<(Filter, bool)> {
+        match self {
+            Filter::And(a, b) => match (a.kernel_superset(), b.kernel_superset()) {
+                (Some((a, a_exact)), Some((b, b_exact))) => Some((Filter::And(Box::new(a), Box::new(b)), a_exact && b_exact)),
+                (Some((filter, _)), None) | (None, Some((filter, _))) => Some((filter, false)),
+                (None, None) => None,
+            },
+            Filter::Or(a, b) => {
+                let ((a, a_exact), (b, b_exact)) = (a.kernel_superset()?, b.kernel_superset()?);
+                Some((Filter::Or(Box::new(a), Box::new(b)), a_exact && b_exact))
+            }
+            Filter::Not(a) => match a.kernel_superset()? {
+                (a, true) => Some((Filter::Not(Box::new(a)), true)),
+                (_, false) => None,
+            },
+            // tags are stripped off frames before socket filters see them
+            Filter::Vlan(_) => None,
+            filter => Some((filter.clone(), true)),
+        }
+    }
+
+    /// Classic BPF program for a capture socket on an Ethernet interface, passing at least the
+    /// frames the filter matches. `None` if it would pass everything or it doesn't fit the reach
+    /// of BPF jumps.
+    pub fn compile(&self) -> Option<Vec<BpfInstruction>> {
+        let (filter, _) = self.kernel_superset()?;
+        let mut codegen = Codegen::default();
+        let (ipv4, ipv6, accept, drop) = (codegen.label(), codegen.label(), codegen.label(), codegen.label());
+        codegen.stmt(BPF_LD | BPF_H | BPF_ABS, ETHERTYPE);
+        let not_ipv4 = codegen.label();
+        codegen.jump(BPF_JMP | BPF_JEQ, 0x0800, ipv4, not_ipv4);
+        codegen.place(not_ipv4);
+        codegen.jump(BPF_JMP | BPF_JEQ, 0x86dd, ipv6, accept);
+
+        // other protocols may tunnel TCP, fragments are reassembled before filtering
+        codegen.place(ipv4);
+        let (tcp, whole) = (codegen.label(), codegen.label());
+        codegen.stmt(BPF_LD | BPF_B | BPF_ABS, IPV4_PROTOCOL);
+        codegen.jump(BPF_JMP | BPF_JEQ, 6, tcp, accept);
+        codegen.place(tcp);
+        codegen.stmt(BPF_LD | BPF_H | BPF_ABS, IPV4_FLAGS_FRAGMENT);
+        codegen.jump(BPF_JMP | BPF_JSET, 0x3fff, accept, whole);
+        codegen.place(whole);
+        codegen.stmt(BPF_LDX | BPF_B | BPF_MSH, IPV4_HEADER);
+        codegen.filter(&filter, Family::Ipv4, accept, drop);
+
+        // extension headers are left to the filter on parsed packets
+        codegen.place(ipv6);
+        let tcp = codegen.label();
+        codegen.stmt(BPF_LD | BPF_B | BPF_ABS, IPV6_NEXT_HEADER);
+        codegen.jump(BPF_JMP | BPF_JEQ, 6, tcp, accept);
+        codegen.place(tcp);
+        codegen.filter(&filter, Family::Ipv6, accept, drop);
+
+        codegen.place(accept);
+        codegen.stmt(BPF_RET, u32::MAX);
+        codegen.place(drop);
+        codegen.stmt(BPF_RET, 0);
+        codegen.assemble()
+    }
+}
+
+impl FromStr for Filter {
+    type Err = ParseFilterError;
+    fn from_str(s: &str) -> Result<Self, ParseFilterError> {
+        let spaced = s.replace('(', " ( ").replace(')', " ) ").replace('!', " ! ");
+        let mut parser = Parser{ tokens: spaced.split_whitespace().collect(), at: 0 };
+        let filter = parser.or().map_err(|message| ParseFilterError(s.to_owned(), message))?;
+        if let Some(token) = parser.peek() {
+            return Err(ParseFilterError(s.to_owned(), format!("unexpected `{}`", token)))
+        }
+        Ok(filter)
+    }
+}
+
+/// Recursive descent over tokens, `not` binds tighter than `and`, which binds tighter than `or`.
+struct Parser<'s> {
+    tokens: Vec<&'s str>,
+    at: usize,
+}
+
+impl<'s> Parser<'s> {
+    fn peek(&self) -> Option<&'s str> {
+        self.tokens.get(self.at).cloned()
+    }
+
+    fn next(&mut self) -> Result<&'s str, String> {
+        let token = self.peek().ok_or("unexpected end")?;
+        self.at += 1;
+        Ok(token)
+    }
+
+    fn or(&mut self) -> Result<Filter, String> {
+        let mut filter = self.and()?;
+        while let Some("or") | Some("||") = self.peek() {
+            self.at += 1;
+            filter = Filter::Or(Box::new(filter), Box::new(self.and()?));
+        }
+        Ok(filter)
+    }
+
+    fn and(&mut self) -> Result<Filter, String> {
+        let mut filter = self.not()?;
+        while let Some("and") | Some("&&") = self.peek() {
+            self.at += 1;
+            filter = Filter::And(Box::new(filter), Box::new(self.not()?));
+        }
+        Ok(filter)
+    }
+
+    fn not(&mut self) -> Result<Filter, String> {
+        match self.peek() {
+            Some("not") | Some("!") => {
+                self.at += 1;
+                Ok(Filter::Not(Box::new(self.not()?)))
+            }
+            Some("(") => {
+                self.at += 1;
+                let filter = self.or()?;
+                match self.next()? {
+                    ")" => Ok(filter),
+                    token => Err(format!("expected `)`, found `{}`", token)),
+                }
+            }
+            _ => self.primitive(),
+        }
+    }
+
+    /// A protocol alone or qualifying the primitive after it, as in `tcp port 443`.
+    fn primitive(&mut self) -> Result<Filter, String> {
+        let protocol = match self.peek() {
+            Some("ip") => Filter::Ipv4,
+            Some("ip6") => Filter::Ipv6,
+            Some("tcp") => Filter::Tcp,
+            _ => return self.qualified(),
+        };
+        self.at += 1;
+        match self.peek() {
+            Some("src") | Some("dst") | Some("host") | Some("net") | Some("port") => {
+                let filter = self.qualified()?;
+                Ok(if protocol == Filter::Tcp { filter } else { Filter::And(Box::new(protocol), Box::new(filter)) })
+            }
+            _ => Ok(protocol),
+        }
+    }
+
+    fn qualified(&mut self) -> Result<Filter, String> {
+        let side = match self.peek() {
+            Some("src") => Endpoint::Src,
+            Some("dst") => Endpoint::Dst,
+            _ => Endpoint::Either,
+        };
+        if side != Endpoint::Either {
+            self.at += 1;
+        }
+        let token = self.next()?;
+        let value = |parser: &mut Self| parser.next().map_err(|_| format!("`{}` requires a value", token));
+        let filter = match token {
+            "host" => {
+                let host = value(self)?;
+                Filter::Host(side, host.parse().map_err(|_| format!("invalid host `{}`", host))?)
+            }
+            "net" => {
+                let net = value(self)?;
+                Filter::Net(side, net.parse().map_err(|_| format!("invalid net `{}`", net))?)
+            }
+            "port" => {
+                let port = value(self)?;
+                Filter::Port(side, port.parse().map_err(|_| format!("invalid port `{}`", port))?)
+            }
+            "vlan" if side == Endpoint::Either => match self.peek().map(str::parse) {
+                Some(Ok(vlan)) => {
+                    self.at += 1;
+                    Filter::Vlan(Some(vlan))
+                }
+                _ => Filter::Vlan(None),
+            },
+            _ => return Err(format!("unsupported `{}`", token)),
+        };
+        Ok(filter)
+    }
+}
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct ParseFilterError(String, String);
+
+impl fmt::Display for ParseFilterError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "invalid filter `{}`: {}", self.0, self.1)
+    }
+}
+
+impl error::Error for ParseFilterError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tcp_iterator::TcpIterator;
+    use crate::testing::PacketBuilder;
+    use crate::types::{Flow, LinkType};
+
+    #[test]
+    fn tcpdump_expressions() {
+        let client = ("10.0.0.5".parse().unwrap(), 40000);
+        let server = ("192.168.1.1".parse().unwrap(), 443);
+        let request = PacketBuilder::new(client, server).build(b"");
+        let response = PacketBuilder::new(server, client).vlan(7).build(b"");
+        let matches = |filter: &str, packet| filter.parse::<Filter>().unwrap().matches(packet);
+
+        assert!(matches("tcp port 443 and host 10.0.0.5", &request));
+        assert!(matches("tcp && port 443", &request));
+        assert!(matches("dst port 443", &request));
+        assert!(!matches("dst port 443", &response));
+        assert!(matches("src net 192.168.0.0/16 and vlan", &response));
+        assert!(!matches("vlan 8 or not ip", &response));
+        assert!(matches("not (port 80 or port 8080)", &request));
+        assert!(matches("!ip6", &request));
+        assert!(!matches("ip6 host 10.0.0.5", &request));
+
+        assert!("port".parse::<Filter>().is_err());
+        assert!("port 443 and".parse::<Filter>().is_err());
+        assert!("(port 443".parse::<Filter>().is_err());
+        assert!("udp".parse::<Filter>().is_err());
+        assert!("src vlan 1".parse::<Filter>().is_err());
+    }
+
+    /// Runs a program the way the kernel does, loads out of the frame drop it.
+    fn run(program: &[BpfInstruction], frame: &[u8]) -> bool {
+        let load = |offset: u32, len: usize| frame.get(offset as usize..offset as usize + len)
+            .map(|bytes| bytes.iter().fold(0, |value, &byte| value << 8 | u32::from(byte)));
+        let (mut a, mut x, mut pc) = (0, 0, 0);
+        loop {
+            let BpfInstruction{ code, jt, jf, k } = program[pc];
+            pc += 1;
+            match code {
+                0x20 => a = match load(k, 4) { Some(value) => value, None => return false },
+                0x28 => a = match load(k, 2) { Some(value) => value, None => return false },
+                0x30 => a = match load(k, 1) { Some(value) => value, None => return false },
+                0x48 => a = match load(x + k, 2) { Some(value) => value, None => return false },
+                0xb1 => x = match load(k, 1) { Some(value) => (value & 0xf) * 4, None => return false },
+                0x54 => a &= k,
+                0x05 => pc += k as usize,
+                0x15 => pc += usize::from(if a == k { jt } else { jf }),
+                0x45 => pc += usize::from(if a & k != 0 { jt } else { jf }),
+                0x06 => return k != 0,
+                _ => panic!("unexpected instruction {:#x}", code),
+            }
+        }
+    }
+
+    fn frame(src: (IpAddr, u16), dst: (IpAddr, u16), vlan: bool) -> Vec<u8> {
+        let mut frame = vec![0; 12];
+        if vlan {
+            frame.extend_from_slice(&[0x81, 0, 0, 7]);
+        }
+        match (src.0, dst.0) {
+            (IpAddr::V4(src), IpAddr::V4(dst)) => {
+                frame.extend_from_slice(&[0x08, 0, 0x45, 0, 0, 40, 0, 0, 0x40, 0, 64, 6, 0, 0]);
+                frame.extend_from_slice(&src.octets());
+                frame.extend_from_slice(&dst.octets());
+            }
+            (IpAddr::V6(src), IpAddr::V6(dst)) => {
+                frame.extend_from_slice(&[0x86, 0xdd, 0x60, 0, 0, 0, 0, 20, 6, 64]);
+                frame.extend_from_slice(&src.octets());
+                frame.extend_from_slice(&dst.octets());
+            }
+            _ => unreachable!(),
+        }
+        frame.extend_from_slice(&src.1.to_be_bytes());
+        frame.extend_from_slice(&dst.1.to_be_bytes());
+        frame.extend_from_slice(&[0, 0, 0, 1, 0, 0, 0, 0, 0x50, 0x02, 0xff, 0xff, 0, 0, 0, 0]);
+        frame
+    }
+
+    #[test]
+    fn compiles_to_kernel_programs() {
+        let (client, server) = (("10.0.0.5".parse().unwrap(), 40000), ("192.168.1.1".parse().unwrap(), 443));
+        let (client6, server6) = (("2001:db8::5".parse().unwrap(), 40000), ("2001:db8:1::1".parse().unwrap(), 443));
+        let frames = [
+            frame(client, server, false),
+            frame(server, client, false),
+            frame(client6, server6, false),
+            frame(server6, client6, false),
+            frame(client, ("10.0.0.6".parse().unwrap(), 80), false),
+        ];
+        let exact = [
+            "tcp port 443 and host 10.0.0.5",
+            "dst port 443",
+            "src net 192.168.0.0/16",
+            "net 2001:db8::/48 and not port 80",
+            "ip6 host 2001:db8::5 or ip and src port 80",
+            "not (port 80 or port 8080)",
+            "net 10.0.0.4/31",
+            "net 0.0.0.0/0 and !ip6",
+            "tcp",
+        ];
+        for expression in &exact {
+            let filter: Filter = expression.parse().unwrap();
+            let program = filter.compile().unwrap();
+            for frame in &frames {
+                let packet = TcpIterator::parse_frame(LinkType::Ethernet, frame).unwrap();
+                assert_eq!(run(&program, frame), filter.matches(&packet), "{} on {}", expression, Flow::from(&packet));
+            }
+        }
+
+        // VLAN tags are left to the filter on parsed packets
+        let filter: Filter = "port 443 and vlan 7".parse().unwrap();
+        let program = filter.compile().unwrap();
+        assert!(run(&program, &frames[0]));
+        assert!(!run(&program, &frames[4]));
+        assert!(run(&program, &frame(client, ("10.0.0.6".parse().unwrap(), 80), true)));
+        assert_eq!("not vlan".parse::<Filter>().unwrap().compile(), None);
+        assert_eq!("vlan or port 443".parse::<Filter>().unwrap().compile(), None);
+    }
+}
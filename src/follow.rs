@@ -0,0 +1,171 @@
+//! Following a directory of rotated capture files, as written by `tcpdump -G` or `-C`.
+//!
+//! Files are processed in name order, numbers within names compared by value, which is the order
+//! rotation names them in, `cap`, `cap1`, ..., `cap10` for `-C`. The newest
+//! file is still being written, so a file is only read once a later one appears. All files
+//! feed one capture source, connections carry on across file boundaries.
+
+use std::cmp::Ordering;
+use std::ffi::{OsStr, OsString};
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+use std::thread;
+use std::time::Duration;
+
+use crate::pcap;
+use crate::tcp_iterator::CaptureSource;
+use crate::types::PacketMeta;
+
+/// How often the directory is listed while waiting for the next file to complete.
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+pub struct DirectoryFollower {
+    dir: PathBuf,
+    current: Option<Box<dyn CaptureSource>>,
+    /// Name of the latest file opened, later files sort after it.
+    last: Option<OsString>,
+    /// Frame of the current file, copied out so that the file can be switched.
+    frame: Vec<u8>,
+}
+
+impl DirectoryFollower {
+    pub fn new(dir: PathBuf) -> io::Result<Self> {
+        if !fs::metadata(&dir)?.is_dir() {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("{} is not a directory", dir.display())))
+        }
+        Ok(Self{ dir, current: None, last: None, frame: Vec::new() })
+    }
+
+    /// Oldest file not read yet, if a newer one shows it's complete.
+    fn next_complete(&self) -> io::Result<Option<OsString>> {
+        let mut names = Vec::new();
+        for entry in fs::read_dir(&self.dir)? {
+            let entry = entry?;
+            if entry.file_type()?.is_file() {
+                names.push(entry.file_name());
+            }
+        }
+        names.retain(|name| self.last.as_ref().is_none_or(|last| natural_cmp(name, last) == Ordering::Greater));
+        names.sort_by(|a, b| natural_cmp(a, b));
+        Ok(if names.len() >= 2 { names.into_iter().next() } else { None })
+    }
+}
+
+/// Compares names with runs of digits as numbers, so that `cap2` comes before `cap10`.
+fn natural_cmp(a: &OsStr, b: &OsStr) -> Ordering {
+    let (a, b) = (a.to_string_lossy(), b.to_string_lossy());
+    let (mut a, mut b) = (a.as_bytes(), b.as_bytes());
+    loop {
+        let ordering = match (a.first(), b.first()) {
+            (None, None) => return Ordering::Equal,
+            (None, Some(_)) => return Ordering::Less,
+            (Some(_), None) => return Ordering::Greater,
+            (Some(x), Some(y)) if x.is_ascii_digit() && y.is_ascii_digit() => {
+                let (x, rest_a) = split_digits(a);
+                let (y, rest_b) = split_digits(b);
+                a = rest_a;
+                b = rest_b;
+                // leading zeros don't change the value but still tell names apart
+                let (value_x, value_y) = (trim_zeros(x), trim_zeros(y));
+                value_x.len().cmp(&value_y.len()).then(value_x.cmp(value_y)).then(x.len().cmp(&y.len()))
+            }
+            (Some(x), Some(y)) => {
+                a = &a[1..];
+                b = &b[1..];
+                x.cmp(y)
+            }
+        };
+        if ordering != Ordering::Equal {
+            return ordering
+        }
+    }
+}
+
+fn split_digits(s: &[u8]) -> (&[u8], &[u8]) {
+    s.split_at(s.iter().position(|c| !c.is_ascii_digit()).unwrap_or(s.len()))
+}
+
+fn trim_zeros(digits: &[u8]) -> &[u8] {
+    &digits[digits.iter().position(|&c| c != b'0').unwrap_or(digits.len())..]
+}
+
+impl CaptureSource for DirectoryFollower {
+    /// Waits for files to complete, the directory is never exhausted.
+    fn next_frame(&mut self) -> io::Result<Option<(PacketMeta, &[u8])>> {
+        loop {
+            let current = match &mut self.current {
+                Some(current) => current,
+                None => {
+                    match self.next_complete()? {
+                        Some(name) => {
+                            let path = self.dir.join(&name);
+                            self.last = Some(name);
+                            match pcap::open(&path) {
+                                Ok(source) => self.current = Some(source),
+                                Err(err) => eprintln!("Skipping capture file {}: {}", path.display(), err),
+                            }
+                        }
+                        None => thread::sleep(POLL_INTERVAL),
+                    }
+                    continue
+                }
+            };
+            match current.next_frame() {
+                Ok(Some((meta, frame))) => {
+                    self.frame.clear();
+                    self.frame.extend_from_slice(frame);
+                    return Ok(Some((meta, &self.frame)))
+                }
+                Ok(None) => self.current = None,
+                // a damaged file shouldn't stop the sensor, the next one is likely fine
+                Err(err) => {
+                    eprintln!("Skipping rest of capture file: {}", err);
+                    self.current = None;
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_completed_files_in_order() {
+        let dir = std::env::temp_dir().join(format!("detect-inj-follow-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let pcap = |frames: &[&[u8]]| {
+            let mut file = vec![0xd4, 0xc3, 0xb2, 0xa1, 2, 0, 4, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0xff, 0xff, 0, 0, 1, 0, 0, 0];
+            for frame in frames {
+                file.extend_from_slice(&[0; 8]);
+                file.extend_from_slice(&(frame.len() as u32).to_le_bytes());
+                file.extend_from_slice(&(frame.len() as u32).to_le_bytes());
+                file.extend_from_slice(frame);
+            }
+            file
+        };
+        fs::write(dir.join("capture-02.pcap"), pcap(&[b"second"])).unwrap();
+        fs::write(dir.join("capture-01.pcap"), pcap(&[b"first", b"more"])).unwrap();
+        fs::write(dir.join("capture-03.pcap"), b"being written").unwrap();
+
+        let mut follower = DirectoryFollower::new(dir.clone()).unwrap();
+        for expected in &[&b"first"[..], b"more", b"second"] {
+            assert_eq!(follower.next_frame().unwrap().unwrap().1, *expected);
+        }
+        // the newest file is left alone until another one shows up
+        assert_eq!(follower.next_complete().unwrap(), None);
+        fs::write(dir.join("capture-04.pcap"), pcap(&[])).unwrap();
+        assert_eq!(follower.next_complete().unwrap(), Some("capture-03.pcap".into()));
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn orders_numbered_rotations() {
+        let mut names: Vec<OsString> = ["cap10", "cap2", "cap", "cap1", "cap-2020-03-01", "cap-2020-02-29"].iter().map(Into::into).collect();
+        names.sort_by(|a, b| natural_cmp(a, b));
+        assert_eq!(names, ["cap", "cap-2020-02-29", "cap-2020-03-01", "cap1", "cap2", "cap10"]);
+        assert_eq!(natural_cmp("cap01".as_ref(), "cap1".as_ref()), Ordering::Greater);
+    }
+}
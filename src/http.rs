@@ -0,0 +1,111 @@
+//! Spotting two different HTTP responses to the same request.
+//!
+//! A quantum insert races the genuine response with a forged one starting at the same position
+//! of the server's stream. Stream overlaps catch it when the sensor sees both segments in full,
+//! comparing response heads catches it when drops or an exhausted history hide the overlap.
+
+use std::collections::hash_map::DefaultHasher;
+use std::collections::VecDeque;
+use std::hash::{Hash, Hasher};
+
+use crate::types::{Sequence, WrappingRange};
+
+/// Response heads remembered per connection, pipelined or keep-alive responses make several.
+const MAX_RESPONSES: usize = 8;
+/// Heads longer than this aren't looked for the end of.
+const MAX_HEAD_LEN: usize = 8 << 10;
+
+/// Status line and headers of a response starting within a segment.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct ResponseHead {
+    pub status: u16,
+    pub content_length: Option<u32>,
+    /// Bytes up to and including the empty line ending the headers.
+    pub head_len: u32,
+    digest: u64,
+}
+
+impl ResponseHead {
+    /// Parses the head a segment starts with, `None` if it doesn't start with a complete one.
+    pub fn parse(payload: &[u8]) -> Option<Self> {
+        if !payload.starts_with(b"HTTP/1.") {
+            return None
+        }
+        let searched = &payload[..payload.len().min(MAX_HEAD_LEN)];
+        let head_len = searched.windows(4).position(|window| window == b"\r\n\r\n")? + 4;
+        let head = std::str::from_utf8(&payload[..head_len]).ok()?;
+        let mut lines = head.split("\r\n");
+        let status = lines.next()?.split(' ').nth(1)?.parse().ok()?;
+        let content_length = lines
+            .filter_map(|line| line.split_once(':'))
+            .find(|(name, _)| name.trim().eq_ignore_ascii_case("content-length"))
+            .and_then(|(_, value)| value.trim().parse().ok());
+        let mut hasher = DefaultHasher::new();
+        head.hash(&mut hasher);
+        Some(Self{ status, content_length, head_len: head_len as u32, digest: hasher.finish() })
+    }
+
+    /// Stream range of the response starting at `seq`, the head only if its body length is unknown.
+    /// The length comes from the server, so it stops at the largest range there is.
+    fn range(&self, seq: Sequence) -> WrappingRange {
+        WrappingRange::new(seq, self.head_len.saturating_add(self.content_length.unwrap_or(0)))
+    }
+}
+
+/// Response heads seen on one direction of a connection.
+#[derive(Debug, Clone, Default)]
+pub struct HttpResponses {
+    responses: VecDeque<(Sequence, ResponseHead)>,
+}
+
+impl HttpResponses {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Notes a response starting at `seq`. Returns the head of an earlier, different response the
+    /// new one starts within, which no server sends, along with the new one's.
+    pub fn observe(&mut self, seq: Sequence, payload: &[u8]) -> Option<(ResponseHead, ResponseHead)> {
+        let head = ResponseHead::parse(payload)?;
+        let conflicting = self.responses.iter()
+            .find(|(start, earlier)| (*start == seq || earlier.range(*start).contains(seq)) && earlier.digest != head.digest)
+            .map(|&(_, earlier)| earlier);
+        if conflicting.is_none() && !self.responses.iter().any(|&(start, _)| start == seq) {
+            if self.responses.len() == MAX_RESPONSES {
+                self.responses.pop_front();
+            }
+            self.responses.push_back((seq, head));
+        }
+        conflicting.map(|earlier| (earlier, head))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn conflicting_responses_at_one_position() {
+        let mut responses = HttpResponses::new();
+        let genuine = b"HTTP/1.1 200 OK\r\nContent-Length: 5\r\n\r\nhello";
+        let start = Sequence::from(1000);
+        assert_eq!(responses.observe(start, genuine), None);
+        // retransmitted, and the next response of a keep-alive connection
+        assert_eq!(responses.observe(start, genuine), None);
+        let next = start + genuine.len() as u32;
+        assert_eq!(responses.observe(next, b"HTTP/1.1 304 Not Modified\r\n\r\n"), None);
+
+        let (first, second) = responses.observe(start, b"HTTP/1.1 302 Found\r\nLocation: http://x/\r\n\r\n").unwrap();
+        assert_eq!((first.status, first.content_length), (200, Some(5)));
+        assert_eq!((second.status, second.content_length), (302, None));
+        // a head cut short isn't taken for a response
+        assert_eq!(responses.observe(next, b"HTTP/1.1 302 Found\r\nLoc"), None);
+        assert_eq!(ResponseHead::parse(b"GET / HTTP/1.1\r\n\r\n"), None);
+
+        // a body as long as the sequence space runs to its end
+        let huge = b"HTTP/1.1 200 OK\r\nContent-Length: 4294967295\r\n\r\n";
+        let mut responses = HttpResponses::new();
+        assert_eq!(responses.observe(start, huge), None);
+        assert!(responses.observe(start + 100, b"HTTP/1.1 200 OK\r\n\r\n").is_some());
+    }
+}
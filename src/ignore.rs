@@ -0,0 +1,110 @@
+//! Flows left out of analysis, e.g. of monitoring appliances and health checks.
+//!
+//! Ignored packets are dropped before connection tracking, so they take no memory and raise no reports.
+
+use std::{error, fmt};
+use std::str::FromStr;
+
+use crate::types::{Cidr, Flow, PacketManifest};
+
+/// Traffic to ignore, parsed from `<cidr>`, `port:<port>` or a flow like `10.0.0.5:80 <-> 10.1.0.9:51000`.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum IgnoreRule {
+    /// Either endpoint is within the network.
+    Network(Cidr),
+    /// Either endpoint uses the port.
+    Port(u16),
+    /// Both directions of the flow, VLAN tags and tunnel included.
+    Flow(Flow),
+}
+
+impl IgnoreRule {
+    pub fn matches(&self, packet: &PacketManifest) -> bool {
+        match self {
+            IgnoreRule::Network(network) => network.contains(packet.ip.src) || network.contains(packet.ip.dst),
+            IgnoreRule::Port(port) => packet.tcp.src == *port || packet.tcp.dst == *port,
+            IgnoreRule::Flow(flow) => {
+                let packet_flow = Flow::from(packet);
+                packet_flow == *flow || packet_flow == flow.reverse()
+            }
+        }
+    }
+}
+
+impl FromStr for IgnoreRule {
+    type Err = ParseIgnoreError;
+    fn from_str(s: &str) -> Result<Self, ParseIgnoreError> {
+        let err = || ParseIgnoreError(s.to_owned());
+        let s = s.trim();
+        if let Some(port) = s.strip_prefix("port:") {
+            Ok(IgnoreRule::Port(port.parse().map_err(|_| err())?))
+        } else if s.contains("<->") {
+            Ok(IgnoreRule::Flow(s.parse().map_err(|_| err())?))
+        } else {
+            Ok(IgnoreRule::Network(s.parse().map_err(|_| err())?))
+        }
+    }
+}
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct ParseIgnoreError(String);
+
+impl fmt::Display for ParseIgnoreError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "invalid ignore rule `{}`, expected `<cidr>`, `port:<port>` or `<addr>:<port> <-> <addr>:<port>`", self.0)
+    }
+}
+
+impl error::Error for ParseIgnoreError {}
+
+#[derive(Debug, Clone, Default)]
+pub struct IgnoreList {
+    rules: Vec<IgnoreRule>,
+    ignored: u64,
+}
+
+impl IgnoreList {
+    pub fn new(rules: Vec<IgnoreRule>) -> Self {
+        Self{ rules, ignored: 0 }
+    }
+
+    /// Whether any rule matches the packet, it should be dropped then.
+    pub fn is_ignored(&mut self, packet: &PacketManifest) -> bool {
+        let ignored = self.rules.iter().any(|rule| rule.matches(packet));
+        if ignored {
+            self.ignored += 1;
+        }
+        ignored
+    }
+
+    /// Packets ignored so far.
+    pub fn ignored(&self) -> u64 {
+        self.ignored
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::PacketBuilder;
+
+    #[test]
+    fn ignores_matching_packets() {
+        let mut ignore = IgnoreList::new(vec![
+            "10.9.0.0/16".parse().unwrap(),
+            "port:9100".parse().unwrap(),
+            "192.168.1.1:443 <-> 10.0.0.5:40000".parse().unwrap(),
+        ]);
+        let client = ("10.0.0.5".parse().unwrap(), 40000);
+        let server = ("192.168.1.1".parse().unwrap(), 443);
+        assert!(ignore.is_ignored(&PacketBuilder::new(client, server).build(&[])));
+        assert!(ignore.is_ignored(&PacketBuilder::new(server, client).build(&[])));
+        assert!(!ignore.is_ignored(&PacketBuilder::new((client.0, 40001), server).build(&[])));
+        assert!(ignore.is_ignored(&PacketBuilder::new(("10.9.3.4".parse().unwrap(), 1), server).build(&[])));
+        assert!(ignore.is_ignored(&PacketBuilder::new((client.0, 40001), (server.0, 9100)).build(&[])));
+        assert_eq!(ignore.ignored(), 4);
+
+        assert!("port:http".parse::<IgnoreRule>().is_err());
+        assert!("10.0.0.5:80 <->".parse::<IgnoreRule>().is_err());
+    }
+}
@@ -0,0 +1,265 @@
+//! Minimal IPFIX (RFC 7011) exporter of observed flows.
+use std::io;
+use std::net::{IpAddr, SocketAddr, UdpSocket};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::types::Flow;
+
+/// Set in [`FlowRecord::anomalies`] if an attack was reported on the connection.
+pub const ANOMALY_ATTACK_REPORTED: u32 = 1;
+
+/// Enterprise-specific element carrying anomaly flags, under the enterprise number the exporter is
+/// configured with. No standard element fits them, they aren't exported without a number.
+const IE_ANOMALY_FLAGS: u16 = 1;
+
+const VERSION: u16 = 10;
+const TEMPLATE_SET_ID: u16 = 2;
+const TEMPLATE_ID_V4: u16 = 256;
+const TEMPLATE_ID_V6: u16 = 257;
+/// Templates are resent every so many messages, collectors may have missed them (it's UDP).
+const TEMPLATE_REFRESH_MESSAGES: u32 = 20;
+/// Keeps messages below a typical MTU.
+const RECORDS_PER_MESSAGE: usize = 16;
+
+/// (information element id, length) pairs common to both templates, following the addresses.
+const COMMON_FIELDS: [(u16, u16); 10] = [
+    (7, 2),    // sourceTransportPort
+    (11, 2),   // destinationTransportPort
+    (4, 1),    // protocolIdentifier
+    (6, 1),    // tcpControlBits
+    (85, 8),   // octetTotalCount
+    (86, 8),   // packetTotalCount
+    (152, 8),  // flowStartMilliseconds
+    (153, 8),  // flowEndMilliseconds
+    (58, 2),   // vlanId
+    (136, 1),  // flowEndReason
+];
+
+/// Why a flow's record is exported, the flowEndReason values of RFC 5102.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum FlowEndReason {
+    /// No packets were seen for the idle timeout.
+    IdleTimeout = 1,
+    /// The flow goes on, its record is exported periodically.
+    ActiveTimeout = 2,
+    /// The connection was reset.
+    EndOfFlow = 3,
+    /// Tracking stopped for all flows, e.g. capture was paused or the capture file ended.
+    ForcedEnd = 4,
+    /// Evicted to make room in a full table.
+    LackOfResources = 5,
+}
+
+/// Bidirectional flow summary, directed from client to server.
+#[derive(Debug, Clone)]
+pub struct FlowRecord {
+    pub flow: Flow,
+    pub start: SystemTime,
+    pub end: SystemTime,
+    pub packets: u64,
+    pub octets: u64,
+    /// Union of TCP flags seen in both directions.
+    pub tcp_flags: u8,
+    pub anomalies: u32,
+    pub end_reason: FlowEndReason,
+}
+
+pub struct IpfixExporter {
+    socket: UdpSocket,
+    observation_domain: u32,
+    /// Private enterprise number anomaly flags are exported under, if any.
+    enterprise_number: Option<u32>,
+    sequence: u32,
+    messages_since_templates: u32,
+}
+
+impl IpfixExporter {
+    pub fn connect(collector: SocketAddr, observation_domain: u32, enterprise_number: Option<u32>) -> io::Result<Self> {
+        let bind_addr: SocketAddr = match collector {
+            SocketAddr::V4(_) => ([0, 0, 0, 0], 0).into(),
+            SocketAddr::V6(_) => ([0u16; 8], 0).into(),
+        };
+        let socket = UdpSocket::bind(bind_addr)?;
+        socket.connect(collector)?;
+        Ok(Self{ socket, observation_domain, enterprise_number, sequence: 0, messages_since_templates: 0 })
+    }
+
+    pub fn export(&mut self, records: &[FlowRecord]) -> io::Result<()> {
+        for chunk in records.chunks(RECORDS_PER_MESSAGE) {
+            let message = self.encode_message(chunk, SystemTime::now());
+            self.socket.send(&message)?;
+        }
+        Ok(())
+    }
+
+    fn encode_message(&mut self, records: &[FlowRecord], now: SystemTime) -> Vec<u8> {
+        let mut message = Vec::with_capacity(1500);
+        message.extend_from_slice(&VERSION.to_be_bytes());
+        message.extend_from_slice(&[0, 0]); // length, filled in below
+        message.extend_from_slice(&(unix_millis(now) / 1000).to_be_bytes()[4..]);
+        message.extend_from_slice(&self.sequence.to_be_bytes());
+        message.extend_from_slice(&self.observation_domain.to_be_bytes());
+
+        if self.messages_since_templates == 0 {
+            encode_templates(&mut message, self.enterprise_number);
+        }
+        for &(template_id, is_v4) in &[(TEMPLATE_ID_V4, true), (TEMPLATE_ID_V6, false)] {
+            let set: Vec<_> = records.iter()
+                .filter(|record| record.flow.src().0.is_ipv4() == is_v4)
+                .collect();
+            if set.is_empty() {
+                continue
+            }
+            let set_start = begin_set(&mut message, template_id);
+            for record in set {
+                encode_record(&mut message, record, self.enterprise_number.is_some());
+            }
+            end_set(&mut message, set_start);
+        }
+
+        let length = message.len() as u16;
+        message[2..4].copy_from_slice(&length.to_be_bytes());
+        self.sequence = self.sequence.wrapping_add(records.len() as u32);
+        self.messages_since_templates = (self.messages_since_templates + 1) % TEMPLATE_REFRESH_MESSAGES;
+        message
+    }
+}
+
+fn encode_templates(message: &mut Vec<u8>, enterprise_number: Option<u32>) {
+    let set_start = begin_set(message, TEMPLATE_SET_ID);
+    let templates: [(u16, [u16; 2], u16); 2] = [
+        (TEMPLATE_ID_V4, [8, 12], 4),    // source/destinationIPv4Address
+        (TEMPLATE_ID_V6, [27, 28], 16),  // source/destinationIPv6Address
+    ];
+    for &(template_id, address_ies, address_len) in &templates {
+        let field_count = 2 + COMMON_FIELDS.len() as u16 + enterprise_number.is_some() as u16;
+        message.extend_from_slice(&template_id.to_be_bytes());
+        message.extend_from_slice(&field_count.to_be_bytes());
+        for ie in &address_ies {
+            message.extend_from_slice(&ie.to_be_bytes());
+            message.extend_from_slice(&address_len.to_be_bytes());
+        }
+        for &(ie, len) in &COMMON_FIELDS {
+            message.extend_from_slice(&ie.to_be_bytes());
+            message.extend_from_slice(&len.to_be_bytes());
+        }
+        if let Some(enterprise_number) = enterprise_number {
+            message.extend_from_slice(&(IE_ANOMALY_FLAGS | 0x8000).to_be_bytes());
+            message.extend_from_slice(&4u16.to_be_bytes());
+            message.extend_from_slice(&enterprise_number.to_be_bytes());
+        }
+    }
+    end_set(message, set_start);
+}
+
+fn encode_record(message: &mut Vec<u8>, record: &FlowRecord, anomalies: bool) {
+    let (src, src_port) = record.flow.src();
+    let (dst, dst_port) = record.flow.dst();
+    for address in &[src, dst] {
+        match address {
+            IpAddr::V4(address) => message.extend_from_slice(&address.octets()),
+            IpAddr::V6(address) => message.extend_from_slice(&address.octets()),
+        }
+    }
+    message.extend_from_slice(&src_port.to_be_bytes());
+    message.extend_from_slice(&dst_port.to_be_bytes());
+    message.push(record.flow.protocol().number());
+    message.push(record.tcp_flags);
+    message.extend_from_slice(&record.octets.to_be_bytes());
+    message.extend_from_slice(&record.packets.to_be_bytes());
+    message.extend_from_slice(&unix_millis(record.start).to_be_bytes());
+    message.extend_from_slice(&unix_millis(record.end).to_be_bytes());
+    message.extend_from_slice(&record.flow.vlan().unwrap_or(0).to_be_bytes());
+    message.push(record.end_reason as u8);
+    if anomalies {
+        message.extend_from_slice(&record.anomalies.to_be_bytes());
+    }
+}
+
+/// Writes set header with a placeholder length, returns set offset for [`end_set`].
+fn begin_set(message: &mut Vec<u8>, set_id: u16) -> usize {
+    let set_start = message.len();
+    message.extend_from_slice(&set_id.to_be_bytes());
+    message.extend_from_slice(&[0, 0]);
+    set_start
+}
+
+fn end_set(message: &mut [u8], set_start: usize) {
+    let length = (message.len() - set_start) as u16;
+    message[set_start + 2..set_start + 4].copy_from_slice(&length.to_be_bytes());
+}
+
+fn unix_millis(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .map(|since_epoch| since_epoch.as_millis() as u64)
+        .unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::Ipv4Addr;
+    use std::time::Duration;
+
+    use crate::types::{PacketManifest, IpLayer, TcpLayer, VlanStack};
+
+    #[test]
+    fn encodes_templates_and_v4_record() {
+        let packet = PacketManifest {
+            ip: IpLayer {
+                src: Ipv4Addr::new(1, 2, 3, 4).into(),
+                dst: Ipv4Addr::new(5, 6, 7, 8).into(),
+                total_len: 40,
+                id: None,
+                ttl: 64,
+            },
+            tcp: TcpLayer { src: 51234, dst: 443, ..Default::default() },
+            tcp_payload: &[],
+            vlans: VlanStack::from(&[10][..]),
+            ethernet: None,
+            tunnel: None,
+            erspan_session: None,
+            bad_checksum: false,
+            meta: Default::default(),
+        };
+        let start = UNIX_EPOCH + Duration::from_secs(1_000);
+        let record = FlowRecord {
+            flow: Flow::from(&packet),
+            start,
+            end: start + Duration::from_millis(1_500),
+            packets: 3,
+            octets: 120,
+            tcp_flags: 0x12,
+            anomalies: ANOMALY_ATTACK_REPORTED,
+            end_reason: FlowEndReason::LackOfResources,
+        };
+        let mut exporter = IpfixExporter::connect(([127, 0, 0, 1], 4739).into(), 7, Some(32473)).unwrap();
+        let message = exporter.encode_message(std::slice::from_ref(&record), start);
+
+        let template_set_len = 4 + 2 * (4 + 13 * 4 + 4);
+        let data_set_len = 4 + 4 + 4 + 2 + 2 + 1 + 1 + 8 + 8 + 8 + 8 + 2 + 1 + 4;
+        assert_eq!(message.len(), 16 + template_set_len + data_set_len);
+        assert_eq!(&message[0..4], &[0, 10, 0, message.len() as u8]);
+        assert_eq!(&message[12..16], &7u32.to_be_bytes());
+
+        let data = &message[16 + template_set_len..];
+        assert_eq!(&data[0..2], &TEMPLATE_ID_V4.to_be_bytes());
+        assert_eq!(&data[4..12], &[1, 2, 3, 4, 5, 6, 7, 8]);
+        assert_eq!(&data[12..16], &[0xc8, 0x22, 0x01, 0xbb]);
+        assert_eq!(&data[34..42], &1_000_000u64.to_be_bytes());
+        assert_eq!(&data[42..50], &1_001_500u64.to_be_bytes());
+        assert_eq!(&data[50..52], &10u16.to_be_bytes());
+        assert_eq!(data[52], 5);
+        assert_eq!(&data[53..57], &ANOMALY_ATTACK_REPORTED.to_be_bytes());
+
+        // templates are not repeated in the next message
+        let message = exporter.encode_message(&[], start);
+        assert_eq!(message.len(), 16);
+
+        // without an enterprise number anomaly flags are left out
+        let mut exporter = IpfixExporter::connect(([127, 0, 0, 1], 4739).into(), 7, None).unwrap();
+        let message = exporter.encode_message(&[record], start);
+        assert_eq!(message.len(), 16 + template_set_len - 2 * 8 + data_set_len - 4);
+        assert!(!message.windows(4).any(|window| window == 32473u32.to_be_bytes()));
+    }
+}
@@ -0,0 +1,111 @@
+//! Resolution of pod IPs to workload names through the kube API, via `kubectl`.
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::io;
+use std::net::IpAddr;
+use std::process::Command;
+use std::rc::Rc;
+use std::time::{Duration, Instant};
+
+use crate::event::{AnomalyReport, AttackReport, AttackReporter};
+
+/// Prints `<pod ip> <namespace>/<pod name>` lines for all pods.
+const PODS_JSONPATH: &str =
+    r#"jsonpath={range .items[*]}{.status.podIP}{" "}{.metadata.namespace}{"/"}{.metadata.name}{"\n"}{end}"#;
+
+/// Pod IP to `namespace/name` map, refreshed from the cluster at most once per `max_age`.
+pub struct PodResolver {
+    max_age: Duration,
+    pods: HashMap<IpAddr, String>,
+    refreshed_at: Option<Instant>,
+}
+
+impl PodResolver {
+    pub fn new(max_age: Duration) -> Self {
+        Self{ max_age, pods: HashMap::new(), refreshed_at: None }
+    }
+
+    /// Name of the pod with the address. A miss refreshes a stale cache, pods come and go.
+    pub fn resolve(&mut self, addr: IpAddr) -> Option<String> {
+        if !self.pods.contains_key(&addr) && self.is_stale() {
+            if let Err(err) = self.refresh() {
+                eprintln!("Failed to list pods: {}", err);
+            }
+        }
+        self.pods.get(&addr).cloned()
+    }
+
+    fn is_stale(&self) -> bool {
+        self.refreshed_at.is_none_or(|refreshed_at| refreshed_at.elapsed() >= self.max_age)
+    }
+
+    fn refresh(&mut self) -> io::Result<()> {
+        // don't retry a failing kubectl on every lookup
+        self.refreshed_at = Some(Instant::now());
+        let output = Command::new("kubectl")
+            .args(["get", "pods", "--all-namespaces", "-o", PODS_JSONPATH])
+            .output()?;
+        if !output.status.success() {
+            return Err(io::Error::other(format!("kubectl exited with {}", output.status)))
+        }
+        self.pods = parse_pods(&String::from_utf8_lossy(&output.stdout));
+        Ok(())
+    }
+}
+
+fn parse_pods(listing: &str) -> HashMap<IpAddr, String> {
+    listing.lines()
+        .filter_map(|line| {
+            let mut fields = line.split_whitespace();
+            // pods without an address yet print an empty IP
+            let addr = fields.next()?.parse().ok()?;
+            let name = fields.next()?;
+            Some((addr, name.to_owned()))
+        })
+        .collect()
+}
+
+/// Attaches pod names of both flow endpoints to reports before passing them on.
+pub struct WorkloadReporter {
+    inner: Box<dyn AttackReporter>,
+    resolver: Rc<RefCell<PodResolver>>,
+}
+
+impl WorkloadReporter {
+    pub fn new(inner: Box<dyn AttackReporter>, resolver: Rc<RefCell<PodResolver>>) -> Self {
+        Self{ inner, resolver }
+    }
+}
+
+impl AttackReporter for WorkloadReporter {
+    fn attacks_reported(&self) -> u32 {
+        self.inner.attacks_reported()
+    }
+
+    fn report_attack(&mut self, mut report: AttackReport) {
+        {
+            let mut resolver = self.resolver.borrow_mut();
+            report.context.src_workload = resolver.resolve(report.flow.src().0);
+            report.context.dst_workload = resolver.resolve(report.flow.dst().0);
+        }
+        self.inner.report_attack(report);
+    }
+
+    fn report_anomaly(&mut self, report: AnomalyReport) {
+        self.inner.report_anomaly(report);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pod_listing() {
+        let pods = parse_pods("10.244.1.7 default/web-5d9c7b\n kube-system/pending-pod\n fd00::12 shop/cart-0\n");
+        assert_eq!(pods.len(), 2);
+        assert_eq!(pods[&"10.244.1.7".parse().unwrap()], "default/web-5d9c7b");
+        assert_eq!(pods[&"fd00::12".parse().unwrap()], "shop/cart-0");
+    }
+}
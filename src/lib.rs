@@ -0,0 +1,35 @@
+pub mod alert;
+pub mod carve;
+pub mod cluster;
+pub mod coalesce;
+pub mod connection_state;
+pub mod decoy;
+pub mod dedup;
+pub mod dns;
+pub mod event;
+pub mod filter;
+pub mod follow;
+pub mod http;
+pub mod ignore;
+pub mod ipfix;
+pub mod kube;
+pub mod metrics;
+pub mod pcap;
+pub mod policy;
+pub mod probe;
+pub mod process;
+pub mod reassembly;
+pub mod remote;
+pub mod replay;
+pub mod reputation;
+pub mod responder;
+#[cfg(target_os = "linux")]
+pub mod ring;
+pub mod schedule;
+#[cfg(unix)]
+pub mod signal;
+pub mod tcp_iterator;
+pub mod tenant;
+pub mod testing;
+pub mod types;
+pub mod utils;
@@ -1,45 +1,293 @@
 use std::{cmp, env, io};
-use std::convert::TryFrom;
+use std::cell::RefCell;
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::time::{Duration, Instant, SystemTime};
+use std::collections::BTreeMap;
 use std::collections::hash_map::{HashMap, Entry};
 
 use pnet::datalink::{self, NetworkInterface};
 use pnet::packet::tcp::TcpFlags;
-use tcp_iterator::{TcpIterator, Packet};
+use time::PrimitiveDateTime;
+use detect_inj::tcp_iterator::{TcpIterator, Packet, ChannelOptions};
 
-use connection_state::Connection;
-use types::Flow;
-use crate::connection_state::ConnectionOptions;
-use crate::event::ConsoleReporter;
+use detect_inj::alert::{MetaAlertReporter, MetaAlerts};
+use detect_inj::cluster::{self, CollectorClient, CollectorReporter};
+use detect_inj::connection_state::{Connection, ConnectionOptions};
+use detect_inj::coalesce::ReassemblyBudget;
+use detect_inj::tenant::Tenants;
+use detect_inj::policy::PortPolicies;
+use detect_inj::ignore::IgnoreList;
+use detect_inj::types::{Flow, HomeNetwork, PacketManifest};
+use detect_inj::event::{AttackReport, AttackReporter, ConsoleReporter, Fail2banReporter, SuppressingReporter};
+use detect_inj::reputation::{ReputationReporter, ReputationStore};
+#[cfg(target_os = "linux")]
+use detect_inj::ring::{RingCapture, RingConfig};
+use detect_inj::responder::{BlockingReporter, NftBlocker};
+use detect_inj::ipfix::{FlowEndReason, IpfixExporter};
+use detect_inj::decoy::{self, DecoyFlows};
+use detect_inj::dedup::MirrorDedup;
+use detect_inj::filter::Filter;
+use detect_inj::kube::{PodResolver, WorkloadReporter};
+use detect_inj::metrics::{EvictionReason, FlowTableMetrics};
+use detect_inj::pcap;
+use detect_inj::follow::DirectoryFollower;
+use detect_inj::remote::RemoteCapture;
+use detect_inj::replay::{Replay, ReportDiff};
+use detect_inj::probe::ProbeQueue;
+use detect_inj::process::ProcessAttributingReporter;
+use detect_inj::schedule::{DetectorSwitches, Schedule, ScheduleTarget};
+use detect_inj::signal;
+use crate::options::{Options, USAGE};
 
-mod connection_state;
-mod event;
-mod tcp_iterator;
-mod types;
-mod utils;
+mod options;
+
+/// How often flow table metrics are printed.
+const METRICS_INTERVAL: Duration = Duration::from_secs(60);
+/// How often records of live connections are exported to IPFIX collector.
+const IPFIX_ACTIVE_TIMEOUT: Duration = Duration::from_secs(60);
+/// How often offenders recorded meanwhile are saved to the reputation store.
+const REPUTATION_SAVE_INTERVAL: Duration = Duration::from_secs(30);
+/// How long the pod list is trusted before a lookup miss fetches it again.
+const POD_CACHE_MAX_AGE: Duration = Duration::from_secs(30);
+/// How often schedules are checked for window transitions.
+const SCHEDULE_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+/// Share of the table evicted at once when it's full.
+const LRU_EVICTION_SHARE: usize = 64;
+/// How often, in capture time, reset and idle connections are looked for.
+const EXPIRY_INTERVAL: Duration = Duration::from_secs(10);
 
 fn main() -> io::Result<()> {
-    let interface_name = env::args().nth(1).expect("interface not given");
-    let interface_names_match =
-        |iface: &&NetworkInterface| iface.name == interface_name;
-
-    // Find the network interface with the provided name
-    let interfaces = datalink::interfaces();
-    let interface = interfaces.iter()
-        .find(interface_names_match);
-
-    let interface = match interface {
-        Some(iface) => iface,
-        None => {
-            eprintln!("Interface is not found. Here's list of available: {:?}", interfaces);
+    let options = match Options::from_args(env::args().skip(1)) {
+        Ok(options) => options,
+        Err(err) => {
+            eprintln!("{}\n\n{}", err, USAGE);
             return Err(io::ErrorKind::InvalidInput.into())
         }
     };
+    if let Some(listen) = options.collect {
+        return cluster::run_collector(listen)
+    }
+    if let Some((a, b)) = &options.compare {
+        return compare(&options, a, b)
+    }
+    let detectors = Detectors::new(&options);
+    let report_once = Rc::new(options.report_once.clone());
+    let blocker = match options.block_ttl {
+        Some(ttl) => {
+            let allowlist = options.block_allowlist.iter().chain(&options.home_networks).cloned().collect();
+            let blocker = NftBlocker::new(ttl, allowlist);
+            blocker.setup()?;
+            Some(Rc::new(RefCell::new(blocker)))
+        }
+        None => None,
+    };
+    let fail2ban_log = match &options.fail2ban_log {
+        Some(path) => Some(Rc::new(RefCell::new(OpenOptions::new().create(true).append(true).open(path)?))),
+        None => None,
+    };
+    let pod_resolver = if options.kube { Some(Rc::new(RefCell::new(PodResolver::new(POD_CACHE_MAX_AGE)))) } else { None };
+    let meta_alerts = options.meta_alerts.map(|config| Rc::new(RefCell::new(MetaAlerts::new(config))));
+    let collector = options.collector.map(|collector| {
+        let sensor_id = options.sensor_id.clone().unwrap_or_else(default_sensor_id);
+        let client = CollectorClient::new(collector, sensor_id);
+        client.spawn_heartbeats();
+        client
+    });
+    let reputation = match &options.reputation {
+        Some(path) => {
+            let mut store = ReputationStore::open(path.clone())?;
+            for import in &options.reputation_imports {
+                let count = store.import(import)?;
+                eprintln!("Imported {} offenders from {}", count, import.display());
+            }
+            store.save()?;
+            Some(Rc::new(RefCell::new(store)))
+        }
+        None => None,
+    };
+    if let Some(bait) = options.bait {
+        decoy::spawn_bait(bait)?;
+    }
+    let schedule = Schedule::new(options.schedule.clone());
+    let mut capturing = true;
+    let offline = options.read.is_some() || options.follow.is_some();
+    let mut schedule_checked_at: Option<SystemTime> = None;
+    let decoy_flows = DecoyFlows::default();
+    decoy_flows.set_enabled(schedule.is_active(ScheduleTarget::Decoy, PrimitiveDateTime::now()));
+    if !options.decoys.is_empty() {
+        decoy::spawn_decoys(options.decoys.clone(), options.decoy_interval, decoy_flows.clone());
+    }
+    let (mut tcp_packets, iface_index) = match (&options.read, &options.follow, &options.ssh) {
+        (Some(path), _, _) => (TcpIterator::from_source(pcap::open(path)?), 0),
+        (None, Some(dir), _) => (TcpIterator::from_source(Box::new(DirectoryFollower::new(dir.clone())?)), 0),
+        (None, None, Some(destination)) => {
+            let remote = RemoteCapture::spawn(destination, &options.interface)?;
+            (TcpIterator::from_source(Box::new(remote)), 0)
+        }
+        (None, None, None) => {
+            let interface_names_match =
+                |iface: &&NetworkInterface| iface.name == options.interface;
+
+            // Find the network interface with the provided name
+            let interfaces = datalink::interfaces();
+            let interface = interfaces.iter()
+                .find(interface_names_match);
 
-    let mut tcp_packets = TcpIterator::try_from(interface)?;
+            let interface = match interface {
+                Some(iface) => iface,
+                None => {
+                    eprintln!("Interface is not found. Here's list of available: {:?}", interfaces);
+                    return Err(io::ErrorKind::InvalidInput.into())
+                }
+            };
+            let tcp_packets = match options.ring_block_timeout {
+                #[cfg(target_os = "linux")]
+                Some(block_timeout) => {
+                    let mut config = RingConfig{ block_timeout, promiscuous: options.promiscuous, ..RingConfig::default() };
+                    if let Some(buffer_size) = options.buffer_size {
+                        config.block_count = (buffer_size / config.block_size as usize).max(1) as u32;
+                    }
+                    let mut ring = RingCapture::open(interface.index, config)?;
+                    if let Some(program) = options.filter.as_ref().and_then(Filter::compile) {
+                        ring.attach_filter(&program)?;
+                    }
+                    TcpIterator::from_source(Box::new(ring))
+                }
+                #[cfg(not(target_os = "linux"))]
+                Some(_) => unreachable!("--ring is rejected off Linux"),
+                None => {
+                    let channel = ChannelOptions {
+                        snaplen: options.snaplen,
+                        promiscuous: options.promiscuous,
+                        read_timeout: options.read_timeout,
+                        netmap: options.netmap,
+                    };
+                    TcpIterator::open(interface, &channel)?
+                }
+            };
+            (tcp_packets, interface.index)
+        }
+    };
+    tcp_packets.set_forwarding(options.bridge)?;
+    if let Some(filter) = options.filter.clone() {
+        tcp_packets.set_filter(filter);
+    }
+    if let Some(policy) = options.checksum_policy {
+        tcp_packets.set_checksum_policy(policy);
+    }
+    let mut ignore = IgnoreList::new(options.ignore.clone());
+    let mut dedup = options.dedup_window.map(MirrorDedup::new);
     let mut connections: HashMap<Flow, Connection> = HashMap::new();
+    let mut metrics = FlowTableMetrics::with_capacity(options.max_connections);
+    let mut metrics_printed_at = Instant::now();
+    let mut ipfix_exporter = match options.ipfix_collector {
+        Some(collector) => Some(IpfixExporter::connect(collector, iface_index, options.ipfix_enterprise)?),
+        None => None,
+    };
+    let mut ipfix_exported_at = Instant::now();
+    let mut reputation_saved_at = Instant::now();
+    signal::handle_dump_requests()?;
+    // time of the latest packet, capture files are replayed faster than they were recorded
+    let mut capture_time = SystemTime::UNIX_EPOCH;
+    let mut expired_at = SystemTime::UNIX_EPOCH;
 
     loop {
-        match tcp_packets.next()? {
+        if metrics_printed_at.elapsed() >= METRICS_INTERVAL {
+            metrics.set_connections(connections.len());
+            let mut tenant_connections = BTreeMap::new();
+            for tenant in connections.values().filter_map(Connection::tenant) {
+                *tenant_connections.entry(tenant.to_owned()).or_insert(0) += 1;
+            }
+            metrics.set_tenant_connections(tenant_connections);
+            metrics.set_reassembly(detectors.reassembly_budget.used(), detectors.reassembly_budget.degraded());
+            metrics.set_connection_stats(connections.values().map(Connection::stats).sum());
+            eprintln!("Flow table: {}", metrics);
+            print_capture_stats(&mut tcp_packets, &ignore, dedup.as_ref());
+            metrics_printed_at = Instant::now();
+        }
+        if signal::take_dump_request() {
+            if let Err(err) = dump_connections(&connections, options.dump_file.as_deref()) {
+                eprintln!("Failed to dump the connection table: {}", err);
+            }
+        }
+        if capture_time.duration_since(expired_at).is_ok_and(|elapsed| elapsed >= EXPIRY_INTERVAL) {
+            let closed = remove_connections(&mut connections, |connection| connection.is_finished(capture_time));
+            let idle = match options.idle_timeout {
+                Some(idle_timeout) => remove_connections(&mut connections, |connection| connection.is_idle(capture_time, idle_timeout)),
+                None => Vec::new(),
+            };
+            retire(&closed, FlowEndReason::EndOfFlow, &mut ipfix_exporter);
+            retire(&idle, FlowEndReason::IdleTimeout, &mut ipfix_exporter);
+            for _ in &idle {
+                metrics.record_eviction(EvictionReason::Idle);
+            }
+            expired_at = capture_time;
+        }
+        if let Some(exporter) = &mut ipfix_exporter {
+            if ipfix_exported_at.elapsed() >= IPFIX_ACTIVE_TIMEOUT {
+                let records: Vec<_> = connections.values().map(|connection| connection.flow_record(FlowEndReason::ActiveTimeout)).collect();
+                if let Err(err) = exporter.export(&records) {
+                    eprintln!("IPFIX export failed: {}", err);
+                }
+                ipfix_exported_at = Instant::now();
+            }
+        }
+        if let Some(store) = &reputation {
+            if reputation_saved_at.elapsed() >= REPUTATION_SAVE_INTERVAL {
+                if let Err(err) = store.borrow_mut().save() {
+                    eprintln!("Failed to save reputation store: {}", err);
+                }
+                reputation_saved_at = Instant::now();
+            }
+        }
+
+        let packet = match tcp_packets.next_packet()? {
+            Some(packet) => packet,
+            None => break,
+        };
+        if let Packet::Tcp(packet) = &packet {
+            capture_time = capture_time.max(packet.meta.ts);
+        }
+        // capture files are checked against the schedule in effect when they were recorded
+        let clock = match packet {
+            _ if !offline => Some(SystemTime::now()),
+            Packet::Tcp(_) => Some(capture_time),
+            _ => None,
+        };
+        // the clock going backwards is checked again as it may have crossed a window
+        let due = clock.filter(|&clock| schedule_checked_at.is_none_or(|at| clock.duration_since(at).map_or(true, |elapsed| elapsed >= SCHEDULE_CHECK_INTERVAL)));
+        if let Some(clock) = due {
+            let now = PrimitiveDateTime::from(clock);
+            let capture = schedule.is_active(ScheduleTarget::Capture, now);
+            if capture != capturing {
+                if capture {
+                    eprintln!("Schedule: capture enabled");
+                } else {
+                    // analysis resumes with a clean table, connections would miss packets meanwhile
+                    eprintln!("Schedule: capture disabled, dropping {} connections", connections.len());
+                    if let Some(exporter) = &mut ipfix_exporter {
+                        let records: Vec<_> = connections.values().map(|connection| connection.flow_record(FlowEndReason::ForcedEnd)).collect();
+                        if let Err(err) = exporter.export(&records) {
+                            eprintln!("IPFIX export failed: {}", err);
+                        }
+                    }
+                    connections.clear();
+                }
+                capturing = capture;
+            }
+            let hijack = schedule.is_active(ScheduleTarget::Hijack, now);
+            detectors.switches.hijack.set(log_transition("hijack", detectors.switches.hijack.get(), hijack));
+            let decoy = schedule.is_active(ScheduleTarget::Decoy, now);
+            decoy_flows.set_enabled(log_transition("decoy", decoy_flows.is_enabled(), decoy));
+            schedule_checked_at = Some(clock);
+        }
+        match packet {
+            Packet::Tcp(_) if !capturing => {}
+            Packet::Tcp(packet) if ignore.is_ignored(&packet) => {}
+            Packet::Tcp(packet) if dedup.as_mut().is_some_and(|dedup| dedup.is_duplicate(&packet)) => {}
             Packet::Tcp(packet) => {
 //                println!("Got TCP packet \n\
 //                         \t ethernet: src={e_src}, dst={e_dst}\n\
@@ -55,22 +303,288 @@ fn main() -> io::Result<()> {
 //                         rst= packet.tcp.get_flags() & TcpFlags::RST != 0,
 //                         fin= packet.tcp.get_flags() & TcpFlags::FIN != 0);
                 let flow = cmp::min(Flow::from(&packet), Flow::from(&packet).reverse());
-                match connections.entry(flow) {
-                    Entry::Occupied(mut connection) => {
-                        connection.get_mut().receive_packet(packet);
+                let decoy_report = decoy_flows.inspect(&packet);
+                if let Some(max_connections) = options.max_connections {
+                    if connections.len() >= max_connections && !connections.contains_key(&flow) {
+                        // a batch at once, so that a scan doesn't cost a pass over the table per packet
+                        let evicted = remove_least_recent(&mut connections, max_connections / LRU_EVICTION_SHARE + 1);
+                        retire(&evicted, FlowEndReason::LackOfResources, &mut ipfix_exporter);
+                        for _ in &evicted {
+                            metrics.record_eviction(EvictionReason::Lru);
+                        }
+                    }
+                }
+                let connection = match connections.entry(flow) {
+                    Entry::Occupied(connection) => {
+                        let connection = connection.into_mut();
+                        connection.receive_packet(packet);
+                        connection
                     }
                     Entry::Vacant(new_connection) => {
-                        println!("New connection: {:?}", flow);
-                        let options = ConnectionOptions {
-                            attack_reporter: Box::new(ConsoleReporter::default()),
-                            skip_hijack_detection_count: 1000,
-                        };
-                        new_connection.insert(Connection::from_packet(packet, options));
+                        let mut attack_reporter: Box<dyn AttackReporter> = Box::new(ConsoleReporter::default());
+                        if options.local_processes {
+                            attack_reporter = Box::new(ProcessAttributingReporter::new(attack_reporter));
+                        }
+                        if let Some(resolver) = &pod_resolver {
+                            attack_reporter = Box::new(WorkloadReporter::new(attack_reporter, resolver.clone()));
+                        }
+                        if let Some(log) = &fail2ban_log {
+                            attack_reporter = Box::new(Fail2banReporter::new(attack_reporter, log.clone()));
+                        }
+                        if let Some(blocker) = &blocker {
+                            attack_reporter = Box::new(BlockingReporter::new(attack_reporter, blocker.clone(), options.block_confidence));
+                        }
+                        // outside the blocker, repeat offenders are blocked at a lower confidence
+                        if let Some(store) = &reputation {
+                            attack_reporter = Box::new(ReputationReporter::new(attack_reporter, store.clone()));
+                        }
+                        if let Some(collector) = &collector {
+                            attack_reporter = Box::new(CollectorReporter::new(attack_reporter, collector.clone()));
+                        }
+                        if let Some(meta_alerts) = &meta_alerts {
+                            attack_reporter = Box::new(MetaAlertReporter::new(attack_reporter, meta_alerts.clone()));
+                        }
+                        // outermost, suppressed reports reach none of the others
+                        if !report_once.is_empty() {
+                            attack_reporter = Box::new(SuppressingReporter::new(attack_reporter, report_once.clone()));
+                        }
+                        let options = detectors.connection_options(&options, &packet, attack_reporter);
+                        let connection = new_connection.insert(Connection::from_packet(packet, options));
+                        println!("New connection: {} ({})", flow, connection.direction());
+                        connection
                     }
+                };
+                if let Some(report) = decoy_report {
+                    connection.report_attack(report);
                 }
             }
             _ => {}
         }
+
+        if let Some(probes) = &detectors.probes {
+            for frame in probes.borrow_mut().drain(..) {
+                tcp_packets.send_frame(&frame)?;
+            }
+        }
+    }
+
+    // end of a capture file
+    metrics.set_connections(connections.len());
+    metrics.set_connection_stats(connections.values().map(Connection::stats).sum());
+    eprintln!("Flow table: {}", metrics);
+    print_capture_stats(&mut tcp_packets, &ignore, dedup.as_ref());
+    if let Some(exporter) = &mut ipfix_exporter {
+        let records: Vec<_> = connections.values().map(|connection| connection.flow_record(FlowEndReason::ForcedEnd)).collect();
+        exporter.export(&records)?;
+    }
+    if let Some(store) = &reputation {
+        store.borrow_mut().save()?;
     }
+    Ok(())
+}
 
+/// Settings the connections of one detector configuration share.
+struct Detectors {
+    home_network: Rc<HomeNetwork>,
+    tenants: Tenants,
+    port_policies: PortPolicies,
+    carve_dir: Option<Rc<PathBuf>>,
+    reassembly_budget: Rc<ReassemblyBudget>,
+    switches: Rc<DetectorSwitches>,
+    probes: Option<ProbeQueue>,
+}
+
+impl Detectors {
+    fn new(options: &Options) -> Self {
+        Self {
+            home_network: Rc::new(HomeNetwork::new(options.home_networks.clone())),
+            tenants: Tenants::new(options.tenants.clone()),
+            port_policies: PortPolicies::new(options.port_policies.clone()),
+            carve_dir: options.carve_dir.clone().map(Rc::new),
+            reassembly_budget: Rc::new(ReassemblyBudget::new(options.connection_buffer, options.reassembly_budget)),
+            switches: Rc::new(DetectorSwitches::default()),
+            probes: if options.probe { Some(ProbeQueue::default()) } else { None },
+        }
+    }
+
+    /// Options of the connection `packet` starts, reporting to `attack_reporter`.
+    fn connection_options(&self, options: &Options, packet: &PacketManifest, attack_reporter: Box<dyn AttackReporter>) -> ConnectionOptions {
+        let flow = cmp::min(Flow::from(packet), Flow::from(packet).reverse());
+        // the first packet is the client's, unless the connection started before the capture
+        let flags = packet.tcp.flags;
+        let picked_up = options.midstream && (!flags.syn || flags.ack) && !flags.fin && !flags.rst;
+        let server_port = if picked_up { cmp::min(packet.tcp.src, packet.tcp.dst) } else { packet.tcp.dst };
+        let policy = self.port_policies.policy_for(server_port);
+        ConnectionOptions {
+            attack_reporter,
+            skip_hijack_detection_count: if policy.strict { u64::MAX } else { 1000 },
+            // strict mode looks all through the connection whatever the window
+            hijack_detection_period: if policy.strict { None } else { options.hijack_window },
+            home_network: self.home_network.clone(),
+            probes: self.probes.clone(),
+            tenant: self.tenants.tenant_of(&flow).map(str::to_owned),
+            carve_dir: self.carve_dir.clone(),
+            switches: self.switches.clone(),
+            // nothing to compare retransmissions for
+            stream_history: if policy.overlap { options.stream_history } else { 0 },
+            reassembly_budget: self.reassembly_budget.clone(),
+            packet_history: options.packet_history,
+            history_digests: options.history_digests,
+            policy,
+            midstream: options.midstream,
+        }
+    }
+}
+
+/// Replays the capture with the options of config files `a` and `b` on top of the command line's at
+/// once, then lists the reports only one of them made and those both made.
+fn compare(options: &Options, a: &Path, b: &Path) -> io::Result<()> {
+    let config = |path: &Path| {
+        let args = env::args().skip(1).chain(["--config".to_owned(), path.to_string_lossy().into_owned()]);
+        Options::from_args(args).map_err(|err| io::Error::new(io::ErrorKind::InvalidInput, format!("{}: {}", path.display(), err)))
+    };
+    let (options_a, options_b) = (config(a)?, config(b)?);
+    let mut tcp_packets = TcpIterator::from_source(pcap::open(options.read.as_deref().expect("--compare requires --read"))?);
+    if let Some(filter) = options.filter.clone() {
+        tcp_packets.set_filter(filter);
+    }
+    if let Some(policy) = options.checksum_policy {
+        tcp_packets.set_checksum_policy(policy);
+    }
+    let mut replays = [&options_a, &options_b].map(|options| {
+        (replay(options), IgnoreList::new(options.ignore.clone()), options.dedup_window.map(MirrorDedup::new))
+    });
+    while let Some(packet) = tcp_packets.next_packet()? {
+        let packet = match packet {
+            Packet::Tcp(packet) => packet,
+            _ => continue,
+        };
+        for (replay, ignore, dedup) in &mut replays {
+            if !ignore.is_ignored(&packet) && !dedup.as_mut().is_some_and(|dedup| dedup.is_duplicate(&packet)) {
+                replay.receive_packet(packet.clone());
+            }
+        }
+    }
+
+    let [(replay_a, ..), (replay_b, ..)] = replays;
+    let diff = ReportDiff::new(replay_a.into_reports(), replay_b.into_reports());
+    let describe = |report: &AttackReport| format!("{} {} on {} at {}, {}% confidence",
+        report.kind.code(), report.kind.name(), report.flow, report.time.format("%Y-%m-%d %H:%M:%S"), report.confidence);
+    for report in &diff.only_a {
+        println!("Only in {}: {}", a.display(), describe(report));
+    }
+    for report in &diff.only_b {
+        println!("Only in {}: {}", b.display(), describe(report));
+    }
+    for (report, other) in &diff.common {
+        println!("Common: {} against {}%", describe(report), other.confidence);
+    }
+    println!("{} only in {}, {} only in {}, {} common", diff.only_a.len(), a.display(), diff.only_b.len(), b.display(), diff.common.len());
+    Ok(())
+}
+
+/// Connection table replaying packets with the detectors of `options`, nothing is probed or carved.
+fn replay(options: &Options) -> Replay<impl FnMut(&PacketManifest, Box<dyn AttackReporter>) -> ConnectionOptions + '_> {
+    let detectors = Detectors{ probes: None, carve_dir: None, ..Detectors::new(options) };
+    let report_once = Rc::new(options.report_once.clone());
+    Replay::new(move |packet: &PacketManifest, mut attack_reporter: Box<dyn AttackReporter>| {
+        if !report_once.is_empty() {
+            attack_reporter = Box::new(SuppressingReporter::new(attack_reporter, report_once.clone()));
+        }
+        detectors.connection_options(options, packet, attack_reporter)
+    })
+}
+
+/// Removes the connections matching `done` from the table and hands them back.
+fn remove_connections(connections: &mut HashMap<Flow, Connection>, done: impl Fn(&Connection) -> bool) -> Vec<(Flow, Connection)> {
+    let flows: Vec<Flow> = connections.iter()
+        .filter(|(_, connection)| done(connection))
+        .map(|(flow, _)| *flow)
+        .collect();
+    flows.iter().filter_map(|flow| connections.remove_entry(flow)).collect()
+}
+
+/// Removes the `count` connections that were active least recently and hands them back.
+fn remove_least_recent(connections: &mut HashMap<Flow, Connection>, count: usize) -> Vec<(Flow, Connection)> {
+    let mut by_activity: Vec<(SystemTime, Flow)> = connections.iter()
+        .map(|(flow, connection)| (connection.last_seen(), *flow))
+        .collect();
+    if count < by_activity.len() {
+        by_activity.select_nth_unstable(count);
+        by_activity.truncate(count);
+    }
+    by_activity.iter().filter_map(|(_, flow)| connections.remove_entry(flow)).collect()
+}
+
+/// Logs connections leaving the table, e.g. `expired`, and exports their final records.
+fn retire(removed: &[(Flow, Connection)], reason: FlowEndReason, ipfix_exporter: &mut Option<IpfixExporter>) {
+    let how = match reason {
+        FlowEndReason::EndOfFlow => "reset",
+        FlowEndReason::IdleTimeout => "expired",
+        FlowEndReason::LackOfResources => "evicted, the table is full",
+        FlowEndReason::ActiveTimeout | FlowEndReason::ForcedEnd => "dropped",
+    };
+    for (flow, connection) in removed {
+        println!("Connection {}: {} ({})", how, flow, connection.stats());
+    }
+    if let (Some(exporter), false) = (ipfix_exporter, removed.is_empty()) {
+        let records: Vec<_> = removed.iter().map(|(_, connection)| connection.flow_record(reason)).collect();
+        if let Err(err) = exporter.export(&records) {
+            eprintln!("IPFIX export failed: {}", err);
+        }
+    }
+}
+
+/// Writes a line per tracked connection, the busiest first, to the file or else to standard error.
+fn dump_connections(connections: &HashMap<Flow, Connection>, path: Option<&Path>) -> io::Result<()> {
+    let mut snapshots: Vec<_> = connections.values().map(Connection::snapshot).collect();
+    snapshots.sort_by_key(|snapshot| cmp::Reverse(snapshot.packet_count));
+    let mut out: Box<dyn Write> = match path {
+        Some(path) => Box::new(BufWriter::new(File::create(path)?)),
+        None => Box::new(io::stderr().lock()),
+    };
+    writeln!(out, "Connection table: {} connections", snapshots.len())?;
+    for snapshot in &snapshots {
+        writeln!(out, "{}", snapshot)?;
+    }
+    out.flush()
+}
+
+/// Host name, sensors of a fleet usually run on different hosts.
+fn default_sensor_id() -> String {
+    fs::read_to_string("/etc/hostname")
+        .ok()
+        .and_then(|name| name.split_whitespace().next().map(str::to_owned))
+        .unwrap_or_else(|| "sensor".to_owned())
+}
+
+/// Logs a scheduled detector transition, returns the new state.
+fn log_transition(name: &str, enabled: bool, active: bool) -> bool {
+    if enabled != active {
+        eprintln!("Schedule: {} detection {}", name, if active { "enabled" } else { "disabled" });
+    }
+    active
+}
+
+/// Fragment counters stay quiet on networks without fragments.
+/// Frames dropped before reaching the sensor mean attacks may have gone unseen.
+fn print_capture_stats(tcp_packets: &mut TcpIterator, ignore: &IgnoreList, dedup: Option<&MirrorDedup>) {
+    match tcp_packets.capture_stats() {
+        Ok(Some(stats)) => eprintln!("Capture: {}", stats),
+        Ok(None) => {}
+        Err(err) => eprintln!("Failed to read capture statistics: {}", err),
+    }
+    let stats = tcp_packets.fragment_stats();
+    if stats != Default::default() {
+        eprintln!("Fragments: {}", stats);
+    }
+    if ignore.ignored() > 0 {
+        eprintln!("Ignored packets: {}", ignore.ignored());
+    }
+    if let Some(dedup) = dedup.filter(|dedup| dedup.duplicates() > 0) {
+        eprintln!("Mirrored duplicates dropped: {}", dedup.duplicates());
+    }
+    if tcp_packets.bad_checksums() > 0 {
+        eprintln!("Bad TCP checksums: {}", tcp_packets.bad_checksums());
+    }
 }
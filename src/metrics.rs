@@ -0,0 +1,132 @@
+use std::collections::BTreeMap;
+use std::fmt;
+
+use crate::connection_state::ConnectionStats;
+
+/// Reason a connection was removed from the flow table before it was closed. Memory pressure
+/// doesn't remove connections, it stops their buffering, counted as degraded connections.
+#[derive(Eq, PartialEq, Copy, Clone, Debug)]
+pub enum EvictionReason {
+    /// No packets were seen for longer than the idle timeout.
+    Idle,
+    /// Table was full and the least recently active connection made room for a new one.
+    Lru,
+}
+
+/// Occupancy gauges and eviction counters of the flow table.
+#[derive(Default, Debug, Clone)]
+pub struct FlowTableMetrics {
+    connections: usize,
+    capacity: Option<usize>,
+    evicted_idle: u64,
+    evicted_lru: u64,
+    /// Out-of-order bytes buffered by all connections.
+    buffered_bytes: usize,
+    /// Connections that stopped buffering as the reassembly budget ran out, the memory pressure
+    /// counter.
+    degraded_connections: u64,
+    /// Totals of the connections tracked.
+    segments: ConnectionStats,
+    tenant_connections: BTreeMap<String, usize>,
+}
+
+impl FlowTableMetrics {
+    pub fn with_capacity(capacity: Option<usize>) -> Self {
+        Self{ capacity, ..Default::default() }
+    }
+
+    pub fn set_connections(&mut self, connections: usize) {
+        self.connections = connections;
+    }
+
+    /// Replaces per-tenant connection counts.
+    pub fn set_tenant_connections(&mut self, tenant_connections: BTreeMap<String, usize>) {
+        self.tenant_connections = tenant_connections;
+    }
+
+    /// Updates the reassembly budget figures.
+    pub fn set_reassembly(&mut self, buffered_bytes: usize, degraded_connections: u64) {
+        self.buffered_bytes = buffered_bytes;
+        self.degraded_connections = degraded_connections;
+    }
+
+    /// Replaces the totals of the connections tracked.
+    pub fn set_connection_stats(&mut self, segments: ConnectionStats) {
+        self.segments = segments;
+    }
+
+    pub fn tenant_connections(&self, tenant: &str) -> usize {
+        self.tenant_connections.get(tenant).cloned().unwrap_or(0)
+    }
+
+    pub fn record_eviction(&mut self, reason: EvictionReason) {
+        match reason {
+            EvictionReason::Idle => self.evicted_idle += 1,
+            EvictionReason::Lru => self.evicted_lru += 1,
+        }
+    }
+
+    /// Number of currently tracked connections.
+    pub fn connections(&self) -> usize {
+        self.connections
+    }
+
+    /// Configured maximum number of tracked connections, `None` if unbounded.
+    pub fn capacity(&self) -> Option<usize> {
+        self.capacity
+    }
+
+    /// Fraction of the table in use, `None` if the table is unbounded.
+    pub fn occupancy(&self) -> Option<f64> {
+        self.capacity.map(|capacity| self.connections as f64 / capacity.max(1) as f64)
+    }
+
+    pub fn evictions(&self, reason: EvictionReason) -> u64 {
+        match reason {
+            EvictionReason::Idle => self.evicted_idle,
+            EvictionReason::Lru => self.evicted_lru,
+        }
+    }
+}
+
+impl fmt::Display for FlowTableMetrics {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.capacity {
+            Some(capacity) => write!(f, "connections={}/{}", self.connections, capacity)?,
+            None => write!(f, "connections={}", self.connections)?,
+        }
+        write!(f, " evicted_idle={} evicted_lru={}", self.evicted_idle, self.evicted_lru)?;
+        write!(f, " buffered_bytes={} degraded={}", self.buffered_bytes, self.degraded_connections)?;
+        write!(f, " retransmissions={} out_of_order={} overlaps={}",
+               self.segments.retransmissions, self.segments.out_of_order, self.segments.overlaps)?;
+        for (tenant, connections) in &self.tenant_connections {
+            write!(f, " connections[{}]={}", tenant, connections)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn occupancy_and_evictions() {
+        let mut metrics = FlowTableMetrics::with_capacity(Some(4));
+        metrics.set_connections(3);
+        metrics.record_eviction(EvictionReason::Lru);
+        metrics.record_eviction(EvictionReason::Lru);
+        metrics.record_eviction(EvictionReason::Idle);
+
+        assert_eq!(metrics.occupancy(), Some(0.75));
+        assert_eq!(metrics.evictions(EvictionReason::Lru), 2);
+        assert_eq!(metrics.evictions(EvictionReason::Idle), 1);
+        assert_eq!(metrics.to_string(),
+                   "connections=3/4 evicted_idle=1 evicted_lru=2 buffered_bytes=0 degraded=0 \
+                    retransmissions=0 out_of_order=0 overlaps=0");
+
+        metrics.set_tenant_connections(vec![("acme".to_owned(), 2), ("globex".to_owned(), 1)].into_iter().collect());
+        assert_eq!(metrics.tenant_connections("acme"), 2);
+        assert!(metrics.to_string().ends_with(" connections[acme]=2 connections[globex]=1"));
+    }
+}
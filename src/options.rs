@@ -0,0 +1,639 @@
+use std::collections::VecDeque;
+use std::fs;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use detect_inj::alert::MetaAlertConfig;
+use detect_inj::coalesce::{DEFAULT_CONNECTION_BUFFER, DEFAULT_REASSEMBLY_BUDGET, DEFAULT_STREAM_HISTORY};
+use detect_inj::connection_state::DEFAULT_PACKET_HISTORY;
+use detect_inj::dedup::DEFAULT_DEDUP_WINDOW;
+use detect_inj::filter::Filter;
+use detect_inj::schedule::ScheduleRule;
+use detect_inj::tcp_iterator::{ChecksumPolicy, DEFAULT_SNAPLEN};
+use detect_inj::tenant::TenantRule;
+use detect_inj::policy::PortPolicyRule;
+use detect_inj::responder::DEFAULT_BLOCK_CONFIDENCE;
+use detect_inj::ignore::IgnoreRule;
+use detect_inj::types::Cidr;
+
+pub const USAGE: &str = "\
+Usage: detect-inj [OPTIONS] <INTERFACE>
+       detect-inj [OPTIONS] --read <FILE>
+       tcpdump -w - | detect-inj [OPTIONS] -
+       detect-inj [OPTIONS] --follow <DIR>
+       detect-inj [OPTIONS] --ssh <[USER@]HOST> <REMOTE INTERFACE>
+
+Options:
+    --config <FILE>        read options from FILE, one `<option> [value]` per line
+                           without the leading dashes, e.g. `home-net 10.0.0.0/8`
+    --interface <NAME>     interface to capture on, same as the positional argument
+    --read <FILE>          analyze a pcap or pcapng file instead of capturing live,
+                           `-` reads it from the standard input
+    --compare <FILE> <FILE>
+                           replay the --read capture with the options of each
+                           config file on top of the others at once, and list
+                           the reports only one of them makes and the common
+                           ones
+    --follow <DIR>         analyze rotated capture files written to DIR, e.g. by
+                           `tcpdump -G`, in name order as each one completes
+    --ssh <[USER@]HOST>    capture on the interface of a remote host, streamed by
+                           tcpdump over SSH; tcpdump must be able to capture
+                           there, e.g. as root
+    --bridge               inline mode, send every captured frame back out of the
+                           interface; by default traffic is only observed
+    --backend <pnet|ring|netmap>
+                           how to capture on the interface: pnet by default,
+                           ring is the same as --ring, netmap takes the NIC
+                           over for line rate on FreeBSD and needs a build
+                           with the netmap feature
+    --ring                 capture through a TPACKET_V3 ring for high rates,
+                           Linux only; frames are observed, not forwarded;
+                           kernel drop counters are printed with the flow table
+    --ring-block-timeout <MS>
+                           hand over a partially filled ring block after this
+                           long, 64 by default
+    --buffer-size <MIB>    kernel buffer of the --ring, 64 by default; raise it
+                           when drops are reported on bursty links
+    --snaplen <BYTES>      bytes read per frame without --ring, 65535 by default;
+                           truncated payloads can't be compared for overlaps
+    --no-promisc           don't put the interface in promiscuous mode, only
+                           traffic addressed to this host is seen
+    --read-timeout <MS>    wake up after this long without frames to print
+                           statistics on quiet links, waits forever by default;
+                           frames are always delivered immediately
+    --filter <EXPRESSION>  analyze only packets matching the tcpdump style filter,
+                           e.g. `tcp port 443 and host 10.0.0.5`; supports host,
+                           net, port, vlan, ip, ip6, src, dst, and, or, not;
+                           with --ring the kernel drops most other traffic
+    --ignore <CIDR>|port:<PORT>|<FLOW>
+                           leave out packets touching the network or port, or
+                           of the flow given as in reports, e.g. of monitoring
+                           appliances or health checks; may be repeated
+    --dedup-window <MS>    drop copies of a packet captured again within this
+                           long, as SPAN ports mirroring both directions of a
+                           switch port deliver; 10 by default, 0 turns it off
+    --verify-checksums <drop|flag>
+                           verify TCP checksums and drop segments failing it,
+                           or report those carrying data or a reset; off by
+                           default as checksum offloading makes this host's
+                           own segments fail
+    --max-connections <COUNT>
+                           most connections tracked at once, the least
+                           recently active ones make room for new ones;
+                           unlimited by default
+    --idle-timeout <SECONDS>
+                           forget connections without packets for this long,
+                           in capture time; 300 by default, 0 keeps them
+    --stream-history <BYTES>
+                           delivered bytes kept per direction to compare
+                           retransmissions against, a retransmission changing
+                           them is reported; 16384 by default, 0 turns it off
+    --connection-buffer <BYTES>
+                           out-of-order bytes a connection buffers, gaps are
+                           given up on beyond it; 1048576 by default
+    --reassembly-budget <MIB>
+                           out-of-order bytes all connections buffer; beyond
+                           it connections stop buffering and comparing
+                           segments; 256 by default
+    --packet-history <PACKETS>
+                           latest packets of a connection attached to its
+                           reports; 16 by default, 0 turns it off
+    --history-digests      attach a hash of each packet's payload as well
+    --dump-file <FILE>     where the connection table is written on SIGUSR1,
+                           standard error by default; the table is dumped
+                           once the next frame arrives or --read-timeout ends
+    --ipfix <HOST:PORT>    export observed flows to an IPFIX collector
+    --ipfix-enterprise <PEN>
+                           export anomaly flags of flows as an element of this
+                           IANA private enterprise number, left out otherwise
+    --home-net <CIDR>      network considered local, may be repeated
+    --block <SECONDS>      block attack sources with nftables for this long
+    --block-confidence <PERCENT>
+                           only block on attacks detected with at least this
+                           confidence, 80 by default
+    --allow <CIDR>         never block this network, may be repeated;
+                           home networks are always allowed
+    --fail2ban-log <FILE>  append a fail2ban compatible line per attack
+    --hijack-window <SECONDS>
+                           look for hijacks this long from a connection's
+                           start instead of for its first 1000 packets
+    --midstream            learn the sequence numbers of connections picked up
+                           after their handshake from both sides' ACKs before
+                           reporting on them, taking the side on the lower port
+                           for the server; by default the first packet seen is
+                           taken for the client's and checked at once
+    --probe                send keep-alives to both endpoints of a suspected
+                           hijack to tell which SYN-ACK was genuine
+    --decoy <HOST:PORT>    periodically connect to this silent bait listener,
+                           any data or reset on the connection is an injection;
+                           may be repeated
+    --decoy-interval <SECONDS>
+                           time between decoy rounds, 300 by default
+    --bait <ADDR:PORT>     run a silent bait listener for other sensors' decoys
+    --local-processes      name the local process owning an attacked socket
+    --kube                 name pods of attacked flow endpoints using kubectl
+    --tenant <LABEL>=vlan:<ID>|<LABEL>=<CIDR>
+                           tag flows on the VLAN or touching the network with
+                           the tenant label, may be repeated, first match wins
+    --port-policy <PORTS>=<strict|http|no-<DETECTION>>,...
+                           detections run on connections to the server ports,
+                           e.g. `8080,9000-9100=no-overlap` or `22,443=strict`;
+                           detections are hijack, overlap, reset, reuse, ttl,
+                           ip-id, keep-alive, urgent, options, dns, insertion,
+                           window, unseen-ack and desync; strict looks for
+                           hijacks all through the connection; http compares
+                           responses to the same request, for ports carrying
+                           plain HTTP; may be repeated, first match wins
+    --report-once <TYPE>,...
+                           report attacks of the types only once per connection,
+                           types are names like `ttl_deviation`, codes like
+                           `INJ-006` or `all`; every attack is reported otherwise
+    --carve <DIR>          write reconstructed streams of attacked connections
+                           to DIR, both as first-wins and last-wins
+    --schedule \"<capture|hijack|decoy> <DAYS> <HH:MM>-<HH:MM>\"
+                           run capture or a detector only within the UTC window,
+                           e.g. `capture mon-fri 09:00-18:00`; days are `*`,
+                           days like `sat` or ranges like `mon-fri` separated by
+                           commas; may be repeated, any matching window enables;
+                           capture files go by the time of their packets
+    --meta-alerts          alert on attack campaigns against one server and on
+                           spikes of the report rate
+    --campaign-flows <N>   attacked flows to one server within 5 minutes making
+                           a campaign, 5 by default
+    --collector <HOST:PORT>
+                           stream reports and heartbeats to a collector
+    --sensor-id <ID>       name of this sensor at the collector, the host name
+                           by default
+    --collect <ADDR:PORT>  run as the collector of a sensor fleet instead of
+                           capturing, no interface is needed
+    --reputation <FILE>    keep offending addresses and packet fingerprints in
+                           FILE, reports from known offenders get their earlier
+                           report count and a higher confidence
+    --reputation-import <FILE>
+                           merge a reputation file exported by another sensor,
+                           may be repeated";
+
+const DEFAULT_DECOY_INTERVAL: Duration = Duration::from_secs(300);
+const DEFAULT_IDLE_TIMEOUT: Duration = Duration::from_secs(300);
+const DEFAULT_RING_BLOCK_TIMEOUT: Duration = Duration::from_millis(64);
+
+/// Command line options.
+#[derive(Debug)]
+pub struct Options {
+    pub interface: String,
+    /// Capture file to analyze instead of the interface.
+    pub read: Option<PathBuf>,
+    /// Config files whose detectors the capture file is replayed with side by side.
+    pub compare: Option<(PathBuf, PathBuf)>,
+    /// Directory of rotated capture files to follow instead of the interface.
+    pub follow: Option<PathBuf>,
+    /// SSH destination to capture on instead of a local interface.
+    pub ssh: Option<String>,
+    /// Forward captured frames instead of only observing them.
+    pub bridge: bool,
+    pub filter: Option<Filter>,
+    pub ignore: Vec<IgnoreRule>,
+    /// Mirrored copies of a packet are dropped within the window, `None` keeps them.
+    pub dedup_window: Option<Duration>,
+    /// TCP checksums are verified if set.
+    pub checksum_policy: Option<ChecksumPolicy>,
+    /// Connections tracked at once, unlimited if `None`.
+    pub max_connections: Option<usize>,
+    /// Connections without packets for this long are dropped, kept however long if `None`.
+    pub idle_timeout: Option<Duration>,
+    /// Delivered bytes kept per direction, 0 doesn't compare retransmissions.
+    pub stream_history: usize,
+    /// Out-of-order bytes buffered per connection and by all of them, the latter given in MiB.
+    pub connection_buffer: usize,
+    pub reassembly_budget: usize,
+    /// Packets attached to reports, 0 attaches none.
+    pub packet_history: usize,
+    pub history_digests: bool,
+    /// File the connection table is dumped to on SIGUSR1, standard error if `None`.
+    pub dump_file: Option<PathBuf>,
+    /// Ring block timeout, capture goes through the ring if set.
+    pub ring_block_timeout: Option<Duration>,
+    /// Ring buffer size in bytes, the ring default if `None`.
+    pub buffer_size: Option<usize>,
+    pub snaplen: usize,
+    pub promiscuous: bool,
+    pub read_timeout: Option<Duration>,
+    /// Capture through netmap.
+    pub netmap: bool,
+    pub ipfix_collector: Option<SocketAddr>,
+    pub ipfix_enterprise: Option<u32>,
+    pub home_networks: Vec<Cidr>,
+    pub block_ttl: Option<Duration>,
+    /// Least confidence in percent of an attack its offender is blocked for.
+    pub block_confidence: u8,
+    pub block_allowlist: Vec<Cidr>,
+    pub fail2ban_log: Option<PathBuf>,
+    /// Time from a connection's start hijacks are looked for, a packet count limit if `None`.
+    pub hijack_window: Option<Duration>,
+    /// Connections picked up without their handshake are synced before they are reported on.
+    pub midstream: bool,
+    pub probe: bool,
+    pub decoys: Vec<SocketAddr>,
+    pub decoy_interval: Duration,
+    pub bait: Option<SocketAddr>,
+    pub local_processes: bool,
+    pub kube: bool,
+    pub tenants: Vec<TenantRule>,
+    pub port_policies: Vec<PortPolicyRule>,
+    /// Names or codes of attack types reported once per connection.
+    pub report_once: Vec<String>,
+    pub carve_dir: Option<PathBuf>,
+    pub schedule: Vec<ScheduleRule>,
+    /// Meta-alerts are off if `None`.
+    pub meta_alerts: Option<MetaAlertConfig>,
+    pub collector: Option<SocketAddr>,
+    pub sensor_id: Option<String>,
+    /// Address to run the collector on instead of capturing.
+    pub collect: Option<SocketAddr>,
+    pub reputation: Option<PathBuf>,
+    pub reputation_imports: Vec<PathBuf>,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Self {
+            interface: String::new(),
+            read: None,
+            compare: None,
+            follow: None,
+            ssh: None,
+            bridge: false,
+            filter: None,
+            ignore: Vec::new(),
+            dedup_window: Some(DEFAULT_DEDUP_WINDOW),
+            checksum_policy: None,
+            max_connections: None,
+            idle_timeout: Some(DEFAULT_IDLE_TIMEOUT),
+            stream_history: DEFAULT_STREAM_HISTORY,
+            connection_buffer: DEFAULT_CONNECTION_BUFFER,
+            reassembly_budget: DEFAULT_REASSEMBLY_BUDGET,
+            packet_history: DEFAULT_PACKET_HISTORY,
+            history_digests: false,
+            dump_file: None,
+            ring_block_timeout: None,
+            buffer_size: None,
+            snaplen: DEFAULT_SNAPLEN,
+            promiscuous: true,
+            read_timeout: None,
+            netmap: false,
+            ipfix_collector: None,
+            ipfix_enterprise: None,
+            home_networks: Vec::new(),
+            block_ttl: None,
+            block_confidence: DEFAULT_BLOCK_CONFIDENCE,
+            block_allowlist: Vec::new(),
+            fail2ban_log: None,
+            hijack_window: None,
+            midstream: false,
+            probe: false,
+            decoys: Vec::new(),
+            decoy_interval: DEFAULT_DECOY_INTERVAL,
+            bait: None,
+            local_processes: false,
+            kube: false,
+            tenants: Vec::new(),
+            port_policies: Vec::new(),
+            report_once: Vec::new(),
+            carve_dir: None,
+            schedule: Vec::new(),
+            meta_alerts: None,
+            collector: None,
+            sensor_id: None,
+            collect: None,
+            reputation: None,
+            reputation_imports: Vec::new(),
+        }
+    }
+}
+
+impl Options {
+    /// Parses arguments that follow the program name.
+    pub fn from_args<I: IntoIterator<Item = String>>(args: I) -> Result<Self, String> {
+        let mut options = Options::default();
+        let mut interface = None;
+        let mut args: VecDeque<String> = args.into_iter().collect();
+
+        while let Some(arg) = args.pop_front() {
+            match arg.as_str() {
+                "--ipfix" => {
+                    options.ipfix_collector = Some(socket_addr(&arg, args.pop_front())?);
+                }
+                "--ipfix-enterprise" => {
+                    let number = value(&arg, args.pop_front())?;
+                    options.ipfix_enterprise = Some(number.parse().map_err(|e| format!("invalid {} `{}`: {}", arg, number, e))?);
+                }
+                "--home-net" => {
+                    let network = value(&arg, args.pop_front())?;
+                    options.home_networks.push(network.parse().map_err(|e| format!("{}: {}", arg, e))?);
+                }
+                "--block" => {
+                    let ttl = value(&arg, args.pop_front())?;
+                    let seconds = ttl.parse().map_err(|e| format!("invalid {} duration `{}`: {}", arg, ttl, e))?;
+                    options.block_ttl = Some(Duration::from_secs(seconds));
+                }
+                "--block-confidence" => {
+                    let confidence = value(&arg, args.pop_front())?;
+                    options.block_confidence = match confidence.parse() {
+                        Ok(confidence) if confidence <= 100 => confidence,
+                        _ => return Err(format!("{} must be a percentage, got `{}`", arg, confidence)),
+                    };
+                }
+                "--allow" => {
+                    let network = value(&arg, args.pop_front())?;
+                    options.block_allowlist.push(network.parse().map_err(|e| format!("{}: {}", arg, e))?);
+                }
+                "--fail2ban-log" => options.fail2ban_log = Some(value(&arg, args.pop_front())?.into()),
+                "--hijack-window" => {
+                    let window = value(&arg, args.pop_front())?;
+                    let seconds = window.parse().map_err(|e| format!("invalid {} duration `{}`: {}", arg, window, e))?;
+                    options.hijack_window = Some(Duration::from_secs(seconds));
+                }
+                "--probe" => options.probe = true,
+                "--decoy" => options.decoys.push(socket_addr(&arg, args.pop_front())?),
+                "--decoy-interval" => {
+                    let interval = value(&arg, args.pop_front())?;
+                    let seconds = interval.parse().map_err(|e| format!("invalid {} `{}`: {}", arg, interval, e))?;
+                    options.decoy_interval = Duration::from_secs(seconds);
+                }
+                "--bait" => options.bait = Some(socket_addr(&arg, args.pop_front())?),
+                "--local-processes" => options.local_processes = true,
+                "--kube" => options.kube = true,
+                "--midstream" => options.midstream = true,
+                "--tenant" => {
+                    let tenant = value(&arg, args.pop_front())?;
+                    options.tenants.push(tenant.parse().map_err(|e| format!("{}: {}", arg, e))?);
+                }
+                "--port-policy" => {
+                    let rule = value(&arg, args.pop_front())?;
+                    options.port_policies.push(rule.parse().map_err(|e| format!("{}: {}", arg, e))?);
+                }
+                "--report-once" => {
+                    for attack_type in value(&arg, args.pop_front())?.split(',').map(str::trim) {
+                        let is_code = attack_type.strip_prefix("INJ-").is_some_and(|number| number.len() == 3 && number.bytes().all(|b| b.is_ascii_digit()));
+                        let is_name = !attack_type.is_empty() && attack_type.bytes().all(|b| b.is_ascii_lowercase() || b == b'_');
+                        if !is_code && !is_name {
+                            return Err(format!("{}: invalid attack type `{}`, expected a name, a code or `all`", arg, attack_type))
+                        }
+                        options.report_once.push(attack_type.to_owned());
+                    }
+                }
+                "--dump-file" => options.dump_file = Some(value(&arg, args.pop_front())?.into()),
+                "--carve" => options.carve_dir = Some(value(&arg, args.pop_front())?.into()),
+                "--schedule" => {
+                    let rule = value(&arg, args.pop_front())?;
+                    options.schedule.push(rule.parse().map_err(|e| format!("{}: {}", arg, e))?);
+                }
+                "--meta-alerts" => {
+                    options.meta_alerts.get_or_insert_with(MetaAlertConfig::default);
+                }
+                "--campaign-flows" => {
+                    let flows = value(&arg, args.pop_front())?;
+                    let flows = flows.parse().map_err(|e| format!("invalid {} `{}`: {}", arg, flows, e))?;
+                    options.meta_alerts.get_or_insert_with(MetaAlertConfig::default).campaign_flows = flows;
+                }
+                "--collector" => options.collector = Some(socket_addr(&arg, args.pop_front())?),
+                "--sensor-id" => {
+                    let id = value(&arg, args.pop_front())?;
+                    if id.is_empty() || id.contains(char::is_whitespace) {
+                        return Err(format!("invalid {} `{}`, must be a single word", arg, id))
+                    }
+                    options.sensor_id = Some(id);
+                }
+                "--collect" => options.collect = Some(socket_addr(&arg, args.pop_front())?),
+                "--reputation" => options.reputation = Some(value(&arg, args.pop_front())?.into()),
+                "--reputation-import" => options.reputation_imports.push(value(&arg, args.pop_front())?.into()),
+                "--bridge" => options.bridge = true,
+                "--read" => options.read = Some(value(&arg, args.pop_front())?.into()),
+                "--compare" => {
+                    let a = value(&arg, args.pop_front())?;
+                    options.compare = Some((a.into(), value(&arg, args.pop_front())?.into()));
+                }
+                "--follow" => options.follow = Some(value(&arg, args.pop_front())?.into()),
+                "--ssh" => options.ssh = Some(value(&arg, args.pop_front())?),
+                "--filter" => {
+                    let filter = value(&arg, args.pop_front())?;
+                    options.filter = Some(filter.parse().map_err(|e| format!("{}: {}", arg, e))?);
+                }
+                "--ignore" => {
+                    let rule = value(&arg, args.pop_front())?;
+                    options.ignore.push(rule.parse().map_err(|e| format!("{}: {}", arg, e))?);
+                }
+                "--dedup-window" => {
+                    let window = value(&arg, args.pop_front())?;
+                    let millis = window.parse().map_err(|e| format!("invalid {} `{}`: {}", arg, window, e))?;
+                    options.dedup_window = Some(Duration::from_millis(millis)).filter(|window| !window.is_zero());
+                }
+                "--verify-checksums" => {
+                    let policy = value(&arg, args.pop_front())?;
+                    options.checksum_policy = Some(policy.parse().map_err(|e| format!("{}: {}", arg, e))?);
+                }
+                "--max-connections" => {
+                    let count = value(&arg, args.pop_front())?;
+                    match count.parse() {
+                        Ok(0) => return Err(format!("{} must be at least 1", arg)),
+                        Ok(count) => options.max_connections = Some(count),
+                        Err(e) => return Err(format!("invalid {} `{}`: {}", arg, count, e)),
+                    }
+                }
+                "--idle-timeout" => {
+                    let timeout = value(&arg, args.pop_front())?;
+                    let seconds = timeout.parse().map_err(|e| format!("invalid {} duration `{}`: {}", arg, timeout, e))?;
+                    options.idle_timeout = if seconds == 0 { None } else { Some(Duration::from_secs(seconds)) };
+                }
+                "--stream-history" => {
+                    let bytes = value(&arg, args.pop_front())?;
+                    options.stream_history = bytes.parse().map_err(|e| format!("invalid {} `{}`: {}", arg, bytes, e))?;
+                }
+                "--connection-buffer" => {
+                    let bytes = value(&arg, args.pop_front())?;
+                    options.connection_buffer = bytes.parse().map_err(|e| format!("invalid {} `{}`: {}", arg, bytes, e))?;
+                }
+                "--reassembly-budget" => {
+                    let size = value(&arg, args.pop_front())?;
+                    let mib: usize = size.parse().map_err(|e| format!("invalid {} `{}`: {}", arg, size, e))?;
+                    options.reassembly_budget = mib.checked_mul(1 << 20).ok_or_else(|| format!("{} `{}` is too large", arg, size))?;
+                }
+                "--packet-history" => {
+                    let packets = value(&arg, args.pop_front())?;
+                    options.packet_history = packets.parse().map_err(|e| format!("invalid {} `{}`: {}", arg, packets, e))?;
+                }
+                "--history-digests" => options.history_digests = true,
+                "--ring" | "--ring-block-timeout" if !cfg!(target_os = "linux") => {
+                    return Err(format!("{} is only available on Linux", arg))
+                }
+                "--ring" => {
+                    options.ring_block_timeout.get_or_insert(DEFAULT_RING_BLOCK_TIMEOUT);
+                }
+                "--backend" => match value(&arg, args.pop_front())?.as_str() {
+                    "pnet" => {
+                        options.ring_block_timeout = None;
+                        options.netmap = false;
+                    }
+                    "ring" if cfg!(target_os = "linux") => {
+                        options.ring_block_timeout.get_or_insert(DEFAULT_RING_BLOCK_TIMEOUT);
+                        options.netmap = false;
+                    }
+                    "ring" => return Err("--backend ring is only available on Linux".to_owned()),
+                    "netmap" if cfg!(feature = "netmap") => {
+                        options.ring_block_timeout = None;
+                        options.netmap = true;
+                    }
+                    "netmap" => return Err("--backend netmap needs a build with the netmap feature".to_owned()),
+                    backend => return Err(format!("unknown {} `{}`", arg, backend)),
+                },
+                "--ring-block-timeout" => {
+                    let timeout = value(&arg, args.pop_front())?;
+                    let millis = timeout.parse().map_err(|e| format!("invalid {} `{}`: {}", arg, timeout, e))?;
+                    options.ring_block_timeout = Some(Duration::from_millis(millis));
+                }
+                "--buffer-size" => {
+                    let size = value(&arg, args.pop_front())?;
+                    let mebibytes: usize = size.parse().map_err(|e| format!("invalid {} `{}`: {}", arg, size, e))?;
+                    match mebibytes.checked_mul(1 << 20) {
+                        Some(0) => return Err(format!("{} must be positive", arg)),
+                        Some(bytes) => options.buffer_size = Some(bytes),
+                        None => return Err(format!("{} `{}` is too large", arg, size)),
+                    }
+                }
+                "--snaplen" => {
+                    let snaplen = value(&arg, args.pop_front())?;
+                    options.snaplen = snaplen.parse().map_err(|e| format!("invalid {} `{}`: {}", arg, snaplen, e))?;
+                }
+                "--no-promisc" => options.promiscuous = false,
+                "--read-timeout" => {
+                    let timeout = value(&arg, args.pop_front())?;
+                    let millis = timeout.parse().map_err(|e| format!("invalid {} `{}`: {}", arg, timeout, e))?;
+                    options.read_timeout = Some(Duration::from_millis(millis));
+                }
+                "--interface" => interface = Some(value(&arg, args.pop_front())?),
+                "--config" => {
+                    let path = value(&arg, args.pop_front())?;
+                    let config = fs::read_to_string(&path).map_err(|e| format!("cannot read {}: {}", path, e))?;
+                    // options from the file take effect where --config appears
+                    for config_arg in config_args(&config).into_iter().rev() {
+                        args.push_front(config_arg);
+                    }
+                }
+                "-" => options.read = Some(arg.into()),
+                _ if arg.starts_with("--") => return Err(format!("unknown option {}", arg)),
+                _ if interface.is_none() => interface = Some(arg),
+                _ => return Err(format!("unexpected argument {}", arg)),
+            }
+        }
+
+        if [options.read.is_some(), options.follow.is_some(), options.ssh.is_some()].iter().filter(|&&given| given).count() > 1 {
+            return Err("--read, --follow and --ssh are exclusive".to_owned())
+        }
+        let offline = options.read.is_some() || options.follow.is_some();
+        if options.netmap && (offline || options.ssh.is_some()) {
+            return Err("--backend netmap needs a live interface".to_owned())
+        }
+        if options.ssh.is_some() && (options.ring_block_timeout.is_some() || options.probe || options.bridge) {
+            return Err("--ssh only observes, it can't be combined with --ring, --probe or --bridge".to_owned())
+        }
+        if (offline || options.ring_block_timeout.is_some()) && options.probe {
+            return Err("--probe needs a live interface without --ring to send probes on".to_owned())
+        }
+        if (offline || options.ring_block_timeout.is_some()) && options.bridge {
+            return Err("--bridge needs a live interface without --ring to forward on".to_owned())
+        }
+        if options.buffer_size.is_some() && options.ring_block_timeout.is_none() {
+            return Err("--buffer-size needs --ring, the plain channel uses the kernel's socket buffer".to_owned())
+        }
+        if options.read_timeout.is_some() && options.ring_block_timeout.is_some() {
+            return Err("--read-timeout doesn't apply to --ring, use --ring-block-timeout".to_owned())
+        }
+        if options.compare.is_some() && options.read.is_none() {
+            return Err("--compare requires --read".to_owned())
+        }
+        if options.snaplen == 0 {
+            return Err("--snaplen must be positive".to_owned())
+        }
+        if options.ipfix_collector.is_none() && options.ipfix_enterprise.is_some() {
+            return Err("--ipfix-enterprise requires --ipfix".to_owned())
+        }
+        if options.reputation.is_none() && !options.reputation_imports.is_empty() {
+            return Err("--reputation-import requires --reputation".to_owned())
+        }
+        options.interface = match interface {
+            Some(interface) => interface,
+            None if options.collect.is_some() || offline => String::new(),
+            None => return Err("interface not given".to_owned()),
+        };
+        Ok(options)
+    }
+}
+
+/// Turns each `key [value]` line of a config file into `--key [value]` arguments.
+/// The value is the rest of the line, empty lines and `#` comments are skipped.
+fn config_args(config: &str) -> Vec<String> {
+    let mut args = Vec::new();
+    for line in config.lines().map(str::trim).filter(|line| !line.is_empty() && !line.starts_with('#')) {
+        let (key, value) = match line.find(char::is_whitespace) {
+            Some(space) => (&line[..space], Some(line[space..].trim())),
+            None => (line, None),
+        };
+        args.push(format!("--{}", key));
+        args.extend(value.map(str::to_owned));
+    }
+    args
+}
+
+fn value(option: &str, value: Option<String>) -> Result<String, String> {
+    value.ok_or_else(|| format!("{} requires a value", option))
+}
+
+fn socket_addr(option: &str, addr: Option<String>) -> Result<SocketAddr, String> {
+    let addr = value(option, addr)?;
+    addr.parse().map_err(|e| format!("invalid {} address `{}`: {}", option, addr, e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn config_file_options() {
+        let config = "# sensor on the shared tap\ninterface eth1\n\nhome-net 10.0.0.0/8\n  probe\ntenant acme=vlan:100\nschedule capture mon-fri 09:00-18:00\n";
+        let args = vec!["--config".to_owned(), "/nonexistent".to_owned()];
+        assert!(Options::from_args(args).is_err());
+
+        assert_eq!(config_args(config), vec![
+            "--interface", "eth1", "--home-net", "10.0.0.0/8", "--probe", "--tenant", "acme=vlan:100",
+            "--schedule", "capture mon-fri 09:00-18:00",
+        ]);
+        let options = Options::from_args(config_args(config)).unwrap();
+        assert_eq!(options.interface, "eth1");
+        assert!(options.probe);
+        assert_eq!(options.tenants[0].label, "acme");
+        assert_eq!(options.schedule.len(), 1);
+        assert_eq!(options.snaplen, DEFAULT_SNAPLEN);
+
+        let args = |args: &[&str]| Options::from_args(args.iter().map(|arg| arg.to_string()));
+        assert!(args(&["eth0", "--buffer-size", "256"]).is_err());
+        if cfg!(target_os = "linux") {
+            assert_eq!(args(&["eth0", "--ring", "--buffer-size", "256"]).unwrap().buffer_size, Some(256 << 20));
+            assert!(args(&["eth0", "--ring", "--buffer-size", "0"]).is_err());
+            assert!(args(&["eth0", "--ring", "--buffer-size", &usize::MAX.to_string()]).is_err());
+        } else {
+            assert!(args(&["eth0", "--backend", "ring"]).is_err());
+        }
+        assert_eq!(args(&["eth0", "--reassembly-budget", "64"]).unwrap().reassembly_budget, 64 << 20);
+        assert!(args(&["eth0", "--reassembly-budget", &(usize::MAX >> 19).to_string()]).is_err());
+        assert_eq!(args(&["-"]).unwrap().read, Some(PathBuf::from("-")));
+        assert_eq!(args(&["eth0", "--max-connections", "100000"]).unwrap().max_connections, Some(100000));
+        assert!(args(&["eth0", "--max-connections", "0"]).is_err());
+        assert_eq!(args(&["eth0", "--block-confidence", "90"]).unwrap().block_confidence, 90);
+        assert!(args(&["eth0", "--block-confidence", "101"]).is_err());
+        assert!(args(&["eth0", "--compare", "a.conf", "b.conf"]).is_err());
+        assert!(args(&["eth0", "--ipfix-enterprise", "32473"]).is_err());
+        assert_eq!(args(&["eth0", "--ipfix", "127.0.0.1:4739", "--ipfix-enterprise", "32473"]).unwrap().ipfix_enterprise, Some(32473));
+        assert_eq!(args(&["--read", "x.pcap", "--compare", "a.conf", "b.conf"]).unwrap().compare, Some(("a.conf".into(), "b.conf".into())));
+    }
+}
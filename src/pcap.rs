@@ -0,0 +1,474 @@
+//! Readers of pcap and pcapng capture files.
+
+use std::convert::TryInto;
+use std::fs::File;
+use std::io::{self, BufReader, Cursor, Read};
+use std::ops::Range;
+use std::path::Path;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::tcp_iterator::{CaptureSource, CaptureStats};
+use crate::types::{LinkType, PacketMeta};
+
+const MAGIC_MICROS: u32 = 0xa1b2_c3d4;
+const MAGIC_NANOS: u32 = 0xa1b2_3c4d;
+/// Larger records are taken for a corrupt file rather than allocated.
+const MAX_RECORD_LEN: u32 = 256 * 1024;
+
+const PCAPNG_SECTION_HEADER: u32 = 0x0a0d_0d0a;
+const PCAPNG_BYTE_ORDER_MAGIC: u32 = 0x1a2b_3c4d;
+const PCAPNG_INTERFACE_DESCRIPTION: u32 = 1;
+/// Obsolete packet block, still written by some tools.
+const PCAPNG_PACKET: u32 = 2;
+const PCAPNG_SIMPLE_PACKET: u32 = 3;
+const PCAPNG_INTERFACE_STATISTICS: u32 = 5;
+const PCAPNG_ENHANCED_PACKET: u32 = 6;
+const PCAPNG_OPTION_END: u16 = 0;
+const PCAPNG_OPTION_TSRESOL: u16 = 9;
+const PCAPNG_OPTION_IFRECV: u16 = 4;
+const PCAPNG_OPTION_IFDROP: u16 = 5;
+/// Packet records plus room for block options.
+const MAX_BLOCK_LEN: u32 = MAX_RECORD_LEN + 64 * 1024;
+
+/// Opens a pcap or pcapng file, `-` reads the capture from the standard input.
+pub fn open(path: &Path) -> io::Result<Box<dyn CaptureSource>> {
+    if path == Path::new("-") {
+        return from_reader(io::stdin())
+    }
+    from_reader(File::open(path)?)
+}
+
+/// Reads a pcap or pcapng capture, telling them apart by the first bytes. The reader needn't
+/// be seekable, so a capture can be streamed through a pipe.
+pub fn from_reader<R: Read + 'static>(mut reader: R) -> io::Result<Box<dyn CaptureSource>> {
+    let mut magic = [0; 4];
+    reader.read_exact(&mut magic)?;
+    let reader = BufReader::new(Cursor::new(magic).chain(reader));
+    if u32::from_le_bytes(magic) == PCAPNG_SECTION_HEADER {
+        Ok(Box::new(PcapNgReader::new(reader)?))
+    } else {
+        Ok(Box::new(PcapReader::new(reader)?))
+    }
+}
+
+pub struct PcapReader<R> {
+    reader: R,
+    link_type: LinkType,
+    swapped: bool,
+    nanos: bool,
+    buffer: Vec<u8>,
+}
+
+impl<R: Read> PcapReader<R> {
+    /// Reads the file header. Ethernet and Linux cooked captures are supported.
+    pub fn new(mut reader: R) -> io::Result<Self> {
+        let mut header = [0; 24];
+        reader.read_exact(&mut header)?;
+        let magic = u32::from_le_bytes([header[0], header[1], header[2], header[3]]);
+        let (swapped, nanos) = match magic {
+            MAGIC_MICROS => (false, false),
+            MAGIC_NANOS => (false, true),
+            _ if magic.swap_bytes() == MAGIC_MICROS => (true, false),
+            _ if magic.swap_bytes() == MAGIC_NANOS => (true, true),
+            _ => return Err(invalid_data("not a pcap file")),
+        };
+        let mut pcap = Self{ reader, link_type: LinkType::Ethernet, swapped, nanos, buffer: Vec::new() };
+        let link_type = pcap.field(&header[20..24]);
+        pcap.link_type = LinkType::from_pcap(link_type)
+            .ok_or_else(|| invalid_data(&format!("unsupported pcap link type {}", link_type)))?;
+        Ok(pcap)
+    }
+
+    fn field(&self, bytes: &[u8]) -> u32 {
+        let bytes = [bytes[0], bytes[1], bytes[2], bytes[3]];
+        if self.swapped { u32::from_be_bytes(bytes) } else { u32::from_le_bytes(bytes) }
+    }
+}
+
+impl<R: Read> CaptureSource for PcapReader<R> {
+    fn next_frame(&mut self) -> io::Result<Option<(PacketMeta, &[u8])>> {
+        let mut header = [0; 16];
+        match self.reader.read_exact(&mut header) {
+            Ok(()) => {}
+            Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+            Err(err) => return Err(err),
+        }
+        let (secs, fraction) = (self.field(&header[0..4]), self.field(&header[4..8]));
+        let (cap_len, wire_len) = (self.field(&header[8..12]), self.field(&header[12..16]));
+        if cap_len > MAX_RECORD_LEN {
+            return Err(invalid_data(&format!("pcap record of {} bytes", cap_len)))
+        }
+        self.buffer.resize(cap_len as usize, 0);
+        self.reader.read_exact(&mut self.buffer)?;
+
+        let fraction = if self.nanos { Duration::from_nanos(fraction.into()) } else { Duration::from_micros(fraction.into()) };
+        let meta = PacketMeta {
+            ts: UNIX_EPOCH + Duration::from_secs(secs.into()) + fraction,
+            iface_id: 0,
+            wire_len,
+            cap_len,
+            link_type: self.link_type,
+        };
+        Ok(Some((meta, &self.buffer)))
+    }
+}
+
+struct Interface {
+    link_type: u16,
+    snap_len: u32,
+    /// Timestamp units per second.
+    ts_units: u64,
+    /// Latest counters from an interface statistics block.
+    stats: Option<CaptureStats>,
+}
+
+/// Reader of pcapng files, possibly made of several sections with different byte orders.
+/// Packets of interfaces of other link types than Ethernet and Linux cooked are skipped.
+pub struct PcapNgReader<R> {
+    reader: R,
+    swapped: bool,
+    /// Interfaces of the current section, by interface ID.
+    interfaces: Vec<Interface>,
+    /// Body of the current block followed by its trailing length.
+    block: Vec<u8>,
+    /// Capture counters of previous sections.
+    earlier_stats: Option<CaptureStats>,
+}
+
+impl<R: Read> PcapNgReader<R> {
+    /// Reads the header of the first section.
+    pub fn new(mut reader: R) -> io::Result<Self> {
+        let mut header = [0; 8];
+        reader.read_exact(&mut header)?;
+        if u32::from_le_bytes([header[0], header[1], header[2], header[3]]) != PCAPNG_SECTION_HEADER {
+            return Err(invalid_data("not a pcapng file"))
+        }
+        let mut pcapng = Self{ reader, swapped: false, interfaces: Vec::new(), block: Vec::new(), earlier_stats: None };
+        pcapng.read_section_header(&header)?;
+        Ok(pcapng)
+    }
+
+    /// Starts a new section, the byte order and the interfaces of the previous one no longer apply.
+    fn read_section_header(&mut self, header: &[u8; 8]) -> io::Result<()> {
+        let mut magic = [0; 4];
+        self.reader.read_exact(&mut magic)?;
+        self.swapped = match u32::from_le_bytes(magic) {
+            PCAPNG_BYTE_ORDER_MAGIC => false,
+            magic if magic.swap_bytes() == PCAPNG_BYTE_ORDER_MAGIC => true,
+            _ => return Err(invalid_data("bad pcapng byte order magic")),
+        };
+        self.earlier_stats = self.section_stats();
+        self.interfaces.clear();
+        let len = self.u32_at(&header[4..8]);
+        // the rest of the header is versions, section length and options, none of them needed
+        self.read_block_body(len, 12)
+    }
+
+    /// Reads the rest of a block of total length `len` of which `read` bytes were read already.
+    fn read_block_body(&mut self, len: u32, read: u32) -> io::Result<()> {
+        if len < read + 4 || !len.is_multiple_of(4) || len > MAX_BLOCK_LEN {
+            return Err(invalid_data(&format!("bad pcapng block length {}", len)))
+        }
+        self.block.resize((len - read) as usize, 0);
+        self.reader.read_exact(&mut self.block)
+    }
+
+    fn u16_at(&self, bytes: &[u8]) -> u16 {
+        let bytes = [bytes[0], bytes[1]];
+        if self.swapped { u16::from_be_bytes(bytes) } else { u16::from_le_bytes(bytes) }
+    }
+
+    fn u32_at(&self, bytes: &[u8]) -> u32 {
+        let bytes = [bytes[0], bytes[1], bytes[2], bytes[3]];
+        if self.swapped { u32::from_be_bytes(bytes) } else { u32::from_le_bytes(bytes) }
+    }
+
+    /// Field of the current block body, which is at least `offset + 4` long.
+    fn field(&self, offset: usize) -> io::Result<u32> {
+        match self.block.get(offset..offset + 4) {
+            Some(bytes) => Ok(self.u32_at(bytes)),
+            None => Err(invalid_data("truncated pcapng block")),
+        }
+    }
+
+    /// Options of the current block starting at `offset`, as codes and values.
+    fn read_options(&self, offset: usize) -> io::Result<Vec<(u16, &[u8])>> {
+        let mut options = self.block.get(offset..self.block.len() - 4).unwrap_or_default();
+        let mut read = Vec::new();
+        while options.len() >= 4 {
+            let (code, len) = (self.u16_at(&options[0..2]), usize::from(self.u16_at(&options[2..4])));
+            let value = options.get(4..4 + len).ok_or_else(|| invalid_data("truncated pcapng option"))?;
+            if code == PCAPNG_OPTION_END {
+                break
+            }
+            read.push((code, value));
+            options = options.get(4 + len.div_ceil(4) * 4..).unwrap_or_default();
+        }
+        Ok(read)
+    }
+
+    fn read_interface(&self) -> io::Result<Interface> {
+        let link_type = self.u16_at(self.block.get(0..2).ok_or_else(|| invalid_data("truncated pcapng block"))?);
+        let snap_len = self.field(4)?;
+        let mut ts_units = 1_000_000;
+        for (code, value) in self.read_options(8)? {
+            if let (PCAPNG_OPTION_TSRESOL, &[tsresol]) = (code, value) {
+                let exponent = u32::from(tsresol & 0x7f);
+                let base: u64 = if tsresol & 0x80 == 0 { 10 } else { 2 };
+                ts_units = base.checked_pow(exponent).ok_or_else(|| invalid_data("bad pcapng timestamp resolution"))?;
+            }
+        }
+        Ok(Interface{ link_type, snap_len, ts_units, stats: None })
+    }
+
+    /// Reads the counters of an interface statistics block, the latest block of an interface wins.
+    fn read_interface_stats(&mut self) -> io::Result<()> {
+        let interface_id = self.field(0)? as usize;
+        let mut stats = CaptureStats::default();
+        for (code, value) in self.read_options(12)? {
+            let counter = match value.try_into() {
+                Ok(bytes) if self.swapped => u64::from_be_bytes(bytes),
+                Ok(bytes) => u64::from_le_bytes(bytes),
+                Err(_) => continue,
+            };
+            match code {
+                PCAPNG_OPTION_IFRECV => stats.received = counter,
+                PCAPNG_OPTION_IFDROP => stats.dropped = counter,
+                _ => {}
+            }
+        }
+        if let Some(interface) = self.interfaces.get_mut(interface_id) {
+            interface.stats = Some(stats);
+        }
+        Ok(())
+    }
+
+    /// Counters of this and earlier sections, `None` if none had any.
+    fn section_stats(&self) -> Option<CaptureStats> {
+        self.interfaces.iter().filter_map(|interface| interface.stats).chain(self.earlier_stats)
+            .reduce(|total, stats| CaptureStats {
+                received: total.received + stats.received,
+                dropped: total.dropped + stats.dropped,
+            })
+    }
+
+    /// Packet of the current block: interface ID, timestamp, frame range and length on the wire.
+    fn read_packet(&self, block_type: u32) -> io::Result<(u32, Option<u64>, Range<usize>, u32)> {
+        let (interface, ts, cap_len, wire_len, data) = match block_type {
+            PCAPNG_ENHANCED_PACKET => {
+                let ts = u64::from(self.field(4)?) << 32 | u64::from(self.field(8)?);
+                (self.field(0)?, Some(ts), self.field(12)?, self.field(16)?, 20)
+            }
+            PCAPNG_PACKET => {
+                let interface = u32::from(self.u16_at(&self.block[0..2]));
+                let ts = u64::from(self.field(4)?) << 32 | u64::from(self.field(8)?);
+                (interface, Some(ts), self.field(12)?, self.field(16)?, 20)
+            }
+            _ => {
+                let wire_len = self.field(0)?;
+                let snap_len = self.interfaces.first().map_or(0, |interface| interface.snap_len);
+                // the captured length is implied by the snapshot length
+                let cap_len = if snap_len == 0 { wire_len } else { wire_len.min(snap_len) };
+                (0, None, cap_len, wire_len, 4)
+            }
+        };
+        let frame = data..data + cap_len as usize;
+        if frame.end + 4 > self.block.len() {
+            return Err(invalid_data("pcapng packet overruns its block"))
+        }
+        Ok((interface, ts, frame, wire_len))
+    }
+}
+
+impl<R: Read> CaptureSource for PcapNgReader<R> {
+    fn next_frame(&mut self) -> io::Result<Option<(PacketMeta, &[u8])>> {
+        loop {
+            let mut header = [0; 8];
+            match self.reader.read_exact(&mut header) {
+                Ok(()) => {}
+                Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+                Err(err) => return Err(err),
+            }
+            let block_type = self.u32_at(&header[0..4]);
+            if block_type == PCAPNG_SECTION_HEADER {
+                self.read_section_header(&header)?;
+                continue
+            }
+            self.read_block_body(self.u32_at(&header[4..8]), 8)?;
+            match block_type {
+                PCAPNG_INTERFACE_DESCRIPTION => {
+                    let interface = self.read_interface()?;
+                    self.interfaces.push(interface);
+                }
+                PCAPNG_INTERFACE_STATISTICS => self.read_interface_stats()?,
+                PCAPNG_ENHANCED_PACKET | PCAPNG_PACKET | PCAPNG_SIMPLE_PACKET => {
+                    let (interface_id, ts, frame, wire_len) = self.read_packet(block_type)?;
+                    let interface = self.interfaces.get(interface_id as usize)
+                        .ok_or_else(|| invalid_data(&format!("pcapng packet of unknown interface {}", interface_id)))?;
+                    let link_type = match LinkType::from_pcap(interface.link_type.into()) {
+                        Some(link_type) => link_type,
+                        None => continue,
+                    };
+                    let meta = PacketMeta {
+                        // simple packet blocks carry no timestamp
+                        ts: ts.map_or(UNIX_EPOCH, |ts| timestamp(ts, interface.ts_units)),
+                        iface_id: interface_id,
+                        wire_len,
+                        cap_len: frame.len() as u32,
+                        link_type,
+                    };
+                    return Ok(Some((meta, &self.block[frame])))
+                }
+                // name resolution and custom blocks
+                _ => {}
+            }
+        }
+    }
+
+    /// Counters the capturing tool recorded in interface statistics blocks read so far.
+    fn stats(&mut self) -> io::Result<Option<CaptureStats>> {
+        Ok(self.section_stats())
+    }
+}
+
+fn timestamp(ts: u64, units_per_second: u64) -> SystemTime {
+    let nanos = u128::from(ts % units_per_second) * 1_000_000_000 / u128::from(units_per_second);
+    UNIX_EPOCH + Duration::from_secs(ts / units_per_second) + Duration::from_nanos(nanos as u64)
+}
+
+fn invalid_data(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pcap(magic: u32, big_endian: bool, records: &[(u32, u32, &[u8])]) -> Vec<u8> {
+        let word = |value: u32| if big_endian { value.to_be_bytes() } else { value.to_le_bytes() };
+        let mut file = Vec::new();
+        file.extend_from_slice(&word(magic));
+        // version 2.4, then zone and accuracy
+        file.extend_from_slice(&if big_endian { [0, 2, 0, 4] } else { [2, 0, 4, 0] });
+        file.extend_from_slice(&[0; 8]);
+        file.extend_from_slice(&word(65535));
+        file.extend_from_slice(&word(1));
+        for &(secs, fraction, frame) in records {
+            for value in &[secs, fraction, frame.len() as u32, frame.len() as u32 + 4] {
+                file.extend_from_slice(&word(*value));
+            }
+            file.extend_from_slice(frame);
+        }
+        file
+    }
+
+    #[test]
+    fn reads_records_in_both_byte_orders() {
+        let file = pcap(MAGIC_MICROS, true, &[(10, 500, b"first"), (11, 0, b"second")]);
+        let mut reader = PcapReader::new(&file[..]).unwrap();
+        let (meta, frame) = reader.next_frame().unwrap().unwrap();
+        assert_eq!(frame, b"first");
+        assert_eq!(meta.ts, UNIX_EPOCH + Duration::from_micros(10_000_500));
+        assert_eq!((meta.cap_len, meta.wire_len), (5, 9));
+        assert_eq!(reader.next_frame().unwrap().unwrap().1, b"second");
+        assert!(reader.next_frame().unwrap().is_none());
+
+        let file = pcap(MAGIC_NANOS, false, &[(1, 7, b"x")]);
+        let (meta, _) = PcapReader::new(&file[..]).unwrap().next_frame().unwrap().unwrap();
+        assert_eq!(meta.ts, UNIX_EPOCH + Duration::from_nanos(1_000_000_007));
+        // the bytes read to tell the format apart are not lost
+        let mut streamed = from_reader(Cursor::new(file)).unwrap();
+        assert_eq!(streamed.next_frame().unwrap().unwrap().1, b"x");
+
+        let mut truncated = pcap(MAGIC_MICROS, false, &[(1, 0, b"frame")]);
+        truncated.pop();
+        assert!(PcapReader::new(&truncated[..]).unwrap().next_frame().is_err());
+        assert!(PcapReader::new(&b"not a capture file at all"[..]).is_err());
+    }
+
+    /// Block with the body padded to 32 bits.
+    fn block(big_endian: bool, block_type: u32, body: &[u8]) -> Vec<u8> {
+        let word = |value: u32| if big_endian { value.to_be_bytes() } else { value.to_le_bytes() };
+        let padded = body.len().div_ceil(4) * 4;
+        let mut block = word(block_type).to_vec();
+        block.extend_from_slice(&word(padded as u32 + 12));
+        block.extend_from_slice(body);
+        block.resize(8 + padded, 0);
+        block.extend_from_slice(&word(padded as u32 + 12));
+        block
+    }
+
+    fn pcapng_section(big_endian: bool, blocks: &[(u32, Vec<u8>)]) -> Vec<u8> {
+        let word = |value: u32| if big_endian { value.to_be_bytes() } else { value.to_le_bytes() };
+        let mut section_header = word(PCAPNG_BYTE_ORDER_MAGIC).to_vec();
+        section_header.extend_from_slice(&[0; 12]);
+        let mut section = block(big_endian, PCAPNG_SECTION_HEADER, &section_header);
+        for (block_type, body) in blocks {
+            section.extend(block(big_endian, *block_type, body));
+        }
+        section
+    }
+
+    #[test]
+    fn reads_pcapng_sections() {
+        let interface = |big_endian: bool, link_type: u16, tsresol: Option<u8>| {
+            let half = |value: u16| if big_endian { value.to_be_bytes() } else { value.to_le_bytes() };
+            let mut body = half(link_type).to_vec();
+            body.extend_from_slice(&[0, 0, 0, 0, 0, 0]);
+            if let Some(tsresol) = tsresol {
+                body.extend_from_slice(&half(PCAPNG_OPTION_TSRESOL));
+                body.extend_from_slice(&half(1));
+                body.extend_from_slice(&[tsresol, 0, 0, 0]);
+            }
+            body.extend_from_slice(&[0; 4]);
+            body
+        };
+        let packet = |big_endian: bool, interface: u32, ts: u64, frame: &[u8]| {
+            let word = |value: u32| if big_endian { value.to_be_bytes() } else { value.to_le_bytes() };
+            let mut body = Vec::new();
+            for value in &[interface, (ts >> 32) as u32, ts as u32, frame.len() as u32, frame.len() as u32] {
+                body.extend_from_slice(&word(*value));
+            }
+            body.extend_from_slice(frame);
+            body
+        };
+        let statistics = |received: u64, dropped: u64| {
+            let mut body = vec![0; 12];
+            for &(code, counter) in &[(PCAPNG_OPTION_IFRECV, received), (PCAPNG_OPTION_IFDROP, dropped)] {
+                body.extend_from_slice(&code.to_le_bytes());
+                body.extend_from_slice(&8u16.to_le_bytes());
+                body.extend_from_slice(&counter.to_le_bytes());
+            }
+            body.extend_from_slice(&[0; 4]);
+            body
+        };
+
+        let mut file = pcapng_section(false, &[
+            (PCAPNG_INTERFACE_DESCRIPTION, interface(false, 1, Some(9))),
+            (PCAPNG_INTERFACE_DESCRIPTION, interface(false, 105, None)),
+            (PCAPNG_ENHANCED_PACKET, packet(false, 1, 0, b"wireless")),
+            (PCAPNG_ENHANCED_PACKET, packet(false, 0, 1_500_000_000, b"nanos")),
+            (PCAPNG_INTERFACE_STATISTICS, statistics(50, 1)),
+            (PCAPNG_INTERFACE_STATISTICS, statistics(100, 3)),
+        ]);
+        file.extend(pcapng_section(true, &[
+            (PCAPNG_INTERFACE_DESCRIPTION, interface(true, 1, None)),
+            (PCAPNG_ENHANCED_PACKET, packet(true, 0, 2_000_001, b"micros")),
+        ]));
+
+        let mut reader = PcapNgReader::new(&file[..]).unwrap();
+        let (meta, frame) = reader.next_frame().unwrap().unwrap();
+        assert_eq!(frame, b"nanos");
+        assert_eq!(meta.ts, UNIX_EPOCH + Duration::from_millis(1500));
+        let (meta, frame) = reader.next_frame().unwrap().unwrap();
+        assert_eq!(frame, b"micros");
+        assert_eq!(meta.ts, UNIX_EPOCH + Duration::from_micros(2_000_001));
+        assert!(reader.next_frame().unwrap().is_none());
+        // the latest statistics of the first section
+        assert_eq!(reader.stats().unwrap(), Some(CaptureStats{ received: 100, dropped: 3 }));
+
+        // packets of an interface the section doesn't describe
+        let file = pcapng_section(false, &[(PCAPNG_ENHANCED_PACKET, packet(false, 0, 0, b"x"))]);
+        assert!(PcapNgReader::new(&file[..]).unwrap().next_frame().is_err());
+    }
+}
@@ -0,0 +1,209 @@
+//! Detections run per server port, e.g. no stream comparison behind middleboxes rewriting payloads.
+
+use std::{error, fmt};
+use std::str::FromStr;
+
+use crate::event::AttackKind;
+
+/// Detections enabled on a connection, all but strict mode and HTTP by default.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct DetectionPolicy {
+    pub hijack: bool,
+    /// Comparing segments covering the same part of a stream.
+    pub overlap: bool,
+    /// Spoofed and blind resets.
+    pub reset: bool,
+    /// Tuples of reset connections taken over by a new SYN.
+    pub reuse: bool,
+    pub ttl: bool,
+    pub ip_id: bool,
+    pub keep_alive: bool,
+    pub urgent: bool,
+    /// Options used otherwise than negotiated.
+    pub options: bool,
+    /// Comparing HTTP responses, off unless the port is known to carry plain HTTP.
+    pub http: bool,
+    /// Comparing DNS responses on port 53.
+    pub dns: bool,
+    pub insertion: bool,
+    pub window: bool,
+    /// Data acknowledged before the sensor saw it sent.
+    pub unseen_ack: bool,
+    /// Sides persistently disagreeing about a stream.
+    pub desync: bool,
+    /// Hijacks are looked for throughout the connection instead of at its start only.
+    pub strict: bool,
+}
+
+impl Default for DetectionPolicy {
+    fn default() -> Self {
+        Self {
+            hijack: true,
+            overlap: true,
+            reset: true,
+            reuse: true,
+            ttl: true,
+            ip_id: true,
+            keep_alive: true,
+            urgent: true,
+            options: true,
+            http: false,
+            dns: true,
+            insertion: true,
+            window: true,
+            unseen_ack: true,
+            desync: true,
+            strict: false,
+        }
+    }
+}
+
+impl DetectionPolicy {
+    /// Whether reports of `kind` are wanted.
+    pub fn allows(&self, kind: &AttackKind) -> bool {
+        match kind {
+            AttackKind::HandshakeHijack { .. } | AttackKind::SynAckMismatch { .. } | AttackKind::HijackVerified { .. } => self.hijack,
+            AttackKind::StreamOverlap { .. } => self.overlap,
+            AttackKind::RstInjection { .. } | AttackKind::BlindReset { .. } => self.reset,
+            AttackKind::ConnectionReuse { .. } => self.reuse,
+            AttackKind::TtlDeviation { .. } => self.ttl,
+            AttackKind::IpIdDeviation { .. } => self.ip_id,
+            AttackKind::SpoofedKeepAlive { .. } => self.keep_alive,
+            AttackKind::UrgentDataAbuse { .. } => self.urgent,
+            AttackKind::OptionTampering { .. } => self.options,
+            AttackKind::HttpResponseConflict { .. } => self.http,
+            AttackKind::DnsResponseConflict { .. } => self.dns,
+            AttackKind::Insertion { .. } => self.insertion,
+            AttackKind::WindowShrink { .. } => self.window,
+            AttackKind::UnseenDataAcknowledged { .. } => self.unseen_ack,
+            AttackKind::StreamDesync { .. } => self.desync,
+            // decoys carry no genuine traffic to be wrong about
+            AttackKind::DecoyTripped { .. } => true,
+        }
+    }
+
+    fn detection_mut(&mut self, name: &str) -> Option<&mut bool> {
+        match name {
+            "hijack" => Some(&mut self.hijack),
+            "overlap" => Some(&mut self.overlap),
+            "reset" => Some(&mut self.reset),
+            "reuse" => Some(&mut self.reuse),
+            "ttl" => Some(&mut self.ttl),
+            "ip-id" => Some(&mut self.ip_id),
+            "keep-alive" => Some(&mut self.keep_alive),
+            "urgent" => Some(&mut self.urgent),
+            "options" => Some(&mut self.options),
+            "dns" => Some(&mut self.dns),
+            "insertion" => Some(&mut self.insertion),
+            "window" => Some(&mut self.window),
+            "unseen-ack" => Some(&mut self.unseen_ack),
+            "desync" => Some(&mut self.desync),
+            _ => None,
+        }
+    }
+}
+
+/// Policy for server ports, parsed from `<ports>=<settings>`.
+///
+/// Ports are a comma separated list of ports or port ranges like `8000-8080`. Settings are a comma
+/// separated list of `strict`, `http` and `no-<detection>`, detections being `hijack`, `overlap`, `reset`,
+/// `reuse`, `ttl`, `ip-id`, `keep-alive`, `urgent`, `options`, `dns`, `insertion`, `window`,
+/// `unseen-ack` and `desync`.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct PortPolicyRule {
+    ports: Vec<(u16, u16)>,
+    pub policy: DetectionPolicy,
+}
+
+impl PortPolicyRule {
+    pub fn matches(&self, port: u16) -> bool {
+        self.ports.iter().any(|&(first, last)| first <= port && port <= last)
+    }
+}
+
+impl FromStr for PortPolicyRule {
+    type Err = ParsePortPolicyError;
+    fn from_str(s: &str) -> Result<Self, ParsePortPolicyError> {
+        let err = || ParsePortPolicyError(s.to_owned());
+        let eq = s.find('=').ok_or_else(err)?;
+        let (ports, settings) = (s[..eq].trim(), s[eq + 1..].trim());
+
+        let mut port_ranges = Vec::new();
+        for item in ports.split(',').map(str::trim) {
+            let (first, last) = match item.find('-') {
+                Some(dash) => (item[..dash].parse().map_err(|_| err())?, item[dash + 1..].parse().map_err(|_| err())?),
+                None => (item.parse().map_err(|_| err())?, item.parse().map_err(|_| err())?),
+            };
+            if first > last {
+                return Err(err())
+            }
+            port_ranges.push((first, last));
+        }
+
+        let mut policy = DetectionPolicy::default();
+        for setting in settings.split(',').map(str::trim) {
+            match setting.strip_prefix("no-") {
+                _ if setting == "strict" => policy.strict = true,
+                _ if setting == "http" => policy.http = true,
+                Some(detection) => *policy.detection_mut(detection).ok_or_else(err)? = false,
+                None => return Err(err()),
+            }
+        }
+        Ok(Self{ ports: port_ranges, policy })
+    }
+}
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct ParsePortPolicyError(String);
+
+impl fmt::Display for ParsePortPolicyError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "invalid port policy `{}`, expected `<ports>=<strict|http|no-<detection>>,...`", self.0)
+    }
+}
+
+impl error::Error for ParsePortPolicyError {}
+
+/// Ordered port policy rules, the first matching one wins.
+#[derive(Debug, Clone, Default)]
+pub struct PortPolicies {
+    rules: Vec<PortPolicyRule>,
+}
+
+impl PortPolicies {
+    pub fn new(rules: Vec<PortPolicyRule>) -> Self {
+        Self{ rules }
+    }
+
+    /// Policy for connections to `server_port`, the default one if no rule matches.
+    pub fn policy_for(&self, server_port: u16) -> DetectionPolicy {
+        self.rules.iter()
+            .find(|rule| rule.matches(server_port))
+            .map_or_else(DetectionPolicy::default, |rule| rule.policy)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn first_matching_rule_wins() {
+        let policies = PortPolicies::new(vec![
+            "22,443=strict".parse().unwrap(),
+            "8000-8080, 443=no-overlap,no-window".parse().unwrap(),
+            "80=http".parse().unwrap(),
+        ]);
+        assert!(policies.policy_for(443).strict);
+        assert!(policies.policy_for(443).overlap);
+        let proxied = policies.policy_for(8080);
+        assert!(!proxied.overlap && !proxied.window && proxied.hijack && !proxied.strict);
+        assert!(policies.policy_for(80).http && !policies.policy_for(8080).http);
+        assert_eq!(policies.policy_for(81), DetectionPolicy::default());
+
+        assert!("443".parse::<PortPolicyRule>().is_err());
+        assert!("443=no-everything".parse::<PortPolicyRule>().is_err());
+        assert!("443=overlap".parse::<PortPolicyRule>().is_err());
+        assert!("9000-8000=strict".parse::<PortPolicyRule>().is_err());
+    }
+}
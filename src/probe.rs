@@ -0,0 +1,137 @@
+//! Crafted keep-alive probes revealing which sequence space an endpoint is in.
+//!
+//! A keep-alive is a bare ACK one byte before the receiver's window. Any TCP stack answers it
+//! with an ACK carrying its own next sequence number and the next sequence number it expects.
+
+use std::cell::RefCell;
+use std::net::IpAddr;
+use std::rc::Rc;
+
+use crate::types::{EthernetLayer, Sequence, VlanStack};
+
+/// Frames waiting to be sent on the capture interface.
+pub type ProbeQueue = Rc<RefCell<Vec<Vec<u8>>>>;
+
+const ETHERTYPE_VLAN: u16 = 0x8100;
+const ETHERTYPE_QINQ: u16 = 0x88a8;
+const ETHERTYPE_IPV4: u16 = 0x0800;
+const ETHERTYPE_IPV6: u16 = 0x86dd;
+const IPPROTO_TCP: u8 = 6;
+const TCP_HEADER_LEN: u16 = 20;
+const TCP_FLAG_ACK: u8 = 0x10;
+const PROBE_TTL: u8 = 64;
+
+/// Builds an Ethernet frame carrying a bare TCP ACK from `src` to `dst`, tagged with `vlans`.
+/// Returns `None` if the addresses are of different families.
+pub fn keepalive_frame(
+    ethernet: EthernetLayer,
+    vlans: VlanStack,
+    src: (IpAddr, u16),
+    dst: (IpAddr, u16),
+    seq: Sequence,
+    ack: Sequence,
+    window: u16,
+) -> Option<Vec<u8>> {
+    let mut frame = Vec::with_capacity(78);
+    frame.extend_from_slice(&ethernet.dst);
+    frame.extend_from_slice(&ethernet.src);
+    for (i, vlan) in vlans.ids().iter().enumerate() {
+        // outer tags are service tags, the innermost one a customer tag
+        let tpid = if i + 1 < vlans.ids().len() { ETHERTYPE_QINQ } else { ETHERTYPE_VLAN };
+        frame.extend_from_slice(&tpid.to_be_bytes());
+        frame.extend_from_slice(&vlan.to_be_bytes());
+    }
+
+    let mut tcp = [0u8; TCP_HEADER_LEN as usize];
+    tcp[0..2].copy_from_slice(&src.1.to_be_bytes());
+    tcp[2..4].copy_from_slice(&dst.1.to_be_bytes());
+    tcp[4..8].copy_from_slice(&u32::from(seq).to_be_bytes());
+    tcp[8..12].copy_from_slice(&u32::from(ack).to_be_bytes());
+    tcp[12] = ((TCP_HEADER_LEN / 4) as u8) << 4;
+    tcp[13] = TCP_FLAG_ACK;
+    tcp[14..16].copy_from_slice(&window.to_be_bytes());
+
+    match (src.0, dst.0) {
+        (IpAddr::V4(src), IpAddr::V4(dst)) => {
+            frame.extend_from_slice(&ETHERTYPE_IPV4.to_be_bytes());
+            let mut ip = [0u8; 20];
+            ip[0] = 0x45;
+            ip[2..4].copy_from_slice(&(20 + TCP_HEADER_LEN).to_be_bytes());
+            // don't fragment
+            ip[6] = 0x40;
+            ip[8] = PROBE_TTL;
+            ip[9] = IPPROTO_TCP;
+            ip[12..16].copy_from_slice(&src.octets());
+            ip[16..20].copy_from_slice(&dst.octets());
+            let ip_checksum = checksum(&[&ip]);
+            ip[10..12].copy_from_slice(&ip_checksum.to_be_bytes());
+
+            let pseudo_header = [0, IPPROTO_TCP, 0, TCP_HEADER_LEN as u8];
+            let tcp_checksum = checksum(&[&src.octets(), &dst.octets(), &pseudo_header, &tcp]);
+            tcp[16..18].copy_from_slice(&tcp_checksum.to_be_bytes());
+            frame.extend_from_slice(&ip);
+        }
+        (IpAddr::V6(src), IpAddr::V6(dst)) => {
+            frame.extend_from_slice(&ETHERTYPE_IPV6.to_be_bytes());
+            let mut ip = [0u8; 40];
+            ip[0] = 0x60;
+            ip[4..6].copy_from_slice(&TCP_HEADER_LEN.to_be_bytes());
+            ip[6] = IPPROTO_TCP;
+            ip[7] = PROBE_TTL;
+            ip[8..24].copy_from_slice(&src.octets());
+            ip[24..40].copy_from_slice(&dst.octets());
+
+            let pseudo_header = [0, 0, 0, TCP_HEADER_LEN as u8, 0, 0, 0, IPPROTO_TCP];
+            let tcp_checksum = checksum(&[&src.octets(), &dst.octets(), &pseudo_header, &tcp]);
+            tcp[16..18].copy_from_slice(&tcp_checksum.to_be_bytes());
+            frame.extend_from_slice(&ip);
+        }
+        _ => return None,
+    }
+    frame.extend_from_slice(&tcp);
+    Some(frame)
+}
+
+/// Internet checksum (RFC 1071) over concatenated chunks, each of even length but the last.
+pub(crate) fn checksum(chunks: &[&[u8]]) -> u16 {
+    let mut sum = chunks.iter()
+        .flat_map(|chunk| chunk.chunks(2))
+        .map(|word| u32::from(u16::from_be_bytes([word[0], word.get(1).copied().unwrap_or(0)])))
+        .sum::<u32>();
+    while sum > 0xffff {
+        sum = (sum & 0xffff) + (sum >> 16);
+    }
+    !(sum as u16)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tcp_iterator::TcpIterator;
+
+    #[test]
+    fn keepalive_frame_parses_back() {
+        let ethernet = EthernetLayer{ src: [2, 0, 0, 0, 0, 1], dst: [2, 0, 0, 0, 0, 2] };
+        let src = ("192.0.2.1".parse().unwrap(), 443);
+        let dst = ("198.51.100.7".parse().unwrap(), 51234);
+        let frame = keepalive_frame(ethernet, VlanStack::from(&[100, 7][..]), src, dst, Sequence::from(99), Sequence::from(1000), 512)
+            .unwrap();
+
+        let packet = TcpIterator::parse_ethernet(&frame).unwrap();
+        assert_eq!(packet.ethernet, Some(ethernet));
+        assert_eq!(packet.vlans.ids(), &[100, 7]);
+        assert_eq!(&frame[12..14], &ETHERTYPE_QINQ.to_be_bytes());
+        assert_eq!((packet.ip.src, packet.tcp.src), src);
+        assert_eq!((packet.ip.dst, packet.tcp.dst), dst);
+        assert_eq!((packet.tcp.seq, packet.tcp.ack, packet.tcp.window), (99, 1000, 512));
+        assert!(packet.tcp.flags.ack && !packet.tcp.flags.syn);
+        assert!(packet.tcp_payload.is_empty());
+
+        // a correct checksum sums up to zero
+        assert_eq!(checksum(&[&frame[22..42]]), 0);
+        assert_eq!(checksum(&[&frame[34..42], &[0, IPPROTO_TCP, 0, 20], &frame[42..]]), 0);
+
+        let v6 = ("2001:db8::1".parse().unwrap(), 1);
+        assert!(keepalive_frame(ethernet, VlanStack::default(), src, v6, Sequence::from(0), Sequence::from(0), 0).is_none());
+    }
+}
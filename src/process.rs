@@ -0,0 +1,154 @@
+//! Attribution of local sockets to the processes owning them, Linux only.
+//!
+//! Sockets are found in `/proc/net/tcp{,6}` by their endpoints, the owner is then found by
+//! looking for the socket inode among `/proc/<pid>/fd` links.
+
+use std::fs;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+
+use crate::event::{AnomalyReport, AttackReport, AttackReporter};
+use crate::types::Flow;
+
+const PROC_NET_TCP: [&str; 2] = ["/proc/net/tcp", "/proc/net/tcp6"];
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct ProcessInfo {
+    pub pid: u32,
+    pub name: String,
+    /// Command line with arguments joined by spaces.
+    pub cmdline: String,
+}
+
+/// Finds the local process owning a socket of the flow, in either direction.
+pub fn lookup(flow: &Flow) -> Option<ProcessInfo> {
+    let (src, dst) = (SocketAddr::from(flow.src()), SocketAddr::from(flow.dst()));
+    let inode = PROC_NET_TCP.iter()
+        .filter_map(|path| fs::read_to_string(path).ok())
+        .flat_map(|table| table.lines().skip(1).filter_map(parse_socket_line).collect::<Vec<_>>())
+        .find(|&(local, remote, inode)| {
+            inode != 0 && ((same(local, src) && same(remote, dst)) || (same(local, dst) && same(remote, src)))
+        })
+        .map(|(_, _, inode)| inode)?;
+    find_owner(inode)
+}
+
+fn find_owner(inode: u64) -> Option<ProcessInfo> {
+    let socket_link = format!("socket:[{}]", inode);
+    for entry in fs::read_dir("/proc").ok()?.flatten() {
+        let pid = match entry.file_name().to_str().and_then(|name| name.parse().ok()) {
+            Some(pid) => pid,
+            None => continue,
+        };
+        // processes may exit or be inaccessible to us, skip those
+        let fds = match fs::read_dir(entry.path().join("fd")) {
+            Ok(fds) => fds,
+            Err(_) => continue,
+        };
+        let owns_socket = fds.flatten()
+            .any(|fd| fs::read_link(fd.path()).map(|link| link.as_os_str() == socket_link.as_str()).unwrap_or(false));
+        if owns_socket {
+            let name = fs::read_to_string(entry.path().join("comm")).unwrap_or_default();
+            let cmdline = fs::read(entry.path().join("cmdline")).unwrap_or_default();
+            return Some(ProcessInfo {
+                pid,
+                name: name.trim_end().to_owned(),
+                cmdline: cmdline.split(|&b| b == 0)
+                    .filter(|arg| !arg.is_empty())
+                    .map(|arg| String::from_utf8_lossy(arg))
+                    .collect::<Vec<_>>()
+                    .join(" "),
+            })
+        }
+    }
+    None
+}
+
+/// Compares addresses treating IPv4 and IPv4-mapped IPv6 ones as equal.
+fn same(a: SocketAddr, b: SocketAddr) -> bool {
+    let mapped = |addr: IpAddr| match addr {
+        IpAddr::V4(addr) => addr.to_ipv6_mapped(),
+        IpAddr::V6(addr) => addr,
+    };
+    a.port() == b.port() && mapped(a.ip()) == mapped(b.ip())
+}
+
+/// Parses a `/proc/net/tcp{,6}` entry into local address, remote address and socket inode.
+fn parse_socket_line(line: &str) -> Option<(SocketAddr, SocketAddr, u64)> {
+    let fields: Vec<_> = line.split_whitespace().collect();
+    let local = parse_socket_addr(fields.get(1)?)?;
+    let remote = parse_socket_addr(fields.get(2)?)?;
+    let inode = fields.get(9)?.parse().ok()?;
+    Some((local, remote, inode))
+}
+
+/// Addresses are hex encoded 32 bit words in host byte order, the port is big endian hex.
+fn parse_socket_addr(s: &str) -> Option<SocketAddr> {
+    let colon = s.find(':')?;
+    let (addr, port) = (&s[..colon], &s[colon + 1..]);
+    let port = u16::from_str_radix(port, 16).ok()?;
+    let word = |i: usize| u32::from_str_radix(addr.get(i * 8..i * 8 + 8)?, 16).ok().map(u32::to_ne_bytes);
+    let addr = match addr.len() {
+        8 => IpAddr::V4(Ipv4Addr::from(word(0)?)),
+        32 => {
+            let mut octets = [0; 16];
+            for i in 0..4 {
+                octets[i * 4..i * 4 + 4].copy_from_slice(&word(i)?);
+            }
+            IpAddr::V6(Ipv6Addr::from(octets))
+        }
+        _ => return None,
+    };
+    Some(SocketAddr::new(addr, port))
+}
+
+/// Attaches the owning local process to reports before passing them on.
+pub struct ProcessAttributingReporter {
+    inner: Box<dyn AttackReporter>,
+}
+
+impl ProcessAttributingReporter {
+    pub fn new(inner: Box<dyn AttackReporter>) -> Self {
+        Self{ inner }
+    }
+}
+
+impl AttackReporter for ProcessAttributingReporter {
+    fn attacks_reported(&self) -> u32 {
+        self.inner.attacks_reported()
+    }
+
+    fn report_attack(&mut self, mut report: AttackReport) {
+        if report.context.process.is_none() {
+            report.context.process = lookup(&report.flow);
+        }
+        self.inner.report_attack(report);
+    }
+
+    fn report_anomaly(&mut self, report: AnomalyReport) {
+        self.inner.report_anomaly(report);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // lines as a little endian host writes them
+    #[test]
+    #[cfg(target_endian = "little")]
+    fn proc_net_tcp_lines() {
+        let v4 = "   1: 0100007F:0CEA 0201A8C0:D431 01 00000000:00000000 00:00000000 00000000  1000        0 25386 1 0000000000000000 20 4 30 10 -1";
+        assert_eq!(
+            parse_socket_line(v4),
+            Some(("127.0.0.1:3306".parse().unwrap(), "192.168.1.2:54321".parse().unwrap(), 25386)),
+        );
+
+        let v6 = "   0: 000080FE00000000FF605A02FE1C3B1A:01BB 0000000000000000FFFF00000100007F:9C40 01 00000000:00000000 00:00000000 00000000     0        0 4242 1";
+        let (local, remote, inode) = parse_socket_line(v6).unwrap();
+        assert_eq!(local, "[fe80::25a:60ff:1a3b:1cfe]:443".parse().unwrap());
+        assert!(same(remote, "127.0.0.1:40000".parse().unwrap()));
+        assert_eq!(inode, 4242);
+
+        assert_eq!(parse_socket_line("  sl  local_address rem_address   st tx_queue"), None);
+    }
+}
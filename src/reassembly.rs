@@ -0,0 +1,196 @@
+//! IPv4 fragment reassembly.
+//!
+//! Fragments are held until the whole datagram is in, only then is its payload parsed. A
+//! datagram with overlapping fragments is dropped altogether, as Linux does, so that an attacker
+//! can't have the sensor see different bytes than the receiver through crafted overlaps.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::net::Ipv4Addr;
+use std::time::{Duration, SystemTime};
+
+/// Incomplete datagrams are given up after this long, in capture time.
+const FRAGMENT_TIMEOUT: Duration = Duration::from_secs(30);
+/// Datagrams being reassembled at once, the oldest one is dropped to make room.
+const MAX_DATAGRAMS: usize = 1024;
+/// Fragment bytes held for all datagrams together.
+const MAX_PENDING_BYTES: usize = 8 * 1024 * 1024;
+const MAX_FRAGMENTS_PER_DATAGRAM: usize = 64;
+const MAX_DATAGRAM_LEN: usize = 65535;
+
+/// Identifies the fragments of one datagram.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+pub struct FragmentKey {
+    pub src: Ipv4Addr,
+    pub dst: Ipv4Addr,
+    pub id: u16,
+    pub protocol: u8,
+}
+
+struct Datagram {
+    first_seen: SystemTime,
+    /// Payload offset and bytes of every fragment received.
+    fragments: Vec<(usize, Vec<u8>)>,
+    received: usize,
+    /// Known once the last fragment came in.
+    len: Option<usize>,
+}
+
+/// Counters of reassembled datagrams and of those given up.
+#[derive(Debug, Default, Copy, Clone, Eq, PartialEq)]
+pub struct ReassemblyStats {
+    pub reassembled: u64,
+    pub timed_out: u64,
+    /// Dropped for overlapping or inconsistent fragments.
+    pub overlapping: u64,
+    /// Dropped for exceeding a size limit, or to make room for newer datagrams.
+    pub dropped: u64,
+}
+
+impl fmt::Display for ReassemblyStats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "reassembled={} timed_out={} overlapping={} dropped={}",
+               self.reassembled, self.timed_out, self.overlapping, self.dropped)
+    }
+}
+
+#[derive(Default)]
+pub struct FragmentCache {
+    datagrams: HashMap<FragmentKey, Datagram>,
+    pending_bytes: usize,
+    /// Payload of the last reassembled datagram.
+    reassembled: Vec<u8>,
+    stats: ReassemblyStats,
+}
+
+impl FragmentCache {
+    /// Adds a fragment at payload `offset`, `more` unless it's the last one.
+    /// Returns the payload of the datagram once all its fragments are in.
+    pub fn insert(&mut self, key: FragmentKey, offset: usize, more: bool, data: &[u8], now: SystemTime) -> Option<&[u8]> {
+        self.expire(now);
+        let end = offset + data.len();
+        if end > MAX_DATAGRAM_LEN || (more && data.is_empty()) {
+            self.stats.dropped += 1;
+            self.remove(&key);
+            return None
+        }
+        self.make_room(&key, data.len());
+        let datagram = self.datagrams.entry(key).or_insert_with(|| Datagram {
+            first_seen: now,
+            fragments: Vec::new(),
+            received: 0,
+            len: None,
+        });
+
+        if datagram.fragments.iter().any(|(at, bytes)| *at == offset && bytes.as_slice() == data) {
+            // retransmitted duplicate
+            return None
+        }
+        let overlaps = datagram.fragments.iter().any(|(at, bytes)| offset < at + bytes.len() && *at < end);
+        let inconsistent = match datagram.len {
+            Some(len) => end > len || (!more && end != len),
+            None => !more && datagram.fragments.iter().any(|(at, bytes)| at + bytes.len() > end),
+        };
+        if overlaps || inconsistent {
+            self.stats.overlapping += 1;
+            self.remove(&key);
+            return None
+        }
+        if datagram.fragments.len() == MAX_FRAGMENTS_PER_DATAGRAM {
+            self.stats.dropped += 1;
+            self.remove(&key);
+            return None
+        }
+        if !more {
+            datagram.len = Some(end);
+        }
+        datagram.fragments.push((offset, data.to_vec()));
+        datagram.received += data.len();
+        self.pending_bytes += data.len();
+        if datagram.len != Some(datagram.received) {
+            return None
+        }
+
+        // fragments don't overlap, so they cover the whole datagram
+        let mut datagram = self.remove(&key)?;
+        datagram.fragments.sort_by_key(|&(at, _)| at);
+        self.reassembled.clear();
+        for (_, bytes) in datagram.fragments {
+            self.reassembled.extend_from_slice(&bytes);
+        }
+        self.stats.reassembled += 1;
+        Some(&self.reassembled)
+    }
+
+    pub fn stats(&self) -> ReassemblyStats {
+        self.stats
+    }
+
+    fn remove(&mut self, key: &FragmentKey) -> Option<Datagram> {
+        let datagram = self.datagrams.remove(key)?;
+        self.pending_bytes -= datagram.received;
+        Some(datagram)
+    }
+
+    fn expire(&mut self, now: SystemTime) {
+        let expired: Vec<_> = self.datagrams.iter()
+            .filter(|(_, datagram)| now.duration_since(datagram.first_seen).is_ok_and(|age| age >= FRAGMENT_TIMEOUT))
+            .map(|(key, _)| *key)
+            .collect();
+        for key in expired {
+            self.stats.timed_out += 1;
+            self.remove(&key);
+        }
+    }
+
+    /// Drops the oldest other datagrams until `len` more bytes of the datagram `key` fit in the limits.
+    fn make_room(&mut self, key: &FragmentKey, len: usize) {
+        loop {
+            let new = !self.datagrams.contains_key(key);
+            if !(new && self.datagrams.len() >= MAX_DATAGRAMS) && self.pending_bytes + len <= MAX_PENDING_BYTES {
+                return
+            }
+            let oldest = self.datagrams.iter()
+                .filter(|(other, _)| *other != key)
+                .min_by_key(|(_, datagram)| datagram.first_seen)
+                .map(|(other, _)| *other);
+            match oldest {
+                Some(oldest) => {
+                    self.stats.dropped += 1;
+                    self.remove(&oldest);
+                }
+                None => return,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::UNIX_EPOCH;
+
+    #[test]
+    fn reassembles_in_any_order() {
+        let key = FragmentKey{ src: Ipv4Addr::new(10, 0, 0, 1), dst: Ipv4Addr::new(10, 0, 0, 2), id: 7, protocol: 6 };
+        let at = |secs| UNIX_EPOCH + Duration::from_secs(secs);
+        let mut cache = FragmentCache::default();
+        assert_eq!(cache.insert(key, 8, false, b"tail", at(1)), None);
+        assert_eq!(cache.insert(key, 0, true, b"head ...", at(1)), Some(&b"head ...tail"[..]));
+
+        // a retransmitted fragment is ignored, a conflicting one drops the datagram
+        assert_eq!(cache.insert(key, 0, true, b"12345678", at(2)), None);
+        assert_eq!(cache.insert(key, 0, true, b"12345678", at(2)), None);
+        assert_eq!(cache.insert(key, 4, true, b"evil!!!!", at(2)), None);
+        assert_eq!(cache.insert(key, 8, false, b"end", at(2)), None);
+        assert_eq!(cache.stats().overlapping, 1);
+
+        // the head arrives too late, the tail timed out
+        assert_eq!(cache.insert(key, 0, true, b"12345678", at(50)), None);
+        assert_eq!(cache.stats().timed_out, 1);
+        let other = FragmentKey{ id: 8, ..key };
+        assert_eq!(cache.insert(other, 0, true, b"12345678", at(50)), None);
+        assert_eq!(cache.insert(other, 65528, false, b"12345678", at(50)), None);
+        assert_eq!(cache.stats(), ReassemblyStats{ reassembled: 1, timed_out: 1, overlapping: 1, dropped: 1 });
+    }
+}
@@ -0,0 +1,85 @@
+//! Capturing on a remote host: `tcpdump` runs there over SSH and streams the capture back.
+//!
+//! Only `ssh` and `tcpdump` are needed on the remote host. The SSH session's own packets are
+//! left out of the capture, otherwise capturing them would generate more of them.
+
+use std::io;
+use std::process::{Child, Command, Stdio};
+
+use crate::pcap;
+use crate::tcp_iterator::CaptureSource;
+use crate::types::PacketMeta;
+
+pub struct RemoteCapture {
+    ssh: Child,
+    source: Box<dyn CaptureSource>,
+}
+
+impl RemoteCapture {
+    /// Captures on `interface` of the `[user@]host` SSH destination, capturing needs root there.
+    pub fn spawn(destination: &str, interface: &str) -> io::Result<Self> {
+        let mut ssh = Command::new("ssh")
+            .arg("-T")
+            .arg("--")
+            .arg(destination)
+            .arg(remote_command(interface)?)
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .spawn()?;
+        let stdout = ssh.stdout.take().ok_or_else(|| io::Error::other("ssh has no output"))?;
+        match pcap::from_reader(stdout) {
+            Ok(source) => Ok(Self{ ssh, source }),
+            Err(err) => {
+                // ssh or tcpdump failed before the capture started, their messages are on stderr
+                let status = ssh.wait()?;
+                Err(io::Error::new(err.kind(), format!("remote capture failed ({}): {}", status, err)))
+            }
+        }
+    }
+}
+
+/// Shell command of the remote host, the interface name is checked as it goes through a shell.
+fn remote_command(interface: &str) -> io::Result<String> {
+    let valid = |c: char| c.is_ascii_alphanumeric() || "._-:@".contains(c);
+    if interface.is_empty() || !interface.chars().all(valid) {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("invalid remote interface `{}`", interface)))
+    }
+    // SSH_CONNECTION is `<client address> <client port> <server address> <server port>`
+    Ok(format!(
+        "set -- $SSH_CONNECTION; exec tcpdump -U -n -s 0 -w - -i {} \"not (host $1 and tcp port $2 and port $4)\"",
+        interface,
+    ))
+}
+
+impl CaptureSource for RemoteCapture {
+    fn next_frame(&mut self) -> io::Result<Option<(PacketMeta, &[u8])>> {
+        match self.source.next_frame()? {
+            Some(frame) => Ok(Some(frame)),
+            None => match self.ssh.wait()? {
+                status if status.success() => Ok(None),
+                status => Err(io::Error::other(format!("remote capture ended ({})", status))),
+            },
+        }
+    }
+}
+
+impl Drop for RemoteCapture {
+    fn drop(&mut self) {
+        // ends tcpdump too as the session closes
+        let _ = self.ssh.kill();
+        let _ = self.ssh.wait();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn remote_interface_is_checked() {
+        let command = remote_command("eth0.100").unwrap();
+        assert!(command.contains("-i eth0.100 "));
+        assert!(remote_command("eth0; reboot").is_err());
+        assert!(remote_command("").is_err());
+    }
+}
@@ -0,0 +1,241 @@
+//! Replaying captured packets through the detectors, keeping what they report.
+//!
+//! Connections stay in the table until the end of the replay, so it reports what a sensor without
+//! a table limit or idle timeout would. Two replays of one capture can be compared report by report,
+//! e.g. to see what a threshold change gains or loses.
+
+use std::cell::RefCell;
+use std::cmp;
+use std::collections::hash_map::{Entry, HashMap};
+use std::collections::VecDeque;
+use std::rc::Rc;
+
+use time::PrimitiveDateTime;
+
+use crate::connection_state::{Connection, ConnectionOptions};
+use crate::event::{AnomalyReport, AttackReport, AttackReporter};
+use crate::types::{Flow, PacketManifest};
+
+/// Keeps attack reports for later, anomalies are dropped.
+pub struct RecordingReporter {
+    reports: Rc<RefCell<Vec<AttackReport>>>,
+    attacks_reported: u32,
+}
+
+impl RecordingReporter {
+    pub fn new(reports: Rc<RefCell<Vec<AttackReport>>>) -> Self {
+        Self{ reports, attacks_reported: 0 }
+    }
+}
+
+impl AttackReporter for RecordingReporter {
+    fn attacks_reported(&self) -> u32 {
+        self.attacks_reported
+    }
+
+    fn report_attack(&mut self, report: AttackReport) {
+        self.attacks_reported += 1;
+        self.reports.borrow_mut().push(report);
+    }
+
+    fn report_anomaly(&mut self, _report: AnomalyReport) {}
+}
+
+/// Connection table of a replay. New connections get their options from `connection_options`,
+/// given the first packet and the reporter to use.
+pub struct Replay<F> {
+    connections: HashMap<Flow, Connection>,
+    reports: Rc<RefCell<Vec<AttackReport>>>,
+    connection_options: F,
+}
+
+impl<F> Replay<F> where F: FnMut(&PacketManifest, Box<dyn AttackReporter>) -> ConnectionOptions {
+    pub fn new(connection_options: F) -> Self {
+        Self{ connections: HashMap::new(), reports: Default::default(), connection_options }
+    }
+
+    pub fn receive_packet(&mut self, packet: PacketManifest) {
+        let flow = cmp::min(Flow::from(&packet), Flow::from(&packet).reverse());
+        match self.connections.entry(flow) {
+            Entry::Occupied(connection) => connection.into_mut().receive_packet(packet),
+            Entry::Vacant(new_connection) => {
+                let attack_reporter = Box::new(RecordingReporter::new(self.reports.clone()));
+                let options = (self.connection_options)(&packet, attack_reporter);
+                new_connection.insert(Connection::from_packet(packet, options));
+            }
+        }
+    }
+
+    /// Reports of the replay in the order they were made.
+    pub fn into_reports(self) -> Vec<AttackReport> {
+        drop(self.connections);
+        self.reports.take()
+    }
+}
+
+/// Reports of two replays of the same packets, matched by time, flow and attack type.
+#[derive(Debug, Default)]
+pub struct ReportDiff {
+    pub only_a: Vec<AttackReport>,
+    pub only_b: Vec<AttackReport>,
+    /// Reports both made, the first of `a`, confidence and details may differ.
+    pub common: Vec<(AttackReport, AttackReport)>,
+}
+
+impl ReportDiff {
+    /// Matches the reports of `a` against those of `b`, each report matches at most one.
+    pub fn new(a: Vec<AttackReport>, b: Vec<AttackReport>) -> Self {
+        let key = |report: &AttackReport| -> (PrimitiveDateTime, Flow, &'static str) { (report.time, report.flow, report.kind.name()) };
+        let mut unmatched: HashMap<_, VecDeque<AttackReport>> = HashMap::new();
+        let mut order = Vec::new();
+        for report in b {
+            order.push(key(&report));
+            unmatched.entry(key(&report)).or_default().push_back(report);
+        }
+        let mut diff = Self::default();
+        for report in a {
+            match unmatched.get_mut(&key(&report)).and_then(VecDeque::pop_front) {
+                Some(other) => diff.common.push((report, other)),
+                None => diff.only_a.push(report),
+            }
+        }
+        // the rest of `b` in the order they were made
+        for key in order {
+            if let Some(report) = unmatched.get_mut(&key).and_then(VecDeque::pop_front) {
+                diff.only_b.push(report);
+            }
+        }
+        diff
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::coalesce::DEFAULT_STREAM_HISTORY;
+    use crate::connection_state::DEFAULT_PACKET_HISTORY;
+    use crate::pcap;
+    use crate::tcp_iterator::{Packet, TcpIterator};
+    use crate::testing::{self, TcpScenario};
+
+    use std::fs;
+    use std::io::Cursor;
+    use std::net::Ipv4Addr;
+    use std::path::Path;
+    use std::time::{Duration, UNIX_EPOCH};
+
+    /// Hijack detection stops after as many packets by default.
+    const DEFAULT_SKIP_HIJACK_DETECTION_COUNT: u64 = 1000;
+
+    fn connection_options(attack_reporter: Box<dyn AttackReporter>, skip_hijack_detection_count: u64) -> ConnectionOptions {
+        ConnectionOptions {
+            attack_reporter,
+            skip_hijack_detection_count,
+            hijack_detection_period: None,
+            home_network: Default::default(),
+            probes: None,
+            tenant: None,
+            carve_dir: None,
+            switches: Default::default(),
+            stream_history: DEFAULT_STREAM_HISTORY,
+            reassembly_budget: Default::default(),
+            packet_history: DEFAULT_PACKET_HISTORY,
+            history_digests: false,
+            policy: Default::default(),
+            midstream: false,
+        }
+    }
+
+    /// Replays a capture file with the default detectors, a line per report as corpus files list
+    /// them.
+    fn replay_capture(capture: Vec<u8>) -> Vec<String> {
+        let mut packets = TcpIterator::from_source(pcap::from_reader(Cursor::new(capture)).unwrap());
+        let mut replay = Replay::new(|_: &PacketManifest, attack_reporter| connection_options(attack_reporter, DEFAULT_SKIP_HIJACK_DETECTION_COUNT));
+        while let Some(packet) = packets.next_packet().unwrap() {
+            if let Packet::Tcp(packet) = packet {
+                replay.receive_packet(packet);
+            }
+        }
+        replay.into_reports().iter()
+            .map(|report| format!("{} {} {} {} {}%", report.time.format("%Y-%m-%d %H:%M:%S"), report.kind.code(), report.kind.name(), report.flow, report.confidence))
+            .collect()
+    }
+
+    /// Captures of documented attacks in `corpus/`, each `<name>.pcap` or `<name>.pcapng` with the
+    /// reports expected of it in `<name>.reports`.
+    #[test]
+    fn corpus_captures() {
+        let entries = match fs::read_dir(Path::new(env!("CARGO_MANIFEST_DIR")).join("corpus")) {
+            Ok(entries) => entries,
+            Err(_) => return,
+        };
+        for entry in entries {
+            let path = entry.unwrap().path();
+            if !path.extension().is_some_and(|extension| extension == "pcap" || extension == "pcapng") {
+                continue
+            }
+            let expected = fs::read_to_string(path.with_extension("reports")).unwrap();
+            assert_eq!(replay_capture(fs::read(&path).unwrap()), expected.lines().collect::<Vec<_>>(), "{}", path.display());
+        }
+    }
+
+    /// The documented attacks acted out, until real captures of them are in the corpus.
+    #[test]
+    fn synthetic_captures() {
+        let capture = |mut packets: Vec<PacketManifest>| {
+            for (i, packet) in packets.iter_mut().enumerate() {
+                packet.meta.ts = UNIX_EPOCH + Duration::from_secs(1_583_064_000) + Duration::from_millis(10 * i as u64);
+            }
+            replay_capture(testing::pcap(&packets))
+        };
+        let client = (Ipv4Addr::new(10, 0, 0, 5).into(), 40000);
+        let server = (Ipv4Addr::new(192, 0, 2, 80).into(), 80);
+        let request = b"GET /?q=blocked HTTP/1.1\r\nHost: example.com\r\n\r\n";
+        let response = b"HTTP/1.1 200 OK\r\nContent-Length: 5\r\n\r\nhello";
+
+        // a spoofed SYN-ACK racing the server's
+        let mut scenario = TcpScenario::new(client, server, 1000, 5000);
+        let [syn, syn_ack, ack] = scenario.handshake();
+        let hijack = scenario.inject_syn_ack(6699);
+        assert_eq!(capture(vec![syn, syn_ack, hijack, ack]), ["2020-03-01 12:00:00 INJ-001 handshake_hijack 192.0.2.80:80 <-> 10.0.0.5:40000 60%"]);
+
+        // a reset spoofed from the server as the request passes, the genuine response following it
+        let mut scenario = TcpScenario::new(client, server, 1000, 5000);
+        let mut packets = scenario.handshake().to_vec();
+        packets.push(scenario.client_data(request));
+        packets.push(scenario.inject_rst_from_server(scenario.server_next_seq()));
+        packets.push(scenario.server_data(response));
+        assert_eq!(capture(packets), ["2020-03-01 12:00:00 INJ-005 rst_injection 192.0.2.80:80 <-> 10.0.0.5:40000 85%"]);
+
+        // a block page spoofed from the server, arriving ahead of the genuine response
+        let mut scenario = TcpScenario::new(client, server, 1000, 5000);
+        let mut packets = scenario.handshake().to_vec();
+        packets.push(scenario.client_data(request));
+        packets.push(scenario.inject_from_server(scenario.server_next_seq(), b"HTTP/1.1 302 Found\r\nLocation: http://block.example/\r\n\r\n"));
+        packets.push(scenario.server_data(response));
+        assert_eq!(capture(packets), ["2020-03-01 12:00:00 INJ-004 stream_overlap 192.0.2.80:80 <-> 10.0.0.5:40000 60%"]);
+    }
+
+    #[test]
+    fn compare_configurations() {
+        let replay = |skip_hijack_detection_count| {
+            let mut replay = Replay::new(|_: &PacketManifest, attack_reporter| connection_options(attack_reporter, skip_hijack_detection_count));
+            let mut scenario = TcpScenario::new((Ipv4Addr::new(1, 2, 3, 4).into(), 1), (Ipv4Addr::new(2, 3, 4, 5).into(), 2), 3, 9);
+            let [syn, syn_ack, ack] = scenario.handshake();
+            replay.receive_packet(syn);
+            replay.receive_packet(syn_ack);
+            // raced during the handshake, and again after it
+            replay.receive_packet(scenario.inject_syn_ack(6699));
+            replay.receive_packet(ack);
+            replay.receive_packet(scenario.inject_syn_ack(7711));
+            replay.into_reports()
+        };
+
+        let diff = ReportDiff::new(replay(4), replay(100));
+        assert_eq!(diff.common.len(), 1);
+        assert_eq!(diff.common[0].0.kind.name(), "handshake_hijack");
+        assert!(diff.only_a.is_empty());
+        assert_eq!(diff.only_b.len(), 1);
+        assert_eq!(diff.only_b[0].kind.name(), "handshake_hijack");
+    }
+}
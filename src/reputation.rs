@@ -0,0 +1,282 @@
+//! On-disk record of addresses seen sending injected packets.
+//!
+//! The store is a text file with one `<source> <reports> <first seen> <last seen>` line per
+//! offender, times in seconds since the Unix epoch. A source is either an address or a
+//! `ttl=<ttl>,window=<window>` fingerprint of the offending packets, which tracks injectors
+//! spoofing an endpoint of the connection. The file can be copied between sensors and merged
+//! into another store with [`ReputationStore::import`].
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::hash::Hash;
+use std::io;
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::str::FromStr;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::event::{AnomalyReport, AttackKind, AttackReport, AttackReporter};
+
+/// Confidence added to a report per earlier report of the offender.
+pub const REPEAT_OFFENDER_CONFIDENCE: u8 = 5;
+/// Earlier reports counted towards confidence at most.
+const REPEAT_OFFENDER_MAX_REPORTS: u32 = 4;
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct Offender {
+    pub reports: u32,
+    pub first_seen: u64,
+    pub last_seen: u64,
+}
+
+impl Offender {
+    fn merge(&mut self, other: Offender) {
+        self.reports += other.reports;
+        self.first_seen = self.first_seen.min(other.first_seen);
+        self.last_seen = self.last_seen.max(other.last_seen);
+    }
+}
+
+/// Header fields an injector tends to send all its packets with, whatever address it spoofs.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash, Ord, PartialOrd)]
+pub struct Fingerprint {
+    pub ttl: u8,
+    pub window: u16,
+}
+
+impl Fingerprint {
+    /// Fingerprint of the offending packet, `None` if it wasn't seen.
+    pub fn of(report: &AttackReport) -> Option<Self> {
+        match &report.kind {
+            AttackKind::HandshakeHijack { competing, .. } => Some(Self{ ttl: competing.ttl, window: competing.window }),
+            // verdict is about a connection, not about a particular packet
+            AttackKind::HijackVerified { .. } => None,
+            // the injector is past the sensor
+            AttackKind::UnseenDataAcknowledged { .. } => None,
+            AttackKind::StreamDesync { .. } => None,
+            // reports are made on receiving the offending packet, the latest one in history
+            _ => report.history.last().map(|packet| Self{ ttl: packet.ttl, window: packet.window }),
+        }
+    }
+}
+
+impl fmt::Display for Fingerprint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "ttl={},window={}", self.ttl, self.window)
+    }
+}
+
+impl FromStr for Fingerprint {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, ()> {
+        let (ttl, window) = s.split_once(',').ok_or(())?;
+        let ttl = ttl.strip_prefix("ttl=").ok_or(())?.parse().map_err(|_| ())?;
+        let window = window.strip_prefix("window=").ok_or(())?.parse().map_err(|_| ())?;
+        Ok(Self{ ttl, window })
+    }
+}
+
+pub struct ReputationStore {
+    path: PathBuf,
+    offenders: HashMap<IpAddr, Offender>,
+    fingerprints: HashMap<Fingerprint, Offender>,
+    /// Changed since loaded or saved.
+    modified: bool,
+}
+
+impl ReputationStore {
+    /// Loads the store, a missing file is an empty store.
+    pub fn open(path: PathBuf) -> io::Result<Self> {
+        let contents = match fs::read_to_string(&path) {
+            Ok(contents) => contents,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => String::new(),
+            Err(err) => return Err(err),
+        };
+        Ok(Self{ path, offenders: parse(&contents), fingerprints: parse(&contents), modified: false })
+    }
+
+    pub fn get(&self, addr: IpAddr) -> Option<Offender> {
+        self.offenders.get(&addr).cloned()
+    }
+
+    pub fn get_fingerprint(&self, fingerprint: Fingerprint) -> Option<Offender> {
+        self.fingerprints.get(&fingerprint).cloned()
+    }
+
+    pub fn record(&mut self, addr: IpAddr, now: u64) {
+        record(&mut self.offenders, addr, now);
+        self.modified = true;
+    }
+
+    pub fn record_fingerprint(&mut self, fingerprint: Fingerprint, now: u64) {
+        record(&mut self.fingerprints, fingerprint, now);
+        self.modified = true;
+    }
+
+    /// Merges a store exported by another sensor, adding up report counts.
+    pub fn import(&mut self, path: &Path) -> io::Result<usize> {
+        let contents = fs::read_to_string(path)?;
+        let (offenders, fingerprints) = (parse(&contents), parse(&contents));
+        let count = offenders.len() + fingerprints.len();
+        merge(&mut self.offenders, offenders);
+        merge(&mut self.fingerprints, fingerprints);
+        self.modified |= count > 0;
+        Ok(count)
+    }
+
+    /// Writes the store out if it changed, replacing the file at once so that a crash doesn't
+    /// truncate it.
+    pub fn save(&mut self) -> io::Result<()> {
+        if !self.modified {
+            return Ok(());
+        }
+        let contents = format_offenders(&self.offenders) + &format_offenders(&self.fingerprints);
+        let tmp = self.path.with_extension("tmp");
+        fs::write(&tmp, contents)?;
+        fs::rename(tmp, &self.path)?;
+        self.modified = false;
+        Ok(())
+    }
+}
+
+fn record<K: Hash + Eq>(offenders: &mut HashMap<K, Offender>, key: K, now: u64) {
+    let offender = offenders.entry(key).or_insert(Offender{ reports: 0, first_seen: now, last_seen: now });
+    offender.reports += 1;
+    offender.last_seen = offender.last_seen.max(now);
+}
+
+fn merge<K: Hash + Eq>(offenders: &mut HashMap<K, Offender>, imported: HashMap<K, Offender>) {
+    for (key, other) in imported {
+        offenders.entry(key)
+            .and_modify(|offender| offender.merge(other))
+            .or_insert(other);
+    }
+}
+
+fn format_offenders<K: Ord + fmt::Display>(offenders: &HashMap<K, Offender>) -> String {
+    let mut offenders: Vec<_> = offenders.iter().collect();
+    offenders.sort_by_key(|&(key, _)| key);
+    offenders.into_iter()
+        .map(|(key, o)| format!("{} {} {} {}\n", key, o.reports, o.first_seen, o.last_seen))
+        .collect()
+}
+
+/// Lines of sources of type `K`, skipping malformed lines, a hand edited store shouldn't stop the
+/// sensor.
+fn parse<K: FromStr + Hash + Eq>(contents: &str) -> HashMap<K, Offender> {
+    contents.lines()
+        .filter_map(|line| {
+            let mut fields = line.split_whitespace();
+            let key = fields.next()?.parse().ok()?;
+            let reports = fields.next()?.parse().ok()?;
+            let first_seen = fields.next()?.parse().ok()?;
+            let last_seen = fields.next()?.parse().ok()?;
+            Some((key, Offender{ reports, first_seen, last_seen }))
+        })
+        .collect()
+}
+
+/// Marks reports from known offenders, raising their confidence, and records offenders in the
+/// store, passing reports on. The store is saved by its owner, not on every report.
+pub struct ReputationReporter {
+    inner: Box<dyn AttackReporter>,
+    store: Rc<RefCell<ReputationStore>>,
+}
+
+impl ReputationReporter {
+    pub fn new(inner: Box<dyn AttackReporter>, store: Rc<RefCell<ReputationStore>>) -> Self {
+        Self{ inner, store }
+    }
+}
+
+impl AttackReporter for ReputationReporter {
+    fn attacks_reported(&self) -> u32 {
+        self.inner.attacks_reported()
+    }
+
+    fn report_attack(&mut self, mut report: AttackReport) {
+        let (offender, fingerprint) = (report.offender(), Fingerprint::of(&report));
+        if offender.is_some() || fingerprint.is_some() {
+            let mut store = self.store.borrow_mut();
+            let by_addr = offender.and_then(|addr| store.get(addr)).map_or(0, |known| known.reports);
+            let by_fingerprint = fingerprint.and_then(|fingerprint| store.get_fingerprint(fingerprint)).map_or(0, |known| known.reports);
+            let prior_reports = by_addr.max(by_fingerprint);
+            report.context.prior_reports = Some(prior_reports);
+            let bump = prior_reports.min(REPEAT_OFFENDER_MAX_REPORTS) * REPEAT_OFFENDER_CONFIDENCE as u32;
+            report.confidence = (report.confidence as u32 + bump).min(100) as u8;
+            let now = SystemTime::now().duration_since(UNIX_EPOCH).map(|since| since.as_secs()).unwrap_or(0);
+            if let Some(addr) = offender {
+                store.record(addr, now);
+            }
+            if let Some(fingerprint) = fingerprint {
+                store.record_fingerprint(fingerprint, now);
+            }
+        }
+        self.inner.report_attack(report);
+    }
+
+    fn report_anomaly(&mut self, report: AnomalyReport) {
+        self.inner.report_anomaly(report);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::event::test_utils::DummyAttackReporter;
+    use time::Date;
+
+    #[test]
+    fn record_save_import() {
+        let dir = std::env::temp_dir().join(format!("detect-inj-reputation-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let (ours, theirs) = (dir.join("ours"), dir.join("theirs"));
+        fs::write(&theirs, "1.2.3.4 2 50 60\nnot an entry\n::1 1 70 70\nttl=52,window=1024 4 40 80\n").unwrap();
+
+        let mut store = ReputationStore::open(ours.clone()).unwrap();
+        let addr = "1.2.3.4".parse().unwrap();
+        let fingerprint = Fingerprint{ ttl: 52, window: 1024 };
+        store.record(addr, 100);
+        store.record_fingerprint(fingerprint, 100);
+        assert_eq!(store.import(&theirs).unwrap(), 3);
+        assert_eq!(store.get(addr), Some(Offender{ reports: 3, first_seen: 50, last_seen: 100 }));
+        assert_eq!(store.get_fingerprint(fingerprint), Some(Offender{ reports: 5, first_seen: 40, last_seen: 100 }));
+        store.save().unwrap();
+
+        let reopened = ReputationStore::open(ours).unwrap();
+        assert_eq!(reopened.get(addr), store.get(addr));
+        assert_eq!(reopened.get("::1".parse().unwrap()).map(|o| o.reports), Some(1));
+        assert_eq!(reopened.get_fingerprint(fingerprint), store.get_fingerprint(fingerprint));
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn raises_confidence_of_repeat_offenders() {
+        let path = std::env::temp_dir().join(format!("detect-inj-reputation-repeat-{}", std::process::id()));
+        let store = Rc::new(RefCell::new(ReputationStore::open(path.clone()).unwrap()));
+        let reports = Rc::new(RefCell::new(Vec::new()));
+        let mut reporter = ReputationReporter::new(Box::new(DummyAttackReporter::new(reports.clone())), store.clone());
+        // the same injector spoofing two addresses
+        let hijack = |flow: &str| AttackReport::new(
+            Date::try_from_ymd(2020, 3, 1).unwrap().midnight(),
+            flow.parse().unwrap(),
+            AttackKind::HandshakeHijack { packet_count: 2, hijack_seq: 1, hijack_ack: 2, first: None, competing: Default::default(), differing: vec!["seq"] },
+        );
+        for _ in 0..6 {
+            reporter.report_attack(hijack("1.2.3.4:443 <-> 5.6.7.8:51234"));
+        }
+        reporter.report_attack(hijack("9.9.9.9:443 <-> 5.6.7.8:51234"));
+
+        let reports = reports.borrow();
+        let confidence: Vec<_> = reports.iter().map(|report| report.confidence).collect();
+        assert_eq!(confidence, [50, 55, 60, 65, 70, 70, 70]);
+        assert_eq!(reports[6].context.prior_reports, Some(6));
+        assert_eq!(store.borrow().get("9.9.9.9".parse().unwrap()).map(|o| o.reports), Some(1));
+        // saved by the owner
+        assert!(!path.exists());
+    }
+}
@@ -0,0 +1,133 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::io::{self, Write};
+use std::net::IpAddr;
+use std::process::{Command, Stdio};
+use std::rc::Rc;
+use std::time::{Duration, Instant};
+
+use crate::event::{AnomalyReport, AttackReport, AttackReporter};
+use crate::types::Cidr;
+
+/// nftables table owned by the responder.
+pub const NFT_TABLE: &str = "detect_inj";
+/// Least confidence in percent of an attack its offender is blocked for by default.
+pub const DEFAULT_BLOCK_CONFIDENCE: u8 = 80;
+
+/// Creates the table, the address sets and a chain dropping their members.
+/// Safe to run repeatedly, existing blocks are kept.
+const NFT_SETUP: &str = "\
+add table inet detect_inj
+add set inet detect_inj blocked_v4 { type ipv4_addr; flags timeout; }
+add set inet detect_inj blocked_v6 { type ipv6_addr; flags timeout; }
+add chain inet detect_inj prerouting { type filter hook prerouting priority -300; policy accept; }
+flush chain inet detect_inj prerouting
+add rule inet detect_inj prerouting ip saddr @blocked_v4 drop
+add rule inet detect_inj prerouting ip6 saddr @blocked_v6 drop
+";
+
+/// Temporarily blocks offending addresses with nftables.
+///
+/// Expiry is left to the kernel via set element timeouts.
+pub struct NftBlocker {
+    ttl: Duration,
+    allowlist: Vec<Cidr>,
+    blocked: HashMap<IpAddr, Instant>,
+}
+
+impl NftBlocker {
+    pub fn new(ttl: Duration, allowlist: Vec<Cidr>) -> Self {
+        Self{ ttl, allowlist, blocked: HashMap::new() }
+    }
+
+    /// Installs the nftables table the blocks go to.
+    pub fn setup(&self) -> io::Result<()> {
+        run_nft(NFT_SETUP)
+    }
+
+    /// Blocks `addr` unless it's allowlisted or already blocked.
+    /// Returns whether a new block was inserted.
+    pub fn block(&mut self, addr: IpAddr) -> io::Result<bool> {
+        if self.allowlist.iter().any(|network| network.contains(addr)) {
+            return Ok(false)
+        }
+        let ttl = self.ttl;
+        self.blocked.retain(|_, blocked_at| blocked_at.elapsed() < ttl);
+        if self.blocked.contains_key(&addr) {
+            return Ok(false)
+        }
+        run_nft(&element_script(addr, ttl))?;
+        self.blocked.insert(addr, Instant::now());
+        Ok(true)
+    }
+}
+
+fn element_script(addr: IpAddr, ttl: Duration) -> String {
+    let set = if addr.is_ipv4() { "blocked_v4" } else { "blocked_v6" };
+    format!("add element inet {} {} {{ {} timeout {}s }}\n", NFT_TABLE, set, addr, ttl.as_secs().max(1))
+}
+
+fn run_nft(script: &str) -> io::Result<()> {
+    let mut nft = Command::new("nft")
+        .args(["-f", "-"])
+        .stdin(Stdio::piped())
+        .spawn()?;
+    nft.stdin.take().expect("stdin is piped").write_all(script.as_bytes())?;
+    let status = nft.wait()?;
+    if !status.success() {
+        return Err(io::Error::other(format!("nft exited with {}", status)))
+    }
+    Ok(())
+}
+
+/// Passes reports on and blocks the offender of attacks reported with at least `min_confidence`.
+pub struct BlockingReporter {
+    inner: Box<dyn AttackReporter>,
+    blocker: Rc<RefCell<NftBlocker>>,
+    min_confidence: u8,
+}
+
+impl BlockingReporter {
+    pub fn new(inner: Box<dyn AttackReporter>, blocker: Rc<RefCell<NftBlocker>>, min_confidence: u8) -> Self {
+        Self{ inner, blocker, min_confidence }
+    }
+}
+
+impl AttackReporter for BlockingReporter {
+    fn attacks_reported(&self) -> u32 {
+        self.inner.attacks_reported()
+    }
+
+    fn report_attack(&mut self, report: AttackReport) {
+        let offender = report.offender().filter(|_| report.confidence >= self.min_confidence);
+        self.inner.report_attack(report);
+        if let Some(addr) = offender {
+            let mut blocker = self.blocker.borrow_mut();
+            match blocker.block(addr) {
+                Ok(true) => eprintln!("Blocked {} for {}s", addr, blocker.ttl.as_secs()),
+                Ok(false) => {}
+                Err(err) => eprintln!("Failed to block {}: {}", addr, err),
+            }
+        }
+    }
+
+    fn report_anomaly(&mut self, report: AnomalyReport) {
+        self.inner.report_anomaly(report);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn allowlisted_addresses_are_never_blocked() {
+        let mut blocker = NftBlocker::new(Duration::from_secs(600), vec!["10.0.0.0/8".parse().unwrap()]);
+        assert!(!blocker.block("10.1.2.3".parse().unwrap()).unwrap());
+
+        assert_eq!(
+            element_script("2001:db8::1".parse().unwrap(), Duration::from_secs(600)),
+            "add element inet detect_inj blocked_v6 { 2001:db8::1 timeout 600s }\n",
+        );
+    }
+}
@@ -0,0 +1,321 @@
+//! Linux AF_PACKET capture through a TPACKET_V3 memory mapped ring.
+//!
+//! The kernel fills fixed size blocks of the ring with frames and hands a block over once it's
+//! full or its timeout expires. Frames are read straight from the ring, the block is given back
+//! to the kernel only after all its frames were analyzed. The ring only receives, frames are
+//! not forwarded.
+
+use std::io;
+use std::ops::Range;
+use std::os::unix::io::RawFd;
+use std::ptr;
+use std::sync::atomic::{fence, Ordering};
+use std::time::{Duration, UNIX_EPOCH};
+
+use crate::filter::BpfInstruction;
+use crate::tcp_iterator::{CaptureSource, CaptureStats};
+use crate::types::{LinkType, PacketMeta};
+
+const TPACKET_V3: libc::c_int = 2;
+const PACKET_RX_RING: libc::c_int = 5;
+const PACKET_STATISTICS: libc::c_int = 6;
+const PACKET_VERSION: libc::c_int = 10;
+const TP_STATUS_KERNEL: u32 = 0;
+const TP_STATUS_USER: u32 = 1;
+
+/// Offsets of `tpacket_block_desc` fields.
+const BLOCK_STATUS: usize = 8;
+const BLOCK_NUM_PACKETS: usize = 12;
+const BLOCK_FIRST_PACKET: usize = 16;
+/// Offsets of `tpacket3_hdr` fields.
+const PACKET_NEXT_OFFSET: usize = 0;
+const PACKET_SEC: usize = 4;
+const PACKET_NSEC: usize = 8;
+const PACKET_SNAPLEN: usize = 12;
+const PACKET_LEN: usize = 16;
+const PACKET_MAC: usize = 24;
+const PACKET_HEADER_LEN: usize = 48;
+
+/// `struct tpacket_req3`
+#[repr(C)]
+struct TpacketReq3 {
+    block_size: u32,
+    block_nr: u32,
+    frame_size: u32,
+    frame_nr: u32,
+    retire_blk_tov: u32,
+    sizeof_priv: u32,
+    feature_req_word: u32,
+}
+
+#[derive(Debug, Copy, Clone)]
+pub struct RingConfig {
+    /// Must be a multiple of the page size.
+    pub block_size: u32,
+    pub block_count: u32,
+    /// Largest frame expected, a hint for the kernel.
+    pub frame_size: u32,
+    /// A block partially filled is handed over after this long, bounding capture latency.
+    pub block_timeout: Duration,
+    /// Whether to see traffic of other hosts, some NICs only deliver our own otherwise.
+    pub promiscuous: bool,
+}
+
+impl Default for RingConfig {
+    fn default() -> Self {
+        Self {
+            block_size: 1 << 20,
+            block_count: 64,
+            frame_size: 2048,
+            block_timeout: Duration::from_millis(64),
+            promiscuous: true,
+        }
+    }
+}
+
+pub struct RingCapture {
+    fd: RawFd,
+    ring: *mut u8,
+    config: RingConfig,
+    iface_id: u32,
+    /// Block being read.
+    block: usize,
+    /// Offset of the next frame in the block and frames left, `None` until the kernel hands the block over.
+    cursor: Option<(usize, u32)>,
+    /// Kernel counters are reset whenever they're read, these add them up.
+    stats: CaptureStats,
+}
+
+/// `struct tpacket_stats_v3`
+#[repr(C)]
+#[derive(Default)]
+struct TpacketStatsV3 {
+    tp_packets: u32,
+    tp_drops: u32,
+    tp_freeze_q_cnt: u32,
+}
+
+impl RingCapture {
+    pub fn open(iface_id: u32, config: RingConfig) -> io::Result<Self> {
+        let protocol = (libc::ETH_P_ALL as u16).to_be();
+        let fd = unsafe { libc::socket(libc::AF_PACKET, libc::SOCK_RAW, libc::c_int::from(protocol)) };
+        if fd < 0 {
+            return Err(io::Error::last_os_error())
+        }
+        // owns the socket from now on, closes it on errors below
+        let mut capture = Self {
+            fd,
+            ring: ptr::null_mut(),
+            config,
+            iface_id,
+            block: 0,
+            cursor: None,
+            stats: CaptureStats::default(),
+        };
+
+        setsockopt(fd, PACKET_VERSION, &TPACKET_V3)?;
+        let request = TpacketReq3 {
+            block_size: config.block_size,
+            block_nr: config.block_count,
+            frame_size: config.frame_size,
+            frame_nr: config.block_size / config.frame_size * config.block_count,
+            retire_blk_tov: config.block_timeout.as_millis() as u32,
+            sizeof_priv: 0,
+            feature_req_word: 0,
+        };
+        setsockopt(fd, PACKET_RX_RING, &request)?;
+        let ring = unsafe {
+            libc::mmap(ptr::null_mut(), capture.ring_len(), libc::PROT_READ | libc::PROT_WRITE, libc::MAP_SHARED, fd, 0)
+        };
+        if ring == libc::MAP_FAILED {
+            return Err(io::Error::last_os_error())
+        }
+        capture.ring = ring as *mut u8;
+
+        let mut addr: libc::sockaddr_ll = unsafe { std::mem::zeroed() };
+        addr.sll_family = libc::AF_PACKET as u16;
+        addr.sll_protocol = protocol;
+        addr.sll_ifindex = iface_id as i32;
+        let addr_len = std::mem::size_of::<libc::sockaddr_ll>() as libc::socklen_t;
+        if unsafe { libc::bind(fd, &addr as *const _ as *const libc::sockaddr, addr_len) } < 0 {
+            return Err(io::Error::last_os_error())
+        }
+        if config.promiscuous {
+            // the kernel leaves promiscuous mode once the socket is closed
+            let membership = libc::packet_mreq {
+                mr_ifindex: iface_id as i32,
+                mr_type: libc::PACKET_MR_PROMISC as u16,
+                mr_alen: 0,
+                mr_address: [0; 8],
+            };
+            setsockopt(fd, libc::PACKET_ADD_MEMBERSHIP, &membership)?;
+        }
+        Ok(capture)
+    }
+
+    /// Has the kernel drop frames the program rejects before they take ring space.
+    pub fn attach_filter(&mut self, program: &[BpfInstruction]) -> io::Result<()> {
+        let program = libc::sock_fprog {
+            len: program.len() as u16,
+            filter: program.as_ptr() as *mut libc::sock_filter,
+        };
+        let len = std::mem::size_of::<libc::sock_fprog>() as libc::socklen_t;
+        let program_ptr = &program as *const libc::sock_fprog as *const libc::c_void;
+        if unsafe { libc::setsockopt(self.fd, libc::SOL_SOCKET, libc::SO_ATTACH_FILTER, program_ptr, len) } < 0 {
+            return Err(io::Error::last_os_error())
+        }
+        Ok(())
+    }
+
+    fn ring_len(&self) -> usize {
+        self.config.block_size as usize * self.config.block_count as usize
+    }
+
+    fn block_ptr(&self) -> *mut u8 {
+        unsafe { self.ring.add(self.block * self.config.block_size as usize) }
+    }
+
+    fn block_slice(&self) -> &[u8] {
+        unsafe { std::slice::from_raw_parts(self.block_ptr(), self.config.block_size as usize) }
+    }
+
+    fn block_status(&self) -> *mut u32 {
+        unsafe { self.block_ptr().add(BLOCK_STATUS) as *mut u32 }
+    }
+
+    fn wait(&self) -> io::Result<()> {
+        let mut poll = libc::pollfd{ fd: self.fd, events: libc::POLLIN | libc::POLLERR, revents: 0 };
+        match unsafe { libc::poll(&mut poll, 1, -1) } {
+            n if n >= 0 => Ok(()),
+            _ => match io::Error::last_os_error() {
+                err if err.kind() == io::ErrorKind::Interrupted => Ok(()),
+                err => Err(err),
+            },
+        }
+    }
+}
+
+impl CaptureSource for RingCapture {
+    fn next_frame(&mut self) -> io::Result<Option<(PacketMeta, &[u8])>> {
+        loop {
+            let (offset, left) = match self.cursor {
+                Some(cursor) => cursor,
+                None => {
+                    if unsafe { ptr::read_volatile(self.block_status()) } & TP_STATUS_USER == 0 {
+                        self.wait()?;
+                        continue
+                    }
+                    // frames written by the kernel are visible after the status
+                    fence(Ordering::Acquire);
+                    let block = self.block_slice();
+                    let cursor = (field(block, BLOCK_FIRST_PACKET) as usize, field(block, BLOCK_NUM_PACKETS));
+                    self.cursor = Some(cursor);
+                    cursor
+                }
+            };
+            if left == 0 {
+                // all frames of the block were analyzed, the kernel may reuse it
+                fence(Ordering::Release);
+                unsafe { ptr::write_volatile(self.block_status(), TP_STATUS_KERNEL) };
+                self.block = (self.block + 1) % self.config.block_count as usize;
+                self.cursor = None;
+                continue
+            }
+
+            let block = self.block_slice();
+            let frame = frame_at(block, offset);
+            let (meta, frame, next) = match frame {
+                Some(frame) => frame,
+                // a corrupt block, skip the rest of it
+                None => {
+                    self.cursor = Some((offset, 0));
+                    continue
+                }
+            };
+            self.cursor = Some((offset + next, left - 1));
+            let meta = PacketMeta{ iface_id: self.iface_id, ..meta };
+            return Ok(Some((meta, &self.block_slice()[frame])))
+        }
+    }
+
+    fn stats(&mut self) -> io::Result<Option<CaptureStats>> {
+        let mut stats = TpacketStatsV3::default();
+        let mut len = std::mem::size_of::<TpacketStatsV3>() as libc::socklen_t;
+        let stats_ptr = &mut stats as *mut TpacketStatsV3 as *mut libc::c_void;
+        if unsafe { libc::getsockopt(self.fd, libc::SOL_PACKET, PACKET_STATISTICS, stats_ptr, &mut len) } < 0 {
+            return Err(io::Error::last_os_error())
+        }
+        // the kernel counts dropped frames in the packets too
+        self.stats.received += u64::from(stats.tp_packets);
+        self.stats.dropped += u64::from(stats.tp_drops);
+        Ok(Some(self.stats))
+    }
+}
+
+impl Drop for RingCapture {
+    fn drop(&mut self) {
+        unsafe {
+            if !self.ring.is_null() {
+                libc::munmap(self.ring as *mut libc::c_void, self.ring_len());
+            }
+            libc::close(self.fd);
+        }
+    }
+}
+
+fn setsockopt<T>(fd: RawFd, option: libc::c_int, value: &T) -> io::Result<()> {
+    let len = std::mem::size_of::<T>() as libc::socklen_t;
+    if unsafe { libc::setsockopt(fd, libc::SOL_PACKET, option, value as *const T as *const libc::c_void, len) } < 0 {
+        return Err(io::Error::last_os_error())
+    }
+    Ok(())
+}
+
+fn field(bytes: &[u8], offset: usize) -> u32 {
+    bytes.get(offset..offset + 4).map_or(0, |field| u32::from_ne_bytes([field[0], field[1], field[2], field[3]]))
+}
+
+/// Frame at `offset` of a block: its metadata, range within the block and offset of the next one.
+fn frame_at(block: &[u8], offset: usize) -> Option<(PacketMeta, Range<usize>, usize)> {
+    let header = block.get(offset..offset + PACKET_HEADER_LEN)?;
+    let mac = u16::from_ne_bytes([header[PACKET_MAC], header[PACKET_MAC + 1]]) as usize;
+    let (cap_len, wire_len) = (field(header, PACKET_SNAPLEN), field(header, PACKET_LEN));
+    let frame = offset + mac..offset + mac + cap_len as usize;
+    if frame.end > block.len() {
+        return None
+    }
+    let meta = PacketMeta {
+        ts: UNIX_EPOCH + Duration::new(field(header, PACKET_SEC).into(), field(header, PACKET_NSEC)),
+        iface_id: 0,
+        wire_len,
+        cap_len,
+        link_type: LinkType::Ethernet,
+    };
+    Some((meta, frame, field(header, PACKET_NEXT_OFFSET) as usize))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn frames_of_a_block() {
+        let mut block = vec![0; 256];
+        let mut put = |offset: usize, value: u32| block[offset..offset + 4].copy_from_slice(&value.to_ne_bytes());
+        for &(at, value) in &[(PACKET_NEXT_OFFSET, 128), (PACKET_SEC, 7), (PACKET_NSEC, 5), (PACKET_SNAPLEN, 4), (PACKET_LEN, 60)] {
+            put(64 + at, value);
+        }
+        block[64 + PACKET_MAC..64 + PACKET_MAC + 2].copy_from_slice(&66u16.to_ne_bytes());
+        block[130..134].copy_from_slice(b"\x02\x00\x00\x01");
+
+        let (meta, frame, next) = frame_at(&block, 64).unwrap();
+        assert_eq!(&block[frame], b"\x02\x00\x00\x01");
+        assert_eq!((meta.cap_len, meta.wire_len, next), (4, 60, 128));
+        assert_eq!(meta.ts, UNIX_EPOCH + Duration::new(7, 5));
+
+        // header or frame past the end of the block
+        assert!(frame_at(&block, 240).is_none());
+        block[64 + PACKET_SNAPLEN] = 200;
+        assert!(frame_at(&block, 64).is_none());
+    }
+}
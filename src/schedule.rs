@@ -0,0 +1,203 @@
+//! Weekly time windows turning capture or particular detectors on and off.
+
+use std::cell::Cell;
+use std::{error, fmt};
+use std::str::FromStr;
+
+use time::PrimitiveDateTime;
+
+const DAY_NAMES: [&str; 7] = ["mon", "tue", "wed", "thu", "fri", "sat", "sun"];
+const MINUTES_PER_DAY: u16 = 24 * 60;
+
+/// What a schedule switches.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum ScheduleTarget {
+    /// All analysis; tracked connections are dropped when it's switched off.
+    Capture,
+    Hijack,
+    Decoy,
+}
+
+impl FromStr for ScheduleTarget {
+    type Err = ParseScheduleError;
+    fn from_str(s: &str) -> Result<Self, ParseScheduleError> {
+        match s {
+            "capture" => Ok(ScheduleTarget::Capture),
+            "hijack" => Ok(ScheduleTarget::Hijack),
+            "decoy" => Ok(ScheduleTarget::Decoy),
+            _ => Err(ParseScheduleError(s.to_owned())),
+        }
+    }
+}
+
+/// Weekly window like `mon-fri 09:00-17:30`, in UTC.
+///
+/// Days are a comma separated list of days or day ranges, `*` for every day.
+/// A window ending before it starts runs past midnight into the next day.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct Window {
+    /// Days the window starts on, Monday first.
+    days: [bool; 7],
+    start: u16,
+    end: u16,
+}
+
+impl Window {
+    /// Whether the window covers `minute` of the day `weekday` (0 is Monday).
+    pub fn contains(&self, weekday: u8, minute: u16) -> bool {
+        let weekday = usize::from(weekday % 7);
+        let yesterday = (weekday + 6) % 7;
+        if self.start <= self.end {
+            self.days[weekday] && self.start <= minute && minute < self.end
+        } else {
+            (self.days[weekday] && minute >= self.start) || (self.days[yesterday] && minute < self.end)
+        }
+    }
+}
+
+impl FromStr for Window {
+    type Err = ParseScheduleError;
+    fn from_str(s: &str) -> Result<Self, ParseScheduleError> {
+        let err = || ParseScheduleError(s.to_owned());
+        let mut parts = s.split_whitespace();
+        let (days, hours) = match (parts.next(), parts.next(), parts.next()) {
+            (Some(days), Some(hours), None) => (days, hours),
+            _ => return Err(err()),
+        };
+
+        let mut day_set = [false; 7];
+        for item in days.split(',') {
+            let (first, last) = match item.find('-') {
+                _ if item == "*" => (0, 6),
+                Some(dash) => (day(&item[..dash]).ok_or_else(err)?, day(&item[dash + 1..]).ok_or_else(err)?),
+                None => (day(item).ok_or_else(err)?, day(item).ok_or_else(err)?),
+            };
+            // ranges may wrap around the week, e.g. `sat-mon`
+            let mut d = first;
+            loop {
+                day_set[d] = true;
+                if d == last {
+                    break
+                }
+                d = (d + 1) % 7;
+            }
+        }
+
+        let dash = hours.find('-').ok_or_else(err)?;
+        let start = minute_of_day(&hours[..dash]).ok_or_else(err)?;
+        let end = minute_of_day(&hours[dash + 1..]).ok_or_else(err)?;
+        Ok(Self{ days: day_set, start, end })
+    }
+}
+
+fn day(name: &str) -> Option<usize> {
+    DAY_NAMES.iter().position(|&day| day.eq_ignore_ascii_case(name))
+}
+
+/// Parses `HH:MM`, `24:00` is accepted as the end of the day.
+fn minute_of_day(s: &str) -> Option<u16> {
+    let colon = s.find(':')?;
+    let (hour, minute): (u16, u16) = (s[..colon].parse().ok()?, s[colon + 1..].parse().ok()?);
+    if minute >= 60 {
+        return None
+    }
+    let minute_of_day = hour.checked_mul(60)?.checked_add(minute)?;
+    if minute_of_day > MINUTES_PER_DAY {
+        return None
+    }
+    Some(minute_of_day)
+}
+
+/// Schedule entry parsed from `<target> <days> <HH:MM>-<HH:MM>`.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct ScheduleRule {
+    pub target: ScheduleTarget,
+    pub window: Window,
+}
+
+impl FromStr for ScheduleRule {
+    type Err = ParseScheduleError;
+    fn from_str(s: &str) -> Result<Self, ParseScheduleError> {
+        let s = s.trim();
+        let space = s.find(char::is_whitespace).ok_or_else(|| ParseScheduleError(s.to_owned()))?;
+        let target = s[..space].parse()?;
+        let window = s[space..].parse().map_err(|_| ParseScheduleError(s.to_owned()))?;
+        Ok(Self{ target, window })
+    }
+}
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct ParseScheduleError(String);
+
+impl fmt::Display for ParseScheduleError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "invalid schedule `{}`, expected `<capture|hijack|decoy> <days> <HH:MM>-<HH:MM>`", self.0)
+    }
+}
+
+impl error::Error for ParseScheduleError {}
+
+/// A target with no windows is always active, otherwise it's active within any of its windows.
+#[derive(Debug, Clone, Default)]
+pub struct Schedule {
+    rules: Vec<ScheduleRule>,
+}
+
+impl Schedule {
+    pub fn new(rules: Vec<ScheduleRule>) -> Self {
+        Self{ rules }
+    }
+
+    pub fn is_active(&self, target: ScheduleTarget, at: PrimitiveDateTime) -> bool {
+        let weekday = at.weekday().number_days_from_monday();
+        let minute = u16::from(at.hour()) * 60 + u16::from(at.minute());
+        let mut windows = self.rules.iter().filter(|rule| rule.target == target).peekable();
+        windows.peek().is_none() || windows.any(|rule| rule.window.contains(weekday, minute))
+    }
+}
+
+/// Detectors which may be switched off at runtime, shared by all connections.
+#[derive(Debug)]
+pub struct DetectorSwitches {
+    pub hijack: Cell<bool>,
+}
+
+impl Default for DetectorSwitches {
+    fn default() -> Self {
+        Self{ hijack: Cell::new(true) }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use time::Date;
+
+    #[test]
+    fn windows_and_schedule() {
+        let business: Window = "mon-fri 09:00-17:30".parse().unwrap();
+        assert!(business.contains(0, 9 * 60));
+        assert!(!business.contains(0, 17 * 60 + 30));
+        assert!(!business.contains(5, 12 * 60));
+
+        let night: Window = "sat,sun 22:00-02:00".parse().unwrap();
+        assert!(night.contains(6, 23 * 60));
+        // Sunday night runs into Monday
+        assert!(night.contains(0, 60));
+        assert!(!night.contains(1, 60));
+
+        assert!("* 00:00-24:00".parse::<Window>().unwrap().contains(3, 0));
+        assert!("mon-fri 09:00".parse::<Window>().is_err());
+        assert!("mon-fry 09:00-10:00".parse::<Window>().is_err());
+        assert!("mon 09:60-10:00".parse::<Window>().is_err());
+        assert!("mon 1000:65000-10:00".parse::<Window>().is_err());
+        assert!("mon 1092:59-10:00".parse::<Window>().is_err());
+
+        let schedule = Schedule::new(vec!["capture mon-fri 09:00-17:00".parse().unwrap()]);
+        // 2020-03-02 was a Monday
+        let monday = |hour| Date::try_from_ymd(2020, 3, 2).unwrap().try_with_hms(hour, 0, 0).unwrap();
+        assert!(schedule.is_active(ScheduleTarget::Capture, monday(10)));
+        assert!(!schedule.is_active(ScheduleTarget::Capture, monday(18)));
+        assert!(schedule.is_active(ScheduleTarget::Hijack, monday(18)));
+    }
+}
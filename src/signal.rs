@@ -0,0 +1,43 @@
+//! Requests sent to a running sensor with signals, e.g. `kill -USR1 <pid>` to dump the connection table.
+//!
+//! Handlers only take note of a request, the capture loop serves it between packets.
+
+use std::io;
+use std::ptr;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+static DUMP_REQUESTED: AtomicBool = AtomicBool::new(false);
+
+extern "C" fn request_dump(_signal: libc::c_int) {
+    DUMP_REQUESTED.store(true, Ordering::Relaxed);
+}
+
+/// Takes SIGUSR1 for a request to dump the connection table instead of terminating.
+pub fn handle_dump_requests() -> io::Result<()> {
+    let mut action: libc::sigaction = unsafe { std::mem::zeroed() };
+    action.sa_sigaction = request_dump as extern "C" fn(libc::c_int) as libc::sighandler_t;
+    // reads blocked waiting for frames go on waiting
+    action.sa_flags = libc::SA_RESTART;
+    if unsafe { libc::sigaction(libc::SIGUSR1, &action, ptr::null_mut()) } < 0 {
+        return Err(io::Error::last_os_error())
+    }
+    Ok(())
+}
+
+/// Whether a dump was requested since the last call.
+pub fn take_dump_request() -> bool {
+    DUMP_REQUESTED.swap(false, Ordering::Relaxed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn dump_requested_once() {
+        handle_dump_requests().unwrap();
+        assert_eq!(unsafe { libc::raise(libc::SIGUSR1) }, 0);
+        assert!(take_dump_request());
+        assert!(!take_dump_request());
+    }
+}
@@ -1,88 +1,600 @@
+use std::collections::VecDeque;
+use std::collections::hash_map::DefaultHasher;
 use std::convert::TryFrom;
-use std::net::IpAddr;
-use std::io;
+use std::hash::{Hash, Hasher};
+use std::net::{IpAddr, Ipv4Addr};
+use std::str::FromStr;
+use std::{error, fmt, io};
+use std::time::{Duration, Instant, SystemTime};
 
-use pnet::datalink::{DataLinkReceiver, DataLinkSender, NetworkInterface, channel};
+use pnet::datalink::{Channel, Config, DataLinkReceiver, DataLinkSender, NetworkInterface};
 use pnet::datalink::Channel::Ethernet;
+#[cfg(not(feature = "netmap"))]
+use pnet::datalink::channel;
 use pdu;
 
-use crate::types::{PacketManifest, IpLayer, TcpLayer, TcpFlags};
+use crate::filter::Filter;
+use crate::probe;
+use crate::reassembly::{FragmentCache, FragmentKey, ReassemblyStats};
+use crate::types::{PacketManifest, PacketMeta, LinkType, VlanStack, Tunnel, Encapsulation, EthernetLayer, IpLayer, TcpLayer, TcpFlags, TcpOptions};
 
-pub struct TcpIterator {
-    send: Box<dyn DataLinkSender + 'static>,
+/// Where captured frames come from: a live interface or a capture file.
+pub trait CaptureSource {
+    /// Next frame with its capture metadata, which tells its link type, `None` once a finite source is exhausted.
+    fn next_frame(&mut self) -> io::Result<Option<(PacketMeta, &[u8])>>;
+
+    /// Frames received and dropped by the capture so far, `None` if the source can't tell.
+    fn stats(&mut self) -> io::Result<Option<CaptureStats>> {
+        Ok(None)
+    }
+}
+
+/// Capture counters, frames dropped for lack of buffer space never made it to analysis.
+#[derive(Debug, Default, Copy, Clone, Eq, PartialEq)]
+pub struct CaptureStats {
+    /// Frames seen by the capture, dropped ones included.
+    pub received: u64,
+    pub dropped: u64,
+}
+
+impl fmt::Display for CaptureStats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let share = if self.received == 0 { 0.0 } else { self.dropped as f64 * 100.0 / self.received as f64 };
+        write!(f, "received={} dropped={} ({:.1}%)", self.received, self.dropped, share)
+    }
+}
+
+/// What to do with TCP segments whose checksum is wrong. Receivers drop such segments, so an
+/// attacker may send them to make a sensor see data the endpoint never does.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum ChecksumPolicy {
+    /// Segments with a bad checksum are filtered out.
+    Drop,
+    /// Segments with a bad checksum are marked, connections report them.
+    Flag,
+}
+
+impl FromStr for ChecksumPolicy {
+    type Err = ParseChecksumPolicyError;
+    fn from_str(s: &str) -> Result<Self, ParseChecksumPolicyError> {
+        match s {
+            "drop" => Ok(ChecksumPolicy::Drop),
+            "flag" => Ok(ChecksumPolicy::Flag),
+            _ => Err(ParseChecksumPolicyError(s.to_owned())),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct ParseChecksumPolicyError(String);
+
+impl fmt::Display for ParseChecksumPolicyError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "invalid checksum policy `{}`, expected `drop` or `flag`", self.0)
+    }
+}
+
+impl error::Error for ParseChecksumPolicyError {}
+
+/// Receive side of a capture interface.
+pub struct LiveCapture {
     recv: Box<dyn DataLinkReceiver + 'static>,
+    iface_id: u32,
 }
 
+impl CaptureSource for LiveCapture {
+    fn next_frame(&mut self) -> io::Result<Option<(PacketMeta, &[u8])>> {
+        let frame = self.recv.next()?;
+        let meta = PacketMeta {
+            // pnet doesn't expose kernel timestamps, receive time is the closest we have
+            ts: SystemTime::now(),
+            iface_id: self.iface_id,
+            wire_len: frame.len() as u32,
+            cap_len: frame.len() as u32,
+            link_type: LinkType::Ethernet,
+        };
+        Ok(Some((meta, frame)))
+    }
+}
+
+pub struct TcpIterator {
+    source: Box<dyn CaptureSource>,
+    /// Transmit side of the capture interface, `None` for sources that can't send.
+    send: Option<Box<dyn DataLinkSender + 'static>>,
+    sent: SentFrames,
+    filter: Option<Filter>,
+    /// Whether captured frames are sent back out, for inline deployments bridging traffic.
+    forward: bool,
+    fragments: FragmentCache,
+    /// TCP checksums are only verified with a policy, offloading makes outgoing ones look wrong.
+    checksum_policy: Option<ChecksumPolicy>,
+    bad_checksums: u64,
+}
+
+/// What parsing needs besides the bytes on the way down the layers of a frame.
+#[derive(Default)]
+struct Decap<'p> {
+    /// Tunnels decapsulated so far.
+    depth: u8,
+    /// Cache for IPv4 fragments and the capture time, `None` if fragments are dropped.
+    fragments: Option<(&'p mut FragmentCache, SystemTime)>,
+    verify_checksums: bool,
+    /// Whether the IP payload at hand was captured whole, its checksum can't be verified otherwise.
+    whole_payload: bool,
+}
+
+// boxing the manifest would allocate for every packet
+#[allow(clippy::large_enum_variant)]
 pub enum Packet<'p> {
     Tcp(PacketManifest<'p>),
     /// Represents a packet that wasn't recognized as TCP.
     FilteredOut(&'p [u8]),
+    /// Frame transmitted by ourselves and captured back, must not be analyzed.
+    SelfSent(&'p [u8]),
+    /// No frame arrived within the read timeout.
+    Idle,
+}
+
+const ETHERTYPE_DOT1Q: u16 = 0x8100;
+/// 802.1ad service tag, the outer tag of QinQ frames.
+const ETHERTYPE_QINQ: u16 = 0x88a8;
+/// Service tag used before 802.1ad was standardized.
+const ETHERTYPE_QINQ_LEGACY: u16 = 0x9100;
+const ETHERTYPE_MPLS: u16 = 0x8847;
+const ETHERTYPE_MPLS_MULTICAST: u16 = 0x8848;
+const ETHERTYPE_PPPOE_SESSION: u16 = 0x8864;
+const PPP_IPV4: u16 = 0x0021;
+const PPP_IPV6: u16 = 0x0057;
+/// GRE protocol of ERSPAN type I and II, told apart by the GRE sequence number.
+const ETHERTYPE_ERSPAN_II: u16 = 0x88be;
+const ETHERTYPE_ERSPAN_III: u16 = 0x22eb;
+const GENEVE_PORT: u16 = 6081;
+/// IPv4 in IP, IPv6 in IP (6in4 and 6in6).
+const IPPROTO_IPIP: u8 = 4;
+const IPPROTO_IPV6: u8 = 41;
+/// Tunnels nested deeper are not decapsulated.
+const MAX_TUNNEL_DEPTH: u8 = 2;
+
+/// Bytes read per frame from a live interface by default, enough for frames coalesced by GRO.
+pub const DEFAULT_SNAPLEN: usize = 65535;
+
+/// Configuration of the datalink channel of a live interface.
+#[derive(Debug, Copy, Clone)]
+pub struct ChannelOptions {
+    /// Frames longer than this are truncated.
+    pub snaplen: usize,
+    /// Whether to see traffic of other hosts, some NICs only deliver our own otherwise.
+    pub promiscuous: bool,
+    /// Waiting for a frame longer than this yields [`Packet::Idle`], waits forever if `None`.
+    pub read_timeout: Option<Duration>,
+    /// Capture through netmap instead of the operating system's packet capture, for line rate
+    /// on FreeBSD. Needs the `netmap` feature.
+    pub netmap: bool,
+}
+
+impl Default for ChannelOptions {
+    fn default() -> Self {
+        Self{ snaplen: DEFAULT_SNAPLEN, promiscuous: true, read_timeout: None, netmap: false }
+    }
+}
+
+/// How long a sent frame is expected to possibly show up in the capture.
+const SENT_FRAME_TTL: Duration = Duration::from_secs(1);
+/// Bound on remembered sent frames, the oldest ones are forgotten first.
+const SENT_FRAMES_MAX: usize = 4096;
+
+/// Hashes of recently transmitted frames.
+#[derive(Default)]
+struct SentFrames {
+    hashes: VecDeque<(u64, Instant)>,
+}
+
+impl SentFrames {
+    fn record(&mut self, frame: &[u8]) {
+        self.expire();
+        if self.hashes.len() == SENT_FRAMES_MAX {
+            self.hashes.pop_front();
+        }
+        self.hashes.push_back((frame_hash(frame), Instant::now()));
+    }
+
+    /// Whether the frame was sent by us. Each sent frame is matched at most once,
+    /// so a genuine duplicate of it still gets through.
+    fn take(&mut self, frame: &[u8]) -> bool {
+        self.expire();
+        let hash = frame_hash(frame);
+        match self.hashes.iter().position(|&(sent, _)| sent == hash) {
+            Some(i) => {
+                self.hashes.remove(i);
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn expire(&mut self) {
+        while let Some(&(_, sent_at)) = self.hashes.front() {
+            if sent_at.elapsed() < SENT_FRAME_TTL {
+                break
+            }
+            self.hashes.pop_front();
+        }
+    }
+}
+
+fn frame_hash(frame: &[u8]) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    frame.hash(&mut hasher);
+    hasher.finish()
+}
+
+#[cfg(feature = "netmap")]
+fn netmap_channel(interface: &NetworkInterface, config: &Config) -> io::Result<Channel> {
+    pnet::datalink::netmap::channel(interface, config.into())
+}
+
+#[cfg(not(feature = "netmap"))]
+fn netmap_channel(_: &NetworkInterface, _: &Config) -> io::Result<Channel> {
+    Err(io::Error::other("built without netmap support, enable the `netmap` feature"))
+}
+
+/// The `netmap` feature makes netmap pnet's default, the system's capture is then asked for by name.
+#[cfg(all(feature = "netmap", target_os = "linux"))]
+fn os_channel(interface: &NetworkInterface, config: Config) -> io::Result<Channel> {
+    pnet::datalink::linux::channel(interface, (&config).into())
+}
+
+#[cfg(all(feature = "netmap", not(target_os = "linux")))]
+fn os_channel(interface: &NetworkInterface, config: Config) -> io::Result<Channel> {
+    pnet::datalink::bpf::channel(interface, (&config).into())
+}
+
+#[cfg(not(feature = "netmap"))]
+fn os_channel(interface: &NetworkInterface, config: Config) -> io::Result<Channel> {
+    channel(interface, config)
 }
 
 impl TryFrom<&NetworkInterface> for TcpIterator {
     type Error = io::Error;
     fn try_from(interface: &NetworkInterface) -> io::Result<Self> {
-        match channel(interface, Default::default())? {
-            Ethernet(send, recv)
-                => Ok(TcpIterator{ send, recv }),
+        TcpIterator::open(interface, &ChannelOptions::default())
+    }
+}
+
+impl TcpIterator {
+    /// Captures on the interface. Frames are delivered as soon as they arrive, the channel has
+    /// no batching to turn off.
+    pub fn open(interface: &NetworkInterface, options: &ChannelOptions) -> io::Result<Self> {
+        let config = Config {
+            read_buffer_size: options.snaplen,
+            promiscuous: options.promiscuous,
+            read_timeout: options.read_timeout,
+            ..Config::default()
+        };
+        let channel = if options.netmap { netmap_channel(interface, &config)? } else { os_channel(interface, config)? };
+        match channel {
+            Ethernet(send, recv) => Ok(TcpIterator {
+                source: Box::new(LiveCapture{ recv, iface_id: interface.index }),
+                send: Some(send),
+                sent: SentFrames::default(),
+                filter: None,
+                forward: false,
+                fragments: FragmentCache::default(),
+                checksum_policy: None,
+                bad_checksums: 0,
+            }),
             _ =>
                 Err(io::Error::new(io::ErrorKind::Other, "cannot construct a channel")),
         }
     }
-}
 
-impl TcpIterator {
-    pub fn next(&mut self) -> io::Result<Packet> {
-        let ethernet_frame = self.recv.next()?;
-        let parsed = Self::parse_ethernet(ethernet_frame);
+    /// Iterates over a source which can't send, e.g. a capture file.
+    pub fn from_source(source: Box<dyn CaptureSource>) -> Self {
+        Self {
+            source,
+            send: None,
+            sent: SentFrames::default(),
+            filter: None,
+            forward: false,
+            fragments: FragmentCache::default(),
+            checksum_policy: None,
+            bad_checksums: 0,
+        }
+    }
+
+    /// Sends every captured frame back out of the interface. Off by default, which suits
+    /// observing a mirror port; forwarding there would duplicate traffic or create loops.
+    pub fn set_forwarding(&mut self, forward: bool) -> io::Result<()> {
+        if forward && self.send.is_none() {
+            return Err(io::Error::other("capture source cannot forward frames"))
+        }
+        self.forward = forward;
+        Ok(())
+    }
+
+    /// Packets not matching the filter are reported as filtered out, they are still forwarded when bridging.
+    pub fn set_filter(&mut self, filter: Filter) {
+        self.filter = Some(filter);
+    }
+
+    /// Verifies TCP checksums and handles segments failing it by the policy.
+    pub fn set_checksum_policy(&mut self, policy: ChecksumPolicy) {
+        self.checksum_policy = Some(policy);
+    }
+
+    /// Next packet, `None` once the source is exhausted.
+    pub fn next_packet(&mut self) -> io::Result<Option<Packet<'_>>> {
+        let (meta, ethernet_frame) = match self.source.next_frame() {
+            Ok(Some(frame)) => frame,
+            Ok(None) => return Ok(None),
+            Err(err) if err.kind() == io::ErrorKind::TimedOut => return Ok(Some(Packet::Idle)),
+            Err(err) => return Err(err),
+        };
+        if self.sent.take(ethernet_frame) {
+            return Ok(Some(Packet::SelfSent(ethernet_frame)))
+        }
+        let decap = Decap {
+            depth: 0,
+            fragments: Some((&mut self.fragments, meta.ts)),
+            verify_checksums: self.checksum_policy.is_some(),
+            whole_payload: false,
+        };
+        let parsed = Self::parse_frame_in(meta.link_type, ethernet_frame, decap);
 
-        let result = self.send.build_and_send(1, ethernet_frame.len(),
-                                              &mut |new_packet| {
-                                                  new_packet.copy_from_slice(ethernet_frame);
-                                              });
-        match result {
-            Some(Ok(())) => {}
-            Some(Err(err)) => return Err(err),
-            None => return Err(io::Error::new(io::ErrorKind::Other, "there is not sufficient capacity in the buffer")),
+        if let (true, Some(sender)) = (self.forward, &mut self.send) {
+            send(&mut **sender, ethernet_frame)?;
+            self.sent.record(ethernet_frame);
         }
 
         match parsed {
-            Some(layers) => Ok(Packet::Tcp(layers)),
-            None => Ok(Packet::FilteredOut(ethernet_frame))
+            Some(layers) if self.filter.as_ref().is_some_and(|filter| !filter.matches(&layers))
+                => Ok(Some(Packet::FilteredOut(ethernet_frame))),
+            Some(layers) if layers.bad_checksum && self.checksum_policy == Some(ChecksumPolicy::Drop) => {
+                self.bad_checksums += 1;
+                Ok(Some(Packet::FilteredOut(ethernet_frame)))
+            }
+            Some(mut layers) => {
+                if layers.bad_checksum {
+                    self.bad_checksums += 1;
+                }
+                layers.meta = meta;
+                Ok(Some(Packet::Tcp(layers)))
+            }
+            None => Ok(Some(Packet::FilteredOut(ethernet_frame)))
         }
     }
 
-    fn parse_ethernet(ethernet_frame: &[u8]) -> Option<PacketManifest> {
+    pub fn capture_stats(&mut self) -> io::Result<Option<CaptureStats>> {
+        self.source.stats()
+    }
+
+    pub fn fragment_stats(&self) -> ReassemblyStats {
+        self.fragments.stats()
+    }
+
+    /// TCP segments failing checksum verification so far.
+    pub fn bad_checksums(&self) -> u64 {
+        self.bad_checksums
+    }
+
+    /// Sends a frame out of the capture interface.
+    /// The frame is not reported back if it's captured.
+    pub fn send_frame(&mut self, frame: &[u8]) -> io::Result<()> {
+        let sender = self.send.as_mut().ok_or_else(|| io::Error::other("capture source cannot send"))?;
+        send(&mut **sender, frame)?;
+        self.sent.record(frame);
+        Ok(())
+    }
+
+    /// Parses a captured frame of the given link type. Never panics on malformed input.
+    /// Fragmented datagrams are not parsed.
+    pub fn parse_frame(link_type: LinkType, frame: &[u8]) -> Option<PacketManifest<'_>> {
+        Self::parse_frame_in(link_type, frame, Decap::default())
+    }
+
+    fn parse_frame_in<'p>(link_type: LinkType, frame: &'p [u8], cx: Decap<'p>) -> Option<PacketManifest<'p>> {
+        match link_type {
+            LinkType::Ethernet => Self::parse_ethernet_in(frame, cx),
+            // the protocol is the last field of the 16 byte SLL header, the first of the 20 byte SLL2 one
+            LinkType::LinuxSll => Self::parse_ip_in(u16::from_be_bytes([*frame.get(14)?, *frame.get(15)?]), frame.get(16..)?, cx),
+            LinkType::LinuxSll2 => Self::parse_ip_in(u16::from_be_bytes([*frame.first()?, *frame.get(1)?]), frame.get(20..)?, cx),
+        }
+    }
+
+    /// Parses a captured Ethernet frame, possibly with stacked VLAN tags. Never panics on malformed input.
+    /// Packets decapsulated from a tunnel keep the link layer of the innermost frame, if any.
+    pub fn parse_ethernet(ethernet_frame: &[u8]) -> Option<PacketManifest<'_>> {
+        Self::parse_ethernet_in(ethernet_frame, Decap::default())
+    }
+
+    fn parse_ethernet_in<'p>(ethernet_frame: &'p [u8], cx: Decap<'p>) -> Option<PacketManifest<'p>> {
         let ethernet_pdu = pdu::EthernetPdu::new(ethernet_frame).ok()?;
-        let inner = &ethernet_frame[ethernet_pdu.computed_ihl()..];
-        Self::parse_ip(ethernet_pdu.ethertype(), inner)
+        let mut vlans = VlanStack::default();
+        let mut ethertype = ethernet_pdu.tpid();
+        let mut inner = ethernet_frame.get(14..)?;
+        while let ETHERTYPE_DOT1Q | ETHERTYPE_QINQ | ETHERTYPE_QINQ_LEGACY = ethertype {
+            let tag = inner.get(..4)?;
+            if !vlans.push(u16::from_be_bytes([tag[0], tag[1]])) {
+                return None
+            }
+            ethertype = u16::from_be_bytes([tag[2], tag[3]]);
+            inner = &inner[4..];
+        }
+        let mut packet = Self::parse_ip_in(ethertype, inner, cx)?;
+        if packet.ethernet.is_none() && packet.tunnel.is_none() {
+            packet.vlans = vlans;
+            packet.ethernet = Some(EthernetLayer {
+                src: ethernet_pdu.source_address(),
+                dst: ethernet_pdu.destination_address(),
+            });
+        }
+        Some(packet)
     }
-    fn parse_ip(ty: u16, buffer: &[u8]) -> Option<PacketManifest> {
+
+    pub fn parse_ip(ty: u16, buffer: &[u8]) -> Option<PacketManifest<'_>> {
+        Self::parse_ip_in(ty, buffer, Decap::default())
+    }
+
+    fn parse_ip_in<'p>(ty: u16, buffer: &'p [u8], mut cx: Decap<'p>) -> Option<PacketManifest<'p>> {
         match ty {
             pdu::EtherType::IPV4 => {
                 let ipv4_pdu = pdu::Ipv4Pdu::new(buffer).ok()?;
-                let ip_layer = IpLayer {
-                    src: IpAddr::V4(ipv4_pdu.source_address().into()),
-                    dst: IpAddr::V4(ipv4_pdu.destination_address().into()),
+                let (src, dst) = (Ipv4Addr::from(ipv4_pdu.source_address()), Ipv4Addr::from(ipv4_pdu.destination_address()));
+                let mut ip_layer = IpLayer {
+                    src: IpAddr::V4(src),
+                    dst: IpAddr::V4(dst),
+                    total_len: u32::from(ipv4_pdu.total_length()),
+                    id: Some(ipv4_pdu.identification()),
+                    ttl: ipv4_pdu.ttl(),
                 };
-                let tcp_buffer = &buffer[ipv4_pdu.computed_ihl()..];
-                Self::parse_tcp(ip_layer, tcp_buffer)
+                let header_len = ipv4_pdu.computed_ihl();
+                if ipv4_pdu.more_fragments() || ipv4_pdu.fragment_offset() != 0 {
+                    // a frame completes at most one datagram, fragments within it aren't reassembled
+                    let (fragments, now) = cx.fragments.take()?;
+                    let key = FragmentKey{ src, dst, id: ipv4_pdu.identification(), protocol: ipv4_pdu.protocol() };
+                    // without the padding of short frames
+                    let data = buffer.get(header_len..usize::from(ipv4_pdu.total_length()))?;
+                    let offset = usize::from(ipv4_pdu.fragment_offset()) * 8;
+                    let datagram = fragments.insert(key, offset, ipv4_pdu.more_fragments(), data, now)?;
+                    ip_layer.total_len = (header_len + datagram.len()) as u32;
+                    return Self::parse_transport(ipv4_pdu.protocol(), ip_layer, datagram, Decap{ whole_payload: true, ..cx })
+                }
+                // without the padding of short frames
+                let end = usize::from(ipv4_pdu.total_length());
+                let payload = buffer.get(header_len..end.min(buffer.len()))?;
+                Self::parse_transport(ipv4_pdu.protocol(), ip_layer, payload, Decap{ whole_payload: end <= buffer.len(), ..cx })
             }
             pdu::EtherType::IPV6 => {
                 let ipv6_pdu = pdu::Ipv6Pdu::new(buffer).ok()?;
                 let ip_layer = IpLayer {
                     src: IpAddr::V6(ipv6_pdu.source_address().into()),
                     dst: IpAddr::V6(ipv6_pdu.destination_address().into()),
+                    total_len: u32::from(ipv6_pdu.payload_length()) + 40,
+                    id: None,
+                    ttl: ipv6_pdu.hop_limit(),
                 };
-                let tcp_buffer = &buffer[ipv6_pdu.computed_ihl()..];
-                Self::parse_tcp(ip_layer, tcp_buffer)
+                let end = ip_layer.total_len as usize;
+                let payload = buffer.get(ipv6_pdu.computed_ihl()..end.min(buffer.len()))?;
+                Self::parse_transport(ipv6_pdu.computed_protocol(), ip_layer, payload, Decap{ whole_payload: end <= buffer.len(), ..cx })
+            }
+            ETHERTYPE_MPLS | ETHERTYPE_MPLS_MULTICAST => {
+                let payload = Self::skip_mpls_labels(buffer)?;
+                // MPLS doesn't tell what it carries, IP is told apart by its version
+                match payload.first()? >> 4 {
+                    4 => Self::parse_ip_in(pdu::EtherType::IPV4, payload, cx),
+                    6 => Self::parse_ip_in(pdu::EtherType::IPV6, payload, cx),
+                    _ => None,
+                }
+            }
+            ETHERTYPE_PPPOE_SESSION => {
+                // version and type, code, session ID and length, then the PPP protocol
+                let header = buffer.get(..8)?;
+                let payload = &buffer[8..];
+                match u16::from_be_bytes([header[6], header[7]]) {
+                    PPP_IPV4 => Self::parse_ip_in(pdu::EtherType::IPV4, payload, cx),
+                    PPP_IPV6 => Self::parse_ip_in(pdu::EtherType::IPV6, payload, cx),
+                    // link control and authentication
+                    _ => None,
+                }
             }
             _ => return None
         }
     }
-    fn parse_tcp(ip: IpLayer, buffer: &[u8]) -> Option<PacketManifest> {
+
+    /// Payload following an MPLS label stack, `None` if the stack has no bottom.
+    fn skip_mpls_labels(mut buffer: &[u8]) -> Option<&[u8]> {
+        loop {
+            let label = buffer.get(..4)?;
+            buffer = &buffer[4..];
+            if label[2] & 0x01 != 0 {
+                return Some(buffer)
+            }
+        }
+    }
+
+    fn parse_transport<'p>(protocol: u8, ip: IpLayer, buffer: &'p [u8], cx: Decap<'p>) -> Option<PacketManifest<'p>> {
+        let tunneled = Decap{ depth: cx.depth + 1, ..cx };
+        match protocol {
+            pdu::IpProto::TCP => {
+                let mut packet = Self::parse_tcp(ip, buffer)?;
+                packet.bad_checksum = cx.verify_checksums && cx.whole_payload && !tcp_checksum_ok(&ip, buffer);
+                Some(packet)
+            }
+            pdu::IpProto::GRE if cx.depth < MAX_TUNNEL_DEPTH => Self::parse_gre(ip, buffer, tunneled),
+            pdu::IpProto::UDP if cx.depth < MAX_TUNNEL_DEPTH => Self::parse_udp_tunnel(ip, buffer, tunneled),
+            IPPROTO_IPIP | IPPROTO_IPV6 if cx.depth < MAX_TUNNEL_DEPTH => {
+                let ethertype = if protocol == IPPROTO_IPIP { pdu::EtherType::IPV4 } else { pdu::EtherType::IPV6 };
+                let mut packet = Self::parse_ip_in(ethertype, buffer, tunneled)?;
+                packet.tunnel.get_or_insert(Tunnel{ encapsulation: Encapsulation::IpInIp, src: ip.src, dst: ip.dst, key: None });
+                Some(packet)
+            }
+            _ => None,
+        }
+    }
+
+    /// Decapsulates GRE, NVGRE included. The innermost tunnel is recorded in the packet.
+    fn parse_gre<'p>(outer: IpLayer, buffer: &'p [u8], cx: Decap<'p>) -> Option<PacketManifest<'p>> {
+        // only RFC 2784 GRE, not the enhanced GRE of PPTP
+        let gre_pdu = pdu::GrePdu::new(buffer).ok()?;
+        let inner = buffer.get(gre_pdu.computed_ihl()..)?;
+        let (mut packet, key) = match gre_pdu.ethertype() {
+            // mirrored frames, the GRE tunnel is the mirror's and no part of the flow
+            ETHERTYPE_ERSPAN_II | ETHERTYPE_ERSPAN_III => return Self::parse_erspan(&gre_pdu, inner, cx),
+            // the low byte of an NVGRE key is per flow entropy, the rest is the virtual subnet ID
+            pdu::EtherType::TEB => (Self::parse_ethernet_in(inner, cx)?, gre_pdu.key().map(|key| key >> 8)),
+            ethertype => (Self::parse_ip_in(ethertype, inner, cx)?, gre_pdu.key()),
+        };
+        packet.tunnel.get_or_insert(Tunnel{ encapsulation: Encapsulation::Gre, src: outer.src, dst: outer.dst, key });
+        Some(packet)
+    }
+
+    /// Parses a frame mirrored with ERSPAN, type I has no header of its own.
+    fn parse_erspan<'p>(gre_pdu: &pdu::GrePdu, buffer: &'p [u8], cx: Decap<'p>) -> Option<PacketManifest<'p>> {
+        // version and session ID are at the same place in type II and III headers
+        let session = |version: u8| match buffer.get(..4) {
+            Some(header) if header[0] >> 4 == version => Some(u16::from_be_bytes([header[2], header[3]]) & 0x03ff),
+            _ => None,
+        };
+        let (header_len, session) = match gre_pdu.ethertype() {
+            ETHERTYPE_ERSPAN_II if !gre_pdu.has_sequence_number() => (0, None),
+            ETHERTYPE_ERSPAN_II => (8, Some(session(1)?)),
+            _ => {
+                // an optional platform specific subheader follows
+                let subheader = buffer.get(11)? & 0x01 != 0;
+                (if subheader { 20 } else { 12 }, Some(session(2)?))
+            }
+        };
+        let mut packet = Self::parse_ethernet_in(buffer.get(header_len..)?, cx)?;
+        if packet.erspan_session.is_none() {
+            packet.erspan_session = session;
+        }
+        Some(packet)
+    }
+
+    /// Decapsulates GENEVE, other UDP traffic isn't analyzed.
+    fn parse_udp_tunnel<'p>(outer: IpLayer, buffer: &'p [u8], cx: Decap<'p>) -> Option<PacketManifest<'p>> {
+        let udp = buffer.get(..8)?;
+        if u16::from_be_bytes([udp[2], udp[3]]) != GENEVE_PORT {
+            return None
+        }
+        let geneve = buffer.get(8..16)?;
+        if geneve[0] >> 6 != 0 {
+            return None
+        }
+        // options follow the fixed header, their length is in 4 byte words
+        let inner = buffer.get(16 + usize::from(geneve[0] & 0x3f) * 4..)?;
+        let vni = u32::from_be_bytes([0, geneve[4], geneve[5], geneve[6]]);
+        let mut packet = match u16::from_be_bytes([geneve[2], geneve[3]]) {
+            pdu::EtherType::TEB => Self::parse_ethernet_in(inner, cx)?,
+            ethertype => Self::parse_ip_in(ethertype, inner, cx)?,
+        };
+        packet.tunnel.get_or_insert(Tunnel{ encapsulation: Encapsulation::Geneve, src: outer.src, dst: outer.dst, key: Some(vni) });
+        Some(packet)
+    }
+
+    pub fn parse_tcp(ip: IpLayer, buffer: &[u8]) -> Option<PacketManifest<'_>> {
         let tcp_pdu = pdu::TcpPdu::new(buffer).ok()?;
-        let tcp_payload = &buffer[tcp_pdu.computed_data_offset()..];
+        let tcp_payload = buffer.get(tcp_pdu.computed_data_offset()..)?;
         Some(PacketManifest {
             ip,
             tcp: TcpLayer {
@@ -95,9 +607,236 @@ impl TcpIterator {
                     ack: tcp_pdu.ack(),
                     fin: tcp_pdu.fin(),
                     rst: tcp_pdu.rst(),
+                    urg: tcp_pdu.urg(),
                 },
+                window: tcp_pdu.window_size(),
+                urgent_ptr: tcp_pdu.urgent_pointer(),
+                options: TcpOptions::from_pdu(&tcp_pdu),
             },
             tcp_payload,
+            vlans: VlanStack::default(),
+            ethernet: None,
+            tunnel: None,
+            erspan_session: None,
+            bad_checksum: false,
+            meta: PacketMeta::default(),
         })
     }
 }
+
+/// Verifies the checksum of a whole TCP segment, the pseudo header taken from `ip`.
+fn tcp_checksum_ok(ip: &IpLayer, segment: &[u8]) -> bool {
+    let len = segment.len() as u32;
+    let sum = match (ip.src, ip.dst) {
+        (IpAddr::V4(src), IpAddr::V4(dst)) => {
+            let pseudo_header = [0, pdu::IpProto::TCP, (len >> 8) as u8, len as u8];
+            probe::checksum(&[&src.octets(), &dst.octets(), &pseudo_header, segment])
+        }
+        (IpAddr::V6(src), IpAddr::V6(dst)) => {
+            let [a, b, c, d] = len.to_be_bytes();
+            let pseudo_header = [a, b, c, d, 0, 0, 0, pdu::IpProto::TCP];
+            probe::checksum(&[&src.octets(), &dst.octets(), &pseudo_header, segment])
+        }
+        _ => return true,
+    };
+    // a correct checksum sums up to zero
+    sum == 0
+}
+
+fn send(sender: &mut dyn DataLinkSender, frame: &[u8]) -> io::Result<()> {
+    let result = sender.build_and_send(1, frame.len(),
+                                       &mut |new_packet| {
+                                           new_packet.copy_from_slice(frame);
+                                       });
+    match result {
+        Some(Ok(())) => Ok(()),
+        Some(Err(err)) => Err(err),
+        None => Err(io::Error::other("there is not sufficient capacity in the buffer")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::Flow;
+
+    #[test]
+    fn sent_frames_match_once() {
+        let mut sent = SentFrames::default();
+        sent.record(b"frame one");
+        sent.record(b"frame two");
+        sent.record(b"frame two");
+
+        assert!(!sent.take(b"frame three"));
+        assert!(sent.take(b"frame two"));
+        assert!(sent.take(b"frame two"));
+        assert!(!sent.take(b"frame two"));
+        assert!(sent.take(b"frame one"));
+    }
+
+    #[test]
+    fn parses_encapsulated_packets() {
+        let ethernet = EthernetLayer{ src: [2, 0, 0, 0, 0, 1], dst: [2, 0, 0, 0, 0, 2] };
+        let src = ("192.0.2.1".parse().unwrap(), 443);
+        let dst = ("198.51.100.7".parse().unwrap(), 51234);
+        let frame = crate::probe::keepalive_frame(ethernet, VlanStack::default(), src, dst, 99.into(), 1000.into(), 512).unwrap();
+        let ip_packet = &frame[14..];
+
+        // outgoing packet of an Ethernet device, 6 byte address, IPv4
+        let mut sll = vec![0, 4, 0, 1, 0, 6, 2, 0, 0, 0, 0, 1, 0, 0, 0x08, 0x00];
+        sll.extend_from_slice(ip_packet);
+        // IPv4, interface index 3, Ethernet device, outgoing, 6 byte address
+        let mut sll2 = vec![0x08, 0x00, 0, 0, 0, 0, 0, 3, 0, 1, 4, 6, 2, 0, 0, 0, 0, 1, 0, 0];
+        sll2.extend_from_slice(ip_packet);
+        // labels 16 and 17, the latter at the bottom of the stack
+        let mut mpls = frame[..12].to_vec();
+        mpls.extend_from_slice(&[0x88, 0x47, 0x00, 0x01, 0x00, 0x40, 0x00, 0x01, 0x11, 0x40]);
+        mpls.extend_from_slice(ip_packet);
+        assert!(TcpIterator::parse_ethernet(&mpls[..18]).is_none());
+        // GRE with key 5 carrying the IP packet, NVGRE with virtual subnet 5 carrying the whole frame
+        let gre = |inner: &[u8], header: &[u8]| {
+            let len = (20 + header.len() + inner.len()) as u16;
+            let mut frame = frame[..12].to_vec();
+            frame.extend_from_slice(&[0x08, 0x00, 0x45, 0, (len >> 8) as u8, len as u8, 0, 0, 0x40, 0, 64, 47, 0, 0]);
+            frame.extend_from_slice(&[10, 0, 0, 2, 10, 0, 0, 1]);
+            frame.extend_from_slice(header);
+            frame.extend_from_slice(inner);
+            frame
+        };
+        let gre_ip = gre(ip_packet, &[0x20, 0, 0x08, 0x00, 0, 0, 0, 5]);
+        let nvgre = gre(&frame, &[0x20, 0, 0x65, 0x58, 0, 0, 0x05, 0x11]);
+        let tunnel = Tunnel{ encapsulation: Encapsulation::Gre, src: "10.0.0.2".parse().unwrap(), dst: "10.0.0.1".parse().unwrap(), key: Some(5) };
+        for tunneled in &[&gre_ip, &nvgre] {
+            let packet = TcpIterator::parse_ethernet(tunneled).unwrap();
+            assert_eq!((packet.ip.src, packet.tunnel), (src.0, Some(tunnel)));
+            assert_eq!(Flow::from(&packet).tunnel(), Some(tunnel.canonical()));
+        }
+        // ERSPAN type II of session 0x123 and type III with a subheader, mirroring the whole frame
+        let erspan_ii = gre(&frame, &[0x10, 0, 0x88, 0xbe, 0, 0, 0, 1, 0x10, 0, 0x01, 0x23, 0, 0, 0, 0]);
+        let mut erspan_iii = vec![0x00, 0, 0x22, 0xeb, 0x20, 0, 0x01, 0x23, 0, 0, 0, 0, 0, 0, 0, 0x01];
+        erspan_iii.resize(24, 0);
+        let erspan_iii = gre(&frame, &erspan_iii);
+        for mirrored in &[erspan_ii, erspan_iii] {
+            let packet = TcpIterator::parse_ethernet(mirrored).unwrap();
+            assert_eq!((packet.erspan_session, packet.tunnel), (Some(0x123), None));
+            assert_eq!(packet.ethernet, Some(ethernet));
+        }
+
+        // IPv4 in IPv4
+        let mut ipip = gre(ip_packet, &[]);
+        ipip[23] = IPPROTO_IPIP;
+        let packet = TcpIterator::parse_ethernet(&ipip).unwrap();
+        assert_eq!(packet.tunnel, Some(Tunnel{ encapsulation: Encapsulation::IpInIp, key: None, ..tunnel }));
+        assert_eq!((packet.ip.src, packet.tcp.src), src);
+
+        // GENEVE with VNI 5 and a 4 byte option carrying the whole frame
+        let mut geneve = frame[..12].to_vec();
+        let len = (20 + 8 + 12 + frame.len()) as u16;
+        geneve.extend_from_slice(&[0x08, 0x00, 0x45, 0, (len >> 8) as u8, len as u8, 0, 0, 0x40, 0, 64, 17, 0, 0]);
+        geneve.extend_from_slice(&[10, 0, 0, 2, 10, 0, 0, 1]);
+        geneve.extend_from_slice(&[0xc3, 0x50, 0x17, 0xc1, ((len - 20) >> 8) as u8, (len - 20) as u8, 0, 0]);
+        geneve.extend_from_slice(&[0x01, 0, 0x65, 0x58, 0, 0, 5, 0, 0x01, 0x02, 0x03, 0x00]);
+        geneve.extend_from_slice(&frame);
+        let packet = TcpIterator::parse_ethernet(&geneve).unwrap();
+        assert_eq!(packet.tunnel, Some(Tunnel{ encapsulation: Encapsulation::Geneve, ..tunnel }));
+        assert_eq!((packet.ip.dst, packet.tcp.dst), dst);
+        geneve[37] = 0xc2;
+        assert!(TcpIterator::parse_ethernet(&geneve).is_none());
+
+        assert!(TcpIterator::parse_ethernet(&nvgre).unwrap().ethernet.is_some());
+        assert!(TcpIterator::parse_ethernet(&gre_ip).unwrap().ethernet.is_none());
+
+        // session 0x1234 carrying IPv4
+        let mut pppoe = frame[..12].to_vec();
+        pppoe.extend_from_slice(&[0x88, 0x64, 0x11, 0x00, 0x12, 0x34, 0, (ip_packet.len() + 2) as u8, 0x00, 0x21]);
+        pppoe.extend_from_slice(ip_packet);
+
+        let frames = [
+            (LinkType::LinuxSll, sll),
+            (LinkType::LinuxSll2, sll2),
+            (LinkType::Ethernet, mpls),
+            (LinkType::Ethernet, gre_ip),
+            (LinkType::Ethernet, pppoe),
+        ];
+        for &(link_type, ref frame) in &frames {
+            let packet = TcpIterator::parse_frame(link_type, frame).unwrap();
+            assert_eq!((packet.ip.src, packet.tcp.src), src);
+            assert_eq!((packet.ip.dst, packet.tcp.dst), dst);
+            assert_eq!(packet.ethernet.is_some(), link_type == LinkType::Ethernet && packet.tunnel.is_none());
+            assert!(TcpIterator::parse_frame(link_type, &frame[..10]).is_none());
+        }
+    }
+
+    /// Frames to replay, the last one returned stays alive until the next call.
+    struct Replay(VecDeque<Vec<u8>>, Vec<u8>);
+
+    impl CaptureSource for Replay {
+        fn next_frame(&mut self) -> io::Result<Option<(PacketMeta, &[u8])>> {
+            match self.0.pop_front() {
+                Some(frame) => {
+                    self.1 = frame;
+                    Ok(Some((PacketMeta::default(), &self.1)))
+                }
+                None => Ok(None),
+            }
+        }
+    }
+
+    #[test]
+    fn reassembles_fragmented_segments() {
+        let ethernet = EthernetLayer{ src: [2, 0, 0, 0, 0, 1], dst: [2, 0, 0, 0, 0, 2] };
+        let src = ("192.0.2.1".parse().unwrap(), 443);
+        let dst = ("198.51.100.7".parse().unwrap(), 51234);
+        let frame = crate::probe::keepalive_frame(ethernet, VlanStack::default(), src, dst, 99.into(), 1000.into(), 512).unwrap();
+        // the 20 byte TCP header split in two, the second fragment first and padded to the minimal frame size
+        let fragment = |offset: u16, more: bool, data: &[u8]| {
+            let mut fragment = frame[..34].to_vec();
+            fragment[16..18].copy_from_slice(&(20 + data.len() as u16).to_be_bytes());
+            fragment[20..22].copy_from_slice(&((offset / 8) | if more { 0x2000 } else { 0 }).to_be_bytes());
+            fragment.extend_from_slice(data);
+            fragment.resize(fragment.len().max(60), 0);
+            fragment
+        };
+        let frames = vec![fragment(8, false, &frame[42..]), fragment(0, true, &frame[34..42])];
+
+        let mut packets = TcpIterator::from_source(Box::new(Replay(frames.into(), Vec::new())));
+        assert!(matches!(packets.next_packet().unwrap(), Some(Packet::FilteredOut(_))));
+        match packets.next_packet().unwrap() {
+            Some(Packet::Tcp(packet)) => {
+                assert_eq!((packet.ip.src, packet.tcp.src), src);
+                assert_eq!((packet.tcp.seq, packet.tcp.ack, packet.tcp.window), (99, 1000, 512));
+                assert!(packet.tcp_payload.is_empty());
+            }
+            _ => panic!("expected the reassembled segment"),
+        }
+        assert_eq!(packets.fragment_stats().reassembled, 1);
+        // first fragments alone aren't taken for a whole segment
+        assert!(TcpIterator::parse_ethernet(&fragment(0, true, &frame[34..])).is_none());
+    }
+
+    #[test]
+    fn verifies_checksums_by_policy() {
+        let ethernet = EthernetLayer{ src: [2, 0, 0, 0, 0, 1], dst: [2, 0, 0, 0, 0, 2] };
+        let src = ("192.0.2.1".parse().unwrap(), 443);
+        let dst = ("198.51.100.7".parse().unwrap(), 51234);
+        // padded to the minimal frame size, the padding isn't part of the segment
+        let mut frame = crate::probe::keepalive_frame(ethernet, VlanStack::default(), src, dst, 99.into(), 1000.into(), 512).unwrap();
+        frame.resize(60, 0);
+        let mut corrupted = frame.clone();
+        corrupted[45] ^= 0x01;
+        let frames = || VecDeque::from(vec![frame.clone(), corrupted.clone()]);
+
+        let mut packets = TcpIterator::from_source(Box::new(Replay(frames(), Vec::new())));
+        packets.set_checksum_policy(ChecksumPolicy::Drop);
+        assert!(matches!(packets.next_packet().unwrap(), Some(Packet::Tcp(ref packet)) if !packet.bad_checksum && packet.tcp_payload.is_empty()));
+        assert!(matches!(packets.next_packet().unwrap(), Some(Packet::FilteredOut(_))));
+        assert_eq!(packets.bad_checksums(), 1);
+
+        let mut packets = TcpIterator::from_source(Box::new(Replay(frames(), Vec::new())));
+        packets.set_checksum_policy(ChecksumPolicy::Flag);
+        assert!(matches!(packets.next_packet().unwrap(), Some(Packet::Tcp(ref packet)) if !packet.bad_checksum));
+        assert!(matches!(packets.next_packet().unwrap(), Some(Packet::Tcp(ref packet)) if packet.bad_checksum));
+        assert_eq!("flag".parse(), Ok(ChecksumPolicy::Flag));
+        assert!("ignore".parse::<ChecksumPolicy>().is_err());
+    }
+}
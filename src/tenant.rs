@@ -0,0 +1,92 @@
+use std::{error, fmt};
+use std::str::FromStr;
+
+use crate::types::{Cidr, Flow};
+
+/// What traffic belongs to a tenant.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum TenantSelector {
+    Vlan(u16),
+    /// Either endpoint is within the network.
+    Network(Cidr),
+}
+
+/// Maps a selector to a tenant label, parsed from `<label>=vlan:<id>` or `<label>=<cidr>`.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct TenantRule {
+    pub label: String,
+    pub selector: TenantSelector,
+}
+
+impl FromStr for TenantRule {
+    type Err = ParseTenantError;
+    fn from_str(s: &str) -> Result<Self, ParseTenantError> {
+        let err = || ParseTenantError(s.to_owned());
+        let eq = s.find('=').ok_or_else(err)?;
+        let (label, selector) = (s[..eq].trim(), s[eq + 1..].trim());
+        if label.is_empty() {
+            return Err(err())
+        }
+        let selector = if let Some(vlan) = selector.strip_prefix("vlan:") {
+            TenantSelector::Vlan(vlan.parse().map_err(|_| err())?)
+        } else {
+            TenantSelector::Network(selector.parse().map_err(|_| err())?)
+        };
+        Ok(Self{ label: label.to_owned(), selector })
+    }
+}
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct ParseTenantError(String);
+
+impl fmt::Display for ParseTenantError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "invalid tenant `{}`, expected `<label>=vlan:<id>` or `<label>=<cidr>`", self.0)
+    }
+}
+
+impl error::Error for ParseTenantError {}
+
+/// Ordered tenant rules, the first matching one wins.
+#[derive(Debug, Clone, Default)]
+pub struct Tenants {
+    rules: Vec<TenantRule>,
+}
+
+impl Tenants {
+    pub fn new(rules: Vec<TenantRule>) -> Self {
+        Self{ rules }
+    }
+
+    pub fn tenant_of(&self, flow: &Flow) -> Option<&str> {
+        self.rules.iter()
+            .find(|rule| match &rule.selector {
+                TenantSelector::Vlan(vlan) => flow.vlan() == Some(*vlan),
+                TenantSelector::Network(network) => network.contains(flow.src().0) || network.contains(flow.dst().0),
+            })
+            .map(|rule| rule.label.as_str())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn first_matching_rule_wins() {
+        let tenants = Tenants::new(vec![
+            "acme=vlan:100".parse().unwrap(),
+            "globex=10.2.0.0/16".parse().unwrap(),
+            "initech=0.0.0.0/0".parse().unwrap(),
+        ]);
+        let flow = |s: &str| s.parse::<Flow>().unwrap();
+
+        assert_eq!(tenants.tenant_of(&flow("10.2.0.1:443 <-> 1.1.1.1:5000 vlan 100")), Some("acme"));
+        assert_eq!(tenants.tenant_of(&flow("1.1.1.1:5000 <-> 10.2.0.1:443")), Some("globex"));
+        assert_eq!(tenants.tenant_of(&flow("1.1.1.1:5000 <-> 2.2.2.2:443")), Some("initech"));
+        assert_eq!(tenants.tenant_of(&flow("[::1]:5000 <-> [::2]:443")), None);
+
+        assert!("=vlan:1".parse::<TenantRule>().is_err());
+        assert!("acme=vlan:x".parse::<TenantRule>().is_err());
+    }
+}
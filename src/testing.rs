@@ -0,0 +1,320 @@
+//! Builders for crafting packets and whole TCP conversations without a capture.
+
+use std::net::IpAddr;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::types::{PacketManifest, PacketMeta, VlanStack, IpLayer, TcpLayer, TcpFlags, TcpOptions};
+
+/// Length of IPv4 and TCP headers without options.
+const IPV4_TCP_HEADERS_LEN: u32 = 40;
+/// Length of IPv6 and TCP headers without options or extension headers.
+const IPV6_TCP_HEADERS_LEN: u32 = 60;
+
+/// Builds a single TCP packet as if it was parsed from the wire.
+#[derive(Copy, Clone, Debug)]
+pub struct PacketBuilder {
+    ip: IpLayer,
+    tcp: TcpLayer,
+    vlans: VlanStack,
+    bad_checksum: bool,
+    meta: PacketMeta,
+}
+
+impl PacketBuilder {
+    pub fn new(src: (IpAddr, u16), dst: (IpAddr, u16)) -> Self {
+        Self {
+            ip: IpLayer{ src: src.0, dst: dst.0, total_len: 0, id: None, ttl: 64 },
+            tcp: TcpLayer{ src: src.1, dst: dst.1, window: u16::MAX, ..Default::default() },
+            vlans: VlanStack::default(),
+            bad_checksum: false,
+            meta: PacketMeta::default(),
+        }
+    }
+
+    pub fn seq(mut self, seq: u32) -> Self {
+        self.tcp.seq = seq;
+        self
+    }
+
+    /// Sets the acknowledgement number along with the ACK flag.
+    pub fn ack(mut self, ack: u32) -> Self {
+        self.tcp.ack = ack;
+        self.tcp.flags.ack = true;
+        self
+    }
+
+    pub fn syn(mut self) -> Self {
+        self.tcp.flags.syn = true;
+        self
+    }
+
+    pub fn fin(mut self) -> Self {
+        self.tcp.flags.fin = true;
+        self
+    }
+
+    pub fn rst(mut self) -> Self {
+        self.tcp.flags.rst = true;
+        self
+    }
+
+    /// Sets the urgent pointer along with the URG flag.
+    pub fn urgent(mut self, urgent_ptr: u16) -> Self {
+        self.tcp.urgent_ptr = urgent_ptr;
+        self.tcp.flags.urg = true;
+        self
+    }
+
+    pub fn flags(mut self, flags: TcpFlags) -> Self {
+        self.tcp.flags = flags;
+        self
+    }
+
+    pub fn ip_id(mut self, id: u16) -> Self {
+        self.ip.id = Some(id);
+        self
+    }
+
+    pub fn ttl(mut self, ttl: u8) -> Self {
+        self.ip.ttl = ttl;
+        self
+    }
+
+    /// Marks the packet as failing checksum verification.
+    pub fn bad_checksum(mut self) -> Self {
+        self.bad_checksum = true;
+        self
+    }
+
+    pub fn window(mut self, window: u16) -> Self {
+        self.tcp.window = window;
+        self
+    }
+
+    pub fn options(mut self, options: TcpOptions) -> Self {
+        self.tcp.options = options;
+        self
+    }
+
+    /// Sets the options encoded in `raw`, padded with NOPs.
+    pub fn raw_options(mut self, raw: &[u8]) -> Self {
+        let mut segment = vec![0u8; 20];
+        segment.extend_from_slice(raw);
+        segment.resize(segment.len().div_ceil(4) * 4, 1);
+        segment[12] = ((segment.len() / 4) << 4) as u8;
+        self.tcp.options = TcpOptions::from_pdu(&pdu::TcpPdu::new(&segment).expect("valid TCP header"));
+        self
+    }
+
+    /// Sets a TCP Fast Open option carrying `cookie`, an empty one requests a cookie.
+    pub fn fast_open(self, cookie: &[u8]) -> Self {
+        let mut raw = vec![34, cookie.len() as u8 + 2];
+        raw.extend_from_slice(cookie);
+        self.raw_options(&raw)
+    }
+
+    /// Tags the packet, inside the tags added before.
+    pub fn vlan(mut self, vlan: u16) -> Self {
+        self.vlans.push(vlan);
+        self
+    }
+
+    pub fn ts(mut self, ts: SystemTime) -> Self {
+        self.meta.ts = ts;
+        self
+    }
+
+    /// Finishes the packet. IP length and capture lengths are derived from the payload.
+    pub fn build(self, payload: &[u8]) -> PacketManifest<'_> {
+        let headers_len = if self.ip.src.is_ipv4() { IPV4_TCP_HEADERS_LEN } else { IPV6_TCP_HEADERS_LEN };
+        let total_len = headers_len + payload.len() as u32;
+        PacketManifest {
+            ip: IpLayer{ total_len, ..self.ip },
+            tcp: self.tcp,
+            tcp_payload: payload,
+            vlans: self.vlans,
+            ethernet: None,
+            tunnel: None,
+            erspan_session: None,
+            bad_checksum: self.bad_checksum,
+            meta: PacketMeta{ wire_len: total_len, cap_len: total_len, ..self.meta },
+        }
+    }
+}
+
+/// Ethernet frame carrying the packet, with checksums and without TCP options.
+pub fn frame(packet: &PacketManifest) -> Vec<u8> {
+    let mut tcp = Vec::with_capacity(20 + packet.tcp_payload.len());
+    tcp.extend_from_slice(&packet.tcp.src.to_be_bytes());
+    tcp.extend_from_slice(&packet.tcp.dst.to_be_bytes());
+    tcp.extend_from_slice(&packet.tcp.seq.to_be_bytes());
+    tcp.extend_from_slice(&packet.tcp.ack.to_be_bytes());
+    tcp.extend_from_slice(&[5 << 4, packet.tcp.flags.bits()]);
+    tcp.extend_from_slice(&packet.tcp.window.to_be_bytes());
+    tcp.extend_from_slice(&[0, 0]);
+    tcp.extend_from_slice(&packet.tcp.urgent_ptr.to_be_bytes());
+    tcp.extend_from_slice(packet.tcp_payload);
+
+    let (ethertype, ip, pseudo_header) = match (packet.ip.src, packet.ip.dst) {
+        (IpAddr::V4(src), IpAddr::V4(dst)) => {
+            let mut ip = vec![0x45, 0];
+            ip.extend_from_slice(&(20 + tcp.len() as u16).to_be_bytes());
+            ip.extend_from_slice(&packet.ip.id.unwrap_or(0).to_be_bytes());
+            ip.extend_from_slice(&[0x40, 0, packet.ip.ttl, 6, 0, 0]);
+            ip.extend_from_slice(&src.octets());
+            ip.extend_from_slice(&dst.octets());
+            let ip_checksum = checksum(&ip);
+            ip[10..12].copy_from_slice(&ip_checksum.to_be_bytes());
+            let mut pseudo_header = ip[12..20].to_vec();
+            pseudo_header.extend_from_slice(&[0, 6]);
+            pseudo_header.extend_from_slice(&(tcp.len() as u16).to_be_bytes());
+            (0x0800u16, ip, pseudo_header)
+        }
+        (IpAddr::V6(src), IpAddr::V6(dst)) => {
+            let mut ip = vec![0x60, 0, 0, 0];
+            ip.extend_from_slice(&(tcp.len() as u16).to_be_bytes());
+            ip.extend_from_slice(&[6, packet.ip.ttl]);
+            ip.extend_from_slice(&src.octets());
+            ip.extend_from_slice(&dst.octets());
+            let mut pseudo_header = ip[8..40].to_vec();
+            pseudo_header.extend_from_slice(&(tcp.len() as u32).to_be_bytes());
+            pseudo_header.extend_from_slice(&[0, 0, 0, 6]);
+            (0x86ddu16, ip, pseudo_header)
+        }
+        _ => panic!("addresses of different families"),
+    };
+    let tcp_checksum = checksum(&[pseudo_header, tcp.clone()].concat());
+    tcp[16..18].copy_from_slice(&tcp_checksum.to_be_bytes());
+
+    let mut frame = vec![0x02, 0, 0, 0, 0, 2, 0x02, 0, 0, 0, 0, 1];
+    for vlan in packet.vlans.ids() {
+        frame.extend_from_slice(&[0x81, 0]);
+        frame.extend_from_slice(&vlan.to_be_bytes());
+    }
+    frame.extend_from_slice(&ethertype.to_be_bytes());
+    frame.extend_from_slice(&ip);
+    frame.extend_from_slice(&tcp);
+    frame
+}
+
+/// Internet checksum of `data`.
+fn checksum(data: &[u8]) -> u16 {
+    let sum = data.chunks(2)
+        .map(|word| u32::from(word[0]) << 8 | u32::from(*word.get(1).unwrap_or(&0)))
+        .fold(0u32, |sum, word| {
+            let sum = sum + word;
+            (sum & 0xffff) + (sum >> 16)
+        });
+    !(sum as u16)
+}
+
+/// Nanosecond pcap file of Ethernet frames of the packets, stamped with their capture time.
+pub fn pcap(packets: &[PacketManifest]) -> Vec<u8> {
+    let mut file = vec![0x4d, 0x3c, 0xb2, 0xa1, 2, 0, 4, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0xff, 0xff, 0, 0, 1, 0, 0, 0];
+    for packet in packets {
+        let frame = frame(packet);
+        let since_epoch = packet.meta.ts.duration_since(UNIX_EPOCH).expect("capture time after the epoch");
+        file.extend_from_slice(&(since_epoch.as_secs() as u32).to_le_bytes());
+        file.extend_from_slice(&since_epoch.subsec_nanos().to_le_bytes());
+        file.extend_from_slice(&(frame.len() as u32).to_le_bytes());
+        file.extend_from_slice(&(frame.len() as u32).to_le_bytes());
+        file.extend_from_slice(&frame);
+    }
+    file
+}
+
+/// Both sides of a TCP conversation, producing packets with consistent sequence numbers.
+///
+/// Crafted packets (`inject_*`) don't advance the conversation.
+#[derive(Copy, Clone, Debug)]
+pub struct TcpScenario {
+    client: (IpAddr, u16),
+    server: (IpAddr, u16),
+    client_next_seq: u32,
+    server_next_seq: u32,
+}
+
+impl TcpScenario {
+    pub fn new(client: (IpAddr, u16), server: (IpAddr, u16), client_isn: u32, server_isn: u32) -> Self {
+        Self{ client, server, client_next_seq: client_isn, server_next_seq: server_isn }
+    }
+
+    pub fn client_next_seq(&self) -> u32 {
+        self.client_next_seq
+    }
+
+    pub fn server_next_seq(&self) -> u32 {
+        self.server_next_seq
+    }
+
+    /// Client to server packet at the current position of the conversation, without flags.
+    pub fn client_packet(&self) -> PacketBuilder {
+        PacketBuilder::new(self.client, self.server).seq(self.client_next_seq)
+    }
+
+    /// Server to client packet at the current position of the conversation, without flags.
+    pub fn server_packet(&self) -> PacketBuilder {
+        PacketBuilder::new(self.server, self.client).seq(self.server_next_seq)
+    }
+
+    pub fn syn(&mut self) -> PacketManifest<'static> {
+        let packet = self.client_packet().syn().build(&[]);
+        self.client_next_seq = self.client_next_seq.wrapping_add(1);
+        packet
+    }
+
+    pub fn syn_ack(&mut self) -> PacketManifest<'static> {
+        let packet = self.server_packet().syn().ack(self.client_next_seq).build(&[]);
+        self.server_next_seq = self.server_next_seq.wrapping_add(1);
+        packet
+    }
+
+    /// Server SYN not acknowledging the client's, as in a simultaneous open or a split handshake.
+    pub fn server_syn(&mut self) -> PacketManifest<'static> {
+        let packet = self.server_packet().syn().build(&[]);
+        self.server_next_seq = self.server_next_seq.wrapping_add(1);
+        packet
+    }
+
+    /// Final ACK of the handshake.
+    pub fn ack(&mut self) -> PacketManifest<'static> {
+        self.client_packet().ack(self.server_next_seq).build(&[])
+    }
+
+    /// SYN, SYN-ACK and ACK in capture order.
+    pub fn handshake(&mut self) -> [PacketManifest<'static>; 3] {
+        [self.syn(), self.syn_ack(), self.ack()]
+    }
+
+    pub fn client_data<'p>(&mut self, payload: &'p [u8]) -> PacketManifest<'p> {
+        let packet = self.client_packet().ack(self.server_next_seq).build(payload);
+        self.client_next_seq = self.client_next_seq.wrapping_add(payload.len() as u32);
+        packet
+    }
+
+    pub fn server_data<'p>(&mut self, payload: &'p [u8]) -> PacketManifest<'p> {
+        let packet = self.server_packet().ack(self.client_next_seq).build(payload);
+        self.server_next_seq = self.server_next_seq.wrapping_add(payload.len() as u32);
+        packet
+    }
+
+    /// Segment spoofed from the server at an arbitrary sequence number.
+    pub fn inject_from_server<'p>(&self, seq: u32, payload: &'p [u8]) -> PacketManifest<'p> {
+        self.server_packet().seq(seq).ack(self.client_next_seq).build(payload)
+    }
+
+    /// Segment spoofed from the client at an arbitrary sequence number.
+    pub fn inject_from_client<'p>(&self, seq: u32, payload: &'p [u8]) -> PacketManifest<'p> {
+        self.client_packet().seq(seq).ack(self.server_next_seq).build(payload)
+    }
+
+    /// Spoofed SYN-ACK answering the client's SYN with a different initial sequence number.
+    pub fn inject_syn_ack(&self, isn: u32) -> PacketManifest<'static> {
+        self.server_packet().seq(isn).syn().ack(self.client_next_seq).build(&[])
+    }
+
+    /// Spoofed reset from the server.
+    pub fn inject_rst_from_server(&self, seq: u32) -> PacketManifest<'static> {
+        self.server_packet().seq(seq).rst().build(&[])
+    }
+}
@@ -0,0 +1,126 @@
+/// Most a sender's IP ID may advance between two of its segments; counters shared with other
+/// connections advance by whatever the host sent in between.
+const MAX_ID_ADVANCE: u16 = 8192;
+/// Segments reordered on the way may arrive with an IP ID this much behind.
+const MAX_ID_REORDER: u16 = 64;
+/// Consistent IP IDs seen before a pattern is relied on.
+const LEARNING_SEGMENTS: u8 = 4;
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+enum IpIdPattern {
+    /// The pattern the IDs so far fit, if any yet.
+    Learning(Option<Learned>),
+    /// Always zero, as some stacks send with don't fragment set.
+    Zero,
+    /// A counter, per connection or per host.
+    Incrementing,
+    /// Nothing to go by, e.g. random IDs.
+    Unpredictable,
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+enum Learned {
+    Zero,
+    Incrementing,
+}
+
+/// How a segment's IP ID fits the ones its side sent before.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum IpIdClass {
+    Typical,
+    /// The segment likely comes from another host, an injector keeping no counter of its own for the connection.
+    Deviating { expected: u16 },
+}
+
+/// IPv4 identification pattern of one side's segments, learned from its first segments.
+/// Deviating segments don't move the pattern on, so that an injector can't teach it.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct IpIdModel {
+    pattern: IpIdPattern,
+    last: Option<u16>,
+    consistent: u8,
+}
+
+impl Default for IpIdModel {
+    fn default() -> Self {
+        Self{ pattern: IpIdPattern::Learning(None), last: None, consistent: 0 }
+    }
+}
+
+impl IpIdModel {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn observe(&mut self, id: u16) -> IpIdClass {
+        let last = match self.last {
+            Some(last) => last,
+            None => {
+                self.last = Some(id);
+                return IpIdClass::Typical
+            }
+        };
+        let advance = id.wrapping_sub(last);
+        let follows = (1..=MAX_ID_ADVANCE).contains(&advance);
+        match self.pattern {
+            IpIdPattern::Learning(candidate) => {
+                let fits = match (id, last) {
+                    (0, 0) => Some(Learned::Zero),
+                    _ if follows => Some(Learned::Incrementing),
+                    _ => None,
+                };
+                self.pattern = match fits {
+                    Some(learned) if candidate.is_none_or(|candidate| candidate == learned) => {
+                        self.consistent += 1;
+                        self.last = Some(id);
+                        match learned {
+                            _ if self.consistent < LEARNING_SEGMENTS => IpIdPattern::Learning(Some(learned)),
+                            Learned::Zero => IpIdPattern::Zero,
+                            Learned::Incrementing => IpIdPattern::Incrementing,
+                        }
+                    }
+                    _ => IpIdPattern::Unpredictable,
+                };
+                IpIdClass::Typical
+            }
+            IpIdPattern::Zero if id == 0 => IpIdClass::Typical,
+            IpIdPattern::Zero => IpIdClass::Deviating{ expected: 0 },
+            IpIdPattern::Incrementing if follows => {
+                self.last = Some(id);
+                IpIdClass::Typical
+            }
+            IpIdPattern::Incrementing if last.wrapping_sub(id) <= MAX_ID_REORDER => IpIdClass::Typical,
+            IpIdPattern::Incrementing => IpIdClass::Deviating{ expected: last.wrapping_add(1) },
+            IpIdPattern::Unpredictable => IpIdClass::Typical,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn learns_counters_and_zero_ids() {
+        let mut counter = IpIdModel::new();
+        for id in &[65530, 65533, 2, 40, 41] {
+            assert_eq!(counter.observe(*id), IpIdClass::Typical);
+        }
+        // reordered, then an injected ID far off the counter
+        assert_eq!(counter.observe(39), IpIdClass::Typical);
+        assert_eq!(counter.observe(30000), IpIdClass::Deviating{ expected: 42 });
+        assert_eq!(counter.observe(45), IpIdClass::Typical);
+
+        let mut zero = IpIdModel::new();
+        for _ in 0..5 {
+            assert_eq!(zero.observe(0), IpIdClass::Typical);
+        }
+        assert_eq!(zero.observe(1234), IpIdClass::Deviating{ expected: 0 });
+
+        // random IDs aren't judged
+        let mut random = IpIdModel::new();
+        for id in &[100, 50000, 7, 30000, 1234] {
+            assert_eq!(random.observe(*id), IpIdClass::Typical);
+        }
+    }
+}
@@ -1,5 +1,15 @@
 pub mod sequence;
 pub mod packet;
+pub mod ring;
+pub mod network;
+pub mod window;
+pub mod ttl;
+pub mod ip_id;
 
 pub use self::sequence::*;
 pub use self::packet::*;
+pub use self::ring::*;
+pub use self::network::*;
+pub use self::window::*;
+pub use self::ttl::*;
+pub use self::ip_id::*;
@@ -0,0 +1,159 @@
+use std::{error, fmt};
+use std::net::IpAddr;
+use std::str::FromStr;
+
+/// IP network in CIDR notation, e.g. `10.0.0.0/8` or `2001:db8::/32`.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+pub struct Cidr {
+    addr: IpAddr,
+    prefix_len: u8,
+}
+
+impl Cidr {
+    pub fn addr(&self) -> IpAddr {
+        self.addr
+    }
+
+    pub fn prefix_len(&self) -> u8 {
+        self.prefix_len
+    }
+
+    pub fn contains(&self, addr: IpAddr) -> bool {
+        match (self.addr, addr) {
+            (IpAddr::V4(net), IpAddr::V4(addr)) =>
+                prefix_matches(&net.octets(), &addr.octets(), self.prefix_len),
+            (IpAddr::V6(net), IpAddr::V6(addr)) =>
+                prefix_matches(&net.octets(), &addr.octets(), self.prefix_len),
+            _ => false,
+        }
+    }
+}
+
+fn prefix_matches(net: &[u8], addr: &[u8], prefix_len: u8) -> bool {
+    let (whole_bytes, rest_bits) = (usize::from(prefix_len / 8), prefix_len % 8);
+    if net[..whole_bytes] != addr[..whole_bytes] {
+        return false
+    }
+    if rest_bits == 0 {
+        return true
+    }
+    let mask = 0xffu8 << (8 - rest_bits);
+    net[whole_bytes] & mask == addr[whole_bytes] & mask
+}
+
+impl fmt::Display for Cidr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}/{}", self.addr, self.prefix_len)
+    }
+}
+
+/// A bare address is parsed as a single-host network.
+impl FromStr for Cidr {
+    type Err = ParseCidrError;
+    fn from_str(s: &str) -> Result<Self, ParseCidrError> {
+        let err = || ParseCidrError(s.to_owned());
+        let (addr, prefix_len) = match s.find('/') {
+            Some(slash) => (&s[..slash], Some(&s[slash + 1..])),
+            None => (s, None),
+        };
+        let addr: IpAddr = addr.parse().map_err(|_| err())?;
+        let max_prefix_len = if addr.is_ipv4() { 32 } else { 128 };
+        let prefix_len = match prefix_len {
+            Some(prefix_len) => prefix_len.parse().map_err(|_| err())?,
+            None => max_prefix_len,
+        };
+        if prefix_len > max_prefix_len {
+            return Err(err())
+        }
+        Ok(Self{ addr, prefix_len })
+    }
+}
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct ParseCidrError(String);
+
+impl fmt::Display for ParseCidrError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "invalid network `{}`, expected `<ip>/<prefix length>`", self.0)
+    }
+}
+
+impl error::Error for ParseCidrError {}
+
+/// Direction of traffic relative to the home network.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum Direction {
+    /// From outside into the home network.
+    Ingress,
+    /// From the home network to outside.
+    Egress,
+    /// Both endpoints are within the home network.
+    Internal,
+    /// Neither endpoint is within the home network.
+    External,
+}
+
+impl fmt::Display for Direction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            Direction::Ingress => "ingress",
+            Direction::Egress => "egress",
+            Direction::Internal => "internal",
+            Direction::External => "external",
+        };
+        f.write_str(name)
+    }
+}
+
+/// Set of networks considered local to the monitored site.
+#[derive(Debug, Clone, Default)]
+pub struct HomeNetwork {
+    networks: Vec<Cidr>,
+}
+
+impl HomeNetwork {
+    pub fn new(networks: Vec<Cidr>) -> Self {
+        Self{ networks }
+    }
+
+    pub fn contains(&self, addr: IpAddr) -> bool {
+        self.networks.iter().any(|network| network.contains(addr))
+    }
+
+    pub fn direction(&self, src: IpAddr, dst: IpAddr) -> Direction {
+        match (self.contains(src), self.contains(dst)) {
+            (false, true) => Direction::Ingress,
+            (true, false) => Direction::Egress,
+            (true, true) => Direction::Internal,
+            (false, false) => Direction::External,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cidr_matching_and_direction() {
+        let home = HomeNetwork::new(vec![
+            "10.0.0.0/8".parse().unwrap(),
+            "192.168.1.128/25".parse().unwrap(),
+            "2001:db8::/32".parse().unwrap(),
+        ]);
+        let addr = |s: &str| s.parse::<IpAddr>().unwrap();
+
+        assert!(home.contains(addr("10.20.30.40")));
+        assert!(home.contains(addr("192.168.1.200")));
+        assert!(!home.contains(addr("192.168.1.100")));
+        assert!(home.contains(addr("2001:db8::1")));
+        assert!(!home.contains(addr("::ffff:10.0.0.1")));
+
+        assert_eq!(home.direction(addr("8.8.8.8"), addr("10.0.0.1")), Direction::Ingress);
+        assert_eq!(home.direction(addr("10.0.0.1"), addr("8.8.8.8")), Direction::Egress);
+
+        assert_eq!("1.2.3.4".parse::<Cidr>().unwrap().to_string(), "1.2.3.4/32");
+        assert!("1.2.3.4/33".parse::<Cidr>().is_err());
+        assert!("0.0.0.0/0".parse::<Cidr>().unwrap().contains(addr("1.2.3.4")));
+    }
+}
@@ -1,58 +1,502 @@
-use std::net::IpAddr;
+use std::{error, fmt};
+use std::net::{IpAddr, SocketAddr};
+use std::str::FromStr;
+use std::time::{SystemTime, UNIX_EPOCH};
 use pnet::packet;
 
+use crate::types::network::{Direction, HomeNetwork};
+
 /// Represents information about TCP packet that matters for injections detection.
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct PacketManifest<'p> {
     pub ip: IpLayer,
     pub tcp: TcpLayer,
     pub tcp_payload: &'p [u8],
+    /// 802.1Q VLAN tags the frame was tagged with.
+    pub vlans: VlanStack,
+    /// Link layer addresses, if the packet came in an Ethernet frame.
+    pub ethernet: Option<EthernetLayer>,
+    /// Tunnel the packet was decapsulated from.
+    pub tunnel: Option<Tunnel>,
+    /// ERSPAN session the frame was mirrored by, for type II and III ERSPAN.
+    pub erspan_session: Option<u16>,
+    /// The TCP checksum was verified and is wrong, the receiver drops the segment.
+    pub bad_checksum: bool,
+    pub meta: PacketMeta,
+}
+
+/// Most VLAN tags a frame may carry, frames with more are not analyzed.
+pub const MAX_VLAN_TAGS: usize = 4;
+
+/// VLAN IDs of the 802.1Q and 802.1ad tags of a frame, outermost first.
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq, Ord, PartialOrd, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct VlanStack {
+    ids: [u16; MAX_VLAN_TAGS],
+    len: u8,
+}
+
+impl VlanStack {
+    /// Adds a tag inside the ones already there, returns `false` if the stack is full.
+    pub fn push(&mut self, id: u16) -> bool {
+        match self.ids.get_mut(usize::from(self.len)) {
+            Some(slot) => {
+                *slot = id & 0x0fff;
+                self.len += 1;
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn ids(&self) -> &[u16] {
+        &self.ids[..usize::from(self.len)]
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Outermost VLAN ID, the service VLAN for QinQ traffic.
+    pub fn outer(&self) -> Option<u16> {
+        self.ids().first().cloned()
+    }
+}
+
+impl From<&[u16]> for VlanStack {
+    /// Extra tags beyond `MAX_VLAN_TAGS` are dropped.
+    fn from(ids: &[u16]) -> Self {
+        let mut stack = Self::default();
+        for &id in ids {
+            stack.push(id);
+        }
+        stack
+    }
+}
+
+/// Formats as dot separated IDs, outermost first, e.g. `100.7`.
+impl fmt::Display for VlanStack {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (i, id) in self.ids().iter().enumerate() {
+            if i > 0 {
+                write!(f, ".")?;
+            }
+            write!(f, "{}", id)?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Encapsulation {
+    Gre,
+    Geneve,
+    /// IPv4 or IPv6 right in IP, as in 6in4.
+    IpInIp,
+}
+
+impl Encapsulation {
+    pub fn name(self) -> &'static str {
+        match self {
+            Encapsulation::Gre => "gre",
+            Encapsulation::Geneve => "geneve",
+            Encapsulation::IpInIp => "ipip",
+        }
+    }
+}
+
+/// Outer endpoints of the tunnel a packet was carried in.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Tunnel {
+    pub encapsulation: Encapsulation,
+    pub src: IpAddr,
+    pub dst: IpAddr,
+    /// GRE key or virtual network ID (NVGRE, GENEVE), tunnels with different keys carry separate networks.
+    pub key: Option<u32>,
+}
+
+impl Tunnel {
+    /// The same tunnel whichever way the packet went through it.
+    pub fn canonical(self) -> Self {
+        if self.src <= self.dst { self } else { Self{ src: self.dst, dst: self.src, ..self } }
+    }
+}
+
+/// Formats as `gre 10.0.0.1 <-> 10.0.0.2`, suffixed with `key <key>` if the tunnel has one.
+impl fmt::Display for Tunnel {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} {} <-> {}", self.encapsulation.name(), self.src, self.dst)?;
+        if let Some(key) = self.key {
+            write!(f, " key {}", key)?;
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for Tunnel {
+    type Err = ParseFlowError;
+    fn from_str(s: &str) -> Result<Self, ParseFlowError> {
+        let err = || ParseFlowError(s.to_owned());
+        let fields: Vec<_> = s.split_whitespace().collect();
+        let encapsulation = match fields.first() {
+            Some(&"gre") => Encapsulation::Gre,
+            Some(&"geneve") => Encapsulation::Geneve,
+            Some(&"ipip") => Encapsulation::IpInIp,
+            _ => return Err(err()),
+        };
+        let key = match fields.get(4..) {
+            Some(&[]) => None,
+            Some(&["key", key]) => Some(key.parse().map_err(|_| err())?),
+            _ => return Err(err()),
+        };
+        match (fields[1].parse(), fields[2], fields[3].parse()) {
+            (Ok(src), "<->", Ok(dst)) => Ok(Self{ encapsulation, src, dst, key }),
+            _ => Err(err()),
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub struct EthernetLayer {
+    pub src: [u8; 6],
+    pub dst: [u8; 6],
+}
+
+/// Where and when the frame carrying the packet was captured.
+#[derive(Copy, Clone, Debug)]
+pub struct PacketMeta {
+    /// Capture time as reported by the packet source.
+    pub ts: SystemTime,
+    /// Index of the interface the frame was captured on.
+    pub iface_id: u32,
+    /// Length of the frame on the wire.
+    pub wire_len: u32,
+    /// Number of captured bytes, less than `wire_len` if the frame was truncated.
+    pub cap_len: u32,
+    /// Header the frame starts with.
+    pub link_type: LinkType,
+}
+
+impl Default for PacketMeta {
+    fn default() -> Self {
+        Self{ ts: UNIX_EPOCH, iface_id: 0, wire_len: 0, cap_len: 0, link_type: LinkType::Ethernet }
+    }
+}
+
+/// Link layer of captured frames.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum LinkType {
+    Ethernet,
+    /// Linux cooked capture, as written by `tcpdump -i any`.
+    LinuxSll,
+    /// Linux cooked capture v2, which also records the interface index.
+    LinuxSll2,
+}
+
+impl LinkType {
+    /// Link type of a pcap `LINKTYPE_` value, `None` for unsupported ones.
+    pub fn from_pcap(link_type: u32) -> Option<Self> {
+        match link_type {
+            1 => Some(LinkType::Ethernet),
+            113 => Some(LinkType::LinuxSll),
+            276 => Some(LinkType::LinuxSll2),
+            _ => None,
+        }
+    }
 }
 
 #[derive(Copy, Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct IpLayer {
     pub src: IpAddr,
     pub dst: IpAddr,
+    /// Length of the whole IP datagram including headers.
+    pub total_len: u32,
+    /// Identification of an IPv4 datagram, IPv6 has none outside of fragments.
+    pub id: Option<u16>,
+    /// TTL of IPv4, hop limit of IPv6.
+    pub ttl: u8,
 }
 
 #[derive(Copy, Clone, Debug, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct TcpLayer {
     pub src: u16,
     pub dst: u16,
     pub ack: u32,
     pub seq: u32,
     pub flags: TcpFlags,
+    /// Advertised receive window, not yet scaled.
+    pub window: u16,
+    /// Offset from `seq` of the byte following the urgent data, set along with the URG flag.
+    pub urgent_ptr: u16,
+    pub options: TcpOptions,
+}
+
+/// At most this many option kinds are remembered per segment, the rest are dropped.
+const MAX_OPTION_KINDS: usize = 16;
+
+/// TCP options of a segment, decoded once while parsing.
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct TcpOptions {
+    kinds: [u8; MAX_OPTION_KINDS],
+    kinds_len: u8,
+    mss: Option<u16>,
+    window_scale: Option<u8>,
+    sack_permitted: bool,
+    sack_blocks: [(u32, u32); 4],
+    sack_blocks_len: u8,
+    timestamps: Option<(u32, u32)>,
+    fast_open: Option<u8>,
+}
+
+/// Magic number of TCP Fast Open in the experimental option kind, from before kind 34 was assigned.
+const FAST_OPEN_EXPERIMENT: [u8; 2] = [0xf9, 0x89];
+
+impl TcpOptions {
+    pub fn from_pdu(tcp: &pdu::TcpPdu) -> Self {
+        let mut options = Self::default();
+        for option in tcp.options() {
+            let kind = match option {
+                // the data includes the kind and length bytes
+                pdu::TcpOption::Raw { option: 34, data } => {
+                    options.fast_open = Some(data.len().saturating_sub(2) as u8);
+                    34
+                }
+                pdu::TcpOption::Raw { option: 254, data } if data.get(2..4) == Some(&FAST_OPEN_EXPERIMENT[..]) => {
+                    options.fast_open = Some(data.len().saturating_sub(4) as u8);
+                    254
+                }
+                pdu::TcpOption::Raw { option, .. } => option,
+                pdu::TcpOption::NoOp => 1,
+                pdu::TcpOption::Mss { size } => {
+                    options.mss = Some(size);
+                    2
+                }
+                pdu::TcpOption::WindowScale { shift } => {
+                    options.window_scale = Some(shift);
+                    3
+                }
+                pdu::TcpOption::SackPermitted => {
+                    options.sack_permitted = true;
+                    4
+                }
+                pdu::TcpOption::Sack { blocks } => {
+                    for block in blocks.iter().flatten() {
+                        options.sack_blocks[usize::from(options.sack_blocks_len)] = *block;
+                        options.sack_blocks_len += 1;
+                    }
+                    5
+                }
+                pdu::TcpOption::Timestamp { val, ecr } => {
+                    options.timestamps = Some((val, ecr));
+                    8
+                }
+            };
+            if usize::from(options.kinds_len) < MAX_OPTION_KINDS {
+                options.kinds[usize::from(options.kinds_len)] = kind;
+                options.kinds_len += 1;
+            }
+        }
+        options
+    }
+
+    /// Option kinds in the order they appear in the segment, including NOPs.
+    pub fn kinds(&self) -> &[u8] {
+        &self.kinds[..usize::from(self.kinds_len)]
+    }
+
+    pub fn mss(&self) -> Option<u16> {
+        self.mss
+    }
+
+    pub fn window_scale(&self) -> Option<u8> {
+        self.window_scale
+    }
+
+    pub fn sack_permitted(&self) -> bool {
+        self.sack_permitted
+    }
+
+    /// SACK blocks as (left edge, right edge) pairs.
+    pub fn sack_blocks(&self) -> &[(u32, u32)] {
+        &self.sack_blocks[..usize::from(self.sack_blocks_len)]
+    }
+
+    /// (TSval, TSecr) pair.
+    pub fn timestamps(&self) -> Option<(u32, u32)> {
+        self.timestamps
+    }
+
+    /// Length of the TCP Fast Open cookie (RFC 7413), 0 for a request of one.
+    pub fn fast_open(&self) -> Option<u8> {
+        self.fast_open
+    }
 }
 
 #[derive(Copy, Clone, Debug, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct TcpFlags {
     pub syn: bool,
     pub ack: bool,
     pub fin: bool,
     pub rst: bool,
+    pub urg: bool,
+}
+
+impl TcpFlags {
+    /// Flags in the on-wire bit layout (FIN is the least significant bit).
+    pub fn bits(&self) -> u8 {
+        (self.fin as u8) | (self.syn as u8) << 1 | (self.rst as u8) << 2 | (self.ack as u8) << 4 | (self.urg as u8) << 5
+    }
 }
 
 #[derive(Debug, Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Protocol {
+    Tcp,
+    Udp,
+}
+
+impl Protocol {
+    /// IANA protocol number.
+    pub fn number(self) -> u8 {
+        match self {
+            Protocol::Tcp => 6,
+            Protocol::Udp => 17,
+        }
+    }
+}
+
+/// Identifies a conversation: transport protocol, both endpoints, the VLAN tags it was seen with
+/// and the tunnel it was carried in.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Flow {
+    protocol: Protocol,
     src: (IpAddr, u16),
     dst: (IpAddr, u16),
+    vlans: VlanStack,
+    /// In canonical form, both directions of a flow go through the same tunnel.
+    tunnel: Option<Tunnel>,
 }
 
 impl<'p> From<&PacketManifest<'p>> for Flow {
     fn from(packet: &PacketManifest<'p>) -> Self {
         let src = (packet.ip.src, packet.tcp.src);
         let dst = (packet.ip.dst, packet.tcp.dst);
-        Self{ src, dst }
+        let tunnel = packet.tunnel.map(Tunnel::canonical);
+        Self{ protocol: Protocol::Tcp, src, dst, vlans: packet.vlans, tunnel }
     }
 }
 
 impl Flow {
+    pub fn protocol(&self) -> Protocol {
+        self.protocol
+    }
+
+    /// Outermost VLAN ID.
+    pub fn vlan(&self) -> Option<u16> {
+        self.vlans.outer()
+    }
+
+    pub fn vlans(&self) -> VlanStack {
+        self.vlans
+    }
+
+    pub fn tunnel(&self) -> Option<Tunnel> {
+        self.tunnel
+    }
+
+    pub fn src(&self) -> (IpAddr, u16) {
+        self.src
+    }
+
+    pub fn dst(&self) -> (IpAddr, u16) {
+        self.dst
+    }
+
     pub fn reverse(mut self) -> Self {
         std::mem::swap(&mut self.src, &mut self.dst);
         self
     }
 }
 
+/// Formats as `1.2.3.4:443 <-> 5.6.7.8:51234`. UDP flows are prefixed with `udp`,
+/// tagged ones are suffixed with `vlan <id>`, or `vlan <outer id>.<inner id>` for stacked tags,
+/// tunneled ones with `in <tunnel>`.
+impl fmt::Display for Flow {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.protocol == Protocol::Udp {
+            write!(f, "udp ")?;
+        }
+        write!(f, "{} <-> {}", SocketAddr::from(self.src), SocketAddr::from(self.dst))?;
+        if !self.vlans.is_empty() {
+            write!(f, " vlan {}", self.vlans)?;
+        }
+        if let Some(tunnel) = self.tunnel {
+            write!(f, " in {}", tunnel)?;
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for Flow {
+    type Err = ParseFlowError;
+    fn from_str(s: &str) -> Result<Self, ParseFlowError> {
+        let err = || ParseFlowError(s.to_owned());
+        let mut rest = s.trim();
+
+        let mut protocol = Protocol::Tcp;
+        for &(prefix, prefix_protocol) in &[("tcp ", Protocol::Tcp), ("udp ", Protocol::Udp)] {
+            if rest.starts_with(prefix) {
+                protocol = prefix_protocol;
+                rest = &rest[prefix.len()..];
+            }
+        }
+        let mut tunnel = None;
+        if let Some(position) = rest.find(" in ") {
+            tunnel = Some(rest[position + " in ".len()..].parse::<Tunnel>().map_err(|_| err())?.canonical());
+            rest = &rest[..position];
+        }
+        let mut vlans = VlanStack::default();
+        if let Some(position) = rest.find(" vlan ") {
+            for id in rest[position + " vlan ".len()..].trim().split('.') {
+                if !vlans.push(id.parse().map_err(|_| err())?) {
+                    return Err(err())
+                }
+            }
+            rest = &rest[..position];
+        }
+
+        let mut endpoints = rest.split("<->").map(|endpoint| endpoint.trim().parse::<SocketAddr>());
+        match (endpoints.next(), endpoints.next(), endpoints.next()) {
+            (Some(Ok(src)), Some(Ok(dst)), None) => Ok(Self{
+                protocol,
+                src: (src.ip(), src.port()),
+                dst: (dst.ip(), dst.port()),
+                vlans,
+                tunnel,
+            }),
+            _ => Err(err()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct ParseFlowError(String);
+
+impl fmt::Display for ParseFlowError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "invalid flow `{}`, expected `<ip>:<port> <-> <ip>:<port>`", self.0)
+    }
+}
+
+impl error::Error for ParseFlowError {}
+
 /// Used to identify packet sender side within Connection
 #[derive(Eq, PartialEq, Copy, Clone)]
 pub struct SideIdentifier {
@@ -65,23 +509,132 @@ impl SideIdentifier {
         Self{ client_flow, server_flow: client_flow.reverse() }
     }
 
+    pub fn client_flow(&self) -> Flow {
+        self.client_flow
+    }
+
     /// Determines which side has sent this packet.
-    ///
-    /// # Panic
-    /// Panics if packet is sent by neither client nor server.
-    pub fn identify(&self, packet: &PacketManifest) -> Side {
-        if self.client_flow == Flow::from(packet) {
-            Side::Client
-        } else if self.server_flow == Flow::from(packet) {
-            Side::Server
+    pub fn identify(&self, packet: &PacketManifest) -> Result<Side, UnknownSender> {
+        let flow = Flow::from(packet);
+        if self.client_flow == flow {
+            Ok(Side::Client)
+        } else if self.server_flow == flow {
+            Ok(Side::Server)
         } else {
-            panic!("Unknown packet sender")
+            Err(UnknownSender{ flow })
         }
     }
+
+    /// Determines which side has sent this packet and which way it goes relative to `home`.
+    pub fn identify_with_direction(&self, packet: &PacketManifest, home: &HomeNetwork)
+        -> Result<(Side, Direction), UnknownSender>
+    {
+        let side = self.identify(packet)?;
+        Ok((side, home.direction(packet.ip.src, packet.ip.dst)))
+    }
+}
+
+/// Packet belongs to neither side of the connection.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct UnknownSender {
+    pub flow: Flow,
 }
 
+impl fmt::Display for UnknownSender {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "packet of flow {} was sent by neither client nor server", self.flow)
+    }
+}
+
+impl error::Error for UnknownSender {}
+
 #[derive(Eq, PartialEq, Copy, Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Side {
     Client,
     Server,
 }
+
+impl Side {
+    /// The other end of the connection.
+    pub fn peer(self) -> Side {
+        match self {
+            Side::Client => Side::Server,
+            Side::Server => Side::Client,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn flow_display_round_trip() {
+        let v4: Flow = "1.2.3.4:443 <-> 5.6.7.8:51234".parse().unwrap();
+        assert_eq!(v4.src(), ("1.2.3.4".parse().unwrap(), 443));
+        assert_eq!(v4.to_string(), "1.2.3.4:443 <-> 5.6.7.8:51234");
+
+        let v6: Flow = "[::1]:80<->[2001:db8::2]:1234".parse().unwrap();
+        assert_eq!(v6.to_string(), "[::1]:80 <-> [2001:db8::2]:1234");
+
+        let udp_tagged: Flow = "udp 1.2.3.4:53 <-> 5.6.7.8:5353 vlan 10".parse().unwrap();
+        assert_eq!(udp_tagged.protocol(), Protocol::Udp);
+        assert_eq!(udp_tagged.vlan(), Some(10));
+        assert_eq!(udp_tagged.to_string(), "udp 1.2.3.4:53 <-> 5.6.7.8:5353 vlan 10");
+        assert_ne!(udp_tagged, "1.2.3.4:53 <-> 5.6.7.8:5353 vlan 10".parse().unwrap());
+        assert_ne!(udp_tagged, "udp 1.2.3.4:53 <-> 5.6.7.8:5353".parse().unwrap());
+
+        assert!("1.2.3.4:443".parse::<Flow>().is_err());
+        let stacked: Flow = "1.2.3.4:443 <-> 5.6.7.8:1 vlan 100.7".parse().unwrap();
+        assert_eq!((stacked.vlan(), stacked.vlans().ids()), (Some(100), &[100, 7][..]));
+        assert_eq!(stacked.to_string(), "1.2.3.4:443 <-> 5.6.7.8:1 vlan 100.7");
+        assert_ne!(stacked, "1.2.3.4:443 <-> 5.6.7.8:1 vlan 200.7".parse().unwrap());
+
+        let tunneled: Flow = "1.2.3.4:443 <-> 5.6.7.8:1 vlan 7 in gre 10.0.0.2 <-> 10.0.0.1 key 5".parse().unwrap();
+        assert_eq!(tunneled.to_string(), "1.2.3.4:443 <-> 5.6.7.8:1 vlan 7 in gre 10.0.0.1 <-> 10.0.0.2 key 5");
+        assert_eq!(tunneled.reverse().tunnel(), tunneled.tunnel());
+        assert_ne!(tunneled, "1.2.3.4:443 <-> 5.6.7.8:1 vlan 7 in gre 10.0.0.2 <-> 10.0.0.1".parse().unwrap());
+
+        assert!("1.2.3.4:443 <-> 5.6.7.8:1 vlan x".parse::<Flow>().is_err());
+        let geneve: Flow = "1.2.3.4:443 <-> 5.6.7.8:1 in geneve 10.0.0.1 <-> 10.0.0.2 key 5".parse().unwrap();
+        assert_ne!(geneve.tunnel(), tunneled.tunnel());
+        assert!("1.2.3.4:443 <-> 5.6.7.8:1 in gre 10.0.0.1".parse::<Flow>().is_err());
+        assert!("1.2.3.4:443 <-> 5.6.7.8:1 vlan 1.".parse::<Flow>().is_err());
+        assert!("1.2.3.4:443 <-> 5.6.7.8:1 vlan 1.2.3.4.5".parse::<Flow>().is_err());
+        assert!("1.2.3.4:443 <-> 5.6.7.8".parse::<Flow>().is_err());
+    }
+
+    #[test]
+    fn tcp_options_decoding() {
+        let mut segment = vec![0u8; 20];
+        segment[12] = 10 << 4; // data offset: 40 bytes
+        segment.extend_from_slice(&[
+            2, 4, 0x05, 0xb4,                  // MSS 1460
+            4, 2,                              // SACK permitted
+            8, 10, 0, 0, 0, 1, 0, 0, 0, 2,     // timestamps
+            1,                                 // NOP
+            3, 3, 7,                           // window scale
+        ]);
+        let pdu = pdu::TcpPdu::new(&segment).unwrap();
+        let options = TcpOptions::from_pdu(&pdu);
+
+        assert_eq!(options.kinds(), &[2, 4, 8, 1, 3]);
+        assert_eq!(options.mss(), Some(1460));
+        assert!(options.sack_permitted());
+        assert_eq!(options.timestamps(), Some((1, 2)));
+        assert_eq!(options.window_scale(), Some(7));
+        assert!(options.sack_blocks().is_empty());
+        assert_eq!(options.fast_open(), None);
+
+        // a Fast Open cookie, then a request for one in the experimental kind
+        for (option, cookie_len) in [(&[34, 10, 1, 2, 3, 4, 5, 6, 7, 8][..], 8), (&[254, 4, 0xf9, 0x89], 0)] {
+            let mut segment = vec![0u8; 20];
+            segment[12] = 8 << 4;
+            segment.extend_from_slice(option);
+            segment.resize(32, 1);
+            let options = TcpOptions::from_pdu(&pdu::TcpPdu::new(&segment).unwrap());
+            assert_eq!(options.fast_open(), Some(cookie_len));
+        }
+    }
+}
@@ -0,0 +1,108 @@
+use std::collections::{vec_deque, VecDeque};
+
+/// Fixed capacity buffer which overwrites the oldest element when full.
+#[derive(Debug, Clone)]
+pub struct Ring<T> {
+    items: VecDeque<T>,
+    capacity: usize,
+}
+
+impl<T> Ring<T> {
+    pub fn new(capacity: usize) -> Self {
+        Self{ items: VecDeque::with_capacity(capacity), capacity }
+    }
+
+    /// Appends an element. If the ring is full, the oldest element is overwritten and returned.
+    /// Zero capacity ring hands the pushed element straight back.
+    pub fn push(&mut self, item: T) -> Option<T> {
+        if self.capacity == 0 {
+            return Some(item)
+        }
+        let evicted = if self.items.len() == self.capacity {
+            self.items.pop_front()
+        } else {
+            None
+        };
+        self.items.push_back(item);
+        evicted
+    }
+
+    pub fn len(&self) -> usize {
+        self.items.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.items.is_empty()
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// Element at logical index `i`, counting from the oldest one.
+    pub fn get(&self, i: usize) -> Option<&T> {
+        self.items.get(i)
+    }
+
+    /// Most recently pushed element.
+    pub fn last(&self) -> Option<&T> {
+        self.items.back()
+    }
+
+    /// Iterates from the oldest element to the newest one.
+    pub fn iter(&self) -> vec_deque::Iter<'_, T> {
+        self.items.iter()
+    }
+
+    pub fn clear(&mut self) {
+        self.items.clear()
+    }
+
+    /// Removes all elements, yielding them from the oldest to the newest one.
+    pub fn drain(&mut self) -> vec_deque::Drain<'_, T> {
+        self.items.drain(..)
+    }
+
+    /// Changes capacity. If it shrinks below current length, the oldest elements are dropped.
+    pub fn resize(&mut self, capacity: usize) {
+        if self.items.len() > capacity {
+            let excess = self.items.len() - capacity;
+            self.items.drain(..excess);
+        }
+        self.capacity = capacity;
+        self.items.shrink_to_fit();
+        self.items.reserve_exact(capacity - self.items.len());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn overwrites_drains_and_resizes() {
+        let mut ring = Ring::new(3);
+        let evicted: Vec<_> = (0..5).filter_map(|i| ring.push(i)).collect();
+        assert_eq!(evicted, vec![0, 1]);
+        assert_eq!(ring.iter().cloned().collect::<Vec<_>>(), vec![2, 3, 4]);
+        assert_eq!(ring.get(0), Some(&2));
+        assert_eq!(ring.get(3), None);
+        assert_eq!(ring.last(), Some(&4));
+
+        ring.resize(2);
+        assert_eq!(ring.capacity(), 2);
+        assert_eq!(ring.iter().cloned().collect::<Vec<_>>(), vec![3, 4]);
+
+        ring.resize(4);
+        assert_eq!(ring.push(5), None);
+        assert_eq!(ring.push(6), None);
+        assert_eq!(ring.drain().collect::<Vec<_>>(), vec![3, 4, 5, 6]);
+        assert!(ring.is_empty());
+
+        ring.push(7);
+        ring.clear();
+        assert_eq!(ring.len(), 0);
+
+        assert_eq!(Ring::new(0).push(8), Some(8));
+    }
+}
@@ -1,12 +1,31 @@
 use std::ops;
 
-#[derive(Ord, PartialOrd, Eq, PartialEq, Copy, Clone, Debug)]
+/// TCP sequence number. As numbers wrap around, they have no plain ordering or difference,
+/// only the serial number arithmetic below.
+#[derive(Eq, PartialEq, Hash, Copy, Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Sequence(u32);
 
-impl ops::Sub for Sequence {
-    type Output = i64;
-    fn sub(self, rhs: Sequence) -> i64 {
-        i64::from(self.0) - i64::from(rhs.0)
+impl Sequence {
+    /// Signed distance from `self` to `other` in serial number arithmetic (RFC 1982).
+    /// Positive if `other` is after `self`, correctly handles the 2^32 wrap.
+    pub fn distance(self, other: Sequence) -> i32 {
+        other.0.wrapping_sub(self.0) as i32
+    }
+
+    /// Whether `self` precedes `other` in serial number arithmetic.
+    pub fn is_before(self, other: Sequence) -> bool {
+        self.distance(other) > 0
+    }
+
+    /// Whether `self` follows `other` in serial number arithmetic.
+    pub fn is_after(self, other: Sequence) -> bool {
+        other.is_before(self)
+    }
+
+    /// Whether `self` lies within `len` bytes starting at `start`.
+    pub fn within_window(self, start: Sequence, len: u32) -> bool {
+        WrappingRange::new(start, len).contains(self)
     }
 }
 
@@ -22,3 +41,194 @@ impl From<u32> for Sequence {
         Sequence(seq)
     }
 }
+
+impl From<Sequence> for u32 {
+    fn from(seq: Sequence) -> Self {
+        seq.0
+    }
+}
+
+/// Half-open range `[start, end)` of sequence numbers which may cross the 2^32 wrap.
+#[derive(Eq, PartialEq, Hash, Copy, Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct WrappingRange {
+    start: Sequence,
+    end: Sequence,
+}
+
+impl WrappingRange {
+    pub fn new(start: Sequence, len: u32) -> Self {
+        Self{ start, end: start + len }
+    }
+
+    pub fn start(&self) -> Sequence {
+        self.start
+    }
+
+    pub fn end(&self) -> Sequence {
+        self.end
+    }
+
+    pub fn len(&self) -> u32 {
+        self.end.0.wrapping_sub(self.start.0)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.start == self.end
+    }
+
+    pub fn contains(&self, seq: Sequence) -> bool {
+        seq.0.wrapping_sub(self.start.0) < self.len()
+    }
+
+    /// Whether every sequence number of `other` is within `self`.
+    pub fn contains_range(&self, other: &WrappingRange) -> bool {
+        let (start, end) = self.offsets_of(other);
+        start >= 0 && end <= i64::from(self.len())
+    }
+
+    /// Whether the ranges share at least one sequence number.
+    pub fn overlaps(&self, other: &WrappingRange) -> bool {
+        self.intersection(other).is_some()
+    }
+
+    /// Common part of the ranges, `None` if they don't overlap.
+    pub fn intersection(&self, other: &WrappingRange) -> Option<WrappingRange> {
+        let (other_start, other_end) = self.offsets_of(other);
+        let start = other_start.max(0);
+        let end = other_end.min(i64::from(self.len()));
+        if start < end {
+            Some(self.range_at(start, end))
+        } else {
+            None
+        }
+    }
+
+    /// Smallest range covering both ranges, `None` if they neither overlap nor adjoin.
+    pub fn merge(&self, other: &WrappingRange) -> Option<WrappingRange> {
+        let (other_start, other_end) = self.offsets_of(other);
+        if other_start.max(0) > other_end.min(i64::from(self.len())) {
+            return None
+        }
+        Some(self.range_at(other_start.min(0), other_end.max(i64::from(self.len()))))
+    }
+
+    /// Splits the range into `[start, seq)` and `[seq, end)`, `None` if `seq` is outside `[start, end]`.
+    pub fn split_at(&self, seq: Sequence) -> Option<(WrappingRange, WrappingRange)> {
+        let offset = seq.0.wrapping_sub(self.start.0);
+        if offset > self.len() {
+            return None
+        }
+        Some((WrappingRange::new(self.start, offset), WrappingRange::new(seq, self.len() - offset)))
+    }
+
+    /// Parts of the range not covered by any of `others`, in order.
+    pub fn uncovered(&self, others: &[WrappingRange]) -> impl Iterator<Item = WrappingRange> {
+        let mut covered: Vec<_> = others.iter()
+            .filter_map(|other| self.intersection(other))
+            .map(|intersection| self.offsets_of(&intersection))
+            .collect();
+        covered.sort_unstable();
+
+        let mut gaps = Vec::new();
+        let mut position = 0;
+        for (start, end) in covered {
+            if start > position {
+                gaps.push(self.range_at(position, start));
+            }
+            position = position.max(end);
+        }
+        if position < i64::from(self.len()) {
+            gaps.push(self.range_at(position, i64::from(self.len())));
+        }
+        gaps.into_iter()
+    }
+
+    /// Bounds of `other` as offsets from `self.start`.
+    fn offsets_of(&self, other: &WrappingRange) -> (i64, i64) {
+        let start = i64::from(self.start.distance(other.start));
+        (start, start + i64::from(other.len()))
+    }
+
+    fn range_at(&self, start: i64, end: i64) -> WrappingRange {
+        WrappingRange::new(self.start + start as u32, (end - start) as u32)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn comparison_across_wrap() {
+        let before_wrap = Sequence::from(u32::MAX - 10);
+        let after_wrap = before_wrap + 20;
+
+        assert_eq!(u32::from(after_wrap), 9);
+        assert_eq!(before_wrap.distance(after_wrap), 20);
+        assert_eq!(after_wrap.distance(before_wrap), -20);
+        assert!(before_wrap.is_before(after_wrap));
+        assert!(after_wrap.is_after(before_wrap));
+        assert!(!before_wrap.is_before(before_wrap));
+    }
+
+    #[test]
+    fn window_across_wrap() {
+        let start = Sequence::from(u32::MAX - 1);
+        let range = WrappingRange::new(start, 4);
+
+        assert_eq!(range.len(), 4);
+        assert!(range.contains(start));
+        assert!(range.contains(Sequence::from(1)));
+        assert!(!range.contains(Sequence::from(2)));
+        assert!(!range.contains(start + u32::MAX));
+        assert!(Sequence::from(0).within_window(start, 4));
+        assert!(WrappingRange::new(start, 0).is_empty());
+    }
+
+    #[test]
+    fn set_operations_across_wrap() {
+        let start = Sequence::from(u32::MAX - 4);
+        let left = WrappingRange::new(start, 10);
+        let right = WrappingRange::new(start + 6, 10);
+        let adjoining = WrappingRange::new(start + 16, 2);
+
+        assert!(left.overlaps(&right));
+        assert_eq!(left.intersection(&right), Some(WrappingRange::new(start + 6, 4)));
+        assert_eq!(right.intersection(&left), left.intersection(&right));
+        assert_eq!(left.merge(&right), Some(WrappingRange::new(start, 16)));
+        assert_eq!(right.merge(&left), left.merge(&right));
+
+        assert!(!right.overlaps(&adjoining));
+        assert_eq!(right.merge(&adjoining), Some(WrappingRange::new(start + 6, 12)));
+        assert_eq!(left.merge(&adjoining), None);
+
+        assert!(left.contains_range(&WrappingRange::new(start + 2, 8)));
+        assert!(!left.contains_range(&right));
+    }
+
+    #[test]
+    fn split_and_uncovered_parts() {
+        let start = Sequence::from(u32::MAX - 4);
+        let range = WrappingRange::new(start, 20);
+
+        let (head, tail) = range.split_at(start + 8).unwrap();
+        assert_eq!((head.len(), tail.len()), (8, 12));
+        assert_eq!(tail.start(), start + 8);
+        assert_eq!(range.split_at(start + 21), None);
+
+        let covering = [
+            WrappingRange::new(start + 2, 3),
+            WrappingRange::new(start + 4, 4),
+            WrappingRange::new(start + 12, 2),
+            WrappingRange::new(start + 30, 5),
+        ];
+        let uncovered: Vec<_> = range.uncovered(&covering).collect();
+        assert_eq!(uncovered, vec![
+            WrappingRange::new(start, 2),
+            WrappingRange::new(start + 8, 4),
+            WrappingRange::new(start + 14, 6),
+        ]);
+        assert_eq!(range.uncovered(&[range]).count(), 0);
+    }
+}
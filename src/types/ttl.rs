@@ -0,0 +1,92 @@
+/// Hops a route may change by without the TTL looking suspicious.
+const MAX_HOP_CHANGE: u8 = 3;
+/// Initial TTLs common stacks send with.
+const INITIAL_TTLS: [u8; 4] = [32, 64, 128, 255];
+
+/// How a segment's TTL compares to the ones its side usually sends with.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum TtlClass {
+    Typical,
+    /// The segment likely comes from another host, e.g. an injector closer than the real endpoint.
+    Deviating { typical: u8 },
+}
+
+/// TTL, or IPv6 hop limit, one side's segments arrive with, learned from the first segment.
+/// Deviating segments don't change what is typical, so that an injector can't teach it.
+#[derive(Debug, Copy, Clone, Default, Eq, PartialEq)]
+pub struct TtlModel {
+    /// Lowest and highest typical TTL seen, at most a route change apart.
+    range: Option<(u8, u8)>,
+}
+
+impl TtlModel {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn observe(&mut self, ttl: u8) -> TtlClass {
+        let class = self.classify(ttl);
+        if class == TtlClass::Typical {
+            self.range = Some(self.range.map_or((ttl, ttl), |(low, high)| (low.min(ttl), high.max(ttl))));
+        }
+        class
+    }
+
+    /// Class of `ttl` without learning from it, any TTL is typical before the first segment.
+    pub fn classify(&self, ttl: u8) -> TtlClass {
+        match self.range {
+            Some((low, high)) if ttl.saturating_add(MAX_HOP_CHANGE) < high || ttl > low.saturating_add(MAX_HOP_CHANGE)
+                => TtlClass::Deviating{ typical: low },
+            _ => TtlClass::Typical,
+        }
+    }
+
+    /// Hops between the side and the sensor, guessing the side started from the next common initial TTL.
+    /// The fewest hops seen are taken, `None` until a segment was seen.
+    pub fn hops(&self) -> Option<u8> {
+        let (_, high) = self.range?;
+        INITIAL_TTLS.iter().find(|&&initial| initial >= high).map(|initial| initial - high)
+    }
+
+    /// Whether a segment sent towards the side, arriving at the sensor with `ttl`, surely expires on the way.
+    /// Routes may be asymmetric by up to a route change.
+    pub fn expires_before(&self, ttl: u8) -> bool {
+        self.hops().is_some_and(|hops| ttl.saturating_add(MAX_HOP_CHANGE) < hops)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tolerates_route_changes_only() {
+        let mut model = TtlModel::new();
+        assert_eq!(model.observe(52), TtlClass::Typical);
+        assert_eq!(model.observe(50), TtlClass::Typical);
+        assert_eq!(model.observe(53), TtlClass::Typical);
+        // an injector a few hops away, and one starting from another initial TTL
+        assert_eq!(model.observe(60), TtlClass::Deviating{ typical: 50 });
+        assert_eq!(model.observe(120), TtlClass::Deviating{ typical: 50 });
+        // the range doesn't creep away by typical segments
+        assert_eq!(model.observe(47), TtlClass::Deviating{ typical: 50 });
+        assert_eq!(model.observe(51), TtlClass::Typical);
+    }
+
+    #[test]
+    fn guesses_hops_from_initial_ttl() {
+        let mut model = TtlModel::new();
+        assert_eq!(model.hops(), None);
+        assert!(!model.expires_before(1));
+        model.observe(116);
+        model.observe(118);
+        assert_eq!(model.hops(), Some(10));
+        assert!(model.expires_before(6));
+        assert!(!model.expires_before(7));
+        // a side next to the sensor
+        let mut local = TtlModel::new();
+        local.observe(64);
+        assert_eq!(local.hops(), Some(0));
+        assert!(!local.expires_before(1));
+    }
+}
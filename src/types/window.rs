@@ -0,0 +1,164 @@
+use crate::types::{Sequence, WrappingRange};
+
+/// Largest shift allowed by RFC 7323, bigger values are clamped to it.
+const MAX_WINDOW_SCALE: u8 = 14;
+
+/// Where a segment falls relative to the receiver's window.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum SegmentClass {
+    InWindow,
+    OutOfWindow,
+    /// All of the segment was already acknowledged.
+    Duplicate,
+}
+
+/// How the receiver treats a RST, following RFC 5961.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum RstClass {
+    /// At the next sequence number expected, the connection is reset.
+    Exact,
+    /// Elsewhere within the window, the receiver answers with a challenge ACK.
+    ChallengeAck,
+    /// Outside of the window, silently dropped.
+    OffWindow,
+}
+
+/// Receive window state of one side of a connection, learned from segments that side sends.
+#[derive(Debug, Copy, Clone, Default, Eq, PartialEq)]
+pub struct WindowTracker {
+    window: u32,
+    scale: u8,
+    highest_ack: Option<Sequence>,
+    /// Whether the window was last advertised in a SYN, unscaled.
+    syn_window: bool,
+}
+
+impl WindowTracker {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the shift negotiated with the window scale option.
+    /// Applies to windows advertised after this call.
+    pub fn set_scale(&mut self, shift: u8) {
+        self.scale = shift.min(MAX_WINDOW_SCALE);
+    }
+
+    /// Records the acknowledgement number and raw window of a segment sent by the tracked side.
+    /// Acknowledgements behind the highest one seen so far don't move the window.
+    pub fn update(&mut self, ack: Sequence, window: u16) {
+        match self.highest_ack {
+            Some(highest_ack) if ack.is_before(highest_ack) => return,
+            _ => {}
+        }
+        self.highest_ack = Some(ack);
+        self.window = u32::from(window) << self.scale;
+        self.syn_window = false;
+    }
+
+    /// Records the acknowledgement number and window of a SYN sent by the tracked side,
+    /// windows of SYNs are never scaled (RFC 7323).
+    pub fn update_from_syn(&mut self, ack: Sequence, window: u16) {
+        match self.highest_ack {
+            Some(highest_ack) if ack.is_before(highest_ack) => return,
+            _ => {}
+        }
+        self.highest_ack = Some(ack);
+        self.window = u32::from(window);
+        self.syn_window = true;
+    }
+
+    /// How far a segment sent by the tracked side moves the right edge of its window back,
+    /// `None` if it doesn't, if the segment is stale, or if the window is only known from a SYN.
+    pub fn retraction(&self, ack: Sequence, window: u16) -> Option<u32> {
+        let highest_ack = self.highest_ack.filter(|_| !self.syn_window)?;
+        if ack.is_before(highest_ack) {
+            return None
+        }
+        let right_edge = highest_ack + self.window;
+        let retraction = (ack + (u32::from(window) << self.scale)).distance(right_edge);
+        if retraction > 0 { Some(retraction as u32) } else { None }
+    }
+
+    pub fn scale(&self) -> u8 {
+        self.scale
+    }
+
+    /// Advertised window in bytes, scale applied.
+    pub fn window(&self) -> u32 {
+        self.window
+    }
+
+    pub fn highest_ack(&self) -> Option<Sequence> {
+        self.highest_ack
+    }
+
+    /// Classifies a segment of `len` bytes starting at `seq` sent towards the tracked side.
+    /// Until the side acknowledges anything every segment is considered in window.
+    pub fn classify(&self, seq: Sequence, len: u32) -> SegmentClass {
+        let highest_ack = match self.highest_ack {
+            Some(highest_ack) => highest_ack,
+            None => return SegmentClass::InWindow,
+        };
+        let segment = WrappingRange::new(seq, len);
+        if len > 0 && !segment.end().is_after(highest_ack) {
+            return SegmentClass::Duplicate
+        }
+        if len == 0 && seq.is_before(highest_ack) {
+            return SegmentClass::Duplicate
+        }
+        // zero window still accepts a segment at exactly the acknowledged sequence
+        let window = WrappingRange::new(highest_ack, self.window.max(1));
+        if window.contains(seq) || window.overlaps(&segment) {
+            SegmentClass::InWindow
+        } else {
+            SegmentClass::OutOfWindow
+        }
+    }
+
+    /// Classifies a RST at `seq` sent towards the tracked side.
+    /// Until the side acknowledges anything every RST is taken to be exact.
+    pub fn classify_rst(&self, seq: Sequence) -> RstClass {
+        let highest_ack = match self.highest_ack {
+            Some(highest_ack) => highest_ack,
+            None => return RstClass::Exact,
+        };
+        if seq == highest_ack {
+            RstClass::Exact
+        } else if WrappingRange::new(highest_ack, self.window.max(1)).contains(seq) {
+            RstClass::ChallengeAck
+        } else {
+            RstClass::OffWindow
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn classifies_against_scaled_window() {
+        let mut tracker = WindowTracker::new();
+        assert_eq!(tracker.classify(Sequence::from(12345), 100), SegmentClass::InWindow);
+
+        tracker.set_scale(2);
+        tracker.update(Sequence::from(u32::MAX - 99), 100);
+        assert_eq!(tracker.window(), 400);
+
+        assert_eq!(tracker.classify(Sequence::from(u32::MAX - 99), 0), SegmentClass::InWindow);
+        assert_eq!(tracker.classify(Sequence::from(250), 50), SegmentClass::InWindow);
+        assert_eq!(tracker.classify(Sequence::from(300), 10), SegmentClass::OutOfWindow);
+        assert_eq!(tracker.classify(Sequence::from(u32::MAX - 199), 100), SegmentClass::Duplicate);
+        assert_eq!(tracker.classify(Sequence::from(u32::MAX - 150), 100), SegmentClass::InWindow);
+
+        assert_eq!(tracker.classify_rst(Sequence::from(u32::MAX - 99)), RstClass::Exact);
+        assert_eq!(tracker.classify_rst(Sequence::from(250)), RstClass::ChallengeAck);
+        assert_eq!(tracker.classify_rst(Sequence::from(300)), RstClass::OffWindow);
+
+        // stale acknowledgement is ignored
+        tracker.update(Sequence::from(u32::MAX - 500), 1);
+        assert_eq!(tracker.highest_ack(), Some(Sequence::from(u32::MAX - 99)));
+        assert_eq!(tracker.window(), 400);
+    }
+}